@@ -0,0 +1,144 @@
+//! Adaptive quality for streams (gRPC `Stream`, the WebRTC live view): when
+//! capturing and encoding a frame takes more than its share of the interval,
+//! lower the frame rate, then the resolution, and raise them again once
+//! frames are cheap, so a stream never starves the app's main thread.
+//! `?adaptive=false` keeps a stream at the quality it asked for, which
+//! `?max_width=` caps.
+
+use crate::{query_param, CaptureOptions};
+#[cfg(feature = "webrtc")]
+use serde_json::{json, Value};
+use std::time::Duration;
+use tauri::{Runtime, WebviewWindow};
+
+/// Steps down from what the stream asked for: how many times longer the
+/// interval is, and what share of the resolution is kept.
+const LEVELS: [(u32, f64); 6] = [(1, 1.0), (2, 1.0), (2, 0.75), (4, 0.75), (4, 0.5), (8, 0.5)];
+/// Share of the interval a frame's capture and encoding may take; the rest
+/// is left to the app.
+const BUDGET: f64 = 0.5;
+/// Frames over budget in a row before stepping down, so a single slow
+/// frame (a page load, a GC pause) doesn't.
+const OVER_BUDGET_FRAMES: u32 = 2;
+/// Frames well under budget in a row before stepping back up.
+const UNDER_BUDGET_FRAMES: u32 = 20;
+
+/// Quality control for one stream.
+#[derive(Debug, Clone)]
+pub(crate) struct Adaptive {
+    enabled: bool,
+    /// The interval the stream asked for.
+    interval: Duration,
+    /// See [`crate::stream_max_width`].
+    max_width: Option<u32>,
+    level: usize,
+    over: u32,
+    under: u32,
+}
+
+impl Adaptive {
+    /// Control a stream of a frame every `interval`, unless `url` has
+    /// `?adaptive=false`, and no wider than its `?max_width=`.
+    pub(crate) fn new(url: &str, interval: Duration) -> Result<Self, String> {
+        let enabled = match query_param(url, "adaptive") {
+            None => true,
+            Some(v) => v
+                .parse()
+                .map_err(|_| format!("adaptive must be true or false, got '{v}'"))?,
+        };
+        Ok(Self {
+            enabled,
+            interval,
+            max_width: crate::stream_max_width(url)?,
+            level: 0,
+            over: 0,
+            under: 0,
+        })
+    }
+
+    /// Time between frames at the current level.
+    pub(crate) fn interval(&self) -> Duration {
+        self.interval * LEVELS[self.level].0
+    }
+
+    /// `settings` for a frame of `window` at the current resolution, after
+    /// the stream's `?max_width=`.
+    pub(crate) fn settings<R: Runtime>(
+        &self,
+        window: &WebviewWindow<R>,
+        settings: &CaptureOptions,
+    ) -> CaptureOptions {
+        let share = LEVELS[self.level].1;
+        let mut settings = crate::capped(window, settings, self.max_width);
+        if share < 1.0 {
+            // The window's scale factor applies when `settings` has none
+            let display_scale = window.scale_factor().unwrap_or(1.0);
+            match settings.width {
+                Some(width) => settings.width = Some(((f64::from(width) * share) as u32).max(1)),
+                None => settings.scale = Some(settings.scale.unwrap_or(display_scale) * share),
+            }
+        }
+        settings
+    }
+
+    /// Account for a frame whose capture and encoding took `work`, moving
+    /// a level down or up when it's time to. `true` if the level changed.
+    pub(crate) fn record(&mut self, work: Duration) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let budget = self.interval().mul_f64(BUDGET);
+        if work > budget {
+            self.under = 0;
+            self.over += 1;
+            if self.over >= OVER_BUDGET_FRAMES && self.level + 1 < LEVELS.len() {
+                self.over = 0;
+                self.level += 1;
+                return true;
+            }
+        } else {
+            self.over = 0;
+            // Going up doubles the work at worst, so only when that fits
+            if work * 2 <= budget {
+                self.under += 1;
+            } else {
+                self.under = 0;
+            }
+            if self.under >= UNDER_BUDGET_FRAMES && self.level > 0 {
+                self.under = 0;
+                self.level -= 1;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// The level (0 is what the stream asked for), interval and share of
+    /// the resolution being applied.
+    pub(crate) fn quality(&self) -> Quality {
+        Quality {
+            level: self.level as u32,
+            interval_ms: self.interval().as_millis() as u32,
+            resolution: LEVELS[self.level].1,
+        }
+    }
+}
+
+/// The quality a stream is at, as reported alongside its frames.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Quality {
+    pub(crate) level: u32,
+    pub(crate) interval_ms: u32,
+    pub(crate) resolution: f64,
+}
+
+#[cfg(feature = "webrtc")]
+impl Quality {
+    pub(crate) fn to_json(self) -> Value {
+        json!({
+            "level": self.level,
+            "interval_ms": self.interval_ms,
+            "resolution": self.resolution,
+        })
+    }
+}
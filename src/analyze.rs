@@ -0,0 +1,127 @@
+//! `GET /screenshot/analyze` and `GET /screenshot/blank`: color statistics
+//! of a capture, for "did anything render?" checks that don't need the
+//! image itself.
+
+use crate::format::Frame;
+use serde_json::{json, Value};
+
+/// Colors are bucketed to 4 bits per channel when finding dominant ones.
+const BUCKET_SHIFT: u32 = 4;
+const DOMINANT_COLORS: usize = 5;
+const HISTOGRAM_BINS: usize = 16;
+/// Default share of the frame one color must cover for it to count as
+/// blank (`?tolerance=`).
+pub(crate) const BLANK_TOLERANCE: f64 = 0.99;
+/// Channel difference (out of 255) below which a pixel counts as unchanged,
+/// so antialiasing jitter doesn't register as a change.
+const PIXEL_TOLERANCE: u8 = 8;
+
+/// Dominant colors (with the share of pixels each covers), average
+/// brightness and its spread, and a luma histogram of `frame`. Brightness
+/// is Rec. 709 luma in `0.0..=1.0`; transparency is ignored.
+pub(crate) fn analyze(frame: &Frame, tolerance: f64) -> Value {
+    let pixels = frame.rgba.len() / 4;
+    let mut histogram = [0u64; HISTOGRAM_BINS];
+    let (mut luma_sum, mut luma_squares) = (0.0f64, 0.0f64);
+    for pixel in frame.rgba.chunks_exact(4) {
+        let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(f64::from);
+        let luma = (0.2126 * r + 0.7152 * g + 0.0722 * b) / 255.0;
+        luma_sum += luma;
+        luma_squares += luma * luma;
+        histogram[((luma * HISTOGRAM_BINS as f64) as usize).min(HISTOGRAM_BINS - 1)] += 1;
+    }
+
+    let colors = dominant_colors(frame);
+    let dominant: Vec<Value> = colors
+        .iter()
+        .take(DOMINANT_COLORS)
+        .map(|&(count, color)| json!({ "color": hex(color), "share": share(count, pixels) }))
+        .collect();
+
+    let mean = luma_sum / pixels.max(1) as f64;
+    let variance = (luma_squares / pixels.max(1) as f64 - mean * mean).max(0.0);
+    json!({
+        "width": frame.width,
+        "height": frame.height,
+        "blank": is_blank(&colors, pixels, tolerance),
+        "dominant_colors": dominant,
+        "brightness": {
+            "mean": round(mean),
+            "stddev": round(variance.sqrt()),
+        },
+        "histogram": histogram.map(|count| share(count, pixels)),
+    })
+}
+
+/// Whether one color covers at least `tolerance` of `frame`, and which.
+pub(crate) fn blank(frame: &Frame, tolerance: f64) -> Value {
+    let pixels = frame.rgba.len() / 4;
+    let colors = dominant_colors(frame);
+    let top = colors.first();
+    json!({
+        "blank": is_blank(&colors, pixels, tolerance),
+        "color": top.map(|&(_, color)| hex(color)),
+        "share": top.map_or(0.0, |&(count, _)| share(count, pixels)),
+    })
+}
+
+/// Colors of `frame` by bucket, most common first, as (pixel count, the
+/// bucket's mean color).
+fn dominant_colors(frame: &Frame) -> Vec<(u64, [u8; 3])> {
+    let mut buckets = vec![(0u64, [0u64; 3]); 1 << (3 * (8 - BUCKET_SHIFT))];
+    for pixel in frame.rgba.chunks_exact(4) {
+        let key = pixel[..3].iter().fold(0, |key, &c| {
+            key << (8 - BUCKET_SHIFT) | usize::from(c >> BUCKET_SHIFT)
+        });
+        let bucket = &mut buckets[key];
+        bucket.0 += 1;
+        for (sum, &c) in bucket.1.iter_mut().zip(&pixel[..3]) {
+            *sum += u64::from(c);
+        }
+    }
+
+    let mut colors: Vec<(u64, [u8; 3])> = buckets
+        .into_iter()
+        .filter(|&(count, _)| count > 0)
+        .map(|(count, sums)| (count, sums.map(|sum| (sum / count) as u8)))
+        .collect();
+    colors.sort_by_key(|&(count, _)| std::cmp::Reverse(count));
+    colors
+}
+
+fn is_blank(colors: &[(u64, [u8; 3])], pixels: usize, tolerance: f64) -> bool {
+    colors
+        .first()
+        .is_some_and(|&(count, _)| count as f64 >= tolerance * pixels as f64)
+}
+
+fn share(count: u64, pixels: usize) -> f64 {
+    round(count as f64 / pixels.max(1) as f64)
+}
+
+fn round(v: f64) -> f64 {
+    (v * 10_000.0).round() / 10_000.0
+}
+
+fn hex([r, g, b]: [u8; 3]) -> String {
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+/// Share of pixels that differ between two frames; frames of different
+/// sizes count as entirely changed.
+pub(crate) fn changed_share(a: &Frame, b: &Frame) -> f64 {
+    if (a.width, a.height) != (b.width, b.height) {
+        return 1.0;
+    }
+    let changed = a
+        .rgba
+        .chunks_exact(4)
+        .zip(b.rgba.chunks_exact(4))
+        .filter(|(p, q)| {
+            p.iter()
+                .zip(*q)
+                .any(|(x, y)| x.abs_diff(*y) > PIXEL_TOLERANCE)
+        })
+        .count();
+    changed as f64 / (a.rgba.len() / 4).max(1) as f64
+}
@@ -0,0 +1,205 @@
+//! Animated output: a sequence of captures encoded as one looping GIF,
+//! APNG or WebP, for `POST /storyboard` and `GET /history?format=`.
+
+use crate::format::{self, Frame};
+use crate::ImageFormat;
+use std::time::Duration;
+
+/// GIF quantizer speed, 1 (best) to 30 (fastest). 10 is the encoder's own
+/// default and keeps UI colors close at a tolerable cost per frame.
+const GIF_SPEED: i32 = 10;
+
+/// How long the last frame of a history recording shows when there's no
+/// gap to the next one to go by.
+const LAST_FRAME_DELAY: Duration = Duration::from_millis(1000);
+
+/// Format of an animation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum AnimationFormat {
+    /// 256 colors per frame, but plays everywhere.
+    #[default]
+    Gif,
+    /// Full color and alpha; plays in browsers, shows the first frame
+    /// elsewhere.
+    Apng,
+    /// Lossless, full color and alpha, and usually smaller than APNG.
+    Webp,
+}
+
+impl AnimationFormat {
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "gif" => Some(Self::Gif),
+            "apng" | "png" => Some(Self::Apng),
+            "webp" => Some(Self::Webp),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn content_type(self) -> &'static str {
+        match self {
+            Self::Gif => "image/gif",
+            Self::Apng => "image/apng",
+            Self::Webp => "image/webp",
+        }
+    }
+}
+
+/// Encode `frames`, each shown for its duration, as a looping animation.
+/// The frames must all have the size of the first.
+pub(crate) fn encode(
+    frames: &[(Frame, Duration)],
+    format: AnimationFormat,
+) -> Result<Vec<u8>, String> {
+    let Some((first, _)) = frames.first() else {
+        return Err("no frames to encode".into());
+    };
+    if let Some((i, (frame, _))) = frames
+        .iter()
+        .enumerate()
+        .find(|(_, (frame, _))| (frame.width, frame.height) != (first.width, first.height))
+    {
+        return Err(format!(
+            "frame {i} is {}x{}, the first is {}x{}",
+            frame.width, frame.height, first.width, first.height
+        ));
+    }
+    match format {
+        AnimationFormat::Gif => gif(frames),
+        AnimationFormat::Apng => apng(frames),
+        AnimationFormat::Webp => webp(frames),
+    }
+}
+
+/// Encode history captures (timestamp and encoded image, oldest first) as
+/// a recording: each frame shows until the next was taken.
+pub(crate) fn recording(
+    captures: &[(u64, bytes::Bytes)],
+    format: AnimationFormat,
+) -> Result<Vec<u8>, (u16, String)> {
+    if captures.is_empty() {
+        return Err((404, "no captures to animate".into()));
+    }
+    let frames = captures
+        .iter()
+        .enumerate()
+        .map(|(i, (timestamp, bytes))| {
+            let delay = captures.get(i + 1).map_or(LAST_FRAME_DELAY, |(next, _)| {
+                Duration::from_millis(next.saturating_sub(*timestamp))
+            });
+            Ok((format::decode(bytes)?, delay))
+        })
+        .collect::<Result<Vec<_>, String>>()
+        .map_err(|e| (500, e))?;
+    let (width, height) = (frames[0].0.width, frames[0].0.height);
+    if frames
+        .iter()
+        .any(|(frame, _)| (frame.width, frame.height) != (width, height))
+    {
+        return Err((
+            409,
+            "the captures differ in size; narrow them down with a test name or ?last=".into(),
+        ));
+    }
+    encode(&frames, format).map_err(|e| (500, e))
+}
+
+fn gif(frames: &[(Frame, Duration)]) -> Result<Vec<u8>, String> {
+    use image::codecs::gif::{GifEncoder, Repeat};
+
+    let mut out = Vec::new();
+    {
+        let mut encoder = GifEncoder::new_with_speed(&mut out, GIF_SPEED);
+        encoder
+            .set_repeat(Repeat::Infinite)
+            .map_err(|e| format!("GIF encoding failed: {e}"))?;
+        for (frame, delay) in frames {
+            let buffer = image::RgbaImage::from_raw(frame.width, frame.height, frame.rgba.clone())
+                .ok_or("GIF encoding failed: frame buffer of the wrong size")?;
+            encoder
+                .encode_frame(image::Frame::from_parts(
+                    buffer,
+                    0,
+                    0,
+                    image::Delay::from_saturating_duration(*delay),
+                ))
+                .map_err(|e| format!("GIF encoding failed: {e}"))?;
+        }
+    }
+    Ok(out)
+}
+
+fn apng(frames: &[(Frame, Duration)]) -> Result<Vec<u8>, String> {
+    let failed = |e: png::EncodingError| format!("APNG encoding failed: {e}");
+    let (first, _) = &frames[0];
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, first.width, first.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    // Zero plays loops forever
+    encoder
+        .set_animated(frames.len() as u32, 0)
+        .map_err(failed)?;
+    let mut writer = encoder.write_header().map_err(failed)?;
+    for (frame, delay) in frames {
+        let millis = delay.as_millis().min(u128::from(u16::MAX)) as u16;
+        writer.set_frame_delay(millis, 1000).map_err(failed)?;
+        writer.write_image_data(&frame.rgba).map_err(failed)?;
+    }
+    writer.finish().map_err(failed)?;
+    Ok(out)
+}
+
+/// An animated WebP: each frame encoded losslessly on its own and wrapped
+/// in an `ANMF` chunk of the extended container.
+fn webp(frames: &[(Frame, Duration)]) -> Result<Vec<u8>, String> {
+    // VP8X flags
+    const ANIMATION: u8 = 0x02;
+    const ALPHA: u8 = 0x10;
+    // ANMF flags: replace the canvas rather than alpha-blending onto it
+    const NO_BLEND: u8 = 0x02;
+
+    let u24 = |out: &mut Vec<u8>, value: u32| out.extend_from_slice(&value.to_le_bytes()[..3]);
+    let chunk = |out: &mut Vec<u8>, name: &[u8; 4], data: &[u8]| {
+        out.extend_from_slice(name);
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            out.push(0);
+        }
+    };
+
+    let (first, _) = &frames[0];
+    let mut chunks = Vec::new();
+    let mut vp8x = vec![ANIMATION | ALPHA, 0, 0, 0];
+    u24(&mut vp8x, first.width - 1);
+    u24(&mut vp8x, first.height - 1);
+    chunk(&mut chunks, b"VP8X", &vp8x);
+    // Transparent background, zero loops meaning forever
+    chunk(&mut chunks, b"ANIM", &[0, 0, 0, 0, 0, 0]);
+    for (frame, delay) in frames {
+        // A still lossless WebP is the simple container: the RIFF header,
+        // then a single VP8L chunk to move into the frame as it is
+        let still = format::encode(frame, ImageFormat::Webp)?;
+        let image = still
+            .get(12..)
+            .filter(|image| image.starts_with(b"VP8L"))
+            .ok_or("WebP encoding failed: unexpected container")?;
+        let mut anmf = Vec::with_capacity(16 + image.len());
+        u24(&mut anmf, 0);
+        u24(&mut anmf, 0);
+        u24(&mut anmf, frame.width - 1);
+        u24(&mut anmf, frame.height - 1);
+        u24(&mut anmf, delay.as_millis().min(0xff_ffff) as u32);
+        anmf.push(NO_BLEND);
+        anmf.extend_from_slice(image);
+        chunk(&mut chunks, b"ANMF", &anmf);
+    }
+
+    let mut out = Vec::with_capacity(12 + chunks.len());
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+    out.extend_from_slice(b"WEBP");
+    out.extend_from_slice(&chunks);
+    Ok(out)
+}
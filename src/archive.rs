@@ -0,0 +1,239 @@
+//! Disk-backed capture archive: every capture is written to a directory
+//! with an append-only `index.jsonl`, pruned by age and total size.
+
+use crate::logging;
+use crate::{ArchiveConfig, ImageFormat};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const INDEX_FILE: &str = "index.jsonl";
+
+/// Age-based pruning rewrites the index, so it runs at most this often.
+const AGE_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+pub(crate) struct Archive {
+    config: ArchiveConfig,
+    state: Mutex<State>,
+}
+
+struct State {
+    entries: VecDeque<IndexEntry>,
+    total_bytes: u64,
+    seq: u64,
+    last_age_prune_ms: u64,
+}
+
+struct IndexEntry {
+    file: String,
+    timestamp_ms: u64,
+    source: String,
+    /// Test name the capture was taken under (`X-Test-Name` / `?name=`).
+    name: Option<String>,
+    bytes: u64,
+}
+
+impl IndexEntry {
+    fn to_json(&self) -> Value {
+        json!({
+            "file": self.file,
+            "timestamp_ms": self.timestamp_ms,
+            "source": self.source,
+            "name": self.name,
+            "bytes": self.bytes,
+        })
+    }
+
+    fn from_json(line: &str) -> Option<Self> {
+        let v: Value = serde_json::from_str(line).ok()?;
+        Some(Self {
+            file: v["file"].as_str()?.to_string(),
+            timestamp_ms: v["timestamp_ms"].as_u64()?,
+            source: v["source"].as_str().unwrap_or_default().to_string(),
+            name: v["name"].as_str().map(str::to_string),
+            bytes: v["bytes"].as_u64().unwrap_or_default(),
+        })
+    }
+}
+
+impl Archive {
+    /// Open (or create) the archive, picking up an existing index.
+    pub(crate) fn open(config: ArchiveConfig) -> Self {
+        if let Err(e) = fs::create_dir_all(&config.dir) {
+            logging::warn!(
+                "[screenshot-hd] can't create archive dir {}: {e}",
+                config.dir.display()
+            );
+        }
+        let entries: VecDeque<IndexEntry> = fs::read_to_string(config.dir.join(INDEX_FILE))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(IndexEntry::from_json)
+            .collect();
+        let total_bytes = entries.iter().map(|e| e.bytes).sum();
+
+        Self {
+            config,
+            state: Mutex::new(State {
+                entries,
+                total_bytes,
+                seq: 0,
+                last_age_prune_ms: 0,
+            }),
+        }
+    }
+
+    /// Write a capture to disk and index it. Named captures go in a
+    /// subdirectory per test name. Failures are logged, never returned —
+    /// archiving must not fail the capture itself.
+    pub(crate) fn record(&self, source: &str, name: Option<&str>, png: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        state.seq += 1;
+
+        let timestamp_ms = crate::unix_millis();
+        let extension = ImageFormat::detect(png).unwrap_or_default().extension();
+        let file = format!(
+            "{timestamp_ms}-{:06}-{}.{extension}",
+            state.seq,
+            file_safe(source)
+        );
+        let entry = IndexEntry {
+            file: match name {
+                Some(name) => format!("{}/{file}", file_safe(name)),
+                None => file,
+            },
+            timestamp_ms,
+            source: source.to_string(),
+            name: name.map(str::to_string),
+            bytes: png.len() as u64,
+        };
+
+        let path = self.config.dir.join(&entry.file);
+        let written = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, png))
+            .and_then(|_| {
+                let mut index = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(self.config.dir.join(INDEX_FILE))?;
+                writeln!(index, "{}", entry.to_json())
+            });
+        if let Err(e) = written {
+            logging::warn!("[screenshot-hd] archive write failed: {e}");
+            return;
+        }
+
+        state.total_bytes += entry.bytes;
+        state.entries.push_back(entry);
+        self.prune(&mut state);
+    }
+
+    /// Index entries matching the filters, oldest first. `limit` keeps the
+    /// newest matches.
+    pub(crate) fn query(
+        &self,
+        since_ms: Option<u64>,
+        until_ms: Option<u64>,
+        source: Option<&str>,
+        name: Option<&str>,
+        limit: Option<usize>,
+    ) -> Value {
+        let state = self.state.lock().unwrap();
+        let matches: Vec<&IndexEntry> = state
+            .entries
+            .iter()
+            .filter(|e| since_ms.is_none_or(|t| e.timestamp_ms >= t))
+            .filter(|e| until_ms.is_none_or(|t| e.timestamp_ms <= t))
+            .filter(|e| source.is_none_or(|s| e.source == s))
+            .filter(|e| name.is_none_or(|n| e.name.as_deref() == Some(n)))
+            .collect();
+        let skip = limit.map_or(0, |n| matches.len().saturating_sub(n));
+        matches
+            .into_iter()
+            .skip(skip)
+            .map(IndexEntry::to_json)
+            .collect()
+    }
+
+    /// Read an archived capture. Only files present in the index are served.
+    pub(crate) fn read(&self, file: &str) -> Option<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+        state.entries.iter().find(|e| e.file == file)?;
+        fs::read(self.config.dir.join(file)).ok()
+    }
+
+    fn prune(&self, state: &mut State) {
+        let now = crate::unix_millis();
+        let mut pruned = false;
+
+        if let Some(max_age) = self.config.max_age {
+            if now.saturating_sub(state.last_age_prune_ms) >= AGE_PRUNE_INTERVAL.as_millis() as u64
+            {
+                state.last_age_prune_ms = now;
+                let cutoff = now.saturating_sub(max_age.as_millis() as u64);
+                while state
+                    .entries
+                    .front()
+                    .is_some_and(|e| e.timestamp_ms < cutoff)
+                {
+                    self.remove_oldest(state);
+                    pruned = true;
+                }
+            }
+        }
+
+        // Prune down to 90% of the budget so the index isn't rewritten on
+        // every capture once the archive is full.
+        if let Some(max_bytes) = self.config.max_bytes {
+            if state.total_bytes > max_bytes {
+                while state.total_bytes > max_bytes / 10 * 9 && !state.entries.is_empty() {
+                    self.remove_oldest(state);
+                    pruned = true;
+                }
+            }
+        }
+
+        if pruned {
+            if let Err(e) = self.rewrite_index(state) {
+                logging::warn!("[screenshot-hd] archive index rewrite failed: {e}");
+            }
+        }
+    }
+
+    fn remove_oldest(&self, state: &mut State) {
+        if let Some(entry) = state.entries.pop_front() {
+            state.total_bytes -= entry.bytes;
+            let _ = fs::remove_file(self.config.dir.join(&entry.file));
+        }
+    }
+
+    fn rewrite_index(&self, state: &State) -> std::io::Result<()> {
+        let tmp: PathBuf = self.config.dir.join(format!("{INDEX_FILE}.tmp"));
+        let mut out = String::new();
+        for entry in &state.entries {
+            out.push_str(&entry.to_json().to_string());
+            out.push('\n');
+        }
+        fs::write(&tmp, out)?;
+        fs::rename(tmp, self.config.dir.join(INDEX_FILE))
+    }
+}
+
+/// Restrict a label to characters that are safe in file names.
+fn file_safe(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
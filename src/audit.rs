@@ -0,0 +1,85 @@
+//! Append-only audit log of code-execution requests: every `/eval` and BiDi
+//! `script.evaluate`, with who sent it and a hash of what ran.
+
+use crate::logging;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub(crate) struct AuditLog {
+    path: PathBuf,
+    /// Serializes appends so concurrent requests don't interleave lines.
+    write: Mutex<()>,
+}
+
+impl AuditLog {
+    pub(crate) fn open(path: PathBuf) -> Self {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            if let Err(e) = fs::create_dir_all(dir) {
+                logging::warn!(
+                    "[screenshot-hd] can't create audit log dir {}: {e}",
+                    dir.display()
+                );
+            }
+        }
+        Self {
+            path,
+            write: Mutex::new(()),
+        }
+    }
+
+    /// Append an entry for `payload` run via `endpoint` in `window`. Failures
+    /// are logged; the request itself still proceeds.
+    pub(crate) fn record(
+        &self,
+        remote: Option<SocketAddr>,
+        endpoint: &str,
+        window: &str,
+        payload: &str,
+    ) {
+        let entry = json!({
+            "timestamp_ms": crate::unix_millis(),
+            "ip": remote.map(|a| a.ip().to_string()),
+            "endpoint": endpoint,
+            "window": window,
+            "bytes": payload.len(),
+            "sha256": sha256_hex(payload.as_bytes()),
+        });
+
+        let _guard = self.write.lock().unwrap();
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut f| writeln!(f, "{entry}"));
+        if let Err(e) = written {
+            logging::warn!("[screenshot-hd] audit log write failed: {e}");
+        }
+    }
+
+    /// Logged entries, oldest first. `last` keeps only the newest `n`.
+    pub(crate) fn read(&self, last: Option<usize>) -> Result<Value, String> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("can't read audit log: {e}")),
+        };
+        let entries: Vec<Value> = contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        let skip = last.map_or(0, |n| entries.len().saturating_sub(n));
+        Ok(entries.into_iter().skip(skip).collect())
+    }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
@@ -0,0 +1,71 @@
+//! Pluggable capture backends ([`Config::backend`](crate::Config::backend)).
+//!
+//! Without a backend, captures go through the native WKWebView path, which
+//! encodes PNG, JPEG, TIFF and BMP in AppKit straight from the snapshot.
+//! A backend instead hands back decoded pixels, which go through the same
+//! transforms and encoders as every other capture.
+
+use crate::format::{self, Frame};
+use crate::{deadline, logging};
+use crate::{transform, CaptureOptions, ImageFormat};
+use std::fmt;
+use tauri::{Runtime, WebviewWindow};
+
+/// Renders frames for captures, e.g. an offscreen renderer for
+/// deterministic CI output. Runs on the capturing thread, not the main
+/// thread, and only under Tauri's default (Wry) runtime.
+pub trait CaptureBackend: fmt::Debug + Send + Sync {
+    /// Render `window` as described by `options`: honor `clip`,
+    /// `color_space`, `scale` and `width`. Viewport resizing (`size`),
+    /// `wait`, the `pipeline` and encoding in `format` are handled by the
+    /// plugin.
+    fn capture(&self, window: &WebviewWindow, options: &CaptureOptions) -> Result<Frame, String>;
+}
+
+/// The built-in backend: a native WKWebView snapshot (macOS only).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WebKitBackend;
+
+impl CaptureBackend for WebKitBackend {
+    fn capture(&self, window: &WebviewWindow, options: &CaptureOptions) -> Result<Frame, String> {
+        // QOI is the cheapest to encode and decode again; the pipeline runs
+        // afterwards, as for any backend
+        let settings = CaptureOptions {
+            format: ImageFormat::Qoi,
+            pipeline: Vec::new(),
+            ..options.clone()
+        };
+        format::decode(&crate::take_screenshot(window, &settings)?)
+    }
+}
+
+/// Capture with `backend` and encode as `settings` ask. `None` when the
+/// app doesn't use the Wry runtime, so the caller falls back to the native
+/// path.
+pub(crate) fn capture<R: Runtime>(
+    backend: &dyn CaptureBackend,
+    window: &WebviewWindow<R>,
+    settings: &CaptureOptions,
+) -> Option<Result<Vec<u8>, String>> {
+    let Some(window) = crate::hooks::wry_window(window) else {
+        logging::debug!("[screenshot-hd] capture backend skipped: not the Wry runtime");
+        return None;
+    };
+    let frame = match backend
+        .capture(window, settings)
+        .and_then(|frame| deadline::check(settings.deadline, "snapshot").map(|()| frame))
+    {
+        Ok(frame) => frame,
+        Err(e) => return Some(Err(e)),
+    };
+    let expected = u64::from(frame.width) * u64::from(frame.height) * 4;
+    if frame.rgba.len() as u64 != expected {
+        return Some(Err(format!(
+            "backend returned {} bytes for a {}x{} frame",
+            frame.rgba.len(),
+            frame.width,
+            frame.height
+        )));
+    }
+    Some(transform::apply(frame, settings).and_then(|f| format::encode(&f, settings.format)))
+}
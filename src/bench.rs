@@ -0,0 +1,95 @@
+//! `GET /bench`: repeated captures with per-stage latency percentiles, to
+//! spot capture regressions after Tauri or macOS updates.
+
+use crate::{CaptureOptions, CaptureTimings, Context};
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+use tauri::{Runtime, WebviewWindow};
+
+pub(crate) const DEFAULT_ITERATIONS: usize = 20;
+pub(crate) const MAX_ITERATIONS: usize = 200;
+
+/// Capture `iterations` times and summarize. Stops early (reporting
+/// `"truncated": true`) if `deadline` would pass.
+pub(crate) fn run<R: Runtime>(
+    window: &WebviewWindow<R>,
+    context: &Context,
+    settings: &CaptureOptions,
+    iterations: usize,
+    deadline: Instant,
+) -> Result<Value, String> {
+    let mut samples: Vec<(CaptureTimings, Duration)> = Vec::with_capacity(iterations);
+    let mut png_bytes = 0;
+    let mut dimensions = None;
+    let started = Instant::now();
+
+    for _ in 0..iterations {
+        // Leave room for one more capture at the average pace
+        let average = started.elapsed() / (samples.len() as u32).max(1);
+        if Instant::now() + average >= deadline {
+            break;
+        }
+        let _permit = context
+            .capture_gate
+            .acquire(crate::CAPTURE_QUEUE_TIMEOUT, settings.priority)?;
+        let capture_started = Instant::now();
+        let (png, timings) = crate::take_screenshot_timed(window, settings)?;
+        samples.push((timings, capture_started.elapsed()));
+        png_bytes = png.len();
+        dimensions = crate::format::dimensions(&png);
+    }
+    let elapsed = started.elapsed();
+
+    let megapixels = dimensions.map_or(0.0, |(w, h)| f64::from(w) * f64::from(h) / 1e6);
+    let stage = |f: fn(&(CaptureTimings, Duration)) -> Duration| {
+        summarize(samples.iter().map(f).collect(), megapixels)
+    };
+
+    Ok(json!({
+        "iterations": samples.len(),
+        "truncated": samples.len() < iterations,
+        "image": {
+            "width": dimensions.map(|(w, _)| w),
+            "height": dimensions.map(|(_, h)| h),
+            "bytes": png_bytes,
+        },
+        "stages": {
+            "dispatch": stage(|(t, _)| t.dispatch),
+            "setup": stage(|(t, _)| t.setup),
+            "snapshot": stage(|(t, _)| t.snapshot),
+            "encode": stage(|(t, _)| t.encode),
+            "transfer": stage(|(t, _)| t.transfer),
+            "total": stage(|(_, total)| *total),
+        },
+        "throughput": {
+            "captures_per_sec": samples.len() as f64 / elapsed.as_secs_f64(),
+            "megapixels_per_sec": samples.len() as f64 * megapixels / elapsed.as_secs_f64(),
+        },
+    }))
+}
+
+/// Latency percentiles in milliseconds, plus the megapixel rate the stage
+/// alone would sustain.
+fn summarize(mut durations: Vec<Duration>, megapixels: f64) -> Value {
+    if durations.is_empty() {
+        return Value::Null;
+    }
+    durations.sort();
+    let ms = |d: Duration| (d.as_secs_f64() * 1e6).round() / 1e3;
+    // Nearest-rank percentile
+    let percentile = |p: f64| {
+        let rank = (p / 100.0 * durations.len() as f64).ceil() as usize;
+        ms(durations[rank.clamp(1, durations.len()) - 1])
+    };
+    let mean = durations.iter().sum::<Duration>() / durations.len() as u32;
+
+    json!({
+        "min_ms": ms(durations[0]),
+        "p50_ms": percentile(50.0),
+        "p90_ms": percentile(90.0),
+        "p99_ms": percentile(99.0),
+        "max_ms": ms(durations[durations.len() - 1]),
+        "mean_ms": ms(mean),
+        "megapixels_per_sec": (mean > Duration::ZERO).then(|| megapixels / mean.as_secs_f64()),
+    })
+}
@@ -0,0 +1,231 @@
+//! Minimal WebDriver BiDi endpoint (`bidi` feature).
+//!
+//! Speaks just enough of the protocol for BiDi clients to attach to the
+//! webview over `ws://<host>:<port>/session`:
+//!
+//! - `session.status`, `session.new`, `session.end`
+//! - `session.subscribe` / `session.unsubscribe`, accepted so clients that
+//!   subscribe on connect can attach
+//! - `browsingContext.getTree`
+//! - `browsingContext.captureScreenshot` (native capture, base64 PNG)
+//! - `script.evaluate` (macOS only; promises are not awaited)
+//!
+//! The webview is the only browsing context and is identified by its window
+//! label. Each connection is served on its own thread so it doesn't hold up
+//! the HTTP loop.
+//!
+//! It's command/response only: no events (`browsingContext.load`,
+//! `log.entryAdded`, ...) are ever sent. The upgraded connection can't be
+//! read with a timeout, so the session thread has no way to write while it
+//! waits for the next command. `GET /events` covers captures and window
+//! changes instead.
+
+use crate::logging;
+use crate::{CaptureOptions, Context, ErrorKind, ImageFormat};
+use base64::Engine;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tauri::{Runtime, WebviewWindow};
+use tungstenite::{protocol::Role, Message, WebSocket};
+
+/// Complete the WebSocket handshake and serve the session on its own thread.
+pub(crate) fn serve<R: Runtime>(
+    request: tiny_http::Request,
+    window: WebviewWindow<R>,
+    context: Arc<Context>,
+) {
+    let key = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Sec-WebSocket-Key"))
+        .map(|h| h.value.as_str().to_owned());
+    let Some(key) = key else {
+        let resp =
+            tiny_http::Response::from_string("expected a WebSocket upgrade").with_status_code(400);
+        let _ = request.respond(resp);
+        return;
+    };
+
+    let remote = request.remote_addr().copied();
+    let accept = tungstenite::handshake::derive_accept_key(key.as_bytes());
+    let resp = tiny_http::Response::empty(101).with_header(
+        format!("Sec-WebSocket-Accept: {accept}")
+            .parse::<tiny_http::Header>()
+            .unwrap(),
+    );
+    let stream = request.upgrade("websocket", resp);
+
+    std::thread::spawn(move || {
+        let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+        logging::info!("[screenshot-hd] BiDi session opened");
+
+        loop {
+            let text = match socket.read() {
+                Ok(Message::Text(text)) => text,
+                Ok(Message::Close(_)) | Err(_) => break,
+                Ok(_) => continue,
+            };
+            let (reply, end) = handle_command(&window, &context, remote, &text);
+            if socket.send(Message::text(reply.to_string())).is_err() || end {
+                break;
+            }
+        }
+
+        let _ = socket.close(None);
+        logging::info!("[screenshot-hd] BiDi session closed");
+    });
+}
+
+/// Handle one command message. Returns the reply and whether the session ends.
+fn handle_command<R: Runtime>(
+    window: &WebviewWindow<R>,
+    context: &Context,
+    remote: Option<SocketAddr>,
+    text: &str,
+) -> (Value, bool) {
+    let command: Value = match serde_json::from_str(text) {
+        Ok(v) => v,
+        Err(e) => {
+            return (
+                error(Value::Null, "invalid argument", &e.to_string()),
+                false,
+            )
+        }
+    };
+    let id = command["id"].clone();
+    let method = command["method"].as_str().unwrap_or_default();
+    let params = &command["params"];
+    let label = window.label();
+
+    // Commands addressing a context must address ours.
+    let target = params["context"]
+        .as_str()
+        .or_else(|| params["target"]["context"].as_str());
+    if target.is_some_and(|c| c != label) {
+        let msg = format!("no browsing context '{}'", target.unwrap_or_default());
+        return (error(id, "no such frame", &msg), false);
+    }
+
+    let result = match method {
+        "session.status" => Ok(json!({ "ready": true, "message": "screenshot-hd" })),
+        "session.new" => Ok(json!({
+            "sessionId": "screenshot-hd",
+            "capabilities": {
+                "browserName": "tauri",
+                "browserVersion": tauri::VERSION,
+                "platformName": std::env::consts::OS,
+                "acceptInsecureCerts": false,
+                "setWindowRect": false,
+                "userAgent": "",
+            },
+        })),
+        "session.end" => return (success(id, json!({})), true),
+        // Nothing is ever emitted, see the module docs
+        "session.subscribe" | "session.unsubscribe" => Ok(json!({})),
+        "browsingContext.getTree" => Ok(json!({
+            "contexts": [{
+                "context": label,
+                "url": window.url().map(|u| u.to_string()).unwrap_or_default(),
+                "children": [],
+                "parent": null,
+                "userContext": "default",
+                "clientWindow": label,
+                "originalOpener": null,
+            }],
+        })),
+        // BiDi screenshots are always PNG, whatever Config::format says
+        "browsingContext.captureScreenshot" => context
+            .capture_with(
+                window,
+                "bidi",
+                None,
+                &CaptureOptions {
+                    format: ImageFormat::Png,
+                    ..context.capture_defaults.clone()
+                },
+            )
+            .map(|png| json!({ "data": base64::engine::general_purpose::STANDARD.encode(png) }))
+            .map_err(|e| ("unable to capture screen", e)),
+        "script.evaluate" if !context.endpoint_enabled("/eval") => {
+            Err(("unsupported operation", "/eval is disabled".to_string()))
+        }
+        "script.evaluate" if context.read_only_blocks(&tiny_http::Method::Post, "/eval") => Err((
+            "unsupported operation",
+            "/eval is disabled in read-only mode".to_string(),
+        )),
+        "script.evaluate" => match params["expression"].as_str() {
+            Some(expr) => {
+                if let Some(audit) = &context.audit {
+                    audit.record(remote, "bidi:script.evaluate", label, expr);
+                }
+                Ok(evaluate(context, window, expr))
+            }
+            None => Err(("invalid argument", "missing params.expression".to_string())),
+        },
+        _ => Err(("unknown command", format!("unsupported method '{method}'"))),
+    };
+
+    match result {
+        Ok(result) => (success(id, result), false),
+        Err((code, msg)) => (error(id, code, &msg), false),
+    }
+}
+
+/// Run `script.evaluate`, reporting script failures as BiDi exceptions.
+fn evaluate<R: Runtime>(context: &Context, window: &WebviewWindow<R>, expr: &str) -> Value {
+    let realm = window.label();
+    logging::debug!("[screenshot-hd] eval in '{realm}': {}", logging::body(expr));
+    let result = match &context.eval_policy {
+        Some(policy) => policy
+            .wrap_expression(expr)
+            .and_then(|expr| crate::eval_json(window, &expr)),
+        None => crate::eval_json(window, expr),
+    };
+    match result {
+        Ok(value) => json!({ "type": "success", "realm": realm, "result": remote_value(value) }),
+        Err(e) => {
+            context.report(ErrorKind::Eval, window, &e);
+            json!({
+            "type": "exception",
+            "realm": realm,
+            "exceptionDetails": {
+                "text": e,
+                "lineNumber": 0,
+                "columnNumber": 0,
+                "exception": { "type": "error" },
+                "stackTrace": { "callFrames": [] },
+            },
+            })
+        }
+    }
+}
+
+/// Convert a JSON value into a BiDi `script.RemoteValue`.
+fn remote_value(value: Value) -> Value {
+    match value {
+        Value::Null => json!({ "type": "null" }),
+        Value::Bool(b) => json!({ "type": "boolean", "value": b }),
+        Value::Number(n) => json!({ "type": "number", "value": n }),
+        Value::String(s) => json!({ "type": "string", "value": s }),
+        Value::Array(items) => json!({
+            "type": "array",
+            "value": items.into_iter().map(remote_value).collect::<Vec<_>>(),
+        }),
+        Value::Object(entries) => json!({
+            "type": "object",
+            "value": entries
+                .into_iter()
+                .map(|(k, v)| json!([k, remote_value(v)]))
+                .collect::<Vec<_>>(),
+        }),
+    }
+}
+
+fn success(id: Value, result: Value) -> Value {
+    json!({ "type": "success", "id": id, "result": result })
+}
+
+fn error(id: Value, code: &str, message: &str) -> Value {
+    json!({ "type": "error", "id": id, "error": code, "message": message })
+}
@@ -0,0 +1,133 @@
+//! The memory budget (see
+//! [`Config::memory_budget`](crate::Config::memory_budget)): a count of the
+//! bytes the plugin holds on to — captures in flight, the history buffer and
+//! frames on their way into a stream — checked before it takes more. Frame
+//! buffers are pooled and reused rather than reallocated for every capture,
+//! which at ~60 MB a frame on a 5K display is what fragments the heap.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Freed frame buffers kept for reuse.
+const POOL_BUFFERS: usize = 2;
+
+static POOL: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+/// Capacity of the buffers in [`POOL`].
+static POOLED: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) struct MemoryBudget {
+    limit: Option<usize>,
+    used: AtomicUsize,
+}
+
+/// Bytes charged to the budget, released on drop.
+pub(crate) struct Charge<'a> {
+    budget: &'a MemoryBudget,
+    bytes: usize,
+}
+
+impl MemoryBudget {
+    pub(crate) fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// Charge `bytes` for `what` (e.g. "the capture"), or refuse if they
+    /// don't fit.
+    pub(crate) fn reserve(&self, bytes: usize, what: &str) -> Result<Charge<'_>, String> {
+        if !self.charge(bytes) {
+            return Err(format!(
+                "{what} needs {} MB, which would exceed the {} MB memory budget ({} MB in use)",
+                megabytes(bytes),
+                megabytes(self.limit.unwrap_or_default()),
+                megabytes(self.used())
+            ));
+        }
+        Ok(Charge {
+            budget: self,
+            bytes,
+        })
+    }
+
+    /// Charge `bytes` if they fit, to be [`release`](Self::release)d by the
+    /// caller. Pooled buffers count too, and are freed first when they're
+    /// what's in the way.
+    pub(crate) fn charge(&self, bytes: usize) -> bool {
+        let Some(limit) = self.limit else {
+            self.used.fetch_add(bytes, Ordering::SeqCst);
+            return true;
+        };
+        if self.used() + POOLED.load(Ordering::SeqCst) + bytes > limit {
+            drain_pool();
+        }
+        self.used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                (used + bytes <= limit).then_some(used + bytes)
+            })
+            .is_ok()
+    }
+
+    pub(crate) fn release(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::SeqCst);
+    }
+
+    pub(crate) fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Bytes currently charged.
+    pub(crate) fn used(&self) -> usize {
+        self.used.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for Charge<'_> {
+    fn drop(&mut self) {
+        self.budget.release(self.bytes);
+    }
+}
+
+/// An empty buffer with room for `len` bytes, from the pool if one there is
+/// big enough.
+pub(crate) fn buffer(len: usize) -> Vec<u8> {
+    let mut pool = POOL.lock().unwrap();
+    match pool.iter().position(|b| b.capacity() >= len) {
+        Some(i) => {
+            let mut buffer = pool.swap_remove(i);
+            POOLED.fetch_sub(buffer.capacity(), Ordering::SeqCst);
+            buffer.clear();
+            buffer
+        }
+        None => Vec::with_capacity(len),
+    }
+}
+
+/// Give `buffer` back for reuse. The smallest pooled buffer is dropped when
+/// the pool is full.
+pub(crate) fn recycle(buffer: Vec<u8>) {
+    if buffer.capacity() == 0 {
+        return;
+    }
+    let mut pool = POOL.lock().unwrap();
+    POOLED.fetch_add(buffer.capacity(), Ordering::SeqCst);
+    pool.push(buffer);
+    if pool.len() > POOL_BUFFERS {
+        let smallest = (0..pool.len())
+            .min_by_key(|&i| pool[i].capacity())
+            .expect("the pool isn't empty");
+        let dropped = pool.swap_remove(smallest);
+        POOLED.fetch_sub(dropped.capacity(), Ordering::SeqCst);
+    }
+}
+
+fn drain_pool() {
+    let mut pool = POOL.lock().unwrap();
+    pool.clear();
+    POOLED.store(0, Ordering::SeqCst);
+}
+
+fn megabytes(bytes: usize) -> usize {
+    bytes.div_ceil(1 << 20)
+}
@@ -0,0 +1,356 @@
+//! A blocking client for a running screenshot server (`client` feature), so
+//! cargo-based end-to-end suites don't each hand-roll the HTTP plumbing.
+//!
+//! Methods follow `GET /openapi.json`; capture options are the same
+//! [`CaptureOptions`] the server parses from the query string. Requests the
+//! server didn't get to (refused connections, `429`, `503` while the window
+//! is still starting) are retried, see [`ScreenshotClient::with_retries`].
+//!
+//! ```rust,no_run
+//! # fn run() -> Result<(), tauri_plugin_screenshot_hd::client::ClientError> {
+//! use std::time::Duration;
+//! use tauri_plugin_screenshot_hd::client::ScreenshotClient;
+//!
+//! let client = ScreenshotClient::connect("127.0.0.1:21988")?;
+//! client.eval("document.body.classList.add('dark')")?;
+//! client.wait_for("#settings", Duration::from_secs(5))?;
+//! let diff = client.diff(&std::fs::read("baseline.png").unwrap())?;
+//! assert!(diff.changed < 0.001, "{:.2}% changed", diff.changed * 100.0);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{analyze, format, CaptureOptions, Wait};
+use serde_json::Value;
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_RETRIES: u32 = 3;
+/// Delay before the first retry; doubles for each one after.
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+/// How often [`ScreenshotClient::wait_for`] polls.
+const WAIT_POLL: Duration = Duration::from_millis(100);
+
+/// A connection to a screenshot server.
+#[derive(Debug, Clone)]
+pub struct ScreenshotClient {
+    addr: SocketAddr,
+    token: Option<String>,
+    window: Option<String>,
+    timeout: Duration,
+    retries: u32,
+}
+
+/// Why a client call failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ClientError {
+    /// The server couldn't be reached.
+    Connect(String),
+    /// No response, or [`ScreenshotClient::wait_for`] gave up.
+    Timeout(String),
+    /// The server answered with a non-2xx status; `message` is its body.
+    Http { status: u16, message: String },
+    /// The response couldn't be parsed.
+    InvalidResponse(String),
+    /// The request couldn't be built, e.g. a custom pipeline stage or a
+    /// baseline image that doesn't decode.
+    InvalidRequest(String),
+}
+
+/// How a capture compares with a baseline, from [`ScreenshotClient::diff`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub struct Diff {
+    /// Share of pixels that differ (beyond antialiasing jitter), in
+    /// `0.0..=1.0`. `1.0` when the sizes differ.
+    pub changed: f64,
+    /// Width of the capture in pixels.
+    pub width: u32,
+    /// Height of the capture in pixels.
+    pub height: u32,
+    /// Whether the capture and the baseline have the same size.
+    pub same_size: bool,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connect(e) => write!(f, "can't connect: {e}"),
+            Self::Timeout(e) => write!(f, "timed out: {e}"),
+            Self::Http { status, message } => write!(f, "server returned {status}: {message}"),
+            Self::InvalidResponse(e) => write!(f, "invalid response: {e}"),
+            Self::InvalidRequest(e) => write!(f, "invalid request: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl ClientError {
+    /// Whether the server never processed the request, so sending it again
+    /// is safe.
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Connect(_) => true,
+            Self::Http { status, .. } => matches!(status, 429 | 503),
+            _ => false,
+        }
+    }
+}
+
+impl ScreenshotClient {
+    /// Resolve `addr` and check that something accepts connections there.
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self, ClientError> {
+        let addr = addr
+            .to_socket_addrs()
+            .map_err(|e| ClientError::Connect(format!("can't resolve address: {e}")))?
+            .next()
+            .ok_or_else(|| ClientError::Connect("address resolved to nothing".into()))?;
+        TcpStream::connect_timeout(&addr, DEFAULT_TIMEOUT)
+            .map_err(|e| ClientError::Connect(format!("{addr}: {e}")))?;
+        Ok(Self {
+            addr,
+            token: None,
+            window: None,
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+        })
+    }
+
+    /// Send `token` as the bearer token (see `Config::auth_token`).
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Target the window labelled `label` instead of the server's default.
+    pub fn with_window(mut self, label: impl Into<String>) -> Self {
+        self.window = Some(label.into());
+        self
+    }
+
+    /// How long to wait for each response. Default: 30 s
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How often to resend a request the server didn't process (connection
+    /// refused, `429` or `503`), backing off from 100 ms. Default: 3
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// The server's address.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Capture with the server's configured options.
+    pub fn screenshot(&self) -> Result<Vec<u8>, ClientError> {
+        self.request("GET", "/screenshot", &[], &[])
+    }
+
+    /// Capture with `options` (custom pipeline stages can't be sent).
+    pub fn screenshot_with(&self, options: &CaptureOptions) -> Result<Vec<u8>, ClientError> {
+        self.request("GET", "/screenshot", &query(options)?, &[])
+    }
+
+    /// Run `script` in the webview, without waiting for it to finish.
+    pub fn eval(&self, script: &str) -> Result<(), ClientError> {
+        self.request("POST", "/eval", &[], script.as_bytes())
+            .map(drop)
+    }
+
+    /// Run `script`, wait for `options.wait`, then capture with `options`.
+    pub fn eval_and_capture(
+        &self,
+        script: &str,
+        options: &CaptureOptions,
+    ) -> Result<Vec<u8>, ClientError> {
+        let mut query = query(options)?;
+        if options.wait == Wait::None {
+            // ?wait= is what asks /eval for a capture
+            query.push(("wait", "0".into()));
+        }
+        self.request("POST", "/eval", &query, script.as_bytes())
+    }
+
+    /// Capture with the server's configured options and compare the pixels
+    /// with `baseline`, an image in any format the server can produce.
+    pub fn diff(&self, baseline: &[u8]) -> Result<Diff, ClientError> {
+        let baseline = format::decode(baseline)
+            .map_err(|e| ClientError::InvalidRequest(format!("baseline: {e}")))?;
+        // QOI is lossless and the cheapest to decode
+        let bytes = self.request("GET", "/screenshot", &[("format", "qoi".into())], &[])?;
+        let capture = format::decode(&bytes).map_err(ClientError::InvalidResponse)?;
+        Ok(Diff {
+            changed: analyze::changed_share(&baseline, &capture),
+            width: capture.width,
+            height: capture.height,
+            same_size: (baseline.width, baseline.height) == (capture.width, capture.height),
+        })
+    }
+
+    /// Poll until an element matches `selector`, then return it like
+    /// [`ScreenshotClient::element`].
+    pub fn wait_for(&self, selector: &str, timeout: Duration) -> Result<Value, ClientError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.element(selector) {
+                Err(ClientError::Http { status: 404, .. }) if Instant::now() < deadline => {
+                    std::thread::sleep(WAIT_POLL);
+                }
+                Err(ClientError::Http { status: 404, .. }) => {
+                    return Err(ClientError::Timeout(format!(
+                        "no element matches '{selector}' after {timeout:?}"
+                    )));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Geometry and visibility of the first element matching `selector`.
+    pub fn element(&self, selector: &str) -> Result<Value, ClientError> {
+        self.json("/element", &[("selector", selector.into())])
+    }
+
+    /// `innerText` of every element matching `selector`.
+    pub fn text(&self, selector: &str) -> Result<Vec<String>, ClientError> {
+        let texts = self.json("/text", &[("selector", selector.into())])?;
+        serde_json::from_value(texts)
+            .map_err(|e| ClientError::InvalidResponse(format!("/text: {e}")))
+    }
+
+    /// Labels of the app's webview windows.
+    pub fn windows(&self) -> Result<Vec<String>, ClientError> {
+        let windows = self.json("/windows", &[])?;
+        Ok(windows
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|w| w["label"].as_str().map(str::to_string))
+            .collect())
+    }
+
+    /// The server's OpenAPI description.
+    pub fn openapi(&self) -> Result<Value, ClientError> {
+        self.json("/openapi.json", &[])
+    }
+
+    fn json(&self, path: &str, query: &[(&str, String)]) -> Result<Value, ClientError> {
+        let body = self.request("GET", path, query, &[])?;
+        serde_json::from_slice(&body)
+            .map_err(|e| ClientError::InvalidResponse(format!("{path}: {e}")))
+    }
+
+    /// Send one request, retrying what the server didn't process, and
+    /// return the body of a 2xx response.
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        query: &[(&str, String)],
+        body: &[u8],
+    ) -> Result<Vec<u8>, ClientError> {
+        let mut backoff = RETRY_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            match self.send(method, path, query, body) {
+                Err(e) if e.is_retryable() && attempt < self.retries => {
+                    attempt += 1;
+                    std::thread::sleep(backoff);
+                    backoff *= 2;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// HTTP/1.0 keeps the server from chunking, so the body is everything up
+    /// to the server closing the connection.
+    fn send(
+        &self,
+        method: &str,
+        path: &str,
+        query: &[(&str, String)],
+        body: &[u8],
+    ) -> Result<Vec<u8>, ClientError> {
+        let mut target = path.to_string();
+        let window = self.window.iter().map(|w| ("window", w.clone()));
+        for (i, (name, value)) in query.iter().cloned().chain(window).enumerate() {
+            target.push(if i == 0 { '?' } else { '&' });
+            target.push_str(name);
+            target.push('=');
+            target.push_str(&percent_encode(&value));
+        }
+
+        let mut head = format!(
+            "{method} {target} HTTP/1.0\r\nHost: {}\r\nContent-Length: {}\r\n",
+            self.addr,
+            body.len()
+        );
+        if let Some(token) = &self.token {
+            head.push_str(&format!("Authorization: Bearer {token}\r\n"));
+        }
+        head.push_str("\r\n");
+
+        let mut stream = TcpStream::connect_timeout(&self.addr, self.timeout)
+            .map_err(|e| ClientError::Connect(format!("{}: {e}", self.addr)))?;
+        let _ = stream.set_read_timeout(Some(self.timeout));
+        let _ = stream.set_write_timeout(Some(self.timeout));
+        let io_error = |e: std::io::Error| match e.kind() {
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+                ClientError::Timeout(format!("{method} {path} after {:?}", self.timeout))
+            }
+            _ => ClientError::InvalidResponse(format!("{method} {path}: {e}")),
+        };
+        stream
+            .write_all(head.as_bytes())
+            .and_then(|_| stream.write_all(body))
+            .map_err(io_error)?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).map_err(io_error)?;
+
+        let malformed = || ClientError::InvalidResponse(format!("{method} {path}: malformed"));
+        let split = response
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .ok_or_else(malformed)?;
+        let status = std::str::from_utf8(&response[..split])
+            .ok()
+            .and_then(|head| head.split(' ').nth(1))
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(malformed)?;
+        let body = response.split_off(split + 4);
+        if !(200..300).contains(&status) {
+            return Err(ClientError::Http {
+                status,
+                message: String::from_utf8_lossy(&body).into_owned(),
+            });
+        }
+        Ok(body)
+    }
+}
+
+fn query(options: &CaptureOptions) -> Result<Vec<(&'static str, String)>, ClientError> {
+    options.to_query().map_err(ClientError::InvalidRequest)
+}
+
+/// Percent-encode everything but unreserved characters.
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{b:02X}"),
+        })
+        .collect()
+}
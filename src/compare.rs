@@ -0,0 +1,116 @@
+//! `POST /compare-url`: load a reference URL (e.g. the web build of the same
+//! frontend) into a temporary hidden window, capture it, and compare it with
+//! a capture of the app's window.
+
+use crate::format::{self, Frame};
+use crate::scratch::ScratchWindow;
+use crate::{analyze, CaptureOptions, Context, ImageFormat, Wait};
+use base64::Engine;
+use serde_json::{json, Value};
+use std::time::Instant;
+use tauri::{Manager, Runtime, WebviewWindow};
+
+/// What to compare.
+pub(crate) struct Comparison {
+    pub(crate) url: tauri::Url,
+    /// Share of changed pixels still reported as a match.
+    pub(crate) threshold: f64,
+    /// Include both captures as base64, in the capture format.
+    pub(crate) frames: bool,
+    pub(crate) settings: CaptureOptions,
+}
+
+impl Comparison {
+    /// Parse the URL from the request body, and `?threshold=` (default
+    /// `0`), `?frames=` and the capture options from the query.
+    pub(crate) fn parse(url: &str, body: &str, defaults: CaptureOptions) -> Result<Self, String> {
+        let reference = tauri::Url::parse(body.trim())
+            .map_err(|e| format!("invalid URL '{}': {e}", body.trim()))?;
+        if !matches!(reference.scheme(), "http" | "https") {
+            return Err("the URL must be http or https".into());
+        }
+        let threshold = match crate::query_param(url, "threshold").map(|v| v.parse::<f64>()) {
+            None => 0.0,
+            Some(Ok(t)) if (0.0..=1.0).contains(&t) => t,
+            Some(_) => return Err("threshold must be in 0..=1".into()),
+        };
+        let frames = match crate::query_param(url, "frames").map(|v| v.parse::<bool>()) {
+            None => false,
+            Some(Ok(frames)) => frames,
+            Some(Err(_)) => return Err("frames must be true or false".into()),
+        };
+        Ok(Self {
+            url: reference,
+            threshold,
+            frames,
+            settings: defaults.with_query(url)?,
+        })
+    }
+}
+
+/// Load `comparison.url` in a hidden window the size of `window`, wait for
+/// it to load (and `?wait=`), capture both and compare their pixels.
+pub(crate) fn compare<R: Runtime>(
+    window: &WebviewWindow<R>,
+    context: &Context,
+    comparison: &Comparison,
+    deadline: Instant,
+) -> Result<Value, (u16, String)> {
+    let scale = window.scale_factor().map_err(|e| (500, e.to_string()))?;
+    let size = window
+        .inner_size()
+        .map_err(|e| (500, e.to_string()))?
+        .to_logical::<f64>(scale);
+
+    let reference = ScratchWindow::open(
+        window.app_handle(),
+        comparison.url.clone(),
+        size.width,
+        size.height,
+        deadline,
+    )
+    .map_err(|(status, e)| (status, format!("{}: {e}", comparison.url)))?;
+
+    // QOI is the cheapest to encode and decode again. ?wait= lets the
+    // reference page settle; the app is captured as it is.
+    let settings = CaptureOptions {
+        format: ImageFormat::Qoi,
+        ..comparison.settings.clone()
+    };
+    let capture = |window: &WebviewWindow<R>, settings: &CaptureOptions| {
+        context
+            .snapshot(window, settings)
+            .and_then(|bytes| format::decode(&bytes))
+            .map_err(|e| (504, e))
+    };
+    let theirs = capture(reference.window(), &settings)?;
+    drop(reference);
+    let ours = capture(
+        window,
+        &CaptureOptions {
+            wait: Wait::None,
+            ..settings
+        },
+    )?;
+
+    let changed = analyze::changed_share(&ours, &theirs);
+    let mut result = json!({
+        "url": comparison.url.as_str(),
+        "changed": (changed * 10_000.0).round() / 10_000.0,
+        "match": changed <= comparison.threshold,
+        "app": { "width": ours.width, "height": ours.height },
+        "reference": { "width": theirs.width, "height": theirs.height },
+    });
+    if comparison.frames {
+        result["app"]["image"] = encoded(&ours, comparison.settings.format)?;
+        result["reference"]["image"] = encoded(&theirs, comparison.settings.format)?;
+    }
+    Ok(result)
+}
+
+fn encoded(frame: &Frame, format: ImageFormat) -> Result<Value, (u16, String)> {
+    let bytes = format::encode(frame, format).map_err(|e| (500, e))?;
+    Ok(base64::engine::general_purpose::STANDARD
+        .encode(bytes)
+        .into())
+}
@@ -0,0 +1,44 @@
+//! `POST /coverage/start` and `/coverage/stop`: Istanbul coverage gathered
+//! while a visual test runs.
+//!
+//! WKWebView has no protocol for instrumenting scripts at runtime, so the
+//! app has to be built with Istanbul counters (`babel-plugin-istanbul`,
+//! `vite-plugin-istanbul`, ...), which fill `window.__coverage__`. Starting
+//! takes a baseline of those counters and stopping returns the difference,
+//! leaving `window.__coverage__` itself untouched for other tooling.
+
+/// Evaluates to the number of instrumented files, or `null` if the page
+/// isn't instrumented.
+pub(crate) const START_JS: &str = r#"(function () {
+  const coverage = window.__coverage__;
+  if (!coverage) return null;
+  const ns = (window.__SCREENSHOT_HD__ = window.__SCREENSHOT_HD__ || {});
+  ns.coverageBaseline = JSON.parse(JSON.stringify(coverage));
+  return Object.keys(coverage).length;
+})()"#;
+
+/// Evaluates to the Istanbul coverage map accumulated since `START_JS` (or
+/// since page load without a start), or `null` if the page isn't
+/// instrumented.
+pub(crate) const STOP_JS: &str = r#"(function () {
+  const coverage = window.__coverage__;
+  if (!coverage) return null;
+  const ns = window.__SCREENSHOT_HD__ || {};
+  const baseline = ns.coverageBaseline || {};
+  delete ns.coverageBaseline;
+  const result = JSON.parse(JSON.stringify(coverage));
+  for (const [file, data] of Object.entries(result)) {
+    const before = baseline[file];
+    if (!before) continue;
+    for (const id of Object.keys(data.s)) data.s[id] -= before.s[id] || 0;
+    for (const id of Object.keys(data.f)) data.f[id] -= before.f[id] || 0;
+    for (const id of Object.keys(data.b)) {
+      data.b[id] = data.b[id].map((n, i) => n - ((before.b[id] || [])[i] || 0));
+    }
+  }
+  return result;
+})()"#;
+
+/// Returned (409) when the page has no Istanbul counters.
+pub(crate) const NOT_INSTRUMENTED: &str = "window.__coverage__ is missing; build the app with \
+     Istanbul instrumentation (e.g. babel-plugin-istanbul or vite-plugin-istanbul)";
@@ -0,0 +1,119 @@
+//! Crash evidence: a panic hook that saves a final screenshot, the DOM and
+//! recent console output before the app dies.
+
+use crate::logging;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tauri::{Manager, Runtime};
+
+/// How long each piece of evidence may take. A panic on the main thread
+/// blocks the webview callbacks, so these fail fast rather than holding up
+/// the dying process.
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Keeps the last 200 console messages (plus uncaught errors) in
+/// `window.__SCREENSHOT_HD__.console`. Injected on every page load.
+pub(crate) const CONSOLE_BUFFER_JS: &str = r#"(function () {
+  const ns = (window.__SCREENSHOT_HD__ = window.__SCREENSHOT_HD__ || {});
+  if (ns.console) return;
+  const buffer = (ns.console = []);
+  const push = (level, args) => {
+    const message = args
+      .map((a) => {
+        if (typeof a === 'string') return a;
+        if (a instanceof Error) return a.stack || String(a);
+        try { return JSON.stringify(a); } catch (_) { return String(a); }
+      })
+      .join(' ');
+    buffer.push({ level, time: Date.now(), message });
+    if (buffer.length > 200) buffer.shift();
+  };
+  for (const level of ['log', 'info', 'warn', 'error', 'debug']) {
+    const original = console[level];
+    console[level] = function (...args) {
+      push(level, args);
+      return original.apply(this, args);
+    };
+  }
+  window.addEventListener('error', (e) => push('error', [e.error || e.message]));
+  window.addEventListener('unhandledrejection', (e) => push('error', [e.reason]));
+})();"#;
+
+/// Number of `error` entries in the console buffer, or `null` if it isn't
+/// installed.
+pub(crate) const CONSOLE_ERROR_COUNT_JS: &str =
+    "(window.__SCREENSHOT_HD__ && window.__SCREENSHOT_HD__.console) \
+     ? window.__SCREENSHOT_HD__.console.filter((e) => e.level === 'error').length \
+     : null";
+
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+
+/// Install a panic hook that writes crash evidence to `dir`, then defers to
+/// the previously installed hook.
+pub(crate) fn install<R: Runtime>(app: tauri::AppHandle<R>, window_label: String, dir: PathBuf) {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        // Only the first panic is interesting — and capturing must not recurse.
+        if !CAPTURING.swap(true, Ordering::SeqCst) {
+            match save_evidence(&app, &window_label, &dir, &info.to_string()) {
+                Ok(path) => {
+                    logging::error!("[screenshot-hd] crash evidence saved to {}", path.display())
+                }
+                Err(e) => logging::error!("[screenshot-hd] failed to save crash evidence: {e}"),
+            }
+        }
+        previous(info);
+    }));
+}
+
+fn save_evidence<R: Runtime>(
+    app: &tauri::AppHandle<R>,
+    window_label: &str,
+    dir: &Path,
+    panic: &str,
+) -> std::io::Result<PathBuf> {
+    let out = dir.join(format!("crash-{}", crate::unix_millis()));
+    std::fs::create_dir_all(&out)?;
+
+    let backtrace = std::backtrace::Backtrace::force_capture();
+    std::fs::write(out.join("panic.txt"), format!("{panic}\n\n{backtrace}"))?;
+
+    let Some(window) = app.get_webview_window(window_label) else {
+        return Ok(out);
+    };
+
+    let w = window.clone();
+    match with_timeout(move || crate::take_screenshot(&w, &Default::default())) {
+        Ok(png) => std::fs::write(out.join("screenshot.png"), png)?,
+        Err(e) => std::fs::write(out.join("screenshot.err"), e)?,
+    }
+
+    let w = window.clone();
+    match with_timeout(move || crate::eval_json(&w, "document.documentElement.outerHTML")) {
+        Ok(serde_json::Value::String(html)) => std::fs::write(out.join("dom.html"), html)?,
+        Ok(other) => std::fs::write(out.join("dom.err"), format!("unexpected result: {other}"))?,
+        Err(e) => std::fs::write(out.join("dom.err"), e)?,
+    }
+
+    let w = window;
+    let console = "(window.__SCREENSHOT_HD__ && window.__SCREENSHOT_HD__.console) || []";
+    match with_timeout(move || crate::eval_json(&w, console)) {
+        Ok(entries) => std::fs::write(out.join("console.json"), entries.to_string())?,
+        Err(e) => std::fs::write(out.join("console.err"), e)?,
+    }
+
+    Ok(out)
+}
+
+/// Run `f` on a helper thread, giving up after [`CAPTURE_TIMEOUT`].
+fn with_timeout<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, String> + Send + 'static,
+) -> Result<T, String> {
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(f());
+    });
+    rx.recv_timeout(CAPTURE_TIMEOUT)
+        .map_err(|_| "timed out (the panicking thread may be the main thread)".to_string())?
+}
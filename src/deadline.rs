@@ -0,0 +1,79 @@
+//! Client deadlines (`X-Deadline-Ms` / `?deadline=`, see
+//! [`CaptureOptions::deadline`](crate::CaptureOptions::deadline)): every
+//! wait a capture makes — the queue, `?wait=`, a resize, `wait_until`, the
+//! snapshot itself — is cut to what's left of one budget, and running out
+//! names the stage it happened in.
+
+use crate::query_param;
+use std::time::{Duration, Instant};
+
+pub(crate) const HEADER: &str = "X-Deadline-Ms";
+
+/// The deadline `request` asks for, if any: [`HEADER`] or `?deadline=`, in
+/// milliseconds from now.
+pub(crate) fn requested(
+    request: &tiny_http::Request,
+    url: &str,
+) -> Result<Option<Instant>, String> {
+    let value = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(HEADER))
+        .map(|h| h.value.as_str().trim().to_string())
+        .or_else(|| query_param(url, "deadline"));
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let ms: u64 = value
+        .parse()
+        .map_err(|_| format!("the deadline must be a number of milliseconds, got '{value}'"))?;
+    Ok(Instant::now().checked_add(Duration::from_millis(ms)))
+}
+
+/// How long `stage` may wait: `limit`, cut to what's left before
+/// `deadline`. Fails if that's nothing.
+pub(crate) fn remaining(
+    deadline: Option<Instant>,
+    limit: Duration,
+    stage: &str,
+) -> Result<Duration, String> {
+    let Some(deadline) = deadline else {
+        return Ok(limit);
+    };
+    let left = deadline.saturating_duration_since(Instant::now());
+    if left.is_zero() {
+        return Err(exceeded(stage));
+    }
+    Ok(limit.min(left))
+}
+
+/// Fail if `deadline` has passed, blaming `stage`.
+pub(crate) fn check(deadline: Option<Instant>, stage: &str) -> Result<(), String> {
+    remaining(deadline, Duration::MAX, stage).map(|_| ())
+}
+
+/// Sleep for `duration` in `stage`, or fail right away if the deadline
+/// doesn't leave that long.
+pub(crate) fn sleep(
+    deadline: Option<Instant>,
+    duration: Duration,
+    stage: &str,
+) -> Result<(), String> {
+    if remaining(deadline, duration, stage)? < duration {
+        return Err(exceeded(stage));
+    }
+    std::thread::sleep(duration);
+    Ok(())
+}
+
+/// `error` from `stage`, or the deadline's error if that's what ran out.
+pub(crate) fn attribute(deadline: Option<Instant>, stage: &str, error: String) -> String {
+    match check(deadline, stage) {
+        Ok(()) => error,
+        Err(exceeded) => exceeded,
+    }
+}
+
+fn exceeded(stage: &str) -> String {
+    format!("deadline exceeded in stage '{stage}'")
+}
@@ -0,0 +1,79 @@
+//! In-page DOM inspection scripts.
+//!
+//! Each builder returns a JavaScript expression that evaluates to a
+//! JSON-serializable value. Selectors are embedded as JSON string literals so
+//! arbitrary user input can't break out of the script.
+
+/// Bounding rect, visibility, opacity and viewport intersection of the first
+/// element matching `selector`, in both CSS and device pixels.
+///
+/// Evaluates to `null` when nothing matches and `{ error }` when the selector
+/// is invalid.
+pub(crate) fn element_script(selector: &str) -> String {
+    format!(
+        r#"(function (sel) {{
+  let el;
+  try {{ el = document.querySelector(sel); }} catch (e) {{ return {{ error: String(e.message || e) }}; }}
+  if (!el) return null;
+  const r = el.getBoundingClientRect();
+  const style = getComputedStyle(el);
+  const dpr = window.devicePixelRatio || 1;
+  const rect = (k) => ({{ x: r.x * k, y: r.y * k, width: r.width * k, height: r.height * k }});
+  return {{
+    css: rect(1),
+    device: rect(dpr),
+    devicePixelRatio: dpr,
+    visible: style.display !== 'none' && style.visibility !== 'hidden' && r.width > 0 && r.height > 0,
+    opacity: parseFloat(style.opacity),
+    inViewport: r.right > 0 && r.bottom > 0 && r.left < window.innerWidth && r.top < window.innerHeight,
+  }};
+}})({sel})"#,
+        sel = js_literal(selector)
+    )
+}
+
+/// Computed style of the first element matching `selector`, as a
+/// `{ property: value }` map. An empty `props` list returns every property.
+///
+/// Evaluates to `null` when nothing matches and `{ error }` when the selector
+/// is invalid.
+pub(crate) fn style_script(selector: &str, props: &[&str]) -> String {
+    format!(
+        r#"(function (sel, props) {{
+  let el;
+  try {{ el = document.querySelector(sel); }} catch (e) {{ return {{ error: String(e.message || e) }}; }}
+  if (!el) return null;
+  const style = getComputedStyle(el);
+  const names = props.length ? props : Array.from(style);
+  const out = {{}};
+  for (const name of names) out[name] = style.getPropertyValue(name);
+  return out;
+}})({sel}, {props})"#,
+        sel = js_literal(selector),
+        props = js_literal(props)
+    )
+}
+
+/// `innerText` of every element matching `selector`, in document order.
+///
+/// Evaluates to `{ error }` when the selector is invalid.
+pub(crate) fn text_script(selector: &str) -> String {
+    format!(
+        r#"(function (sel) {{
+  try {{ return Array.from(document.querySelectorAll(sel), (el) => el.innerText); }}
+  catch (e) {{ return {{ error: String(e.message || e) }}; }}
+}})({sel})"#,
+        sel = js_literal(selector)
+    )
+}
+
+/// Encode a value as a JavaScript literal.
+pub(crate) fn js_literal(value: impl Into<serde_json::Value>) -> String {
+    // JSON is valid JS, except that U+2028/U+2029 were line terminators in
+    // JS before ES2019 — escape them to be safe.
+    value
+        .into()
+        .to_string()
+        .replace('\u{2028}', "\\u2028")
+        .replace('\u{2029}', "\\u2029")
+}
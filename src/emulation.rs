@@ -0,0 +1,647 @@
+//! Page emulation through an injected shim (`Config::emulation`).
+//!
+//! The shim keeps its state in `sessionStorage`, so whatever was set over
+//! HTTP is re-applied by the init script on every reload and same-origin
+//! navigation. Each feature owns one section of that state:
+//!
+//! - `mocks` — canned responses for matching `fetch`/`XMLHttpRequest` calls
+//! - `network` — added latency or offline mode (`POST /emulate/network`)
+//! - `locale` — `navigator.language`, `Intl` defaults and time zone
+//! - `media` — `prefers-reduced-motion`, `prefers-contrast`, `forced-colors`
+//! - `geolocation` — a fixed position (or error) for `navigator.geolocation`
+//! - `permissions` — `navigator.permissions.query()` results
+//!
+//! The shim also logs every `fetch`/XHR call as a HAR entry (`GET /har`).
+
+use crate::dom::js_literal;
+use serde_json::Value;
+use tauri::{Runtime, WebviewWindow};
+
+/// Installed on every page load when emulation is enabled.
+pub(crate) const SHIM_JS: &str = r#"(function () {
+  const ns = (window.__SCREENSHOT_HD__ = window.__SCREENSHOT_HD__ || {});
+  if (ns.emulation) return;
+  const KEY = '__screenshot_hd_emulation__';
+  let state = {};
+  try { state = JSON.parse(sessionStorage.getItem(KEY)) || {}; } catch (_) {}
+  const listeners = {};
+  ns.emulation = {
+    get: () => state,
+    set(section, value) {
+      if (value === null) delete state[section];
+      else state[section] = value;
+      try { sessionStorage.setItem(KEY, JSON.stringify(state)); } catch (_) {}
+      for (const listener of listeners[section] || []) listener();
+      return state[section] === undefined ? null : state[section];
+    },
+  };
+  const onChange = (section, listener) => (listeners[section] = listeners[section] || []).push(listener);
+  const sleep = (ms) => new Promise((resolve) => setTimeout(resolve, ms || 0));
+
+  // ── network ──
+  const network = () => state.network || {};
+  Object.defineProperty(navigator, 'onLine', { get: () => !network().offline, configurable: true });
+  let wasOffline = !!network().offline;
+  onChange('network', () => {
+    const offline = !!network().offline;
+    if (offline !== wasOffline) window.dispatchEvent(new Event(offline ? 'offline' : 'online'));
+    wasOffline = offline;
+  });
+
+  // ── locale ──
+  const locale = () => state.locale || {};
+  const proto = Navigator.prototype;
+  const language = Object.getOwnPropertyDescriptor(proto, 'language').get;
+  const languages = Object.getOwnPropertyDescriptor(proto, 'languages').get;
+  Object.defineProperty(navigator, 'language', {
+    get: () => locale().language || language.call(navigator),
+    configurable: true,
+  });
+  Object.defineProperty(navigator, 'languages', {
+    get: () => (locale().language ? Object.freeze([locale().language]) : languages.call(navigator)),
+    configurable: true,
+  });
+  const DateTimeFormat = Intl.DateTimeFormat;
+  const validTimeZone = (timeZone) => {
+    try { return !!new DateTimeFormat('en', { timeZone }); } catch (_) { return false; }
+  };
+  const withLocale = (locales) => (locales === undefined && locale().language ? locale().language : locales);
+  const withTimeZone = (options) => {
+    const timeZone = locale().timezone;
+    if (!timeZone || (options && options.timeZone) || !validTimeZone(timeZone)) return options;
+    return Object.assign({}, options, { timeZone });
+  };
+  for (const name of ['DateTimeFormat', 'NumberFormat', 'Collator', 'PluralRules', 'RelativeTimeFormat', 'ListFormat', 'DisplayNames', 'Segmenter']) {
+    const Original = Intl[name];
+    if (!Original) continue;
+    const zoned = name === 'DateTimeFormat';
+    const Wrapped = function (locales, options) {
+      return new Original(withLocale(locales), zoned ? withTimeZone(options) : options);
+    };
+    Wrapped.prototype = Original.prototype;
+    Wrapped.supportedLocalesOf = Original.supportedLocalesOf.bind(Original);
+    Intl[name] = Wrapped;
+  }
+  for (const name of ['toLocaleString', 'toLocaleDateString', 'toLocaleTimeString']) {
+    const original = Date.prototype[name];
+    Date.prototype[name] = function (locales, options) {
+      return original.call(this, withLocale(locales), withTimeZone(options));
+    };
+  }
+  const numberToLocaleString = Number.prototype.toLocaleString;
+  Number.prototype.toLocaleString = function (locales, options) {
+    return numberToLocaleString.call(this, withLocale(locales), options);
+  };
+  const localeCompare = String.prototype.localeCompare;
+  String.prototype.localeCompare = function (that, locales, options) {
+    return localeCompare.call(this, that, withLocale(locales), options);
+  };
+
+  // ── media ──
+  // Emulated feature tests are rewritten into queries that are always true
+  // or false, both in matchMedia() and in same-origin stylesheets.
+  const MEDIA_FEATURE = /\(\s*(prefers-reduced-motion|prefers-contrast|forced-colors)\s*(?::\s*([a-z-]+)\s*)?\)/gi;
+  const NO_PREFERENCE = { 'prefers-reduced-motion': 'no-preference', 'prefers-contrast': 'no-preference', 'forced-colors': 'none' };
+  const media = () => state.media || {};
+  const emulatesMedia = (query) => Object.keys(media()).length > 0 && new RegExp(MEDIA_FEATURE.source, 'i').test(query);
+  const rewriteMedia = (query) =>
+    query.replace(MEDIA_FEATURE, (whole, feature, value) => {
+      feature = feature.toLowerCase();
+      const emulated = media()[feature];
+      if (emulated === undefined) return whole;
+      const matches = value === undefined ? emulated !== NO_PREFERENCE[feature] : value.toLowerCase() === emulated;
+      return matches ? '(min-width: 0px)' : '(max-width: 0px)';
+    });
+
+  const matchMedia = window.matchMedia;
+  let tracked = [];
+  window.matchMedia = function (query) {
+    const text = String(query);
+    const list = matchMedia.call(window, text);
+    if (!new RegExp(MEDIA_FEATURE.source, 'i').test(text)) return list;
+    Object.defineProperty(list, 'matches', {
+      get: () => matchMedia.call(window, rewriteMedia(text)).matches,
+      configurable: true,
+    });
+    tracked = tracked.filter((t) => t.ref.deref());
+    tracked.push({ ref: new WeakRef(list), last: list.matches });
+    return list;
+  };
+
+  const originalMedia = new WeakMap();
+  const rewriteStyleSheets = (force) => {
+    if (!force && !Object.keys(media()).length) return;
+    const visit = (rules) => {
+      for (const rule of rules) {
+        if (rule instanceof CSSMediaRule) {
+          if (!originalMedia.has(rule)) originalMedia.set(rule, rule.media.mediaText);
+          const original = originalMedia.get(rule);
+          const next = emulatesMedia(original) ? rewriteMedia(original) : original;
+          if (rule.media.mediaText !== next) rule.media.mediaText = next;
+        }
+        if (rule.cssRules) visit(rule.cssRules);
+      }
+    };
+    for (const sheet of document.styleSheets) {
+      try { visit(sheet.cssRules); } catch (_) { /* cross-origin */ }
+    }
+  };
+  document.addEventListener('DOMContentLoaded', () => {
+    rewriteStyleSheets();
+    new MutationObserver(() => rewriteStyleSheets()).observe(document.documentElement, { childList: true, subtree: true });
+  });
+  // Stylesheet <link> loads don't bubble, but can be caught on the way down
+  document.addEventListener('load', () => rewriteStyleSheets(), true);
+  onChange('media', () => {
+    rewriteStyleSheets(true);
+    for (const t of tracked) {
+      const list = t.ref.deref();
+      if (!list || list.matches === t.last) continue;
+      t.last = list.matches;
+      list.dispatchEvent(new MediaQueryListEvent('change', { matches: t.last, media: list.media }));
+    }
+  });
+
+  // ── permissions ──
+  const permissions = () => state.permissions || {};
+  if (navigator.permissions) {
+    const query = navigator.permissions.query.bind(navigator.permissions);
+    const statuses = [];
+    navigator.permissions.query = function (descriptor) {
+      const name = descriptor && descriptor.name;
+      if (!permissions()[name]) return query(descriptor);
+      const status = new EventTarget();
+      status.name = name;
+      status.state = permissions()[name];
+      status.onchange = null;
+      status.addEventListener('change', (event) => status.onchange && status.onchange(event));
+      statuses.push(status);
+      return Promise.resolve(status);
+    };
+    onChange('permissions', () => {
+      for (const status of statuses) {
+        const next = permissions()[status.name];
+        if (next && next !== status.state) {
+          status.state = next;
+          status.dispatchEvent(new Event('change'));
+        }
+      }
+    });
+  }
+  if (window.Notification) {
+    const permission = Object.getOwnPropertyDescriptor(Notification, 'permission');
+    const NOTIFICATION = { granted: 'granted', denied: 'denied', prompt: 'default' };
+    Object.defineProperty(Notification, 'permission', {
+      get: () => NOTIFICATION[permissions().notifications] || permission.get.call(Notification),
+      configurable: true,
+    });
+  }
+
+  // ── geolocation ──
+  const geolocation = () => state.geolocation;
+  if (navigator.geolocation) {
+    const real = {
+      getCurrentPosition: navigator.geolocation.getCurrentPosition.bind(navigator.geolocation),
+      watchPosition: navigator.geolocation.watchPosition.bind(navigator.geolocation),
+      clearWatch: navigator.geolocation.clearWatch.bind(navigator.geolocation),
+    };
+    const ERRORS = { denied: [1, 'User denied Geolocation'], unavailable: [2, 'Position unavailable'], timeout: [3, 'Timeout expired'] };
+    const report = (success, error) => {
+      const geo = geolocation();
+      const denied = permissions().geolocation === 'denied' ? 'denied' : geo.error;
+      if (denied) {
+        const [code, message] = ERRORS[denied];
+        if (error) error({ code, message, PERMISSION_DENIED: 1, POSITION_UNAVAILABLE: 2, TIMEOUT: 3 });
+        return;
+      }
+      success({
+        coords: {
+          latitude: geo.latitude, longitude: geo.longitude, accuracy: geo.accuracy,
+          altitude: null, altitudeAccuracy: null, heading: null, speed: null,
+        },
+        timestamp: Date.now(),
+      });
+    };
+    const watches = new Map();
+    let nextWatch = 1;
+    navigator.geolocation.getCurrentPosition = function (success, error, options) {
+      if (!geolocation()) return real.getCurrentPosition(success, error, options);
+      setTimeout(() => report(success, error), 0);
+    };
+    navigator.geolocation.watchPosition = function (success, error, options) {
+      if (!geolocation()) return real.watchPosition(success, error, options);
+      const id = -nextWatch++;
+      watches.set(id, [success, error]);
+      setTimeout(() => watches.has(id) && report(success, error), 0);
+      return id;
+    };
+    navigator.geolocation.clearWatch = function (id) {
+      if (!watches.delete(id)) real.clearWatch(id);
+    };
+    const update = () => {
+      if (!geolocation()) return;
+      for (const [success, error] of watches.values()) report(success, error);
+    };
+    onChange('geolocation', update);
+    onChange('permissions', update);
+  }
+
+  // ── mocks ──
+  const escape = (s) => s.replace(/[.+?^${}()|[\]\\]/g, '\\$&');
+  const matches = (pattern, url) =>
+    pattern.includes('*')
+      ? new RegExp('^' + pattern.split('*').map(escape).join('.*') + '$').test(url)
+      : url.includes(pattern);
+  const findMock = (method, url) =>
+    (state.mocks || []).find(
+      (m) => (!m.method || m.method.toUpperCase() === method.toUpperCase()) && matches(m.url, url)
+    );
+  const mockBody = (m) =>
+    m.body === undefined || m.body === null ? '' : typeof m.body === 'string' ? m.body : JSON.stringify(m.body);
+  const mockHeaders = (m) => {
+    const headers = {};
+    if (m.body !== undefined && m.body !== null && typeof m.body !== 'string') headers['content-type'] = 'application/json';
+    for (const [k, v] of Object.entries(m.headers || {})) headers[k.toLowerCase()] = String(v);
+    return headers;
+  };
+  const absolute = (url) => new URL(String(url), location.href).href;
+  // Only web requests are mocked, delayed, cut off and logged; Tauri's own
+  // IPC (`ipc:`) and asset (`tauri:`, `asset:`) requests pass straight through
+  const emulated = (url) => /^(https?|data|blob):/i.test(url);
+
+  // ── request log ──
+  // The last 1000 fetch/XHR calls as HAR 1.2 entries, for GET /har.
+  const requests = [];
+  ns.emulation.requests = () => requests;
+  const pairs = (entries) => Array.from(entries, ([name, value]) => ({ name, value }));
+  const begin = (initiator, method, url, headers) => {
+    let query = [];
+    try { query = pairs(new URL(url).searchParams); } catch (_) {}
+    return {
+      started: performance.now(),
+      entry: {
+        startedDateTime: new Date().toISOString(),
+        time: 0,
+        request: {
+          method: method.toUpperCase(), url, httpVersion: '', cookies: [], headers: pairs(headers),
+          queryString: query, headersSize: -1, bodySize: -1,
+        },
+        response: null,
+        cache: {},
+        timings: { send: 0, wait: 0, receive: 0 },
+        _initiator: initiator,
+        _mocked: false,
+      },
+    };
+  };
+  const finish = ({ started, entry }, status, statusText, headers, error) => {
+    entry.time = entry.timings.wait = Math.round((performance.now() - started) * 1000) / 1000;
+    const type = headers.find(([name]) => name.toLowerCase() === 'content-type');
+    const length = headers.find(([name]) => name.toLowerCase() === 'content-length');
+    const size = length ? Number(length[1]) : -1;
+    entry.response = {
+      status, statusText, httpVersion: '', cookies: [], headers: pairs(headers),
+      content: { size, mimeType: type ? type[1] : '' }, redirectURL: '', headersSize: -1, bodySize: size,
+    };
+    if (error) entry._error = error;
+    requests.push(entry);
+    if (requests.length > 1000) requests.shift();
+  };
+  const headerPairs = (raw) =>
+    raw.trim().split(/[\r\n]+/).filter(Boolean).map((line) => {
+      const i = line.indexOf(':');
+      return [line.slice(0, i).trim(), line.slice(i + 1).trim()];
+    });
+
+  const fetch = window.fetch;
+  // `request` took over the body of `input`, so it's the one to send on
+  const emulatedFetch = async function (request, log) {
+    const mock = findMock(request.method, request.url);
+    await sleep(network().latency_ms);
+    if (network().offline) throw new TypeError('Failed to fetch');
+    if (!mock) return fetch.call(this, request);
+    log.entry._mocked = true;
+    await sleep(mock.delay_ms);
+    return new Response(mockBody(mock), { status: mock.status || 200, headers: mockHeaders(mock) });
+  };
+  window.fetch = function (input, init) {
+    let url;
+    try { url = absolute(input instanceof Request ? input.url : input); } catch (_) { url = ''; }
+    if (!emulated(url)) return fetch.call(this, input, init);
+    const request = new Request(input, init);
+    const log = begin('fetch', request.method, request.url, request.headers);
+    return emulatedFetch.call(this, request, log).then(
+      (response) => {
+        finish(log, response.status, response.statusText, [...response.headers]);
+        return response;
+      },
+      (error) => {
+        finish(log, 0, '', [], String(error));
+        throw error;
+      }
+    );
+  };
+
+  const open = XMLHttpRequest.prototype.open;
+  const send = XMLHttpRequest.prototype.send;
+  const setRequestHeader = XMLHttpRequest.prototype.setRequestHeader;
+  XMLHttpRequest.prototype.open = function (method, url, ...rest) {
+    this.__screenshotHd = { method: String(method), url: absolute(url), headers: [] };
+    return open.call(this, method, url, ...rest);
+  };
+  XMLHttpRequest.prototype.setRequestHeader = function (name, value) {
+    if (this.__screenshotHd) this.__screenshotHd.headers.push([String(name), String(value)]);
+    return setRequestHeader.call(this, name, value);
+  };
+  XMLHttpRequest.prototype.send = function (body) {
+    const info = this.__screenshotHd;
+    if (!info || !emulated(info.url)) return send.call(this, body);
+    const mock = findMock(info.method, info.url);
+    const log = begin('xhr', info.method, info.url, info.headers);
+    log.entry._mocked = !!mock;
+    this.addEventListener('loadend', () => {
+      const error = this.status === 0 ? 'request failed' : undefined;
+      finish(log, this.status, this.statusText, headerPairs(this.getAllResponseHeaders()), error);
+    }, { once: true });
+    const { latency_ms: latency = 0, offline } = network();
+    const xhr = this;
+    const define = (name, value) => Object.defineProperty(xhr, name, { value, configurable: true });
+    if (offline) {
+      setTimeout(() => {
+        define('readyState', 4);
+        define('status', 0);
+        for (const type of ['readystatechange', 'error', 'loadend']) xhr.dispatchEvent(new Event(type));
+      }, latency);
+      return;
+    }
+    if (!mock) {
+      if (latency) setTimeout(() => send.call(xhr, body), latency);
+      else send.call(xhr, body);
+      return;
+    }
+    const text = mockBody(mock);
+    const headers = mockHeaders(mock);
+    setTimeout(() => {
+      define('readyState', 4);
+      define('status', mock.status || 200);
+      define('statusText', '');
+      define('responseURL', info.url);
+      define('responseText', text);
+      let response = text;
+      if (xhr.responseType === 'json') { try { response = JSON.parse(text); } catch (_) { response = null; } }
+      define('response', response);
+      xhr.getResponseHeader = (name) => headers[String(name).toLowerCase()] ?? null;
+      xhr.getAllResponseHeaders = () => Object.entries(headers).map(([k, v]) => `${k}: ${v}\r\n`).join('');
+      for (const type of ['readystatechange', 'load', 'loadend']) xhr.dispatchEvent(new Event(type));
+    }, latency + (mock.delay_ms || 0));
+  };
+})();"#;
+
+/// Read one section of the page's emulation state (`null` if unset).
+pub(crate) fn get<R: Runtime>(
+    window: &WebviewWindow<R>,
+    section: &str,
+) -> Result<Value, (u16, String)> {
+    run(
+        window,
+        &format!("ns.emulation.get()[{}] ?? null", js_literal(section)),
+    )
+}
+
+/// Replace one section of the page's emulation state; `null` clears it.
+/// Returns the new value.
+pub(crate) fn set<R: Runtime>(
+    window: &WebviewWindow<R>,
+    section: &str,
+    value: &Value,
+) -> Result<Value, (u16, String)> {
+    run(
+        window,
+        &format!(
+            "ns.emulation.set({}, {})",
+            js_literal(section),
+            js_literal(value.clone())
+        ),
+    )
+}
+
+/// The shim's request log as HAR entries, and when the first of them
+/// started (Unix ms).
+pub(crate) fn requests<R: Runtime>(
+    window: &WebviewWindow<R>,
+) -> Result<(Value, Option<u64>), (u16, String)> {
+    let mut log = run(
+        window,
+        "{ entries: ns.emulation.requests(), \
+           since_ms: ns.emulation.requests().length \
+             ? Date.parse(ns.emulation.requests()[0].startedDateTime) : null }",
+    )?;
+    Ok((log["entries"].take(), log["since_ms"].as_u64()))
+}
+
+/// Evaluate `expr` with `ns` bound to the shim's namespace. Errors carry the
+/// status to respond with.
+fn run<R: Runtime>(window: &WebviewWindow<R>, expr: &str) -> Result<Value, (u16, String)> {
+    let script = format!(
+        "(function (ns) {{ return ns && ns.emulation ? {{ value: {expr} }} : null; }})(window.__SCREENSHOT_HD__)"
+    );
+    match crate::eval_json(window, &script) {
+        Ok(Value::Null) => Err((
+            409,
+            "emulation shim not installed: enable Config::emulation (and reload the page)"
+                .to_string(),
+        )),
+        Ok(mut result) => Ok(result["value"].take()),
+        Err(e) => Err((500, format!("eval error: {e}"))),
+    }
+}
+
+/// Validate a mock definition from `POST /mocks`.
+pub(crate) fn validate_mock(mock: &Value) -> Result<(), String> {
+    if mock["url"].as_str().is_none_or(str::is_empty) {
+        return Err("mock needs a non-empty \"url\" pattern".into());
+    }
+    if !(mock["method"].is_null() || mock["method"].is_string()) {
+        return Err("\"method\" must be a string".into());
+    }
+    if !(mock["status"].is_null()
+        || mock["status"]
+            .as_u64()
+            .is_some_and(|s| (200..=599).contains(&s)))
+    {
+        return Err("\"status\" must be between 200 and 599".into());
+    }
+    if !(mock["headers"].is_null()
+        || mock["headers"]
+            .as_object()
+            .is_some_and(|h| h.values().all(Value::is_string)))
+    {
+        return Err("\"headers\" must map names to strings".into());
+    }
+    if !(mock["delay_ms"].is_null() || mock["delay_ms"].is_u64()) {
+        return Err("\"delay_ms\" must be a non-negative integer".into());
+    }
+    Ok(())
+}
+
+/// Sections settable through `/emulate/<section>`.
+pub(crate) const SECTIONS: &[&str] = &["network", "locale", "media", "geolocation", "permissions"];
+
+/// Validate a `POST /emulate/<section>` body and turn it into the state
+/// stored for that section.
+pub(crate) fn normalize(section: &str, body: &Value) -> Result<Value, String> {
+    match section {
+        "network" => network(body),
+        "locale" => locale(body),
+        "media" => media(body),
+        "geolocation" => geolocation(body),
+        "permissions" => permissions(body),
+        _ => Err(format!("unknown emulation '{section}'")),
+    }
+}
+
+/// `{ "preset": "offline" | "slow-3g" | "fast-3g" | "online" }`, optionally
+/// with `latency_ms` / `offline` overriding the preset.
+fn network(body: &Value) -> Result<Value, String> {
+    let (mut latency_ms, mut offline) = match body["preset"].as_str() {
+        None | Some("online") => (0, false),
+        Some("offline") => (0, true),
+        // Chrome DevTools' throttling presets (latency only)
+        Some("slow-3g") => (2000, false),
+        Some("fast-3g") => (560, false),
+        Some(other) => {
+            return Err(format!(
+                "unknown preset '{other}' (online, offline, slow-3g, fast-3g)"
+            ))
+        }
+    };
+    if !body["latency_ms"].is_null() {
+        latency_ms = body["latency_ms"]
+            .as_u64()
+            .ok_or("\"latency_ms\" must be a non-negative integer")?;
+    }
+    if !body["offline"].is_null() {
+        offline = body["offline"]
+            .as_bool()
+            .ok_or("\"offline\" must be a boolean")?;
+    }
+    Ok(serde_json::json!({
+        "preset": body["preset"],
+        "latency_ms": latency_ms,
+        "offline": offline,
+    }))
+}
+
+/// `{ "language": "de-DE", "timezone": "Europe/Berlin" }`; either may be
+/// omitted. Unknown time zones are ignored by the shim.
+fn locale(body: &Value) -> Result<Value, String> {
+    let field = |name: &str, extra: &[char]| -> Result<Value, String> {
+        match &body[name] {
+            Value::Null => Ok(Value::Null),
+            Value::String(s)
+                if !s.is_empty()
+                    && s.chars()
+                        .all(|c| c.is_ascii_alphanumeric() || extra.contains(&c)) =>
+            {
+                Ok(Value::String(s.clone()))
+            }
+            other => Err(format!("invalid \"{name}\": {other}")),
+        }
+    };
+    let language = field("language", &['-'])?;
+    let timezone = field("timezone", &['-', '_', '+', '/'])?;
+    if language.is_null() && timezone.is_null() {
+        return Err("set \"language\" and/or \"timezone\"".into());
+    }
+    Ok(serde_json::json!({ "language": language, "timezone": timezone }))
+}
+
+/// `{ "prefers-reduced-motion": "reduce", "prefers-contrast": "more",
+/// "forced-colors": "active" }`; features left out aren't emulated.
+fn media(body: &Value) -> Result<Value, String> {
+    const FEATURES: &[(&str, &[&str])] = &[
+        ("prefers-reduced-motion", &["no-preference", "reduce"]),
+        (
+            "prefers-contrast",
+            &["no-preference", "more", "less", "custom"],
+        ),
+        ("forced-colors", &["none", "active"]),
+    ];
+    let Some(features) = body.as_object().filter(|f| !f.is_empty()) else {
+        return Err(
+            "expected an object of media features, e.g. {\"prefers-reduced-motion\": \"reduce\"}"
+                .into(),
+        );
+    };
+    for (feature, value) in features {
+        let Some((_, allowed)) = FEATURES.iter().find(|(name, _)| name == feature) else {
+            let names: Vec<&str> = FEATURES.iter().map(|(name, _)| *name).collect();
+            return Err(format!(
+                "unknown media feature '{feature}' ({})",
+                names.join(", ")
+            ));
+        };
+        if !value.as_str().is_some_and(|v| allowed.contains(&v)) {
+            return Err(format!(
+                "{feature} must be one of {}, got {value}",
+                allowed.join(", ")
+            ));
+        }
+    }
+    Ok(body.clone())
+}
+
+/// `{ "latitude": 52.52, "longitude": 13.405, "accuracy": 10 }`, or
+/// `{ "error": "denied" | "unavailable" | "timeout" }` to make every request
+/// fail.
+fn geolocation(body: &Value) -> Result<Value, String> {
+    if let Some(error) = body.get("error") {
+        return match error.as_str() {
+            Some("denied" | "unavailable" | "timeout") => Ok(serde_json::json!({ "error": error })),
+            _ => Err(format!(
+                "unknown error {error} (denied, unavailable, timeout)"
+            )),
+        };
+    }
+    let coordinate = |name: &str, limit: f64| match body[name].as_f64() {
+        Some(v) if v.abs() <= limit => Ok(v),
+        _ => Err(format!(
+            "\"{name}\" must be a number between -{limit} and {limit}"
+        )),
+    };
+    let latitude = coordinate("latitude", 90.0)?;
+    let longitude = coordinate("longitude", 180.0)?;
+    let accuracy = match &body["accuracy"] {
+        Value::Null => 10.0,
+        v => v
+            .as_f64()
+            .filter(|a| *a >= 0.0)
+            .ok_or("\"accuracy\" must be a non-negative number (meters)")?,
+    };
+    Ok(serde_json::json!({
+        "latitude": latitude,
+        "longitude": longitude,
+        "accuracy": accuracy,
+    }))
+}
+
+/// `{ "geolocation": "granted", "notifications": "denied" }`; permissions
+/// left out are answered by WebKit as usual.
+fn permissions(body: &Value) -> Result<Value, String> {
+    let Some(permissions) = body.as_object().filter(|p| !p.is_empty()) else {
+        return Err(
+            "expected an object of permission states, e.g. {\"geolocation\": \"granted\"}".into(),
+        );
+    };
+    for (name, state) in permissions {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_lowercase() || c == '-') {
+            return Err(format!("invalid permission name '{name}'"));
+        }
+        if !matches!(state.as_str(), Some("granted" | "denied" | "prompt")) {
+            return Err(format!(
+                "{name} must be one of granted, denied, prompt, got {state}"
+            ));
+        }
+    }
+    Ok(body.clone())
+}
@@ -0,0 +1,103 @@
+//! `GET /events`: a Server-Sent Events feed of what the plugin sees and
+//! does, so dashboards can follow along without WebSocket infrastructure.
+//!
+//! Event types:
+//!
+//! - `capture` — a capture was taken (`source`, `name`, `window`, `format`,
+//!   `bytes`, `width`, `height`); polls for `/watch` aren't included
+//! - `eval` — a script ran via `/eval` (`window`, `length`, and the first
+//!   [`SCRIPT_PREVIEW`] characters as `script`)
+//! - `window` — a window was created, focused, blurred, resized, moved,
+//!   asked to close, destroyed, or changed theme (`window`, `event`)
+//! - `analysis` — the result of `/screenshot/analyze` or `/screenshot/blank`
+//! - `region-changed` — a `/watch` subscription saw a change (without the
+//!   image)
+//!
+//! Every event's data carries `timestamp_ms`.
+
+use crate::sse::{EventStream, KEEPALIVE};
+use crate::Context;
+use serde_json::{json, Value};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use tauri::{RunEvent, WindowEvent};
+
+/// Characters of an evaluated script included in `eval` events.
+pub(crate) const SCRIPT_PREVIEW: usize = 200;
+
+/// Fans events out to every `/events` subscriber.
+pub(crate) struct EventBus {
+    subscribers: Mutex<Vec<Sender<(&'static str, Value)>>>,
+}
+
+impl EventBus {
+    pub(crate) fn new() -> Self {
+        Self {
+            subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Send `data` (an object) as `event` to every subscriber, dropping
+    /// those that disconnected.
+    pub(crate) fn publish(&self, event: &'static str, mut data: Value) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        data["timestamp_ms"] = json!(crate::unix_millis());
+        subscribers.retain(|tx| tx.send((event, data.clone())).is_ok());
+    }
+
+    /// Publish the `window` event for a Tauri run event, if it's one.
+    pub(crate) fn publish_run_event(&self, event: &RunEvent) {
+        let RunEvent::WindowEvent { label, event, .. } = event else {
+            return;
+        };
+        let mut data = match event {
+            WindowEvent::Focused(true) => json!({ "event": "focused" }),
+            WindowEvent::Focused(false) => json!({ "event": "blurred" }),
+            WindowEvent::Resized(size) => {
+                json!({ "event": "resized", "width": size.width, "height": size.height })
+            }
+            WindowEvent::Moved(position) => {
+                json!({ "event": "moved", "x": position.x, "y": position.y })
+            }
+            WindowEvent::CloseRequested { .. } => json!({ "event": "close-requested" }),
+            WindowEvent::Destroyed => json!({ "event": "destroyed" }),
+            WindowEvent::ThemeChanged(theme) => {
+                json!({ "event": "theme-changed", "theme": theme.to_string() })
+            }
+            _ => return,
+        };
+        data["window"] = json!(label);
+        self.publish("window", data);
+    }
+
+    fn subscribe(&self) -> mpsc::Receiver<(&'static str, Value)> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+}
+
+/// Stream events (only those named in `types`, if given) until the client
+/// disconnects or the server shuts down.
+pub(crate) fn serve(request: tiny_http::Request, context: &Context, types: Option<Vec<String>>) {
+    let events = context.events.subscribe();
+    let Ok(mut stream) = EventStream::open(request) else {
+        return;
+    };
+
+    let wanted = |event: &str| types.as_ref().is_none_or(|t| t.iter().any(|t| t == event));
+    while !context.shutdown.load(Ordering::Relaxed) {
+        let sent = match events.recv_timeout(KEEPALIVE) {
+            Ok((event, data)) if wanted(event) => stream.send(event, &data),
+            Ok(_) | Err(RecvTimeoutError::Timeout) => stream.keepalive(),
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if sent.is_err() {
+            break;
+        }
+    }
+}
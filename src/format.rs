@@ -0,0 +1,414 @@
+//! Output image formats (`?format=` / [`Config::format`](crate::Config)).
+//!
+//! PNG, JPEG, TIFF and BMP come straight from AppKit. Other formats are encoded
+//! here from the snapshot's decoded pixels, skipping PNG compression
+//! entirely.
+
+/// Image format captures are encoded in. Non-exhaustive, since which
+/// formats exist depends on features (`avif`) that another crate in the
+/// build may turn on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ImageFormat {
+    /// Lossless, compressed by AppKit.
+    #[default]
+    Png,
+    /// Lossless "Quite OK Image" format: near-memcpy encoding speed and
+    /// files far smaller than raw RGBA, for high-rate captures.
+    Qoi,
+    /// Lossy, for browsers and bandwidth-constrained consumers.
+    Jpeg,
+    /// Lossless WebP, smaller than PNG in browsers that support it.
+    Webp,
+    /// Uncompressed, for inspection tools that predate PNG.
+    Tiff,
+    /// Uncompressed 32-bit, for inspection tools that predate PNG.
+    Bmp,
+    /// AV1-compressed, lossy: a fraction of the PNG size at a much higher
+    /// encoding cost, for archives kept for months. Requires the `avif`
+    /// feature.
+    #[cfg(feature = "avif")]
+    Avif,
+}
+
+/// JPEG quality for encodes that don't go through AppKit.
+const JPEG_QUALITY: u8 = 90;
+
+/// AVIF encoder settings: visually lossless for UI captures, and the
+/// fastest speed that doesn't noticeably grow the files.
+#[cfg(feature = "avif")]
+const AVIF_QUALITY: f32 = 80.0;
+#[cfg(feature = "avif")]
+const AVIF_SPEED: u8 = 6;
+
+impl ImageFormat {
+    /// Every format this build can produce.
+    pub(crate) const ALL: &'static [Self] = &[
+        Self::Png,
+        Self::Qoi,
+        Self::Jpeg,
+        Self::Webp,
+        Self::Tiff,
+        Self::Bmp,
+        #[cfg(feature = "avif")]
+        Self::Avif,
+    ];
+
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        let s = match s.to_ascii_lowercase().as_str() {
+            "jpg" => "jpeg".to_string(),
+            "tif" => "tiff".to_string(),
+            s => s.to_string(),
+        };
+        Self::ALL.iter().copied().find(|f| f.extension() == s)
+    }
+
+    /// A comma-separated list of formats (`png,jpeg`), without repeats.
+    pub(crate) fn parse_list(s: &str) -> Result<Vec<Self>, String> {
+        let mut formats = Vec::new();
+        for name in s.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let format = Self::parse(name)
+                .ok_or_else(|| format!("unknown format '{name}' ({})", Self::names()))?;
+            if !formats.contains(&format) {
+                formats.push(format);
+            }
+        }
+        if formats.is_empty() {
+            return Err("formats needs at least one format".into());
+        }
+        Ok(formats)
+    }
+
+    /// Whether decoding gives back exactly the pixels that were encoded.
+    pub(crate) fn lossless(self) -> bool {
+        match self {
+            Self::Png | Self::Qoi | Self::Webp | Self::Tiff | Self::Bmp => true,
+            Self::Jpeg => false,
+            #[cfg(feature = "avif")]
+            Self::Avif => false,
+        }
+    }
+
+    /// Comma-separated names of [`Self::ALL`], for error messages.
+    pub(crate) fn names() -> String {
+        let names: Vec<&str> = Self::ALL.iter().map(|f| f.extension()).collect();
+        names.join(", ")
+    }
+
+    /// Recognize already-encoded bytes by their signature.
+    pub(crate) fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Self::Png)
+        } else if bytes.starts_with(b"qoif") {
+            Some(Self::Qoi)
+        } else if bytes.starts_with(b"\xff\xd8\xff") {
+            Some(Self::Jpeg)
+        } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+            Some(Self::Webp)
+        } else if bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*") {
+            Some(Self::Tiff)
+        } else if bytes.starts_with(b"BM") {
+            Some(Self::Bmp)
+        } else {
+            #[cfg(feature = "avif")]
+            if bytes.get(4..12) == Some(b"ftypavif") {
+                return Some(Self::Avif);
+            }
+            None
+        }
+    }
+
+    pub(crate) fn content_type(self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Qoi => "image/qoi",
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+            Self::Tiff => "image/tiff",
+            Self::Bmp => "image/bmp",
+            #[cfg(feature = "avif")]
+            Self::Avif => "image/avif",
+        }
+    }
+
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Qoi => "qoi",
+            Self::Jpeg => "jpeg",
+            Self::Webp => "webp",
+            Self::Tiff => "tiff",
+            Self::Bmp => "bmp",
+            #[cfg(feature = "avif")]
+            Self::Avif => "avif",
+        }
+    }
+
+    /// The `NSBitmapImageFileType` AppKit encodes this format with, if any.
+    #[cfg(target_os = "macos")]
+    pub(crate) fn native_file_type(self) -> Option<u64> {
+        match self {
+            Self::Tiff => Some(0),
+            Self::Bmp => Some(1),
+            Self::Jpeg => Some(3),
+            Self::Png => Some(4),
+            _ => None,
+        }
+    }
+}
+
+/// Width and height from an encoded image's header.
+pub(crate) fn dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let at = |offset: usize| {
+        Some(u32::from_be_bytes(
+            bytes.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    };
+    match ImageFormat::detect(bytes)? {
+        ImageFormat::Png if bytes.get(12..16) == Some(b"IHDR") => Some((at(16)?, at(20)?)),
+        ImageFormat::Png => None,
+        ImageFormat::Qoi => Some((at(4)?, at(8)?)),
+        ImageFormat::Jpeg => jpeg_dimensions(bytes),
+        ImageFormat::Webp => webp_dimensions(bytes),
+        ImageFormat::Tiff => tiff_dimensions(bytes),
+        ImageFormat::Bmp => {
+            let le = |offset: usize| {
+                Some(i32::from_le_bytes(
+                    bytes.get(offset..offset + 4)?.try_into().ok()?,
+                ))
+            };
+            // Negative heights mark top-down bitmaps
+            Some((le(18)?.unsigned_abs(), le(22)?.unsigned_abs()))
+        }
+        // The image spatial extents property: `ispe`, version/flags, width, height
+        #[cfg(feature = "avif")]
+        ImageFormat::Avif => {
+            let header = &bytes[..bytes.len().min(4096)];
+            let ispe = header.windows(4).position(|w| w == b"ispe")?;
+            Some((at(ispe + 8)?, at(ispe + 12)?))
+        }
+    }
+}
+
+/// Width and height from a JPEG's start-of-frame segment.
+fn jpeg_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let u16_at = |offset: usize| {
+        Some(u16::from_be_bytes(
+            bytes.get(offset..offset + 2)?.try_into().ok()?,
+        ))
+    };
+    let mut offset = 2;
+    loop {
+        if *bytes.get(offset)? != 0xff {
+            return None;
+        }
+        let marker = *bytes.get(offset + 1)?;
+        // SOF0–SOF15, except DHT (C4), JPG (C8) and DAC (CC)
+        if (0xc0..=0xcf).contains(&marker) && ![0xc4, 0xc8, 0xcc].contains(&marker) {
+            let height = u16_at(offset + 5)?;
+            let width = u16_at(offset + 7)?;
+            return Some((u32::from(width), u32::from(height)));
+        }
+        offset += 2 + usize::from(u16_at(offset + 2)?);
+    }
+}
+
+/// Width and height from a WebP's first chunk.
+fn webp_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let le = |offset: usize, len: usize| {
+        let mut b = [0; 4];
+        b[..len].copy_from_slice(bytes.get(offset..offset + len)?);
+        Some(u32::from_le_bytes(b))
+    };
+    match bytes.get(12..16)? {
+        b"VP8 " => Some((le(26, 2)? & 0x3fff, le(28, 2)? & 0x3fff)),
+        b"VP8L" => {
+            let bits = le(21, 4)?;
+            Some(((bits & 0x3fff) + 1, (bits >> 14 & 0x3fff) + 1))
+        }
+        b"VP8X" => Some((le(24, 3)? + 1, le(27, 3)? + 1)),
+        _ => None,
+    }
+}
+
+/// ImageWidth and ImageLength from a TIFF's first IFD.
+fn tiff_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let little = bytes.starts_with(b"II");
+    let u16_at = |offset: usize| {
+        let b: [u8; 2] = bytes.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if little {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    };
+    let u32_at = |offset: usize| {
+        let b: [u8; 4] = bytes.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if little {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    };
+
+    let ifd = u32_at(4)? as usize;
+    let (mut width, mut height) = (None, None);
+    for i in 0..u16_at(ifd)? as usize {
+        let entry = ifd + 2 + i * 12;
+        // SHORT (3) or LONG (4), stored inline
+        let value = match u16_at(entry + 2)? {
+            3 => u32::from(u16_at(entry + 8)?),
+            4 => u32_at(entry + 8)?,
+            _ => continue,
+        };
+        match u16_at(entry)? {
+            256 => width = Some(value),
+            257 => height = Some(value),
+            _ => {}
+        }
+    }
+    Some((width?, height?))
+}
+
+/// A decoded capture: 8-bit RGBA with straight (not premultiplied) alpha,
+/// row by row from the top left.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    /// `width * height * 4` bytes.
+    pub rgba: Vec<u8>,
+}
+
+/// Encode `frame` as `format`.
+pub(crate) fn encode(frame: &Frame, format: ImageFormat) -> Result<Vec<u8>, String> {
+    let with_image_crate = |format: image::ImageFormat| {
+        let mut out = std::io::Cursor::new(Vec::new());
+        image::write_buffer_with_format(
+            &mut out,
+            &frame.rgba,
+            frame.width,
+            frame.height,
+            image::ExtendedColorType::Rgba8,
+            format,
+        )
+        .map(|()| out.into_inner())
+        .map_err(|e| format!("{format:?} encoding failed: {e}"))
+    };
+    match format {
+        ImageFormat::Png => with_image_crate(image::ImageFormat::Png),
+        ImageFormat::Webp => with_image_crate(image::ImageFormat::WebP),
+        ImageFormat::Jpeg => {
+            // JPEG has no alpha channel
+            let rgb: Vec<u8> = frame
+                .rgba
+                .chunks_exact(4)
+                .flat_map(|p| [p[0], p[1], p[2]])
+                .collect();
+            let mut out = Vec::new();
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY)
+                .encode(
+                    &rgb,
+                    frame.width,
+                    frame.height,
+                    image::ExtendedColorType::Rgb8,
+                )
+                .map(|()| out)
+                .map_err(|e| format!("JPEG encoding failed: {e}"))
+        }
+        ImageFormat::Tiff => with_image_crate(image::ImageFormat::Tiff),
+        ImageFormat::Bmp => with_image_crate(image::ImageFormat::Bmp),
+        ImageFormat::Qoi => qoi::encode_to_vec(&frame.rgba, frame.width, frame.height)
+            .map_err(|e| format!("QOI encoding failed: {e}")),
+        #[cfg(feature = "avif")]
+        ImageFormat::Avif => {
+            let pixels: Vec<ravif::RGBA8> = frame
+                .rgba
+                .chunks_exact(4)
+                .map(|p| ravif::RGBA8::new(p[0], p[1], p[2], p[3]))
+                .collect();
+            let image = ravif::Img::new(&pixels[..], frame.width as usize, frame.height as usize);
+            ravif::Encoder::new()
+                .with_quality(AVIF_QUALITY)
+                .with_speed(AVIF_SPEED)
+                .encode_rgba(image)
+                .map(|encoded| encoded.avif_file)
+                .map_err(|e| format!("AVIF encoding failed: {e}"))
+        }
+    }
+}
+
+/// Decode an encoded capture back into pixels. QOI (what streams use) is
+/// decoded into a pooled buffer, for [`budget::recycle`](crate::budget::recycle).
+pub(crate) fn decode(bytes: &[u8]) -> Result<Frame, String> {
+    if ImageFormat::detect(bytes) == Some(ImageFormat::Qoi) {
+        let (header, rgba) = qoi::Decoder::new(bytes)
+            .map(|d| d.with_channels(qoi::Channels::Rgba))
+            .and_then(|mut d| {
+                let mut rgba = crate::budget::buffer(d.required_buf_len());
+                rgba.resize(d.required_buf_len(), 0);
+                d.decode_to_buf(&mut rgba)?;
+                Ok((*d.header(), rgba))
+            })
+            .map_err(|e| format!("QOI decoding failed: {e}"))?;
+        return Ok(Frame {
+            width: header.width,
+            height: header.height,
+            rgba,
+        });
+    }
+    let image = image::load_from_memory(bytes)
+        .map_err(|e| format!("decoding failed: {e}"))?
+        .into_rgba8();
+    Ok(Frame {
+        width: image.width(),
+        height: image.height(),
+        rgba: image.into_raw(),
+    })
+}
+
+/// What an `Accept` header prefers for a capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Accepted {
+    Image(ImageFormat),
+    /// The image base64-encoded in a JSON object with its metadata.
+    Json,
+}
+
+/// The highest-`q` type in `accept` that a capture can be sent as, earliest
+/// first on ties. `None` when that's a wildcard or nothing matches, leaving
+/// the configured format. AVIF is never negotiated: browsers advertise it on
+/// every image request, and its encoding is far too slow for that.
+pub(crate) fn negotiate(accept: &str) -> Option<Accepted> {
+    let mut best: Option<(f32, Option<Accepted>)> = None;
+    for item in accept.split(',') {
+        let mut params = item.split(';');
+        let media_type = params
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let q = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        let accepted = match media_type.as_str() {
+            "*/*" | "image/*" => None,
+            "application/json" => Some(Accepted::Json),
+            "image/jpg" => Some(Accepted::Image(ImageFormat::Jpeg)),
+            _ => match ImageFormat::ALL
+                .iter()
+                .find(|f| f.content_type() == media_type)
+            {
+                #[cfg(feature = "avif")]
+                Some(ImageFormat::Avif) => continue,
+                Some(&format) => Some(Accepted::Image(format)),
+                None => continue,
+            },
+        };
+        if q > 0.0 && best.is_none_or(|(best_q, _)| q > best_q) {
+            best = Some((q, accepted));
+        }
+    }
+    best.and_then(|(_, accepted)| accepted)
+}
@@ -0,0 +1,86 @@
+//! Limits how many captures run at once. Each one holds a full decoded
+//! frame (~60 MB on a 5K display) until its PNG is encoded, so unbounded
+//! concurrency turns into memory spikes. Interactive captures queue ahead
+//! of background ones (see [`Priority`]).
+
+use crate::Priority;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+pub(crate) struct CaptureGate {
+    max: usize,
+    state: Mutex<State>,
+    freed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    in_flight: usize,
+    /// Interactive captures waiting for a slot; background ones wait while
+    /// there are any.
+    interactive_waiting: usize,
+}
+
+/// A slot in the gate, released on drop.
+pub(crate) struct Permit<'a>(&'a CaptureGate);
+
+impl CaptureGate {
+    pub(crate) fn new(max: usize) -> Self {
+        Self {
+            max: max.max(1),
+            state: Mutex::new(State::default()),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Wait up to `timeout` for a free slot.
+    pub(crate) fn acquire(
+        &self,
+        timeout: Duration,
+        priority: Priority,
+    ) -> Result<Permit<'_>, String> {
+        let deadline = Instant::now() + timeout;
+        let interactive = priority == Priority::Interactive;
+        let mut state = self.state.lock().unwrap();
+        if interactive {
+            state.interactive_waiting += 1;
+        }
+        loop {
+            let yield_to_interactive = !interactive && state.interactive_waiting > 0;
+            if state.in_flight < self.max && !yield_to_interactive {
+                break;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                if interactive {
+                    state.interactive_waiting -= 1;
+                    // A background capture may have been waiting on us
+                    self.freed.notify_all();
+                }
+                return Err(format!(
+                    "timed out waiting for one of {} concurrent captures to finish",
+                    self.max
+                ));
+            }
+            state = self.freed.wait_timeout(state, remaining).unwrap().0;
+        }
+        state.in_flight += 1;
+        if interactive {
+            state.interactive_waiting -= 1;
+            // Slots left over may go to background captures now
+            if state.in_flight < self.max {
+                self.freed.notify_all();
+            }
+        }
+        Ok(Permit(self))
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().in_flight -= 1;
+        // Waiters of either class may be next, so wake them all to sort it
+        // out
+        self.0.freed.notify_all();
+    }
+}
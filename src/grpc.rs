@@ -0,0 +1,477 @@
+//! gRPC mirror of capture, eval and streaming (`grpc` feature, see
+//! [`Config::grpc_port`](crate::Config::grpc_port)), for tooling that
+//! standardizes on gRPC. The service is described in
+//! `proto/screenshot_hd.proto`; the messages below are its prost
+//! equivalents, written out so building needs no `protoc`.
+//!
+//! Allowed IPs, the rate limit, the auth token (`authorization: Bearer ..`
+//! metadata) and `enabled_endpoints` apply as over HTTP, with `Capture` and
+//! `Stream` counting as `/screenshot` and `Eval` as `/eval`. A call whose
+//! `grpc-timeout` can't cover its `wait=` fails with `DEADLINE_EXCEEDED` up
+//! front, and a stream ends at its deadline.
+
+use crate::adaptive::{Adaptive, Quality};
+use crate::idle::{self, IdleWatch};
+use crate::logging;
+use crate::{format, CaptureOptions, Context, ImageFormat, Scope, Wait};
+use bytes::Bytes;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::ToSocketAddrs;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager, Runtime, WebviewWindow};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::body::Body;
+use tonic::codegen::{http, BoxFuture, Service};
+use tonic::server::{Grpc, ServerStreamingService, UnaryService};
+use tonic::{Request, Response, Status};
+use tonic_prost::ProstCodec;
+
+const SERVICE: &str = "/screenshot_hd.ScreenshotHd/";
+const MIN_STREAM_INTERVAL: Duration = Duration::from_millis(100);
+/// Frames a stream captures ahead of a slow client.
+const STREAM_BUFFER: usize = 2;
+/// How often the server checks for `POST /shutdown`.
+const SHUTDOWN_POLL: Duration = Duration::from_millis(250);
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub(crate) struct CaptureRequest {
+    #[prost(string, tag = "1")]
+    window: String,
+    #[prost(string, tag = "2")]
+    options: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub(crate) struct EvalRequest {
+    #[prost(string, tag = "1")]
+    window: String,
+    #[prost(string, tag = "2")]
+    script: String,
+    #[prost(string, tag = "3")]
+    options: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub(crate) struct EvalReply {
+    #[prost(message, optional, tag = "1")]
+    image: Option<Image>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub(crate) struct StreamRequest {
+    #[prost(string, tag = "1")]
+    window: String,
+    #[prost(string, tag = "2")]
+    options: String,
+    #[prost(uint32, tag = "3")]
+    interval_ms: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub(crate) struct Image {
+    #[prost(bytes = "bytes", tag = "1")]
+    data: Bytes,
+    #[prost(string, tag = "2")]
+    format: String,
+    #[prost(uint32, tag = "3")]
+    width: u32,
+    #[prost(uint32, tag = "4")]
+    height: u32,
+    #[prost(uint64, tag = "5")]
+    timestamp_ms: u64,
+    /// Set on stream frames.
+    #[prost(message, optional, tag = "6")]
+    quality: Option<StreamQuality>,
+    /// On the first stream frame after an idle pause, how long it lasted.
+    #[prost(uint64, tag = "7")]
+    idle_gap_ms: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub(crate) struct StreamQuality {
+    #[prost(uint32, tag = "1")]
+    level: u32,
+    #[prost(uint32, tag = "2")]
+    interval_ms: u32,
+    #[prost(double, tag = "3")]
+    resolution: f64,
+}
+
+impl From<Quality> for StreamQuality {
+    fn from(quality: Quality) -> Self {
+        Self {
+            level: quality.level,
+            interval_ms: quality.interval_ms,
+            resolution: quality.resolution,
+        }
+    }
+}
+
+impl Image {
+    fn new(data: impl Into<Bytes>) -> Self {
+        let data = data.into();
+        let (width, height) = format::dimensions(&data).unwrap_or_default();
+        Self {
+            format: ImageFormat::detect(&data)
+                .unwrap_or_default()
+                .extension()
+                .to_string(),
+            width,
+            height,
+            timestamp_ms: crate::unix_millis(),
+            quality: None,
+            idle_gap_ms: 0,
+            data,
+        }
+    }
+}
+
+/// Serve the gRPC service on `host:port` on Tauri's async runtime, until
+/// `POST /shutdown`.
+pub(crate) fn serve<R: Runtime>(
+    app: AppHandle<R>,
+    window_label: String,
+    context: Arc<Context>,
+    host: &str,
+    port: u16,
+) {
+    let addr = match (host, port).to_socket_addrs().map(|mut a| a.next()) {
+        Ok(Some(addr)) => addr,
+        _ => {
+            logging::warn!("[screenshot-hd] can't resolve {host}:{port} for gRPC");
+            return;
+        }
+    };
+    let service = GrpcService {
+        app,
+        window_label,
+        context: context.clone(),
+    };
+    tauri::async_runtime::spawn(async move {
+        let incoming = match tonic::transport::server::TcpIncoming::bind(addr) {
+            Ok(incoming) => incoming,
+            Err(e) => {
+                logging::warn!("[screenshot-hd] failed to start gRPC on {addr}: {e}");
+                return;
+            }
+        };
+        logging::info!("[screenshot-hd] gRPC listening on {addr}");
+        let shutdown = async move {
+            while !context.shutdown.load(Ordering::Relaxed) {
+                tokio::time::sleep(SHUTDOWN_POLL).await;
+            }
+        };
+        let served = tonic::transport::Server::builder()
+            .serve_with_incoming_shutdown(service, incoming, shutdown)
+            .await;
+        if let Err(e) = served {
+            logging::warn!("[screenshot-hd] gRPC server stopped: {e}");
+        }
+    });
+}
+
+struct GrpcService<R: Runtime> {
+    app: AppHandle<R>,
+    window_label: String,
+    context: Arc<Context>,
+}
+
+impl<R: Runtime> Clone for GrpcService<R> {
+    fn clone(&self) -> Self {
+        Self {
+            app: self.app.clone(),
+            window_label: self.window_label.clone(),
+            context: self.context.clone(),
+        }
+    }
+}
+
+impl<R: Runtime> Service<http::Request<Body>> for GrpcService<R> {
+    type Response = http::Response<Body>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        let service = self.clone();
+        Box::pin(async move {
+            let method = request.uri().path().strip_prefix(SERVICE).unwrap_or("");
+            Ok(match method {
+                "Capture" => {
+                    let handler = Handler(move |r| service.clone().capture(r));
+                    Grpc::new(ProstCodec::default())
+                        .unary(handler, request)
+                        .await
+                }
+                "Eval" => {
+                    let handler = Handler(move |r| service.clone().eval(r));
+                    Grpc::new(ProstCodec::default())
+                        .unary(handler, request)
+                        .await
+                }
+                "Stream" => {
+                    let handler = Handler(move |r| service.clone().stream(r));
+                    Grpc::new(ProstCodec::default())
+                        .server_streaming(handler, request)
+                        .await
+                }
+                _ => {
+                    Status::unimplemented(format!("no method {}", request.uri().path())).into_http()
+                }
+            })
+        })
+    }
+}
+
+/// Adapts an async handler to tonic's unary and server-streaming services.
+struct Handler<F>(F);
+
+impl<F, Req, Res, Fut> UnaryService<Req> for Handler<F>
+where
+    F: FnMut(Request<Req>) -> Fut,
+    Fut: Future<Output = Result<Response<Res>, Status>>,
+{
+    type Response = Res;
+    type Future = Fut;
+
+    fn call(&mut self, request: Request<Req>) -> Fut {
+        (self.0)(request)
+    }
+}
+
+impl<F, Req, Res, Fut> ServerStreamingService<Req> for Handler<F>
+where
+    F: FnMut(Request<Req>) -> Fut,
+    Fut: Future<Output = Result<Response<ReceiverStream<Result<Res, Status>>>, Status>>,
+{
+    type Response = Res;
+    type ResponseStream = ReceiverStream<Result<Res, Status>>;
+    type Future = Fut;
+
+    fn call(&mut self, request: Request<Req>) -> Fut {
+        (self.0)(request)
+    }
+}
+
+impl<R: Runtime> GrpcService<R> {
+    async fn capture(self, request: Request<CaptureRequest>) -> Result<Response<Image>, Status> {
+        let deadline = self.admit(&request, "/screenshot")?;
+        let CaptureRequest { window, options } = request.into_inner();
+        let settings = self.options(&options, deadline)?;
+        let window = self.window(&window)?;
+        let image = blocking(move || {
+            self.context
+                .capture_with(&window, "grpc", None, &settings)
+                .map(Image::new)
+                .map_err(Status::unavailable)
+        })
+        .await?;
+        Ok(Response::new(image))
+    }
+
+    async fn eval(self, request: Request<EvalRequest>) -> Result<Response<EvalReply>, Status> {
+        let deadline = self.admit(&request, "/eval")?;
+        let remote = request.remote_addr();
+        let EvalRequest {
+            window,
+            script,
+            options,
+        } = request.into_inner();
+        let settings = self.options(&options, deadline)?;
+        let window = self.window(&window)?;
+
+        let context = &self.context;
+        if let Some(audit) = &context.audit {
+            audit.record(remote, "grpc:Eval", window.label(), &script);
+        }
+        context.eval(&window, &script).map_err(Status::internal)?;
+        context.events.publish(
+            "eval",
+            serde_json::json!({
+                "window": window.label(),
+                "length": script.len(),
+                "script": script.chars().take(crate::events::SCRIPT_PREVIEW).collect::<String>(),
+            }),
+        );
+        if let Some(recorder) = &context.recorder {
+            recorder.record(serde_json::json!({
+                "command": "eval",
+                "script": script,
+                "url": format!("/eval?{options}"),
+                "name": null,
+            }));
+        }
+
+        if settings.wait == Wait::None {
+            return Ok(Response::new(EvalReply { image: None }));
+        }
+        let image = blocking(move || {
+            self.context
+                .capture_with(&window, "eval", None, &settings)
+                .map(Image::new)
+                .map_err(Status::unavailable)
+        })
+        .await?;
+        Ok(Response::new(EvalReply { image: Some(image) }))
+    }
+
+    async fn stream(
+        self,
+        request: Request<StreamRequest>,
+    ) -> Result<Response<ReceiverStream<Result<Image, Status>>>, Status> {
+        let deadline = self.admit(&request, "/screenshot")?;
+        let StreamRequest {
+            window,
+            options,
+            interval_ms,
+        } = request.into_inner();
+        let settings = self.options(&options, deadline)?;
+        let window = self.window(&window)?;
+        // `fps=` or `interval=` in the options take over from `interval_ms`
+        let query = format!("?{options}");
+        let interval = crate::stream_interval(&query, Duration::from_millis(interval_ms.into()))
+            .map_err(Status::invalid_argument)?
+            .max(MIN_STREAM_INTERVAL);
+        let mut adaptive = Adaptive::new(&query, interval).map_err(Status::invalid_argument)?;
+
+        let mut idle = IdleWatch::new(self.context.idle_pause);
+
+        let (frames, stream) = tokio::sync::mpsc::channel(STREAM_BUFFER);
+        std::thread::spawn(move || {
+            let context = &self.context;
+            while !context.shutdown.load(Ordering::Relaxed) {
+                if deadline.is_some_and(|d| Instant::now() >= d) {
+                    let _ = frames.blocking_send(Err(Status::deadline_exceeded("stream deadline")));
+                    break;
+                }
+                if idle.as_mut().is_some_and(|idle| idle.paused(&window)) {
+                    std::thread::sleep(idle::POLL);
+                    continue;
+                }
+                let idle_gap = idle.as_mut().and_then(IdleWatch::take_gap);
+                let started = Instant::now();
+                let frame = context
+                    .snapshot(&window, &adaptive.settings(&window, &settings))
+                    .map(|data| Image {
+                        quality: Some(adaptive.quality().into()),
+                        idle_gap_ms: idle_gap.map_or(0, |gap| gap.as_millis() as u64),
+                        ..Image::new(data)
+                    })
+                    .map_err(Status::unavailable);
+                adaptive.record(started.elapsed());
+                let failed = frame.is_err();
+                if frames.blocking_send(frame).is_err() || failed {
+                    break;
+                }
+                std::thread::sleep(adaptive.interval());
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(stream)))
+    }
+
+    /// Apply the HTTP server's access checks to `request`, counting it, and
+    /// return its deadline.
+    fn admit<T>(&self, request: &Request<T>, endpoint: &str) -> Result<Option<Instant>, Status> {
+        let context = &self.context;
+        context.counters.requests.fetch_add(1, Ordering::Relaxed);
+        let ip = request.remote_addr().map(|a| a.ip());
+        if !context.ip_allowed(ip) {
+            return Err(Status::permission_denied("client not allowed"));
+        }
+        if let (Some(limiter), Some(ip)) = (&context.rate_limiter, ip) {
+            if limiter.check(ip).is_err() {
+                return Err(Status::resource_exhausted("rate limit exceeded"));
+            }
+        }
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        // Every gRPC call is a POST, so the HTTP checks apply as for one
+        let method = tiny_http::Method::Post;
+        match context.authorize(token, Scope::of(&method, endpoint)) {
+            Ok(()) => {}
+            Err((401, e)) => return Err(Status::unauthenticated(e)),
+            Err((_, e)) => return Err(Status::permission_denied(e)),
+        }
+        if !context.endpoint_enabled(endpoint) {
+            return Err(Status::permission_denied(format!("{endpoint} is disabled")));
+        }
+        if context.read_only_blocks(&method, endpoint) {
+            return Err(Status::permission_denied(format!(
+                "{endpoint} is disabled in read-only mode"
+            )));
+        }
+        Ok(deadline(request))
+    }
+
+    /// Parse capture options given as a query string, refusing a wait the
+    /// deadline can't cover.
+    fn options(&self, options: &str, deadline: Option<Instant>) -> Result<CaptureOptions, Status> {
+        let mut settings = self
+            .context
+            .capture_defaults
+            .clone()
+            .with_query(&format!("?{options}"))
+            .map_err(Status::invalid_argument)?;
+        settings.deadline = deadline;
+        if let (Some(wait), Some(deadline)) = (settings.wait.max_duration(), deadline) {
+            if Instant::now() + wait > deadline {
+                return Err(Status::deadline_exceeded("the wait exceeds the deadline"));
+            }
+        }
+        Ok(settings)
+    }
+
+    /// The window labelled `label`, or the configured one when it's empty.
+    fn window(&self, label: &str) -> Result<WebviewWindow<R>, Status> {
+        if label.is_empty() {
+            return self
+                .app
+                .get_webview_window(&self.window_label)
+                .ok_or_else(|| {
+                    Status::unavailable(format!(
+                        "window '{}' not found yet — app may still be starting",
+                        self.window_label
+                    ))
+                });
+        }
+        self.app
+            .get_webview_window(label)
+            .ok_or_else(|| Status::not_found(format!("window '{label}' not found")))
+    }
+}
+
+/// Run `f` off the async runtime, since captures block on the main thread.
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, Status> + Send + 'static,
+) -> Result<T, Status> {
+    tauri::async_runtime::spawn_blocking(f)
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+}
+
+/// The call's deadline, from its `grpc-timeout` metadata (e.g. `500m`).
+fn deadline<T>(request: &Request<T>) -> Option<Instant> {
+    let timeout = request.metadata().get("grpc-timeout")?.to_str().ok()?;
+    let (value, unit) = timeout.split_at(timeout.len().checked_sub(1)?);
+    let value: u64 = value.parse().ok()?;
+    let timeout = match unit {
+        "H" => Duration::from_secs(value.saturating_mul(3600)),
+        "M" => Duration::from_secs(value.saturating_mul(60)),
+        "S" => Duration::from_secs(value),
+        "m" => Duration::from_millis(value),
+        "u" => Duration::from_micros(value),
+        "n" => Duration::from_nanos(value),
+        _ => return None,
+    };
+    Instant::now().checked_add(timeout)
+}
@@ -0,0 +1,81 @@
+//! `GET /har`: the emulation shim's request log as HAR 1.2, with the
+//! session's screenshots referenced alongside so a triager can see what was
+//! on screen when each request ran.
+
+use crate::Context;
+use serde_json::{json, Value};
+use tauri::{Runtime, WebviewWindow};
+
+/// Build the HAR document for `window`. Screenshots are the history buffer
+/// plus archived captures since the first logged request.
+pub(crate) fn export<R: Runtime>(
+    window: &WebviewWindow<R>,
+    context: &Context,
+) -> Result<Value, (u16, String)> {
+    let (entries, since) = crate::emulation::requests(window)?;
+
+    let mut screenshots: Vec<Value> = Vec::new();
+    if let Value::Array(history) = context.history.list(None, None) {
+        screenshots.extend(history.into_iter().map(|e| {
+            json!({
+                "startedDateTime": iso8601(e["timestamp_ms"].as_u64().unwrap_or(0)),
+                "source": e["trigger"],
+                "name": e["name"],
+                "url": format!("/history/{}", e["id"]),
+            })
+        }));
+    }
+    if let (Some(archive), Some(since)) = (&context.archive, since) {
+        if let Value::Array(archived) = archive.query(Some(since), None, None, None, None) {
+            screenshots.extend(archived.into_iter().map(|e| {
+                json!({
+                    "startedDateTime": iso8601(e["timestamp_ms"].as_u64().unwrap_or(0)),
+                    "source": e["source"],
+                    "name": e["name"],
+                    "url": format!("/archive/{}", e["file"].as_str().unwrap_or_default()),
+                })
+            }));
+        }
+    }
+    screenshots.sort_by(|a, b| {
+        a["startedDateTime"]
+            .as_str()
+            .cmp(&b["startedDateTime"].as_str())
+    });
+
+    Ok(json!({
+        "log": {
+            "version": "1.2",
+            "creator": {
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "pages": [],
+            "entries": entries,
+            "_screenshots": screenshots,
+        }
+    }))
+}
+
+/// `1718000000123` → `2024-06-10T06:13:20.123Z`.
+fn iso8601(ms: u64) -> String {
+    let days = (ms / 86_400_000) as i64;
+    let ms_of_day = ms % 86_400_000;
+    // Howard Hinnant's civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        ms_of_day / 3_600_000,
+        ms_of_day / 60_000 % 60,
+        ms_of_day / 1000 % 60,
+        ms_of_day % 1000
+    )
+}
@@ -0,0 +1,164 @@
+//! Bounded in-memory history of captures, served at `GET /history`.
+
+use crate::budget::MemoryBudget;
+use crate::logging;
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A ring buffer of recent captures; the oldest entry is dropped when full
+/// or, with a retention period, once it's too old, and the oldest entries
+/// make way for a new one that wouldn't fit the memory budget.
+pub(crate) struct History {
+    capacity: usize,
+    retention: Option<Duration>,
+    memory: Arc<MemoryBudget>,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    next_id: u64,
+    entries: VecDeque<Entry>,
+}
+
+pub(crate) struct Entry {
+    pub(crate) id: u64,
+    pub(crate) timestamp_ms: u64,
+    /// What caused the capture, e.g. `resized`.
+    pub(crate) trigger: String,
+    /// Test name the capture was taken under (`X-Test-Name` / `?name=`).
+    pub(crate) name: Option<String>,
+    pub(crate) png: Bytes,
+}
+
+impl History {
+    pub(crate) fn new(
+        capacity: usize,
+        retention: Option<Duration>,
+        memory: Arc<MemoryBudget>,
+    ) -> Self {
+        Self {
+            capacity,
+            retention,
+            memory,
+            inner: Mutex::new(Inner {
+                next_id: 1,
+                entries: VecDeque::with_capacity(capacity),
+            }),
+        }
+    }
+
+    /// Store a capture and return its id.
+    pub(crate) fn push(
+        &self,
+        trigger: impl Into<String>,
+        name: Option<&str>,
+        png: impl Into<Bytes>,
+    ) -> u64 {
+        let png = png.into();
+        let mut inner = self.lock();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        if self.capacity == 0 {
+            return id;
+        }
+        while inner.entries.len() >= self.capacity {
+            self.evict(&mut inner);
+        }
+        while !self.memory.charge(png.len()) {
+            if !self.evict(&mut inner) {
+                logging::warn!(
+                    "[screenshot-hd] capture {id} ({} bytes) not kept in history: over the memory budget",
+                    png.len()
+                );
+                return id;
+            }
+        }
+        inner.entries.push_back(Entry {
+            id,
+            timestamp_ms: crate::unix_millis(),
+            trigger: trigger.into(),
+            name: name.map(str::to_string),
+            png,
+        });
+        id
+    }
+
+    /// Metadata of the last `last` stored captures (all if `None`), oldest
+    /// first, optionally only those taken under test `name`.
+    pub(crate) fn list(&self, last: Option<usize>, name: Option<&str>) -> serde_json::Value {
+        let inner = self.lock();
+        matching(&inner, last, name)
+            .map(|e| {
+                serde_json::json!({
+                    "id": e.id,
+                    "timestamp_ms": e.timestamp_ms,
+                    "trigger": e.trigger,
+                    "name": e.name,
+                    "bytes": e.png.len(),
+                })
+            })
+            .collect()
+    }
+
+    /// Timestamps and encoded images of the captures [`History::list`]
+    /// would list, oldest first. The images are shared, not copied.
+    pub(crate) fn captures(&self, last: Option<usize>, name: Option<&str>) -> Vec<(u64, Bytes)> {
+        let inner = self.lock();
+        matching(&inner, last, name)
+            .map(|e| (e.timestamp_ms, e.png.clone()))
+            .collect()
+    }
+
+    /// Encoded image of the capture with the given id, if still buffered.
+    pub(crate) fn get(&self, id: u64) -> Option<Bytes> {
+        let inner = self.lock();
+        inner
+            .entries
+            .iter()
+            .find(|e| e.id == id)
+            .map(|e| e.png.clone())
+    }
+
+    /// Lock the buffer, first dropping entries past the retention period.
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(retention) = self.retention {
+            let cutoff = crate::unix_millis().saturating_sub(retention.as_millis() as u64);
+            while inner
+                .entries
+                .front()
+                .is_some_and(|e| e.timestamp_ms < cutoff)
+            {
+                self.evict(&mut inner);
+            }
+        }
+        inner
+    }
+
+    /// Drop the oldest entry, if any.
+    fn evict(&self, inner: &mut Inner) -> bool {
+        let Some(entry) = inner.entries.pop_front() else {
+            return false;
+        };
+        self.memory.release(entry.png.len());
+        true
+    }
+}
+
+/// The last `last` entries (all if `None`) taken under test `name` (any if
+/// `None`), oldest first.
+fn matching<'a>(
+    inner: &'a Inner,
+    last: Option<usize>,
+    name: Option<&'a str>,
+) -> impl Iterator<Item = &'a Entry> {
+    let matches: Vec<&Entry> = inner
+        .entries
+        .iter()
+        .filter(|e| name.is_none_or(|n| e.name.as_deref() == Some(n)))
+        .collect();
+    let skip = last.map_or(0, |n| matches.len().saturating_sub(n));
+    matches.into_iter().skip(skip)
+}
@@ -0,0 +1,131 @@
+//! Rust callbacks around every capture ([`Config::on_before_capture`] and
+//! [`Config::on_after_capture`]), and for every failed capture or eval
+//! ([`Config::on_error`]).
+//!
+//! [`Config::on_before_capture`]: crate::Config::on_before_capture
+//! [`Config::on_after_capture`]: crate::Config::on_after_capture
+//! [`Config::on_error`]: crate::Config::on_error
+
+use crate::logging;
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+use tauri::{Runtime, WebviewWindow};
+
+/// Signature of [`Config::on_before_capture`](crate::Config::on_before_capture).
+pub type BeforeCapture = dyn Fn(&WebviewWindow) + Send + Sync;
+
+/// Signature of [`Config::on_after_capture`](crate::Config::on_after_capture):
+/// the window and the encoded image, or why the capture failed.
+pub type AfterCapture = dyn Fn(&WebviewWindow, Result<&[u8], &str>) + Send + Sync;
+
+/// Signature of [`Config::on_error`](crate::Config::on_error).
+pub type OnError = dyn Fn(&Error) + Send + Sync;
+
+/// A failed capture or eval, as passed to [`Config::on_error`](crate::Config::on_error).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Error {
+    pub kind: ErrorKind,
+    /// Label of the window it was for.
+    pub window: String,
+    /// What went wrong, as the endpoint reports it.
+    pub message: String,
+}
+
+/// What failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// A capture, from any endpoint, stream or automatic trigger.
+    Capture,
+    /// A script run through `/eval`, gRPC, BiDi, a storyboard, a replay or
+    /// [`ScreenshotHd::eval`](crate::ScreenshotHd::eval).
+    Eval,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            ErrorKind::Capture => "capture",
+            ErrorKind::Eval => "eval",
+        };
+        write!(f, "{kind} of '{}' failed: {}", self.window, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A capture callback, built with [`CaptureHook::before`],
+/// [`CaptureHook::after`] or [`CaptureHook::error`].
+///
+/// Hooks run on the capturing thread (not the main thread), and the capture
+/// waits for them. Before and after hooks only run under Tauri's default
+/// (Wry) runtime.
+pub struct CaptureHook<F: ?Sized>(Arc<F>);
+
+impl CaptureHook<BeforeCapture> {
+    /// Run `hook` right before every capture, e.g. to pause animations or
+    /// hide dev overlays.
+    pub fn before(hook: impl Fn(&WebviewWindow) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+
+    pub(crate) fn run<R: Runtime>(&self, window: &WebviewWindow<R>) {
+        match wry_window(window) {
+            Some(window) => (self.0)(window),
+            None => logging::debug!("[screenshot-hd] capture hooks skipped: not the Wry runtime"),
+        }
+    }
+}
+
+impl CaptureHook<AfterCapture> {
+    /// Run `hook` after every capture with its result, e.g. to restore what
+    /// a [`CaptureHook::before`] hook changed or to log captures.
+    pub fn after(
+        hook: impl Fn(&WebviewWindow, Result<&[u8], &str>) + Send + Sync + 'static,
+    ) -> Self {
+        Self(Arc::new(hook))
+    }
+
+    pub(crate) fn run<R: Runtime>(
+        &self,
+        window: &WebviewWindow<R>,
+        result: &Result<Vec<u8>, String>,
+    ) {
+        match wry_window(window) {
+            Some(window) => (self.0)(window, result.as_deref().map_err(String::as_str)),
+            None => logging::debug!("[screenshot-hd] capture hooks skipped: not the Wry runtime"),
+        }
+    }
+}
+
+impl CaptureHook<OnError> {
+    /// Run `hook` for every failed capture or eval, e.g. to forward it to
+    /// error tracking.
+    pub fn error(hook: impl Fn(&Error) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+
+    pub(crate) fn run(&self, error: &Error) {
+        (self.0)(error)
+    }
+}
+
+impl<F: ?Sized> Clone for CaptureHook<F> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<F: ?Sized> fmt::Debug for CaptureHook<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CaptureHook(..)")
+    }
+}
+
+/// `window` as the default-runtime window the public API takes, if it is
+/// one.
+pub(crate) fn wry_window<R: Runtime>(window: &WebviewWindow<R>) -> Option<&WebviewWindow> {
+    (window as &dyn Any).downcast_ref::<WebviewWindow>()
+}
@@ -0,0 +1,93 @@
+//! Idle pauses for streams (see [`Config::idle_pause`](crate::Config::idle_pause)):
+//! a page with no DOM mutations and no input for a while stops producing
+//! frames until something happens again, so long recordings of a mostly
+//! idle app don't burn CPU and disk on identical frames.
+
+use std::time::{Duration, Instant};
+use tauri::{Runtime, WebviewWindow};
+
+/// How often a paused stream checks for activity.
+pub(crate) const POLL: Duration = Duration::from_millis(250);
+
+/// Keeps `window.__SCREENSHOT_HD__.lastActivity` at the `performance.now()`
+/// of the latest DOM mutation or input, and `lastMutation` at that of the
+/// latest mutation. Injected on every page load when `Config::idle_pause`,
+/// `capture_on_mutation` or `activity_tracker` is set; a load counts as
+/// both.
+pub(crate) const ACTIVITY_JS: &str = r#"(function () {
+  const ns = (window.__SCREENSHOT_HD__ = window.__SCREENSHOT_HD__ || {});
+  if ('lastActivity' in ns) return;
+  const touch = () => { ns.lastActivity = performance.now(); };
+  const mutated = () => { ns.lastMutation = ns.lastActivity = performance.now(); };
+  mutated();
+  ['pointermove', 'pointerdown', 'keydown', 'wheel', 'scroll', 'touchstart', 'input', 'resize']
+    .forEach((type) => window.addEventListener(type, touch, { capture: true, passive: true }));
+  const observe = () => new MutationObserver(mutated).observe(document, {
+    subtree: true, childList: true, attributes: true, characterData: true,
+  });
+  if (document.documentElement) observe();
+  else document.addEventListener('DOMContentLoaded', observe);
+})()"#;
+
+/// Milliseconds since the page last changed or had input, `null` before
+/// the tracker has run.
+const IDLE_MS_JS: &str = r#"(function () {
+  const ns = window.__SCREENSHOT_HD__;
+  return ns && ns.lastActivity !== undefined ? performance.now() - ns.lastActivity : null;
+})()"#;
+
+/// Milliseconds since the DOM last changed, `null` before the tracker has
+/// run.
+const MUTATION_MS_JS: &str = r#"(function () {
+  const ns = window.__SCREENSHOT_HD__;
+  return ns && ns.lastMutation !== undefined ? performance.now() - ns.lastMutation : null;
+})()"#;
+
+/// How long ago the page's DOM last changed, if the tracker can be read.
+pub(crate) fn since_mutation<R: Runtime>(window: &WebviewWindow<R>) -> Option<Duration> {
+    let ms = crate::eval_json(window, MUTATION_MS_JS).ok()?.as_f64()?;
+    Some(Duration::from_secs_f64(ms.max(0.0) / 1000.0))
+}
+
+/// Whether one stream is paused, and the gap to report when it resumes.
+pub(crate) struct IdleWatch {
+    after: Duration,
+    paused_since: Option<Instant>,
+    gap: Option<Duration>,
+}
+
+impl IdleWatch {
+    /// Pause after `after` without activity; `None` when idle pauses are
+    /// off.
+    pub(crate) fn new(after: Option<Duration>) -> Option<Self> {
+        Some(Self {
+            after: after?,
+            paused_since: None,
+            gap: None,
+        })
+    }
+
+    /// Whether the stream should skip producing a frame now. A page the
+    /// tracker can't be read from counts as active.
+    pub(crate) fn paused<R: Runtime>(&mut self, window: &WebviewWindow<R>) -> bool {
+        let idle = crate::eval_json(window, IDLE_MS_JS)
+            .ok()
+            .and_then(|value| value.as_f64())
+            .is_some_and(|ms| ms >= self.after.as_millis() as f64);
+        match (idle, self.paused_since) {
+            (true, None) => self.paused_since = Some(Instant::now()),
+            (false, Some(since)) => {
+                self.paused_since = None;
+                self.gap = Some(since.elapsed());
+            }
+            _ => {}
+        }
+        idle
+    }
+
+    /// How long the stream was paused, once, on the first frame after it
+    /// resumed.
+    pub(crate) fn take_gap(&mut self) -> Option<Duration> {
+        self.gap.take()
+    }
+}
@@ -0,0 +1,131 @@
+//! `POST /iterate`: visit a list of routes or Storybook stories and take a
+//! named capture of each, so covering a component library takes one request
+//! instead of hundreds.
+
+use crate::{CaptureOptions, Context};
+use serde_json::{json, Value};
+use std::time::Instant;
+use tauri::{Runtime, Url, WebviewWindow};
+
+/// What to visit, in order.
+pub(crate) struct Iteration {
+    /// Capture name and URL of each page.
+    pages: Vec<(String, Url)>,
+    settings: CaptureOptions,
+}
+
+impl Iteration {
+    /// Parse a `{"routes": [..]}` or `{"stories": [..], "storybook": ..}`
+    /// body. Routes resolve against `current`, the window's URL, and so
+    /// does `storybook` (default: its origin). Capture names are the route
+    /// or story ID, under `prefix` (the request's test name) if any.
+    pub(crate) fn parse(
+        url: &str,
+        body: &str,
+        current: &Url,
+        prefix: Option<&str>,
+        defaults: CaptureOptions,
+    ) -> Result<Self, String> {
+        let body: Value = serde_json::from_str(body).map_err(|e| format!("invalid JSON: {e}"))?;
+        let strings = |key: &str| -> Result<Option<Vec<String>>, String> {
+            match &body[key] {
+                Value::Null => Ok(None),
+                Value::Array(items) => items
+                    .iter()
+                    .map(|item| item.as_str().map(str::to_string))
+                    .collect::<Option<_>>()
+                    .map(Some)
+                    .ok_or_else(|| format!("\"{key}\" must be an array of strings")),
+                _ => Err(format!("\"{key}\" must be an array of strings")),
+            }
+        };
+        let join = |base: &Url, path: &str| {
+            base.join(path)
+                .map_err(|e| format!("invalid URL '{path}': {e}"))
+        };
+
+        let pages = match (strings("routes")?, strings("stories")?) {
+            (Some(routes), None) => routes
+                .iter()
+                .map(|route| {
+                    let name = match route.trim_matches('/') {
+                        "" => "index",
+                        name => name,
+                    };
+                    Ok((name.to_string(), join(current, route)?))
+                })
+                .collect::<Result<Vec<_>, String>>()?,
+            (None, Some(stories)) => {
+                let mut storybook = join(current, body["storybook"].as_str().unwrap_or("/"))?;
+                // Keep the last path segment when joining iframe.html
+                if !storybook.path().ends_with('/') {
+                    let path = format!("{}/", storybook.path());
+                    storybook.set_path(&path);
+                }
+                stories
+                    .iter()
+                    .map(|id| {
+                        let mut page = join(&storybook, "iframe.html")?;
+                        page.query_pairs_mut()
+                            .append_pair("id", id)
+                            .append_pair("viewMode", "story");
+                        Ok((id.clone(), page))
+                    })
+                    .collect::<Result<Vec<_>, String>>()?
+            }
+            _ => return Err("pass exactly one of \"routes\" and \"stories\"".into()),
+        };
+        let pages = pages
+            .into_iter()
+            .map(|(name, page)| match prefix {
+                Some(prefix) => (format!("{prefix}/{name}"), page),
+                None => (name, page),
+            })
+            .collect();
+
+        Ok(Self {
+            pages,
+            settings: defaults.with_query(url)?,
+        })
+    }
+}
+
+/// Navigate to each page in turn, wait for it to load, and capture it under
+/// its name. A page that fails is reported and skipped; pages left at
+/// `deadline` are reported as `"truncated": true`.
+pub(crate) fn run<R: Runtime>(
+    window: &WebviewWindow<R>,
+    context: &Context,
+    iteration: &Iteration,
+    deadline: Instant,
+) -> Value {
+    let mut captures = Vec::new();
+    let mut truncated = false;
+    for (name, page) in &iteration.pages {
+        if Instant::now() >= deadline {
+            truncated = true;
+            break;
+        }
+        let result = crate::page::navigate(window, page.clone(), deadline)
+            .and_then(|_| context.capture_with(window, "iterate", Some(name), &iteration.settings));
+        captures.push(match result {
+            Ok(bytes) => {
+                let (width, height) = crate::format::dimensions(&bytes).unzip();
+                json!({
+                    "name": name,
+                    "url": page.as_str(),
+                    "bytes": bytes.len(),
+                    "width": width,
+                    "height": height,
+                })
+            }
+            Err(e) => json!({ "name": name, "url": page.as_str(), "error": e }),
+        });
+    }
+    let failed = captures.iter().filter(|c| c["error"].is_string()).count();
+    json!({
+        "captures": captures,
+        "failed": failed,
+        "truncated": truncated,
+    })
+}
@@ -6,8 +6,46 @@
 //! feature is enabled) that exposes:
 //!
 //! - `GET /screenshot` — native WKWebView.takeSnapshot on macOS (PNG bytes)
+//! - `GET /screenshot/analyze` — dominant colors, brightness and histogram (JSON)
+//! - `GET /screenshot/blank` — whether the frame is (near-)uniform, and its color
+//! - `POST /render` — render HTML or a URL in a hidden window and return the capture
+//! - `POST /compare-url` — compare the window with a reference URL loaded in a hidden window
+//! - `GET /watch?selector=S` — Server-Sent Events when a region of the page changes
+//! - `GET /events` — Server-Sent Events for captures, evals, window lifecycle and analyses
 //! - `POST /eval` — execute JavaScript in the webview
 //! - `POST /eval?wait=N` — execute JS, wait N ms, then return a screenshot
+//! - `GET /element?selector=S` — geometry and visibility of an element (JSON)
+//! - `GET /element/style?selector=S&props=P` — computed style values (JSON)
+//! - `GET /text?selector=S` — `innerText` of every matching element (JSON)
+//! - `GET /health` — readiness, the warm-up capture and memory use (JSON)
+//! - `GET /state` — focus, visibility, occlusion, route and time since the DOM last changed
+//! - `GET /windows` — webview window labels; `?window=<label>` targets one
+//! - `GET|POST /devtools` — read or open/close/toggle the web inspector
+//! - `POST /reload`, `POST /clear-cache` — reset page state between scenarios
+//! - `POST /replay` — re-run a session recorded with `Config::session_log`
+//! - `POST /iterate` — visit routes or Storybook stories and capture each by name
+//! - `POST /storyboard` — run interaction steps and return their captures as an animation
+//! - `POST /shutdown`, `POST /app/exit?code=` — tear down (needs `Config::auth_token`)
+//! - `GET|POST /read-only` — read or switch read-only mode (see [`Config::read_only`])
+//! - `GET|POST|DELETE /mocks` — mock `fetch`/XHR responses (see [`Config::emulation`])
+//! - `GET /har` — logged fetch/XHR calls as HAR 1.2, with the session's screenshots
+//! - `GET|POST|DELETE /emulate/network` — offline and slow-network emulation
+//! - `GET|POST|DELETE /emulate/locale` — language and time zone emulation
+//! - `GET|POST|DELETE /emulate/media` — reduced motion, contrast and forced colors
+//! - `GET|POST|DELETE /emulate/geolocation` — a fixed position for `navigator.geolocation`
+//! - `GET|POST|DELETE /emulate/permissions` — `navigator.permissions.query()` results
+//! - `GET /perf` — Navigation Timing, paint timings, long tasks and JS heap (JSON)
+//! - `POST /coverage/start`, `POST /coverage/stop` — Istanbul coverage for a test run
+//! - `GET /history` — buffered automatic captures (see [`Config::flight_recorder`]), or `?format=apng` to play them back
+//! - `GET /archive` — query the disk archive (see [`Config::archive`])
+//! - `GET /audit` — the `/eval` audit log (see [`Config::audit_log`])
+//! - `GET /bench?iterations=N` — capture latency percentiles per stage (JSON)
+//! - `GET /session` — WebDriver BiDi WebSocket (`bidi` feature)
+//! - `POST /recording/start`, `POST /recording/stop` — video through ffmpeg (`ffmpeg` feature)
+//! - `POST /webrtc`, `DELETE /webrtc/<id>` — live view over WebRTC (`webrtc` feature)
+//! - `GET /public-key` — the key captures are signed with (`signing` feature)
+//! - `GET /openapi.json` — OpenAPI 3.1 description of every endpoint
+//! - `GET /` — interactive capture UI (`ui` feature)
 //!
 //! ## Usage
 //!
@@ -30,8 +68,91 @@
 #[macro_use]
 extern crate objc;
 
+#[cfg(any(feature = "grpc", feature = "webrtc"))]
+mod adaptive;
+mod analyze;
+mod animation;
+mod archive;
+mod audit;
+mod backend;
+mod bench;
+#[cfg(feature = "bidi")]
+mod bidi;
+mod budget;
+#[cfg(feature = "client")]
+pub mod client;
+mod compare;
+mod coverage;
+mod crash;
+mod deadline;
+mod dom;
+mod emulation;
+mod events;
+mod format;
+mod gate;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod har;
+mod history;
+mod hooks;
+mod idle;
+mod iterate;
+#[cfg(feature = "webrtc")]
+mod live;
+mod logging;
+#[cfg(feature = "mdns")]
+mod mdns;
+mod openapi;
+mod options;
+mod origin;
+mod page;
+mod perf;
+mod policy;
+mod rate_limit;
+#[cfg(feature = "ffmpeg")]
+mod recording;
+mod scope;
+mod scratch;
+mod session;
+#[cfg(feature = "signing")]
+mod signing;
+mod sse;
+mod state;
+mod storyboard;
+#[cfg(feature = "tracing")]
+mod trace;
+mod transform;
+mod triggers;
+mod viewport;
+mod watch;
+mod window_state;
+
+pub use backend::{CaptureBackend, WebKitBackend};
+pub use format::{Frame, ImageFormat};
+pub use hooks::{AfterCapture, BeforeCapture, CaptureHook, Error, ErrorKind, OnError};
+pub use logging::{LogConfig, DEFAULT_LOG_TARGET};
+pub use options::{CaptureOptions, Clip, Priority, Wait};
+pub use scope::Scope;
+pub use state::{ScreenshotHd, ServerStatus, Stats, FAILED_EVENT, READY_EVENT};
+pub use transform::{Background, Channel, Flip, Rotation, Stage, StageFn};
+
+use archive::Archive;
+use audit::AuditLog;
+use base64::Engine;
+use budget::MemoryBudget;
+use bytes::Bytes;
+use events::EventBus;
+use gate::CaptureGate;
+use history::History;
+use rate_limit::RateLimiter;
+use session::SessionRecorder;
+use std::cell::Cell;
 use std::io::Read;
-use std::sync::OnceLock;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 use tauri::{
     plugin::{Builder as PluginBuilder, TauriPlugin},
     Manager, Runtime,
@@ -39,6 +160,41 @@ use tauri::{
 
 const DEFAULT_PORT: u16 = 21988;
 const DEFAULT_HOST: &str = "127.0.0.1";
+const DEFAULT_HISTORY_SIZE: usize = 20;
+const DEFAULT_MAX_BODY_BYTES: usize = 1024 * 1024;
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 64;
+/// Upper bounds for `?scale=` / `?width=`, to keep captures a sane size.
+const MAX_SCALE: f64 = 4.0;
+const MAX_WIDTH: u32 = 16384;
+const DEFAULT_MAX_CONCURRENT_CAPTURES: usize = 2;
+/// How long a capture waits for a free slot (see [`Config::max_concurrent_captures`]).
+const CAPTURE_QUEUE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// H.264 in yuv420p, which most players accept, with the index up front so
+/// browsers can start playing before the whole file has loaded.
+const DEFAULT_FFMPEG_ARGS: &[&str] = &[
+    "-c:v",
+    "libx264",
+    "-pix_fmt",
+    "yuv420p",
+    "-movflags",
+    "+faststart",
+];
+
+/// Width of the frames `wait_until=stable` compares; small enough to be
+/// cheap, large enough to see a spinner turn.
+const STABLE_PROBE_WIDTH: u32 = 320;
+/// Time between `wait_until=stable` frames.
+const STABLE_POLL: Duration = Duration::from_millis(100);
+/// Time between lookups while `?wait_for_window=` waits for the window.
+const WINDOW_POLL: Duration = Duration::from_millis(50);
+/// `Retry-After` (seconds) while the window doesn't exist yet.
+const WINDOW_RETRY_AFTER: u64 = 1;
+
+/// Whether the plugin does anything in this build: always in debug, and in
+/// release only with the `release` feature.
+const ENABLED: bool = cfg!(debug_assertions) || cfg!(feature = "release");
 
 /// Configuration for the screenshot server.
 #[derive(Debug, Clone)]
@@ -47,8 +203,376 @@ pub struct Config {
     pub host: String,
     /// Port to listen on. Default: `21988`
     pub port: u16,
+    /// How many ports after `port` to try when it's taken, e.g. `10` to
+    /// settle on the first free one up to `port + 10`. The bound address is
+    /// reported by [`ScreenshotHd::addr`], the ready event and mDNS.
+    /// Default: `0` (only `port`)
+    pub port_fallback: u16,
     /// Name of the webview window to capture. Default: `main`
     pub window_label: String,
+    /// Level and target of the plugin's own log messages, and whether eval
+    /// scripts are logged in full. Default: info and up, to
+    /// [`DEFAULT_LOG_TARGET`], scripts redacted
+    pub log: LogConfig,
+    /// Directory for crash evidence. When set, a panic hook saves a final
+    /// screenshot, the DOM and recent console output to a `crash-<ms>`
+    /// folder here before the app dies. Default: `None`
+    pub crash_dir: Option<PathBuf>,
+    /// Keep a buffer of recent console output in the page, so multipart
+    /// captures can report the console error count. Always on when
+    /// `crash_dir` is set. Default: `false`
+    pub console_buffer: bool,
+    /// Inject the emulation shim, which wraps `fetch`/`XMLHttpRequest` (and
+    /// other page APIs) so endpoints like `/mocks` can make captures
+    /// deterministic. Its state survives reloads via `sessionStorage`.
+    /// Default: `false`
+    pub emulation: bool,
+    /// Count long main-thread tasks in the page, so `GET /perf` can report
+    /// them. Default: `false`
+    pub perf_observer: bool,
+    /// Track DOM mutations and input in the page, so `GET /state` can
+    /// report how long it has been quiet. Always on when `idle_pause` or
+    /// `capture_on_mutation` is set. Default: `false`
+    pub activity_tracker: bool,
+    /// Window events that trigger an automatic capture into the history
+    /// buffer (`GET /history`). Default: none
+    pub capture_on_events: Vec<CaptureEvent>,
+    /// Number of captures kept in the history buffer; the oldest is dropped
+    /// when full. Default: `20`
+    pub history_size: usize,
+    /// Drop history entries older than this. Default: `None` (keep until
+    /// evicted by `history_size`)
+    pub history_retention: Option<Duration>,
+    /// Flight recorder: continuously capture into the history buffer at
+    /// this interval, so recent frames can be rewound. Default: `None` (off)
+    pub flight_recorder: Option<Duration>,
+    /// Capture into the history buffer whenever the page's DOM changes and
+    /// then settles, at a limited rate. Injects the activity tracker;
+    /// reading it needs macOS. Default: `None` (off)
+    pub capture_on_mutation: Option<MutationCapture>,
+    /// Write every capture to disk, with an index and retention pruning.
+    /// Default: `None` (off)
+    pub archive: Option<ArchiveConfig>,
+    /// Endpoints the server answers, e.g. `["/screenshot"]` for a
+    /// capture-only server; everything else gets 403. An entry also enables
+    /// the paths below it (`/history` covers `/history/<id>`), and BiDi's
+    /// `script.evaluate` additionally requires `/eval`. Default: `None` (all)
+    pub enabled_endpoints: Option<Vec<String>>,
+    /// Refuse, with 403, the endpoints that navigate or change the app's
+    /// state (`/eval`, `/reload`, `/clear-cache`, `/replay`, `/iterate`,
+    /// `/storyboard`, `/render`, `/compare-url`, `/recording/*`,
+    /// `/coverage/*`, starting and ending `/webrtc` views, `/shutdown`,
+    /// `/app/exit`, and changes through `/mocks`, `/emulate/*` and
+    /// `/devtools`) while capture stays available. Can be switched at
+    /// runtime with `POST /read-only` or [`ScreenshotHd::set_read_only`].
+    /// Default: `false`
+    pub read_only: bool,
+    /// Largest request body accepted (the `/eval` script); bigger uploads
+    /// get 413. Default: 1 MiB
+    pub max_body_bytes: usize,
+    /// Deadline for a whole request, from reading the body through any
+    /// `?wait=`; requests that can't finish in time get 408. Default: 30 s
+    pub request_timeout: Duration,
+    /// Requests handled at once, each on its own thread; more get 503
+    /// until one finishes. Streams (`/watch`, `/events`, `/recording`)
+    /// hold theirs for as long as they're open. Default: `64`
+    pub max_concurrent_requests: usize,
+    /// Append-only JSONL log of every `/eval` (and BiDi `script.evaluate`):
+    /// timestamp, client IP, target window and a SHA-256 of the script.
+    /// Readable at `GET /audit`. Default: `None` (off)
+    pub audit_log: Option<PathBuf>,
+    /// Record every eval, capture, reload and emulation change to this
+    /// JSONL file, for `POST /replay`. Default: `None` (off)
+    pub session_log: Option<PathBuf>,
+    /// Clients allowed to connect; everyone else gets 403. Worth setting
+    /// when binding to `0.0.0.0`. Default: `None` (any)
+    pub allowed_ips: Option<Vec<IpAddr>>,
+    /// Origins (`scheme://host[:port]`, e.g. `http://localhost:5173`) of
+    /// browser dashboards allowed to call the server; they get CORS
+    /// headers. Requests from other pages get 403, and any page must send
+    /// an `X-Screenshot-HD` header to endpoints beyond [`Scope::Capture`].
+    /// Default: empty (only the server's own pages)
+    pub allowed_origins: Vec<String>,
+    /// Per-client request rate limit; excess requests get 429 with a
+    /// `Retry-After` header. Default: `None` (unlimited)
+    pub rate_limit: Option<RateLimit>,
+    /// Bearer token every request must carry (`Authorization: Bearer
+    /// <token>`); others get 401, except the static web UI page, which
+    /// asks for it. Also enables `POST /shutdown` and
+    /// `POST /app/exit`, which are refused without it. Default: `None`
+    pub auth_token: Option<String>,
+    /// Further bearer tokens, each limited to some [`Scope`]s: a request
+    /// with a token lacking the endpoint's scope gets 403. Setting any
+    /// makes tokens required, like `auth_token`, which keeps every scope.
+    /// Default: empty
+    pub auth_tokens: Vec<AuthToken>,
+    /// Advertise the server as `_screenshot-hd._tcp` over mDNS, with the
+    /// window label and versions in TXT records. Only when `host` isn't
+    /// loopback; requires the `mdns` feature. Default: `false`
+    pub mdns: bool,
+    /// Also serve the gRPC mirror of capture, eval and streaming (described
+    /// in `proto/screenshot_hd.proto`) on this port of `host`; requires the
+    /// `grpc` feature. Default: `None` (off)
+    pub grpc_port: Option<u16>,
+    /// Enable `POST /recording/start` and `/recording/stop`, which pipe
+    /// captures into an external `ffmpeg` to make videos; requires the
+    /// `ffmpeg` feature. Default: `None` (off)
+    pub ffmpeg: Option<FfmpegConfig>,
+    /// Sign every capture the server returns with Ed25519, for tamper
+    /// evidence, and serve the public key at `GET /public-key`; requires
+    /// the `signing` feature. Default: `None` (off)
+    pub signing: Option<SigningConfig>,
+    /// Guardrails for scripts run through `/eval` and the other remote eval
+    /// paths (gRPC, BiDi, storyboards, replays): globals they may not use,
+    /// a length limit and a time limit. Scripts then run inside a function,
+    /// so their top-level `var`, `let`, `const`, `function` and `class`
+    /// declarations don't outlive them; assign to `window.name` to leave a
+    /// global for later scripts. Default: `None` (unrestricted)
+    pub eval_policy: Option<EvalPolicy>,
+    /// Pause streams (gRPC `Stream`, `/recording`, `/webrtc`) once the page
+    /// has had no DOM mutations or input for this long, until it does
+    /// again, noting the gap. Injects an activity tracker into the page;
+    /// reading it needs macOS, elsewhere streams never pause.
+    /// Default: `None` (off)
+    pub idle_pause: Option<Duration>,
+    /// Image format captures are encoded in, including automatic ones.
+    /// Overridable per request with `?format=`. Default: [`ImageFormat::Png`]
+    pub format: ImageFormat,
+    /// Color space captures are converted to. Overridable per request with
+    /// `?color_space=`. Default: [`ColorSpace::Native`]
+    pub color_space: ColorSpace,
+    /// Embed the capture's ICC profile in the PNG, so viewers and diff
+    /// tools know how to interpret it. Overridable per request with
+    /// `?icc=true|false`. Default: `false`
+    pub embed_icc_profile: bool,
+    /// Output scale relative to CSS pixels, e.g. `1.0` for 1x captures on a
+    /// Retina display (to match baselines from non-Retina machines).
+    /// Overridable per request with `?scale=`. Default: `None` (the
+    /// display's backing scale)
+    pub scale: Option<f64>,
+    /// Captures allowed to run at once; further ones queue. Each holds a
+    /// full decoded frame until it's encoded, so this bounds peak memory
+    /// under concurrent use. Default: `2`
+    pub max_concurrent_captures: usize,
+    /// Bytes the plugin may hold at once for captures in flight (each
+    /// charged its decoded frame size), the history buffer and frames
+    /// queued for `/recording` and `/webrtc`. A capture that wouldn't fit
+    /// fails, history drops its oldest entries to make room, and streams
+    /// skip frames. Default: `None` (no limit)
+    pub memory_budget: Option<usize>,
+    /// Take a throwaway capture once the window appears. The first
+    /// snapshot after launch is 5–10x slower than later ones; with this
+    /// it's out of the way before the ready event and
+    /// [`ScreenshotHd::wait_ready`] report the plugin ready. Its progress
+    /// is in `GET /health`. Default: `false`
+    pub warmup: bool,
+    /// Exit the app (status 1) unless, within this long of setup, the
+    /// server is listening, the window exists and a trial capture of it
+    /// succeeds — for test-harness builds, where a plugin that came up
+    /// degraded is worse than no app at all. The trial capture is the
+    /// [`warmup`](Self::warmup) one, taken whether or not that's set. Setup
+    /// itself can't wait: creating the window and capturing it both need
+    /// the main thread. Default: `None`
+    pub block_until_ready: Option<Duration>,
+    /// Called right before every capture, e.g. to pause animations or hide
+    /// dev overlays. Default: `None`
+    pub on_before_capture: Option<CaptureHook<BeforeCapture>>,
+    /// Called after every capture with the encoded image or the error.
+    /// Default: `None`
+    pub on_after_capture: Option<CaptureHook<AfterCapture>>,
+    /// Called for every failed capture or eval, whichever endpoint, stream
+    /// or API call it came from, e.g. to forward it to error tracking.
+    /// Default: `None`
+    pub on_error: Option<CaptureHook<OnError>>,
+    /// Post-processing stages applied, in order, to every capture before
+    /// it's encoded. Replaced per request by `?pipeline=`; `?thumbnail=`,
+    /// `?grayscale=` and `?channel=` append to it. Default: none
+    pub pipeline: Vec<Stage>,
+    /// Renders captures instead of the native WKWebView snapshot, e.g. an
+    /// offscreen renderer for deterministic CI output. Default: `None`
+    pub backend: Option<Arc<dyn CaptureBackend>>,
+}
+
+/// Color space of captured images (see [`Config::color_space`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorSpace {
+    /// Whatever the display uses — Display P3 on most recent Macs.
+    #[default]
+    Native,
+    /// Convert to sRGB, e.g. to diff against baselines taken on sRGB
+    /// monitors.
+    Srgb,
+    /// Convert to Display P3.
+    DisplayP3,
+}
+
+impl ColorSpace {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "native" => Some(Self::Native),
+            "srgb" => Some(Self::Srgb),
+            "p3" | "display-p3" => Some(Self::DisplayP3),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "client")]
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Native => "native",
+            Self::Srgb => "srgb",
+            Self::DisplayP3 => "p3",
+        }
+    }
+}
+
+/// Token-bucket rate limit, applied per client IP (see [`Config::rate_limit`]).
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    /// Sustained requests per second; at least `0.001`.
+    pub per_second: f64,
+    /// Requests a client may make in a burst before being limited.
+    pub burst: u32,
+}
+
+/// A bearer token and what it may do (see [`Config::auth_tokens`]).
+#[derive(Debug, Clone)]
+pub struct AuthToken {
+    /// The token, as sent in `Authorization: Bearer <token>`.
+    pub token: String,
+    /// Endpoints the token may use.
+    pub scopes: Vec<Scope>,
+}
+
+impl AuthToken {
+    /// `token`, allowed `scopes`.
+    pub fn new(token: impl Into<String>, scopes: impl IntoIterator<Item = Scope>) -> Self {
+        Self {
+            token: token.into(),
+            scopes: scopes.into_iter().collect(),
+        }
+    }
+}
+
+/// Disk archive settings (see [`Config::archive`]).
+#[derive(Debug, Clone)]
+pub struct ArchiveConfig {
+    /// Directory captures and `index.jsonl` are written to.
+    pub dir: PathBuf,
+    /// Delete captures older than this. Checked at most once a minute.
+    pub max_age: Option<Duration>,
+    /// Delete the oldest captures once the archive grows past this many
+    /// bytes (pruning down to 90% of it).
+    pub max_bytes: Option<u64>,
+}
+
+/// Restrictions on remote scripts (see [`Config::eval_policy`]).
+///
+/// Scripts run in a wrapper that checks every global they look up, which
+/// stops mistakes rather than a determined script: APIs stay reachable
+/// through allowed objects (`document.defaultView.fetch`).
+#[derive(Debug, Clone, Default)]
+pub struct EvalPolicy {
+    /// Globals scripts may not read or assign, e.g. `fetch`,
+    /// `XMLHttpRequest` or `localStorage`.
+    pub deny_globals: Vec<String>,
+    /// When set, the only globals scripts may use (`document`, `JSON`,
+    /// ...); `deny_globals` still applies on top.
+    pub allow_globals: Option<Vec<String>>,
+    /// Longest script accepted, in bytes; longer ones get 413.
+    pub max_script_len: Option<usize>,
+    /// Time after which the script throws at its next global lookup, so a
+    /// runaway script stops instead of hanging the page.
+    pub timeout: Option<Duration>,
+}
+
+/// Recording settings (see [`Config::ffmpeg`]).
+#[derive(Debug, Clone)]
+pub struct FfmpegConfig {
+    /// The ffmpeg executable. Default: `ffmpeg`, looked up on `PATH`
+    pub program: PathBuf,
+    /// Output arguments, between the raw RGBA input ffmpeg is given and the
+    /// output file. Default: H.264 in yuv420p
+    pub args: Vec<String>,
+    /// Extension of the output files, which also tells ffmpeg the
+    /// container. Default: `mp4`
+    pub extension: String,
+    /// Directory recordings are written to. Default:
+    /// `screenshot-hd-recordings` in the system temp directory
+    pub dir: PathBuf,
+}
+
+impl Default for FfmpegConfig {
+    fn default() -> Self {
+        Self {
+            program: PathBuf::from("ffmpeg"),
+            args: DEFAULT_FFMPEG_ARGS.iter().map(|a| a.to_string()).collect(),
+            extension: "mp4".to_string(),
+            dir: std::env::temp_dir().join("screenshot-hd-recordings"),
+        }
+    }
+}
+
+/// Capture signing settings (see [`Config::signing`]).
+#[derive(Debug, Clone, Default)]
+pub struct SigningConfig {
+    /// Ed25519 secret key (its 32-byte seed). Default: `None`, a new key
+    /// each start, logged and served at `GET /public-key`
+    pub key: Option<[u8; 32]>,
+    /// Also embed the signature in PNG captures as a `tEXt` chunk, so it
+    /// stays with the file once saved. Default: `false`
+    pub embed_in_png: bool,
+}
+
+/// Captures on DOM changes (see [`Config::capture_on_mutation`]).
+#[derive(Debug, Clone, Copy)]
+pub struct MutationCapture {
+    /// How long the DOM must be unchanged before the change is captured,
+    /// so a burst of updates yields one capture of the result.
+    /// Default: 500 ms
+    pub debounce: Duration,
+    /// Least time between two captures; changes in between are captured
+    /// once it has passed. Default: 2 s
+    pub min_interval: Duration,
+}
+
+impl Default for MutationCapture {
+    fn default() -> Self {
+        Self {
+            debounce: Duration::from_millis(500),
+            min_interval: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Window events that can trigger an automatic capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureEvent {
+    /// The window was resized. Captured once the resize settles, so a drag
+    /// yields a single capture.
+    Resized,
+    /// The window gained focus.
+    Focused,
+    /// The window lost focus.
+    Blurred,
+    /// The system theme changed.
+    ThemeChanged,
+    /// The page's route changed: a navigation, `history.pushState` or
+    /// `replaceState`, or a new fragment. Captured once the route has
+    /// held for a moment, under the route as its name.
+    RouteChanged,
+}
+
+impl CaptureEvent {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Resized => "resized",
+            Self::Focused => "focused",
+            Self::Blurred => "blurred",
+            Self::ThemeChanged => "theme-changed",
+            Self::RouteChanged => "route-changed",
+        }
+    }
 }
 
 impl Default for Config {
@@ -56,9 +580,534 @@ impl Default for Config {
         Self {
             host: DEFAULT_HOST.to_string(),
             port: DEFAULT_PORT,
+            port_fallback: 0,
             window_label: "main".to_string(),
+            log: LogConfig::default(),
+            crash_dir: None,
+            console_buffer: false,
+            emulation: false,
+            perf_observer: false,
+            activity_tracker: false,
+            capture_on_events: Vec::new(),
+            history_size: DEFAULT_HISTORY_SIZE,
+            history_retention: None,
+            flight_recorder: None,
+            capture_on_mutation: None,
+            archive: None,
+            enabled_endpoints: None,
+            read_only: false,
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            max_concurrent_requests: DEFAULT_MAX_CONCURRENT_REQUESTS,
+            audit_log: None,
+            session_log: None,
+            allowed_ips: None,
+            allowed_origins: Vec::new(),
+            rate_limit: None,
+            auth_token: None,
+            auth_tokens: Vec::new(),
+            mdns: false,
+            grpc_port: None,
+            ffmpeg: None,
+            signing: None,
+            eval_policy: None,
+            idle_pause: None,
+            format: ImageFormat::Png,
+            color_space: ColorSpace::Native,
+            embed_icc_profile: false,
+            scale: None,
+            max_concurrent_captures: DEFAULT_MAX_CONCURRENT_CAPTURES,
+            memory_budget: None,
+            warmup: false,
+            block_until_ready: None,
+            on_before_capture: None,
+            on_after_capture: None,
+            on_error: None,
+            pipeline: Vec::new(),
+            backend: None,
+        }
+    }
+}
+
+/// State shared by the HTTP server and automatic captures.
+pub(crate) struct Context {
+    pub(crate) history: History,
+    pub(crate) archive: Option<Archive>,
+    pub(crate) audit: Option<AuditLog>,
+    pub(crate) recorder: Option<SessionRecorder>,
+    pub(crate) enabled_endpoints: Option<Vec<String>>,
+    /// See [`Config::read_only`].
+    pub(crate) read_only: AtomicBool,
+    pub(crate) max_body_bytes: usize,
+    pub(crate) request_timeout: Duration,
+    pub(crate) max_concurrent_requests: usize,
+    /// Threads serving requests, see [`RequestSlot`].
+    pub(crate) active_requests: AtomicUsize,
+    pub(crate) allowed_ips: Option<Vec<IpAddr>>,
+    pub(crate) allowed_origins: Vec<String>,
+    pub(crate) rate_limiter: Option<RateLimiter>,
+    pub(crate) auth_token: Option<String>,
+    pub(crate) auth_tokens: Vec<AuthToken>,
+    /// Set by `POST /shutdown`; the server loop exits when it sees it.
+    pub(crate) shutdown: AtomicBool,
+    pub(crate) capture_defaults: CaptureOptions,
+    pub(crate) capture_gate: CaptureGate,
+    pub(crate) memory: Arc<MemoryBudget>,
+    /// Feeds `GET /events`.
+    pub(crate) events: EventBus,
+    pub(crate) before_capture: Option<CaptureHook<BeforeCapture>>,
+    pub(crate) after_capture: Option<CaptureHook<AfterCapture>>,
+    pub(crate) on_error: Option<CaptureHook<OnError>>,
+    pub(crate) backend: Option<Arc<dyn CaptureBackend>>,
+    #[cfg(feature = "ffmpeg")]
+    pub(crate) video_recorder: Option<recording::VideoRecorder>,
+    #[cfg(feature = "webrtc")]
+    pub(crate) live: live::LiveSessions,
+    #[cfg(feature = "signing")]
+    pub(crate) signer: Option<signing::Signer>,
+    /// See [`Config::idle_pause`].
+    pub(crate) idle_pause: Option<Duration>,
+    pub(crate) eval_policy: Option<EvalPolicy>,
+    /// The server's address, once bound.
+    pub(crate) addr: OnceLock<SocketAddr>,
+    /// Why the server isn't running, if it failed.
+    pub(crate) failure: OnceLock<String>,
+    pub(crate) counters: state::Counters,
+    pub(crate) readiness: state::Readiness,
+    pub(crate) warmup: state::Warmup,
+    /// When the plugin was set up, for how long a request has been kept
+    /// waiting on a window that isn't there yet.
+    pub(crate) started: Instant,
+}
+
+impl Context {
+    /// Whether `ip` may connect, per [`Config::allowed_ips`].
+    fn ip_allowed(&self, ip: Option<IpAddr>) -> bool {
+        let Some(allowed) = &self.allowed_ips else {
+            return true;
+        };
+        // IPv4 clients of a dual-stack socket show up as `::ffff:a.b.c.d`
+        ip.is_some_and(|ip| {
+            allowed
+                .iter()
+                .any(|a| a.to_canonical() == ip.to_canonical())
+        })
+    }
+
+    /// Whether requests need a bearer token ([`Config::auth_token`] or
+    /// [`Config::auth_tokens`]).
+    pub(crate) fn auth_required(&self) -> bool {
+        self.auth_token.is_some() || !self.auth_tokens.is_empty()
+    }
+
+    /// Check that the bearer token `given` grants `scope`: 401 without a
+    /// known token, 403 when it lacks the scope. Anything goes while no
+    /// token is configured.
+    pub(crate) fn authorize(&self, given: Option<&str>, scope: Scope) -> Result<(), (u16, String)> {
+        if !self.auth_required() {
+            return Ok(());
+        }
+        let scopes = given
+            .and_then(|given| self.token_scopes(given))
+            .ok_or((401, "missing or invalid bearer token".to_string()))?;
+        if !scopes.contains(&scope) {
+            return Err((403, format!("the token doesn't have the {scope} scope")));
+        }
+        Ok(())
+    }
+
+    /// The scopes of the configured token `given` is, if any.
+    fn token_scopes(&self, given: &str) -> Option<&[Scope]> {
+        use sha2::{Digest, Sha256};
+
+        // Compare digests so the comparison time doesn't depend on how much
+        // of the token was guessed right
+        let given = Sha256::digest(given.trim());
+        if self
+            .auth_token
+            .as_ref()
+            .is_some_and(|token| given == Sha256::digest(token))
+        {
+            return Some(&Scope::ALL);
+        }
+        self.auth_tokens
+            .iter()
+            .find(|t| given == Sha256::digest(&t.token))
+            .map(|t| t.scopes.as_slice())
+    }
+
+    /// Whether `path` is allowed by [`Config::enabled_endpoints`]. An entry
+    /// also enables the paths below it (`/history` covers `/history/<id>`).
+    pub(crate) fn endpoint_enabled(&self, path: &str) -> bool {
+        let Some(enabled) = &self.enabled_endpoints else {
+            return true;
+        };
+        enabled.iter().any(|e| match e.trim_end_matches('/') {
+            "" => path == "/",
+            e => path == e || path.strip_prefix(e).is_some_and(|rest| rest.starts_with('/')),
+        })
+    }
+
+    /// Whether read-only mode refuses `method` on `path`, i.e. it's on and
+    /// the request would navigate or change the app's state: anything
+    /// beyond [`Scope::Capture`] (so `/render`, `/compare-url`, recording,
+    /// WebRTC and coverage too) except reading the audit log, switching
+    /// read-only mode itself, and BiDi sessions, which only lose
+    /// `script.evaluate`.
+    pub(crate) fn read_only_blocks(&self, method: &tiny_http::Method, path: &str) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+            && Scope::of(method, path) != Scope::Capture
+            && !matches!(path, "/audit" | "/read-only" | "/session")
+    }
+
+    /// Sign `image`, a capture of `window` just taken, if
+    /// [`Config::signing`] is set: the image, with the signature embedded
+    /// if configured, and the signature.
+    #[cfg(feature = "signing")]
+    pub(crate) fn sign<R: Runtime>(
+        &self,
+        image: Bytes,
+        window: &tauri::WebviewWindow<R>,
+    ) -> (Bytes, Option<signing::Signature>) {
+        let Some(signer) = &self.signer else {
+            return (image, None);
+        };
+        let url = window.url().map(|u| u.to_string()).ok();
+        let (image, signature) = signer.sign(image, window.label(), url);
+        (image, Some(signature))
+    }
+
+    /// Capture `window` with the configured settings, archiving the result.
+    /// `source` records what asked for it (`screenshot`, `flight-recorder`, …).
+    pub(crate) fn capture<R: Runtime>(
+        &self,
+        window: &tauri::WebviewWindow<R>,
+        source: &str,
+    ) -> Result<Bytes, String> {
+        self.capture_with(window, source, None, &self.capture_defaults)
+    }
+
+    /// Like [`Context::capture`], with per-request settings. Captures taken
+    /// under a test `name` are also kept in the history buffer, sharing the
+    /// returned bytes rather than a copy.
+    pub(crate) fn capture_with<R: Runtime>(
+        &self,
+        window: &tauri::WebviewWindow<R>,
+        source: &str,
+        name: Option<&str>,
+        settings: &CaptureOptions,
+    ) -> Result<Bytes, String> {
+        let result = self.snapshot(window, settings);
+        self.counters.record(&result);
+        let png = Bytes::from(result?);
+        if let Some(archive) = &self.archive {
+            archive.record(source, name, &png);
+        }
+        if name.is_some() {
+            self.history.push(source, name, png.clone());
+        }
+        let dimensions = format::dimensions(&png);
+        self.events.publish(
+            "capture",
+            serde_json::json!({
+                "source": source,
+                "name": name,
+                "window": window.label(),
+                "format": ImageFormat::detect(&png).unwrap_or_default().extension(),
+                "bytes": png.len(),
+                "width": dimensions.map(|(w, _)| w),
+                "height": dimensions.map(|(_, h)| h),
+            }),
+        );
+        Ok(png)
+    }
+
+    /// Capture without archiving or keeping the result, for polling.
+    pub(crate) fn snapshot<R: Runtime>(
+        &self,
+        window: &tauri::WebviewWindow<R>,
+        settings: &CaptureOptions,
+    ) -> Result<Vec<u8>, String> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!(
+            "screenshot_hd.capture",
+            window = window.label(),
+            format = settings.format.extension(),
+        )
+        .entered();
+        let deadline = settings.deadline;
+        if let Wait::Delay(delay) = settings.wait {
+            deadline::sleep(deadline, delay, "wait")
+                .inspect_err(|e| self.report(ErrorKind::Capture, window, e))?;
+        }
+        let _permit = deadline::remaining(deadline, CAPTURE_QUEUE_TIMEOUT, "queue")
+            .and_then(|timeout| {
+                self.capture_gate
+                    .acquire(timeout, settings.priority)
+                    .map_err(|e| deadline::attribute(deadline, "queue", e))
+            })
+            .inspect_err(|e| self.report(ErrorKind::Capture, window, e))?;
+        let _charge = self
+            .memory
+            .reserve(frame_bytes(window, settings), "the capture")
+            .inspect_err(|e| self.report(ErrorKind::Capture, window, e))?;
+        if let Some(hook) = &self.before_capture {
+            hook.run(window);
+        }
+        let render = || {
+            // After a resize, so it's framed in the viewport being captured
+            let framed = framed(window, settings)?;
+            let settings = framed.as_ref().unwrap_or(settings);
+            if let Wait::Stable(timeout) = settings.wait {
+                let timeout = deadline::remaining(deadline, timeout, "stable")?;
+                self.wait_stable(window, settings, timeout)
+                    .map_err(|e| deadline::attribute(deadline, "stable", e))?;
+            }
+            if settings.show_cursor {
+                return self.render(window, &with_cursor(window, settings)?);
+            }
+            self.render(window, settings)
+        };
+        let result = match settings.size {
+            Some(size) => viewport::with_size(window, size, settings.restore, deadline, render),
+            None => render(),
+        };
+        if let Some(hook) = &self.after_capture {
+            hook.run(window, &result);
         }
+        result.inspect_err(|e| self.report(ErrorKind::Capture, window, e))
+    }
+
+    /// Run `script` in `window` under the eval policy, without waiting for
+    /// it.
+    pub(crate) fn eval<R: Runtime>(
+        &self,
+        window: &tauri::WebviewWindow<R>,
+        script: &str,
+    ) -> Result<(), String> {
+        logging::debug!(
+            "[screenshot-hd] eval in '{}': {}",
+            window.label(),
+            logging::body(script)
+        );
+        let wrapped;
+        let script = match &self.eval_policy {
+            Some(policy) => {
+                wrapped = policy
+                    .wrap(script)
+                    .inspect_err(|e| self.report(ErrorKind::Eval, window, e))?;
+                &wrapped
+            }
+            None => script,
+        };
+        window
+            .eval(script)
+            .map_err(|e| format!("eval error: {e}"))
+            .inspect_err(|e| self.report(ErrorKind::Eval, window, e))
+    }
+
+    /// Pass a failure to [`Config::on_error`].
+    pub(crate) fn report<R: Runtime>(
+        &self,
+        kind: ErrorKind,
+        window: &tauri::WebviewWindow<R>,
+        message: &str,
+    ) {
+        if let Some(hook) = &self.on_error {
+            hook.run(&Error {
+                kind,
+                window: window.label().to_string(),
+                message: message.to_string(),
+            });
+        }
+    }
+
+    /// Take cheap low-resolution frames (of the same clip) until two in a
+    /// row are identical, i.e. animations, transitions and late renders have
+    /// finished, or fail after `timeout`.
+    fn wait_stable<R: Runtime>(
+        &self,
+        window: &tauri::WebviewWindow<R>,
+        settings: &CaptureOptions,
+        timeout: Duration,
+    ) -> Result<(), String> {
+        use sha2::{Digest, Sha256};
+
+        let probe = CaptureOptions {
+            format: ImageFormat::Qoi,
+            width: Some(STABLE_PROBE_WIDTH),
+            size: None,
+            wait: Wait::None,
+            pipeline: Vec::new(),
+            ..settings.clone()
+        };
+        let deadline = Instant::now() + timeout;
+        let mut previous = None;
+        loop {
+            let hash = Sha256::digest(self.render(window, &probe)?);
+            if previous == Some(hash) {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(format!("the page didn't settle within {timeout:?}"));
+            }
+            previous = Some(hash);
+            std::thread::sleep(STABLE_POLL);
+        }
+    }
+
+    /// Capture with the configured backend, or natively.
+    fn render<R: Runtime>(
+        &self,
+        window: &tauri::WebviewWindow<R>,
+        settings: &CaptureOptions,
+    ) -> Result<Vec<u8>, String> {
+        LAST_TIMINGS.set(None);
+        self.backend
+            .as_deref()
+            .and_then(|backend| backend::capture(backend, window, settings))
+            .unwrap_or_else(|| {
+                let (png, timings) = take_screenshot_timed(window, settings)?;
+                LAST_TIMINGS.set(Some(timings));
+                Ok(png)
+            })
+    }
+}
+
+/// Roughly the memory a capture of `window` with `settings` holds at its
+/// peak: the decoded frame plus the largest frame the pipeline makes from
+/// it.
+fn frame_bytes<R: Runtime>(window: &tauri::WebviewWindow<R>, settings: &CaptureOptions) -> usize {
+    let (width, height) = frame_size(window, settings);
+    (width as usize * height as usize * 4)
+        .saturating_add(transform::peak_bytes(width, height, settings))
+}
+
+/// `settings` narrowed to `max_width` pixels where a capture would be
+/// wider, for a stream's `?max_width=`.
+pub(crate) fn capped<R: Runtime>(
+    window: &tauri::WebviewWindow<R>,
+    settings: &CaptureOptions,
+    max_width: Option<u32>,
+) -> CaptureOptions {
+    let mut settings = settings.clone();
+    if let Some(max_width) = max_width {
+        if frame_size(window, &settings).0 > max_width {
+            settings.width = Some(max_width);
+        }
+    }
+    settings
+}
+
+/// The pixel size of a capture of `window` with `settings` before the
+/// pipeline, worked out the way `snapshot_configuration` does from the
+/// (framed) clip, width and scale.
+fn frame_size<R: Runtime>(
+    window: &tauri::WebviewWindow<R>,
+    settings: &CaptureOptions,
+) -> (u32, u32) {
+    let backing = window.scale_factor().unwrap_or(1.0);
+    let viewport = match settings.size {
+        Some((width, height)) => (f64::from(width), f64::from(height)),
+        None => window.inner_size().map_or((0.0, 0.0), |size| {
+            let size = size.to_logical::<f64>(backing);
+            (size.width, size.height)
+        }),
+    };
+    let clip = framed_clip(settings, viewport);
+    let (css_width, css_height) = clip.map_or(viewport, |clip| (clip.width, clip.height));
+    let width = match (settings.width, settings.scale) {
+        (Some(width), _) => f64::from(width),
+        (None, scale) => css_width * scale.unwrap_or(backing),
+    };
+    let height = if css_width > 0.0 {
+        width * css_height / css_width
+    } else {
+        0.0
+    };
+    (width.round() as u32, height.round() as u32)
+}
+
+/// `settings` with the clip grown by its padding and brought to its aspect
+/// ratio in the current viewport, or `None` if it has neither.
+fn framed<R: Runtime>(
+    window: &tauri::WebviewWindow<R>,
+    settings: &CaptureOptions,
+) -> Result<Option<CaptureOptions>, String> {
+    if settings.padding <= 0.0 && settings.aspect.is_none() {
+        return Ok(None);
+    }
+    let scale_factor = window.scale_factor().map_err(|e| e.to_string())?;
+    let viewport = window
+        .inner_size()
+        .map_err(|e| e.to_string())?
+        .to_logical::<f64>(scale_factor);
+    Ok(Some(CaptureOptions {
+        clip: framed_clip(settings, (viewport.width, viewport.height)),
+        padding: 0.0,
+        aspect: None,
+        ..settings.clone()
+    }))
+}
+
+/// The clip of `settings`, grown by its padding and brought to its aspect
+/// ratio in a `viewport` of this CSS size.
+fn framed_clip(settings: &CaptureOptions, viewport: (f64, f64)) -> Option<Clip> {
+    if settings.padding <= 0.0 && settings.aspect.is_none() {
+        return settings.clip;
     }
+    let region = settings.clip.unwrap_or(Clip {
+        x: 0.0,
+        y: 0.0,
+        width: viewport.0,
+        height: viewport.1,
+    });
+    Some(region.framed(settings.padding, settings.aspect, viewport))
+}
+
+/// `settings` with a [`Stage::Cursor`] at the mouse pointer, in output
+/// pixels of the captured viewport or clip, ahead of the pipeline. Left as
+/// they are when the pointer is outside it.
+fn with_cursor<R: Runtime>(
+    window: &tauri::WebviewWindow<R>,
+    settings: &CaptureOptions,
+) -> Result<CaptureOptions, String> {
+    let scale_factor = window.scale_factor().map_err(|e| e.to_string())?;
+    let cursor = window.cursor_position().map_err(|e| e.to_string())?;
+    let origin = window.inner_position().map_err(|e| e.to_string())?;
+    let viewport = window
+        .inner_size()
+        .map_err(|e| e.to_string())?
+        .to_logical::<f64>(scale_factor);
+    // Pointer position in CSS pixels of the viewport
+    let x = (cursor.x - f64::from(origin.x)) / scale_factor;
+    let y = (cursor.y - f64::from(origin.y)) / scale_factor;
+
+    let region = settings.clip.unwrap_or(Clip {
+        x: 0.0,
+        y: 0.0,
+        width: viewport.width,
+        height: viewport.height,
+    });
+    let (x, y) = (x - region.x, y - region.y);
+    let mut settings = settings.clone();
+    if !(0.0..region.width).contains(&x) || !(0.0..region.height).contains(&y) {
+        return Ok(settings);
+    }
+    let scale = settings
+        .width
+        .map(|width| f64::from(width) / region.width)
+        .or(settings.scale)
+        .unwrap_or(scale_factor);
+    settings.pipeline.insert(
+        0,
+        Stage::Cursor {
+            x: (x * scale) as u32,
+            y: (y * scale) as u32,
+            scale,
+        },
+    );
+    Ok(settings)
 }
 
 /// Initialize the plugin with default config.
@@ -70,28 +1119,246 @@ pub fn init<R: Runtime>() -> TauriPlugin<R> {
 
 /// Initialize the plugin with custom config.
 pub fn init_with<R: Runtime>(config: Config) -> TauriPlugin<R> {
-    PluginBuilder::new("screenshot-hd")
+    logging::init(&config.log);
+    let mut builder = PluginBuilder::new("screenshot-hd");
+    let memory = Arc::new(MemoryBudget::new(config.memory_budget));
+    let context = Arc::new(Context {
+        history: History::new(
+            config.history_size,
+            config.history_retention,
+            memory.clone(),
+        ),
+        archive: config.archive.clone().filter(|_| ENABLED).map(Archive::open),
+        audit: config.audit_log.clone().filter(|_| ENABLED).map(AuditLog::open),
+        recorder: config.session_log.clone().filter(|_| ENABLED).map(SessionRecorder::open),
+        enabled_endpoints: config.enabled_endpoints.clone(),
+        read_only: AtomicBool::new(config.read_only),
+        max_body_bytes: config.max_body_bytes,
+        request_timeout: config.request_timeout,
+        max_concurrent_requests: config.max_concurrent_requests.max(1),
+        active_requests: AtomicUsize::new(0),
+        allowed_ips: config.allowed_ips.clone(),
+        allowed_origins: config.allowed_origins.clone(),
+        rate_limiter: config.rate_limit.map(RateLimiter::new),
+        auth_token: config.auth_token.clone(),
+        auth_tokens: config.auth_tokens.clone(),
+        shutdown: AtomicBool::new(false),
+        capture_defaults: CaptureOptions {
+            format: config.format,
+            color_space: config.color_space,
+            embed_icc_profile: config.embed_icc_profile,
+            scale: config.scale,
+            pipeline: config.pipeline.clone(),
+            ..Default::default()
+        },
+        capture_gate: CaptureGate::new(config.max_concurrent_captures),
+        memory,
+        events: EventBus::new(),
+        before_capture: config.on_before_capture.clone(),
+        after_capture: config.on_after_capture.clone(),
+        on_error: config.on_error.clone(),
+        backend: config.backend.clone(),
+        #[cfg(feature = "ffmpeg")]
+        video_recorder: config.ffmpeg.clone().map(recording::VideoRecorder::new),
+        #[cfg(feature = "webrtc")]
+        live: Default::default(),
+        #[cfg(feature = "signing")]
+        signer: config.signing.as_ref().and_then(|signing| {
+            signing::Signer::new(signing)
+                .inspect_err(|e| logging::error!("[screenshot-hd] {e}; captures won't be signed"))
+                .ok()
+        }),
+        idle_pause: config.idle_pause,
+        eval_policy: config.eval_policy.clone(),
+        addr: OnceLock::new(),
+        failure: OnceLock::new(),
+        counters: Default::default(),
+        readiness: Default::default(),
+        warmup: state::Warmup::new(config.warmup || config.block_until_ready.is_some()),
+        started: Instant::now(),
+    });
+
+    // Don't wrap the app's console unless something reads the buffer (and
+    // the plugin is active at all).
+    if ENABLED && (config.console_buffer || config.crash_dir.is_some()) {
+        builder = builder.js_init_script(crash::CONSOLE_BUFFER_JS);
+    }
+
+    if ENABLED && config.emulation {
+        builder = builder.js_init_script(emulation::SHIM_JS);
+    }
+
+    if ENABLED && config.perf_observer {
+        builder = builder.js_init_script(perf::OBSERVER_JS);
+    }
+
+    let track_activity = config.activity_tracker
+        || config.idle_pause.is_some()
+        || config.capture_on_mutation.is_some();
+    if ENABLED && track_activity {
+        builder = builder.js_init_script(idle::ACTIVITY_JS);
+    }
+
+    if ENABLED {
+        builder = builder.on_event({
+            let context = context.clone();
+            move |_app, event| context.events.publish_run_event(event)
+        });
+
+        builder = builder.on_webview_ready({
+            let label = config.window_label.clone();
+            let context = context.clone();
+            move |webview| {
+                if webview.label() == label {
+                    state::check_ready(webview.app_handle(), &label, &context);
+                }
+            }
+        });
+
+        let label = config.window_label.clone();
+        let events = config.capture_on_events.clone();
+        let context = context.clone();
+        builder = builder.on_window_ready(move |window| {
+            context.events.publish(
+                "window",
+                serde_json::json!({ "window": window.label(), "event": "created" }),
+            );
+            if window.label() == label && !events.is_empty() {
+                triggers::watch_window_events(window, events.clone(), context.clone());
+            }
+        });
+    }
+
+    builder
         .setup(move |app, _api| {
             // Respect debug-only default: skip in release unless feature is set
-            #[cfg(not(feature = "release"))]
-            if !cfg!(debug_assertions) {
+            if !ENABLED {
                 return Ok(());
             }
 
+            app.manage(ScreenshotHd::new(
+                app.clone(),
+                config.window_label.clone(),
+                context.clone(),
+            ));
+            if let Some(timeout) = config.block_until_ready {
+                state::require_ready(
+                    ScreenshotHd::new(app.clone(), config.window_label.clone(), context.clone()),
+                    timeout,
+                );
+            }
+
+            if let Some(dir) = config.crash_dir.clone() {
+                crash::install(app.clone(), config.window_label.clone(), dir);
+            }
+
+            if let Some(mutation) = config.capture_on_mutation {
+                triggers::run_mutation_captures(
+                    app.clone(),
+                    config.window_label.clone(),
+                    mutation,
+                    context.clone(),
+                );
+            }
+
+            if let Some(interval) = config.flight_recorder {
+                triggers::run_flight_recorder(
+                    app.clone(),
+                    config.window_label.clone(),
+                    interval,
+                    context.clone(),
+                );
+            }
+
+            #[cfg(not(feature = "mdns"))]
+            if config.mdns {
+                logging::warn!("[screenshot-hd] Config::mdns is set but the `mdns` feature isn't");
+            }
+            #[cfg(feature = "mdns")]
+            let package = app.package_info().clone();
+
+            #[cfg(not(feature = "ffmpeg"))]
+            if config.ffmpeg.is_some() {
+                logging::warn!(
+                    "[screenshot-hd] Config::ffmpeg is set but the `ffmpeg` feature isn't"
+                );
+            }
+
+            #[cfg(not(feature = "signing"))]
+            if config.signing.is_some() {
+                logging::warn!(
+                    "[screenshot-hd] Config::signing is set but the `signing` feature isn't"
+                );
+            }
+
+            #[cfg(not(feature = "grpc"))]
+            if config.grpc_port.is_some() {
+                logging::warn!(
+                    "[screenshot-hd] Config::grpc_port is set but the `grpc` feature isn't"
+                );
+            }
+            #[cfg(feature = "grpc")]
+            if let Some(port) = config.grpc_port {
+                grpc::serve(
+                    app.clone(),
+                    config.window_label.clone(),
+                    context.clone(),
+                    &config.host,
+                    port,
+                );
+            }
+
             let app_handle = app.clone();
-            let addr = format!("{}:{}", config.host, config.port);
 
             std::thread::spawn(move || {
-                let server = match tiny_http::Server::http(&addr) {
+                let server = match bind(&config.host, config.port, config.port_fallback) {
                     Ok(s) => s,
                     Err(e) => {
-                        log::warn!("[screenshot-hd] failed to start on {addr}: {e}");
+                        state::fail(&app_handle, &context, e);
                         return;
                     }
                 };
-                log::info!("[screenshot-hd] listening on http://{addr}");
+                if let Some(bound) = server.server_addr().to_ip() {
+                    logging::info!("[screenshot-hd] listening on http://{bound}");
+                    let _ = context.addr.set(bound);
+                }
+                state::check_ready(&app_handle, &config.window_label, &context);
+
+                // Kept alive for as long as the server runs
+                #[cfg(feature = "mdns")]
+                let _mdns = config
+                    .mdns
+                    .then(|| {
+                        mdns::advertise(
+                            &config.host,
+                            context.addr.get().map_or(config.port, |a| a.port()),
+                            &config.window_label,
+                            &package.name,
+                            &package.version.to_string(),
+                        )
+                    })
+                    .flatten();
 
-                serve_loop(server, app_handle, config.window_label);
+                let serve = std::panic::AssertUnwindSafe(|| {
+                    serve_loop(
+                        server,
+                        app_handle.clone(),
+                        config.window_label.clone(),
+                        context.clone(),
+                    )
+                });
+                if let Err(panic) = std::panic::catch_unwind(serve) {
+                    let message = panic
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "unknown panic".into());
+                    state::fail(
+                        &app_handle,
+                        &context,
+                        format!("server thread panicked: {message}"),
+                    );
+                }
             });
 
             Ok(())
@@ -99,219 +1366,2481 @@ pub fn init_with<R: Runtime>(config: Config) -> TauriPlugin<R> {
         .build()
 }
 
+/// Bind to `port`, or else the first free one of the `fallback` ports after
+/// it.
+fn bind(host: &str, port: u16, fallback: u16) -> Result<tiny_http::Server, String> {
+    let last = port.saturating_add(fallback);
+    let mut error = String::new();
+    for port in port..=last {
+        match tiny_http::Server::http(format!("{host}:{port}")) {
+            Ok(server) => return Ok(server),
+            Err(e) => {
+                logging::debug!("[screenshot-hd] can't bind {host}:{port}: {e}");
+                error = e.to_string();
+            }
+        }
+    }
+    Err(if last == port {
+        format!("failed to start on {host}:{port}: {error}")
+    } else {
+        format!("failed to start on {host}, ports {port}..={last}: {error}")
+    })
+}
+
 /// Main HTTP server loop.
 ///
-/// The window is resolved lazily on first request — this avoids the race
-/// condition where the plugin's `setup` runs before windows are created.
+/// Each request is handled on its own thread, so a slow upload or a long
+/// `?wait=` doesn't hold up other requests. The window is resolved lazily on
+/// first request — this avoids the race condition where the plugin's `setup`
+/// runs before windows are created.
 fn serve_loop<R: Runtime>(
     server: tiny_http::Server,
     app_handle: tauri::AppHandle<R>,
     window_label: String,
+    context: Arc<Context>,
 ) {
-    let window_cell: OnceLock<tauri::WebviewWindow<R>> = OnceLock::new();
+    let window_cell: Arc<OnceLock<tauri::WebviewWindow<R>>> = Arc::new(OnceLock::new());
 
     loop {
-        let mut request = match server.recv_timeout(std::time::Duration::from_millis(500)) {
+        if context.shutdown.load(Ordering::SeqCst) {
+            logging::info!("[screenshot-hd] server stopped");
+            return;
+        }
+        let request = match server.recv_timeout(std::time::Duration::from_millis(500)) {
             Ok(Some(r)) => r,
             Ok(None) | Err(_) => continue,
         };
+        context.counters.requests.fetch_add(1, Ordering::Relaxed);
+
+        // Turn away unwanted clients before spending a thread on them
+        let ip = request.remote_addr().map(|a| a.ip());
+        if !context.ip_allowed(ip) {
+            let resp = tiny_http::Response::from_string("client not allowed").with_status_code(403);
+            let _ = request.respond(resp);
+            continue;
+        }
+        if let (Some(limiter), Some(ip)) = (&context.rate_limiter, ip) {
+            if let Err(retry_after) = limiter.check(ip) {
+                let secs = retry_after
+                    .as_secs()
+                    .saturating_add(u64::from(retry_after.subsec_nanos() > 0));
+                let resp = tiny_http::Response::from_string("rate limit exceeded")
+                    .with_status_code(429)
+                    .with_header(
+                        format!("Retry-After: {secs}")
+                            .parse::<tiny_http::Header>()
+                            .unwrap(),
+                    );
+                let _ = request.respond(resp);
+                continue;
+            }
+        }
+        // Preflights carry no token; they only say what a page may send
+        if request.method() == &tiny_http::Method::Options {
+            origin::preflight(request, &context.allowed_origins);
+            continue;
+        }
+        let url = request.url();
+        let path = url.split('?').next().unwrap_or(url);
+        let scope = Scope::of(request.method(), path);
+        let bound = context.addr.get().copied();
+        if let Err(e) = origin::check(&request, path, scope, &context.allowed_origins, bound) {
+            let resp = tiny_http::Response::from_string(e).with_status_code(403);
+            let _ = request.respond(resp);
+            continue;
+        }
+        let token = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Authorization"))
+            .and_then(|h| h.value.as_str().strip_prefix("Bearer "));
+        // The UI page is static; it asks for the token and sends it itself
+        let public =
+            cfg!(feature = "ui") && path == "/" && request.method() == &tiny_http::Method::Get;
+        let authorized = if public {
+            Ok(())
+        } else {
+            context.authorize(token, scope)
+        };
+        if let Err((status, e)) = authorized {
+            let mut resp = tiny_http::Response::from_string(e).with_status_code(status);
+            if status == 401 {
+                resp.add_header(
+                    "WWW-Authenticate: Bearer"
+                        .parse::<tiny_http::Header>()
+                        .unwrap(),
+                );
+            }
+            let _ = request.respond(resp);
+            continue;
+        }
+
+        let Some(slot) = RequestSlot::take(&context) else {
+            let resp = tiny_http::Response::from_string("too many requests in flight")
+                .with_status_code(503)
+                .with_header("Retry-After: 1".parse::<tiny_http::Header>().unwrap());
+            let _ = request.respond(resp);
+            continue;
+        };
+        let app_handle = app_handle.clone();
+        let window_label = window_label.clone();
+        let window_cell = window_cell.clone();
+        let context = context.clone();
+        std::thread::spawn(move || {
+            let _slot = slot;
+            handle_request(request, &app_handle, &window_label, &window_cell, &context)
+        });
+    }
+}
+
+/// A thread's place in [`Config::max_concurrent_requests`], given back
+/// when it's dropped.
+struct RequestSlot(Arc<Context>);
+
+impl RequestSlot {
+    /// A slot for a new request, or `None` when they're all taken.
+    fn take(context: &Arc<Context>) -> Option<Self> {
+        let active = context.active_requests.fetch_add(1, Ordering::SeqCst);
+        let slot = Self(context.clone());
+        (active < context.max_concurrent_requests).then_some(slot)
+    }
+
+    /// A slot whether or not any are free, for a thread a request can't
+    /// do without.
+    fn hold(context: &Arc<Context>) -> Self {
+        context.active_requests.fetch_add(1, Ordering::SeqCst);
+        Self(context.clone())
+    }
+}
+
+impl Drop for RequestSlot {
+    fn drop(&mut self) {
+        self.0.active_requests.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Route and answer a single request.
+fn handle_request<R: Runtime>(
+    mut request: tiny_http::Request,
+    app_handle: &tauri::AppHandle<R>,
+    window_label: &str,
+    window_cell: &OnceLock<tauri::WebviewWindow<R>>,
+    context: &Arc<Context>,
+) {
+    let deadline = Instant::now() + context.request_timeout;
+    let url = request.url().to_string();
+    let path = url.split('?').next().unwrap_or(&url);
+    let test_name = test_name(&request, &url);
+    #[cfg(feature = "tracing")]
+    let _span = trace::request_span(&request, path).entered();
 
+    if !context.endpoint_enabled(path) {
+        let resp = tiny_http::Response::from_string(format!("{path} is disabled"))
+            .with_status_code(403);
+        respond(request, resp);
+        return;
+    }
+    if context.read_only_blocks(request.method(), path) {
+        let resp =
+            tiny_http::Response::from_string(format!("{path} is disabled in read-only mode"))
+                .with_status_code(403);
+        respond(request, resp);
+        return;
+    }
+    // A client deadline covers the whole capture and cuts the request's own
+    let client_deadline = match deadline::requested(&request, &url) {
+        Ok(client) => client,
+        Err(e) => {
+            let resp = tiny_http::Response::from_string(e).with_status_code(400);
+            respond(request, resp);
+            return;
+        }
+    };
+    let deadline = client_deadline.map_or(deadline, |client| client.min(deadline));
+    let defaults = CaptureOptions {
+        deadline: client_deadline,
+        ..context.capture_defaults.clone()
+    };
+
+    // Describes the API, so it doesn't need a window
+    if path == "/openapi.json" {
+        let spec = openapi::spec(context.addr.get().copied());
+        let resp = tiny_http::Response::from_string(spec.to_string()).with_header(
+            "Content-Type: application/json"
+                .parse::<tiny_http::Header>()
+                .unwrap(),
+        );
+        respond(request, resp);
+        return;
+    }
+
+    // `?wait_for_window=<ms>` waits for the window to be created
+    let window_wait = match query_param(&url, "wait_for_window").map(|v| v.parse::<u64>()) {
+        None => Duration::ZERO,
+        Some(Ok(ms)) => {
+            Duration::from_millis(ms).min(deadline.saturating_duration_since(Instant::now()))
+        }
+        Some(Err(_)) => {
+            let resp = tiny_http::Response::from_string(
+                "wait_for_window must be a number of milliseconds",
+            )
+            .with_status_code(400);
+            respond(request, resp);
+            return;
+        }
+    };
+
+    // `?window=<label>` targets another window for this request
+    let requested_window;
+    let window = if let Some(label) = query_param(&url, "window") {
+        match find_window(app_handle, &label, window_wait) {
+            Some(w) => {
+                requested_window = w;
+                &requested_window
+            }
+            None => {
+                let resp =
+                    tiny_http::Response::from_string(format!("window '{label}' not found"))
+                        .with_status_code(404);
+                respond(request, resp);
+                return;
+            }
+        }
+    } else {
         // Lazy window lookup
-        let window = match window_cell.get() {
+        match window_cell.get() {
             Some(w) => w,
             None => {
-                match app_handle.get_webview_window(&window_label) {
+                match find_window(app_handle, window_label, window_wait) {
                     Some(w) => {
                         let _ = window_cell.set(w);
                         window_cell.get().unwrap()
                     }
                     None => {
-                        let resp = tiny_http::Response::from_string(format!(
-                            "window '{}' not found yet — app may still be starting",
-                            window_label
-                        ))
-                        .with_status_code(503);
-                        let _ = request.respond(resp);
-                        continue;
+                        let body = serde_json::json!({
+                            "error": format!(
+                                "window '{window_label}' not found yet — app may still be starting"
+                            ),
+                            "window": window_label,
+                            "waiting_ms": context.started.elapsed().as_millis() as u64,
+                        });
+                        let resp = tiny_http::Response::from_string(body.to_string())
+                            .with_status_code(503)
+                            .with_header(
+                                "Content-Type: application/json"
+                                    .parse::<tiny_http::Header>()
+                                    .unwrap(),
+                            )
+                            .with_header(
+                                format!("Retry-After: {WINDOW_RETRY_AFTER}")
+                                    .parse::<tiny_http::Header>()
+                                    .unwrap(),
+                            );
+                        respond(request, resp);
+                        return;
                     }
                 }
             }
-        };
-
-        let url = request.url().to_string();
-        let path = url.split('?').next().unwrap_or(&url);
+        }
+    };
 
-        match path {
-            "/screenshot" => {
-                match take_screenshot(window) {
-                    Ok(bytes) => {
-                        let resp = tiny_http::Response::from_data(bytes).with_header(
-                            "Content-Type: image/png"
-                                .parse::<tiny_http::Header>()
-                                .unwrap(),
-                        );
-                        let _ = request.respond(resp);
+    match path {
+        "/screenshot" => {
+            let mut settings = match defaults.clone().with_query(&url) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    let resp = tiny_http::Response::from_string(e).with_status_code(400);
+                    respond(request, resp);
+                    return;
+                }
+            };
+            let formats = match query_param(&url, "formats").map(|v| ImageFormat::parse_list(&v)) {
+                None => None,
+                Some(Ok(formats)) => Some(formats),
+                Some(Err(e)) => {
+                    let resp = tiny_http::Response::from_string(e).with_status_code(400);
+                    respond(request, resp);
+                    return;
+                }
+            };
+            let accept = request
+                .headers()
+                .iter()
+                .find(|h| h.field.equiv("Accept"))
+                .map(|h| h.value.as_str().to_string())
+                .unwrap_or_default();
+            let multipart = accept.contains("multipart/mixed");
+            let accepted = format::negotiate(&accept);
+            if let Some(format::Accepted::Image(format)) = accepted {
+                // An explicit ?format= wins
+                if query_param(&url, "format").is_none() {
+                    settings.format = format;
+                }
+            }
+            let json = accepted == Some(format::Accepted::Json);
+            if let Some(recorder) = &context.recorder {
+                recorder.record(serde_json::json!({
+                    "command": "screenshot",
+                    "url": url,
+                    "name": test_name,
+                }));
+            }
+            if let Some(formats) = formats {
+                // One snapshot, taken in a lossless format if one is listed
+                // and re-encoded for the others
+                settings.format = formats
+                    .iter()
+                    .copied()
+                    .find(|f| f.lossless())
+                    .unwrap_or(formats[0]);
+                let started = Instant::now();
+                let result =
+                    context.capture_with(window, "screenshot", test_name.as_deref(), &settings);
+                let resp = match result.and_then(|bytes| {
+                    let metadata = capture_metadata(&bytes, window, &settings, started.elapsed());
+                    #[cfg(feature = "signing")]
+                    let (bytes, metadata) = signed(context, bytes, metadata, window);
+                    Ok((transcode(&bytes, &formats)?, metadata.to_string()))
+                }) {
+                    Ok((encoded, metadata)) => {
+                        let mut parts: Vec<(&str, &[u8])> = encoded
+                            .iter()
+                            .map(|(format, bytes)| (format.content_type(), &bytes[..]))
+                            .collect();
+                        parts.push(("application/json", metadata.as_bytes()));
+                        multipart_response(&parts)
                     }
                     Err(e) => {
-                        log::error!("[screenshot-hd] capture failed: {e}");
-                        let resp = tiny_http::Response::from_string(e).with_status_code(504);
-                        let _ = request.respond(resp);
+                        logging::error!("[screenshot-hd] capture failed: {e}");
+                        tiny_http::Response::from_string(e).with_status_code(504)
                     }
+                };
+                respond(request, resp);
+                return;
+            }
+            let started = Instant::now();
+            match context.capture_with(window, "screenshot", test_name.as_deref(), &settings) {
+                Ok(bytes) if multipart => {
+                    let metadata = capture_metadata(&bytes, window, &settings, started.elapsed());
+                    #[cfg(feature = "signing")]
+                    let (bytes, metadata) = signed(context, bytes, metadata, window);
+                    let resp = multipart_response(&[
+                        (settings.format.content_type(), &bytes),
+                        ("application/json", metadata.to_string().as_bytes()),
+                    ]);
+                    respond(request, resp);
+                }
+                Ok(bytes) if json => {
+                    let body = capture_metadata(&bytes, window, &settings, started.elapsed());
+                    #[cfg(feature = "signing")]
+                    let (bytes, mut body) = signed(context, bytes, body, window);
+                    #[cfg(not(feature = "signing"))]
+                    let mut body = body;
+                    body["content_type"] = settings.format.content_type().into();
+                    body["data"] = base64::engine::general_purpose::STANDARD
+                        .encode(&bytes)
+                        .into();
+                    let resp = tiny_http::Response::from_string(body.to_string())
+                        .with_header(
+                            "Content-Type: application/json"
+                                .parse::<tiny_http::Header>()
+                                .unwrap(),
+                        )
+                        .with_header("Vary: Accept".parse::<tiny_http::Header>().unwrap());
+                    respond(request, resp);
+                }
+                Ok(bytes) => {
+                    let resp =
+                        capture_response(context, bytes, window, &settings, started.elapsed())
+                            .with_header("Vary: Accept".parse::<tiny_http::Header>().unwrap());
+                    respond(request, resp);
+                }
+                Err(e) => {
+                    logging::error!("[screenshot-hd] capture failed: {e}");
+                    let resp = tiny_http::Response::from_string(e).with_status_code(504);
+                    respond(request, resp);
                 }
             }
+        }
 
-            "/eval" => {
-                let mut body = String::new();
-                if let Err(e) = request.as_reader().read_to_string(&mut body) {
-                    let resp = tiny_http::Response::from_string(format!("read error: {e}"))
+        "/screenshot/analyze" | "/screenshot/blank" => {
+            let tolerance = match query_param(&url, "tolerance").map(|v| v.parse::<f64>()) {
+                None => analyze::BLANK_TOLERANCE,
+                Some(Ok(t)) if (0.0..=1.0).contains(&t) => t,
+                Some(_) => {
+                    let resp = tiny_http::Response::from_string("tolerance must be in 0..=1")
                         .with_status_code(400);
-                    let _ = request.respond(resp);
-                    continue;
+                    respond(request, resp);
+                    return;
                 }
+            };
+            let settings = match defaults.clone().with_query(&url) {
+                // QOI is the cheapest to encode and decode again
+                Ok(settings) => CaptureOptions {
+                    format: ImageFormat::Qoi,
+                    ..settings
+                },
+                Err(e) => {
+                    let resp = tiny_http::Response::from_string(e).with_status_code(400);
+                    respond(request, resp);
+                    return;
+                }
+            };
+            let resp = match context
+                .capture_with(window, "analyze", None, &settings)
+                .and_then(|bytes| format::decode(&bytes))
+            {
+                Ok(frame) => {
+                    let result = if path == "/screenshot/blank" {
+                        analyze::blank(&frame, tolerance)
+                    } else {
+                        analyze::analyze(&frame, tolerance)
+                    };
+                    budget::recycle(frame.rgba);
+                    context.events.publish("analysis", result.clone());
+                    tiny_http::Response::from_string(result.to_string()).with_header(
+                        "Content-Type: application/json"
+                            .parse::<tiny_http::Header>()
+                            .unwrap(),
+                    )
+                }
+                Err(e) => tiny_http::Response::from_string(e).with_status_code(504),
+            };
+            respond(request, resp);
+        }
 
-                if let Err(e) = window.eval(&body) {
-                    let resp = tiny_http::Response::from_string(format!("eval error: {e}"))
-                        .with_status_code(500);
-                    let _ = request.respond(resp);
-                    continue;
-                }
-
-                // ?wait=N — wait N ms then return screenshot
-                let wait_ms: Option<u64> = url
-                    .split('?')
-                    .nth(1)
-                    .and_then(|qs| {
-                        qs.split('&')
-                            .find(|p| p.starts_with("wait="))
-                            .and_then(|p| p[5..].parse().ok())
-                    });
-
-                if let Some(ms) = wait_ms {
-                    std::thread::sleep(std::time::Duration::from_millis(ms));
-                    match take_screenshot(window) {
-                        Ok(bytes) => {
-                            let resp = tiny_http::Response::from_data(bytes).with_header(
-                                "Content-Type: image/png"
-                                    .parse::<tiny_http::Header>()
-                                    .unwrap(),
-                            );
-                            let _ = request.respond(resp);
+        "/render" => {
+            if request.method() != &tiny_http::Method::Post {
+                let resp = tiny_http::Response::from_string("use POST").with_status_code(405);
+                respond(request, resp);
+                return;
+            }
+            let Some((request, body)) = read_body(request, context, deadline) else {
+                return;
+            };
+            let render = body.and_then(|body| {
+                scratch::Render::parse(&url, &body, defaults.clone()).map_err(|e| (400, e))
+            });
+            let render = match render {
+                Ok(render) => render,
+                Err((status, e)) => {
+                    let resp = tiny_http::Response::from_string(e).with_status_code(status);
+                    respond(request, resp);
+                    return;
+                }
+            };
+            let scratch = match scratch::ScratchWindow::open(
+                window.app_handle(),
+                render.url,
+                render.width,
+                render.height,
+                deadline,
+            ) {
+                Ok(scratch) => scratch,
+                Err((status, e)) => {
+                    let resp = tiny_http::Response::from_string(e).with_status_code(status);
+                    respond(request, resp);
+                    return;
+                }
+            };
+            let started = Instant::now();
+            let resp = match context.capture_with(
+                scratch.window(),
+                "render",
+                test_name.as_deref(),
+                &render.settings,
+            ) {
+                Ok(bytes) => capture_response(
+                    context,
+                    bytes,
+                    scratch.window(),
+                    &render.settings,
+                    started.elapsed(),
+                )
+                .boxed(),
+                Err(e) => tiny_http::Response::from_string(e)
+                    .with_status_code(504)
+                    .boxed(),
+            };
+            drop(scratch);
+            respond(request, resp);
+        }
+
+        "/compare-url" => {
+            if request.method() != &tiny_http::Method::Post {
+                let resp = tiny_http::Response::from_string("use POST").with_status_code(405);
+                respond(request, resp);
+                return;
+            }
+            let Some((request, body)) = read_body(request, context, deadline) else {
+                return;
+            };
+            let result = body
+                .and_then(|body| {
+                    compare::Comparison::parse(&url, &body, defaults.clone()).map_err(|e| (400, e))
+                })
+                .and_then(|comparison| compare::compare(window, context, &comparison, deadline));
+            let resp = match result {
+                Ok(result) => tiny_http::Response::from_string(result.to_string()).with_header(
+                    "Content-Type: application/json"
+                        .parse::<tiny_http::Header>()
+                        .unwrap(),
+                ),
+                Err((status, e)) => tiny_http::Response::from_string(e).with_status_code(status),
+            };
+            respond(request, resp);
+        }
+
+        "/events" => {
+            let types = query_param(&url, "types")
+                .map(|t| t.split(',').map(|t| t.trim().to_string()).collect());
+            events::serve(request, context, types);
+        }
+
+        "/watch" => match watch::Watch::from_query(&url, context.capture_defaults.clone()) {
+            Ok(watch) => watch::serve(request, window, context, watch),
+            Err(e) => {
+                let resp = tiny_http::Response::from_string(e).with_status_code(400);
+                respond(request, resp);
+            }
+        },
+
+        "/eval" => {
+            let settings = match defaults.clone().with_query(&url) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    let resp = tiny_http::Response::from_string(e).with_status_code(400);
+                    respond(request, resp);
+                    return;
+                }
+            };
+            let Some((request, body)) = read_body(request, context, deadline) else {
+                return;
+            };
+            let body = match body {
+                Ok(body) => body,
+                Err((status, e)) => {
+                    let resp = tiny_http::Response::from_string(e).with_status_code(status);
+                    respond(request, resp);
+                    return;
+                }
+            };
+
+            let too_long = context.eval_policy.as_ref().map(|p| p.check_length(&body));
+            if let Some(Err(e)) = too_long {
+                let resp = tiny_http::Response::from_string(e).with_status_code(413);
+                respond(request, resp);
+                return;
+            }
+
+            // ?wait=N or ?wait_until=stable — wait, then return screenshot
+            let wait = settings.wait.max_duration();
+
+            // Refuse up front rather than running the script and timing out
+            if wait.is_some_and(|delay| Instant::now() + delay > deadline) {
+                let resp = tiny_http::Response::from_string("the wait exceeds the request timeout")
+                    .with_status_code(408);
+                respond(request, resp);
+                return;
+            }
+
+            if let Some(audit) = &context.audit {
+                audit.record(
+                    request.remote_addr().copied(),
+                    "/eval",
+                    window.label(),
+                    &body,
+                );
+            }
+
+            #[cfg(feature = "tracing")]
+            let _eval_span =
+                tracing::info_span!("screenshot_hd.eval", length = body.len()).entered();
+            if let Err(e) = context.eval(window, &body) {
+                let resp = tiny_http::Response::from_string(e).with_status_code(500);
+                respond(request, resp);
+                return;
+            }
+            context.events.publish(
+                "eval",
+                serde_json::json!({
+                    "window": window.label(),
+                    "length": body.len(),
+                    "script": body.chars().take(events::SCRIPT_PREVIEW).collect::<String>(),
+                }),
+            );
+            if let Some(recorder) = &context.recorder {
+                recorder.record(serde_json::json!({
+                    "command": "eval",
+                    "script": body,
+                    "url": url,
+                    "name": test_name,
+                }));
+            }
+
+            if wait.is_some() {
+                let started = Instant::now();
+                match context.capture_with(window, "eval", test_name.as_deref(), &settings) {
+                    Ok(bytes) => {
+                        let resp =
+                            capture_response(context, bytes, window, &settings, started.elapsed());
+                        respond(request, resp);
+                    }
+                    Err(e) => {
+                        let resp =
+                            tiny_http::Response::from_string(e).with_status_code(504);
+                        respond(request, resp);
+                    }
+                }
+            } else {
+                let resp = tiny_http::Response::from_string("ok");
+                respond(request, resp);
+            }
+        }
+
+        "/element" => {
+            let Some(selector) = query_param(&url, "selector") else {
+                let resp = tiny_http::Response::from_string("missing ?selector=")
+                    .with_status_code(400);
+                respond(request, resp);
+                return;
+            };
+            respond_dom_query(request, window, &selector, &dom::element_script(&selector));
+        }
+
+        "/element/style" => {
+            let Some(selector) = query_param(&url, "selector") else {
+                let resp = tiny_http::Response::from_string("missing ?selector=")
+                    .with_status_code(400);
+                respond(request, resp);
+                return;
+            };
+            let props = query_param(&url, "props").unwrap_or_default();
+            let props: Vec<&str> = props
+                .split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .collect();
+            let script = dom::style_script(&selector, &props);
+            respond_dom_query(request, window, &selector, &script);
+        }
+
+        "/text" => {
+            let Some(selector) = query_param(&url, "selector") else {
+                let resp = tiny_http::Response::from_string("missing ?selector=")
+                    .with_status_code(400);
+                respond(request, resp);
+                return;
+            };
+            respond_dom_query(request, window, &selector, &dom::text_script(&selector));
+        }
+
+        "/mocks" => {
+            let body = if request.method() == &tiny_http::Method::Post {
+                let Some((read, body)) = read_body(request, context, deadline) else {
+                    return;
+                };
+                request = read;
+                body
+            } else {
+                Ok(String::new())
+            };
+            let result = match request.method() {
+                tiny_http::Method::Get => emulation::get(window, "mocks"),
+                tiny_http::Method::Delete => {
+                    emulation::set(window, "mocks", &serde_json::Value::Null)
+                }
+                tiny_http::Method::Post => {
+                    body.and_then(|body| {
+                        // One mock or an array of them, appended to the current list
+                        let added = match serde_json::from_str(&body) {
+                            Ok(serde_json::Value::Array(mocks)) => mocks,
+                            Ok(mock) => vec![mock],
+                            Err(e) => return Err((400, format!("invalid JSON: {e}"))),
+                        };
+                        for mock in &added {
+                            emulation::validate_mock(mock).map_err(|e| (400, e))?;
                         }
-                        Err(e) => {
+                        let mut mocks = match emulation::get(window, "mocks")? {
+                            serde_json::Value::Array(mocks) => mocks,
+                            _ => Vec::new(),
+                        };
+                        mocks.extend(added);
+                        emulation::set(window, "mocks", &mocks.into())
+                    })
+                }
+                _ => Err((405, "use GET, POST or DELETE".to_string())),
+            };
+            // An empty section reads back as null; report it as no mocks
+            let result = result.map(|mocks| {
+                if mocks.is_null() {
+                    serde_json::json!([])
+                } else {
+                    mocks
+                }
+            });
+            if let (Some(recorder), Ok(mocks)) = (&context.recorder, &result) {
+                if request.method() != &tiny_http::Method::Get {
+                    recorder.record(serde_json::json!({
+                        "command": "emulate",
+                        "section": "mocks",
+                        "value": mocks,
+                    }));
+                }
+            }
+            let resp = match result {
+                Ok(mocks) => tiny_http::Response::from_string(mocks.to_string()).with_header(
+                    "Content-Type: application/json"
+                        .parse::<tiny_http::Header>()
+                        .unwrap(),
+                ),
+                Err((status, e)) => tiny_http::Response::from_string(e).with_status_code(status),
+            };
+            respond(request, resp);
+        }
+
+        "/har" => {
+            let resp = match har::export(window, context) {
+                Ok(har) => tiny_http::Response::from_string(har.to_string()).with_header(
+                    "Content-Type: application/json"
+                        .parse::<tiny_http::Header>()
+                        .unwrap(),
+                ),
+                Err((status, e)) => tiny_http::Response::from_string(e).with_status_code(status),
+            };
+            respond(request, resp);
+        }
+
+        p if p.starts_with("/emulate/") => {
+            let section = &p["/emulate/".len()..];
+            let body = if request.method() == &tiny_http::Method::Post {
+                let Some((read, body)) = read_body(request, context, deadline) else {
+                    return;
+                };
+                request = read;
+                body
+            } else {
+                Ok(String::new())
+            };
+            let result = match request.method() {
+                _ if !emulation::SECTIONS.contains(&section) => {
+                    Err((404, format!("unknown emulation '{section}'")))
+                }
+                tiny_http::Method::Get => emulation::get(window, section),
+                tiny_http::Method::Delete => {
+                    emulation::set(window, section, &serde_json::Value::Null)
+                }
+                tiny_http::Method::Post => body.and_then(|body| {
+                    let body = match body.trim() {
+                        "" => serde_json::json!({}),
+                        body => serde_json::from_str(body)
+                            .map_err(|e| (400, format!("invalid JSON: {e}")))?,
+                    };
+                    let value = emulation::normalize(section, &body).map_err(|e| (400, e))?;
+                    emulation::set(window, section, &value)
+                }),
+                _ => Err((405, "use GET, POST or DELETE".to_string())),
+            };
+            if let (Some(recorder), Ok(value)) = (&context.recorder, &result) {
+                if request.method() != &tiny_http::Method::Get {
+                    recorder.record(serde_json::json!({
+                        "command": "emulate",
+                        "section": section,
+                        "value": value,
+                    }));
+                }
+            }
+            let resp = match result {
+                Ok(value) => tiny_http::Response::from_string(value.to_string()).with_header(
+                    "Content-Type: application/json"
+                        .parse::<tiny_http::Header>()
+                        .unwrap(),
+                ),
+                Err((status, e)) => tiny_http::Response::from_string(e).with_status_code(status),
+            };
+            respond(request, resp);
+        }
+
+        "/perf" => {
+            let capture = match query_param(&url, "capture").map(|v| v.parse::<bool>()) {
+                None => false,
+                Some(Ok(capture)) => capture,
+                Some(Err(_)) => {
+                    let resp = tiny_http::Response::from_string("capture must be true or false")
+                        .with_status_code(400);
+                    respond(request, resp);
+                    return;
+                }
+            };
+            let settings = match defaults.clone().with_query(&url) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    let resp = tiny_http::Response::from_string(e).with_status_code(400);
+                    respond(request, resp);
+                    return;
+                }
+            };
+            let metrics = match eval_json(window, perf::METRICS_JS) {
+                Ok(metrics) => metrics,
+                Err(e) => {
+                    let resp = tiny_http::Response::from_string(format!("eval error: {e}"))
+                        .with_status_code(500);
+                    respond(request, resp);
+                    return;
+                }
+            };
+            if !capture {
+                let resp = tiny_http::Response::from_string(metrics.to_string()).with_header(
+                    "Content-Type: application/json"
+                        .parse::<tiny_http::Header>()
+                        .unwrap(),
+                );
+                respond(request, resp);
+                return;
+            }
+            // Captured right after, so the image shows what the metrics describe
+            let resp = match context.capture_with(window, "perf", test_name.as_deref(), &settings) {
+                Ok(bytes) => multipart_response(&[
+                    ("application/json", metrics.to_string().as_bytes()),
+                    (settings.format.content_type(), &bytes),
+                ]),
+                Err(e) => tiny_http::Response::from_string(e).with_status_code(504),
+            };
+            respond(request, resp);
+        }
+
+        "/coverage/start" | "/coverage/stop" => {
+            if request.method() != &tiny_http::Method::Post {
+                let resp = tiny_http::Response::from_string("use POST").with_status_code(405);
+                respond(request, resp);
+                return;
+            }
+            let script = if path == "/coverage/start" {
+                coverage::START_JS
+            } else {
+                coverage::STOP_JS
+            };
+            let resp = match eval_json(window, script) {
+                Ok(serde_json::Value::Null) => {
+                    tiny_http::Response::from_string(coverage::NOT_INSTRUMENTED)
+                        .with_status_code(409)
+                }
+                Ok(files) if path == "/coverage/start" => tiny_http::Response::from_string(
+                    serde_json::json!({ "files": files }).to_string(),
+                )
+                .with_header(
+                    "Content-Type: application/json"
+                        .parse::<tiny_http::Header>()
+                        .unwrap(),
+                ),
+                Ok(coverage) => tiny_http::Response::from_string(coverage.to_string()).with_header(
+                    "Content-Type: application/json"
+                        .parse::<tiny_http::Header>()
+                        .unwrap(),
+                ),
+                Err(e) => tiny_http::Response::from_string(format!("eval error: {e}"))
+                    .with_status_code(500),
+            };
+            respond(request, resp);
+        }
+
+        "/history" => {
+            let last = query_param(&url, "last").and_then(|v| v.parse().ok());
+            if let Some(format) = query_param(&url, "format") {
+                let result = animation::AnimationFormat::parse(&format)
+                    .ok_or_else(|| (400, format!("unknown format '{format}' (gif, apng, webp)")))
+                    .and_then(|format| {
+                        let captures = context.history.captures(last, test_name.as_deref());
+                        animation::recording(&captures, format).map(|bytes| (bytes, format))
+                    });
+                let resp = match result {
+                    Ok((animation, format)) => tiny_http::Response::from_data(animation)
+                        .with_header(
+                            format!("Content-Type: {}", format.content_type())
+                                .parse::<tiny_http::Header>()
+                                .unwrap(),
+                        ),
+                    Err((status, e)) => {
+                        tiny_http::Response::from_string(e).with_status_code(status)
+                    }
+                };
+                respond(request, resp);
+                return;
+            }
+            let entries = context.history.list(last, test_name.as_deref());
+            let resp = tiny_http::Response::from_string(entries.to_string()).with_header(
+                "Content-Type: application/json"
+                    .parse::<tiny_http::Header>()
+                    .unwrap(),
+            );
+            respond(request, resp);
+        }
+
+        p if p.starts_with("/history/") => {
+            let entry = p["/history/".len()..]
+                .parse()
+                .ok()
+                .and_then(|id| context.history.get(id));
+            let resp = match entry {
+                Some(bytes) => image_response(bytes).boxed(),
+                None => tiny_http::Response::from_string("no such history entry")
+                    .with_status_code(404)
+                    .boxed(),
+            };
+            respond(request, resp);
+        }
+
+        "/archive" => {
+            let Some(archive) = &context.archive else {
+                let resp = tiny_http::Response::from_string("archive is not enabled")
+                    .with_status_code(404);
+                respond(request, resp);
+                return;
+            };
+            let number = |name| query_param(&url, name).and_then(|v| v.parse().ok());
+            let index = archive.query(
+                number("since"),
+                number("until"),
+                query_param(&url, "source").as_deref(),
+                test_name.as_deref(),
+                number("limit").map(|n: u64| n as usize),
+            );
+            let resp = tiny_http::Response::from_string(index.to_string()).with_header(
+                "Content-Type: application/json"
+                    .parse::<tiny_http::Header>()
+                    .unwrap(),
+            );
+            respond(request, resp);
+        }
+
+        p if p.starts_with("/archive/") => {
+            let file = &p["/archive/".len()..];
+            let resp = match context.archive.as_ref().and_then(|a| a.read(file)) {
+                Some(bytes) => image_response(bytes).boxed(),
+                None => tiny_http::Response::from_string("no such archived capture")
+                    .with_status_code(404)
+                    .boxed(),
+            };
+            respond(request, resp);
+        }
+
+        "/bench" => {
+            let settings = match defaults.clone().with_query(&url) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    let resp = tiny_http::Response::from_string(e).with_status_code(400);
+                    respond(request, resp);
+                    return;
+                }
+            };
+            let iterations = query_param(&url, "iterations")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(bench::DEFAULT_ITERATIONS)
+                .clamp(1, bench::MAX_ITERATIONS);
+            let resp = match bench::run(window, context, &settings, iterations, deadline) {
+                Ok(report) => tiny_http::Response::from_string(report.to_string()).with_header(
+                    "Content-Type: application/json"
+                        .parse::<tiny_http::Header>()
+                        .unwrap(),
+                ),
+                Err(e) => tiny_http::Response::from_string(e).with_status_code(504),
+            };
+            respond(request, resp);
+        }
+
+        "/audit" => {
+            let Some(audit) = &context.audit else {
+                let resp = tiny_http::Response::from_string("audit log is not enabled")
+                    .with_status_code(404);
+                respond(request, resp);
+                return;
+            };
+            let last = query_param(&url, "last").and_then(|v| v.parse().ok());
+            let resp = match audit.read(last) {
+                Ok(entries) => tiny_http::Response::from_string(entries.to_string()).with_header(
+                    "Content-Type: application/json"
+                        .parse::<tiny_http::Header>()
+                        .unwrap(),
+                ),
+                Err(e) => tiny_http::Response::from_string(e).with_status_code(500),
+            };
+            respond(request, resp);
+        }
+
+        "/reload" | "/clear-cache" => {
+            if request.method() != &tiny_http::Method::Post {
+                let resp = tiny_http::Response::from_string("use POST").with_status_code(405);
+                respond(request, resp);
+                return;
+            }
+            let flag = if path == "/reload" {
+                "ignore_cache"
+            } else {
+                "all"
+            };
+            let enabled = match query_param(&url, flag).map(|v| v.parse::<bool>()) {
+                None => false,
+                Some(Ok(enabled)) => enabled,
+                Some(Err(_)) => {
+                    let resp =
+                        tiny_http::Response::from_string(format!("{flag} must be true or false"))
+                            .with_status_code(400);
+                    respond(request, resp);
+                    return;
+                }
+            };
+            let result = if path == "/reload" {
+                if let Some(recorder) = &context.recorder {
+                    recorder.record(serde_json::json!({
+                        "command": "reload",
+                        "ignore_cache": enabled,
+                    }));
+                }
+                page::reload(window, enabled, deadline).map_err(|e| (504, e))
+            } else {
+                page::clear_cache(window, enabled).map_err(|e| (500, e))
+            };
+            let resp = match result {
+                Ok(()) => tiny_http::Response::from_string("ok"),
+                Err((status, e)) => tiny_http::Response::from_string(e).with_status_code(status),
+            };
+            respond(request, resp);
+        }
+
+        "/replay" => {
+            if request.method() != &tiny_http::Method::Post {
+                let resp = tiny_http::Response::from_string("use POST").with_status_code(405);
+                respond(request, resp);
+                return;
+            }
+            let delays = match query_param(&url, "delays").map(|v| v.parse::<bool>()) {
+                None => true,
+                Some(Ok(delays)) => delays,
+                Some(Err(_)) => {
+                    let resp = tiny_http::Response::from_string("delays must be true or false")
+                        .with_status_code(400);
+                    respond(request, resp);
+                    return;
+                }
+            };
+            let remote = request.remote_addr().copied();
+            let Some((request, session)) = read_body(request, context, deadline) else {
+                return;
+            };
+            let session = match session {
+                Ok(body) => body,
+                Err((status, e)) => {
+                    let resp = tiny_http::Response::from_string(e).with_status_code(status);
+                    respond(request, resp);
+                    return;
+                }
+            };
+            let resp = match session::replay(window, context, &session, delays, remote, deadline) {
+                Ok(report) => tiny_http::Response::from_string(report.to_string()).with_header(
+                    "Content-Type: application/json"
+                        .parse::<tiny_http::Header>()
+                        .unwrap(),
+                ),
+                Err(e) => tiny_http::Response::from_string(e).with_status_code(400),
+            };
+            respond(request, resp);
+        }
+
+        "/iterate" => {
+            if request.method() != &tiny_http::Method::Post {
+                let resp = tiny_http::Response::from_string("use POST").with_status_code(405);
+                respond(request, resp);
+                return;
+            }
+            let Some((request, body)) = read_body(request, context, deadline) else {
+                return;
+            };
+            let result = body
+                .and_then(|body| {
+                    let current = window.url().map_err(|e| (500, e.to_string()))?;
+                    let defaults = defaults.clone();
+                    iterate::Iteration::parse(&url, &body, &current, test_name.as_deref(), defaults)
+                        .map_err(|e| (400, e))
+                })
+                .map(|iteration| iterate::run(window, context, &iteration, deadline));
+            let resp = match result {
+                Ok(report) => tiny_http::Response::from_string(report.to_string()).with_header(
+                    "Content-Type: application/json"
+                        .parse::<tiny_http::Header>()
+                        .unwrap(),
+                ),
+                Err((status, e)) => tiny_http::Response::from_string(e).with_status_code(status),
+            };
+            respond(request, resp);
+        }
+
+        "/storyboard" => {
+            if request.method() != &tiny_http::Method::Post {
+                let resp = tiny_http::Response::from_string("use POST").with_status_code(405);
+                respond(request, resp);
+                return;
+            }
+            let remote = request.remote_addr().copied();
+            let Some((request, body)) = read_body(request, context, deadline) else {
+                return;
+            };
+            let result = body
+                .and_then(|body| {
+                    let current = window.url().map_err(|e| (500, e.to_string()))?;
+                    let defaults = defaults.clone();
+                    storyboard::Storyboard::parse(&url, &body, &current, defaults)
+                        .map_err(|e| (400, e))
+                })
+                .and_then(|storyboard| {
+                    storyboard::run(window, context, &storyboard, remote, deadline)
+                        .map(|animation| (animation, storyboard.format))
+                });
+            let resp = match result {
+                Ok((animation, format)) => tiny_http::Response::from_data(animation).with_header(
+                    format!("Content-Type: {}", format.content_type())
+                        .parse::<tiny_http::Header>()
+                        .unwrap(),
+                ),
+                Err((status, e)) => tiny_http::Response::from_string(e).with_status_code(status),
+            };
+            respond(request, resp);
+        }
+
+        #[cfg(feature = "ffmpeg")]
+        "/recording/start" | "/recording/stop" => {
+            if request.method() != &tiny_http::Method::Post {
+                let resp = tiny_http::Response::from_string("use POST").with_status_code(405);
+                respond(request, resp);
+                return;
+            }
+            let Some(recorder) = &context.video_recorder else {
+                let resp = tiny_http::Response::from_string("recording is not enabled")
+                    .with_status_code(404);
+                respond(request, resp);
+                return;
+            };
+            let result = if path == "/recording/start" {
+                recorder.start(window.clone(), context.clone(), &url)
+            } else {
+                recorder.stop()
+            };
+            let resp = match result {
+                Ok(summary) => tiny_http::Response::from_string(summary.to_string()).with_header(
+                    "Content-Type: application/json"
+                        .parse::<tiny_http::Header>()
+                        .unwrap(),
+                ),
+                Err((status, e)) => tiny_http::Response::from_string(e).with_status_code(status),
+            };
+            respond(request, resp);
+        }
+
+        #[cfg(feature = "webrtc")]
+        "/webrtc" => {
+            if request.method() != &tiny_http::Method::Post {
+                let resp = tiny_http::Response::from_string("use POST").with_status_code(405);
+                respond(request, resp);
+                return;
+            }
+            let Some((request, offer)) = read_body(request, context, deadline) else {
+                return;
+            };
+            let result = offer.and_then(|offer| {
+                live::connect(window.clone(), context.clone(), &url, offer, deadline)
+            });
+            let resp = match result {
+                Ok((id, answer)) => tiny_http::Response::from_string(answer)
+                    .with_status_code(201)
+                    .with_header(
+                        "Content-Type: application/sdp"
+                            .parse::<tiny_http::Header>()
+                            .unwrap(),
+                    )
+                    .with_header(
+                        format!("Location: /webrtc/{id}")
+                            .parse::<tiny_http::Header>()
+                            .unwrap(),
+                    ),
+                Err((status, e)) => tiny_http::Response::from_string(e).with_status_code(status),
+            };
+            respond(request, resp);
+        }
+
+        #[cfg(feature = "webrtc")]
+        p if p.starts_with("/webrtc/") => {
+            let id = p["/webrtc/".len()..].parse().ok();
+            let resp = match request.method() {
+                tiny_http::Method::Get => match id.and_then(|id| context.live.quality(id)) {
+                    Some(quality) => {
+                        tiny_http::Response::from_string(quality.to_json().to_string()).with_header(
+                            "Content-Type: application/json"
+                                .parse::<tiny_http::Header>()
+                                .unwrap(),
+                        )
+                    }
+                    None => {
+                        tiny_http::Response::from_string("no such live view").with_status_code(404)
+                    }
+                },
+                tiny_http::Method::Delete if id.is_some_and(|id| context.live.stop(id)) => {
+                    tiny_http::Response::from_string("").with_status_code(200)
+                }
+                tiny_http::Method::Delete => {
+                    tiny_http::Response::from_string("no such live view").with_status_code(404)
+                }
+                _ => tiny_http::Response::from_string("use GET or DELETE").with_status_code(405),
+            };
+            respond(request, resp);
+        }
+
+        "/shutdown" | "/app/exit" => {
+            if request.method() != &tiny_http::Method::Post {
+                let resp = tiny_http::Response::from_string("use POST").with_status_code(405);
+                respond(request, resp);
+                return;
+            }
+            if !context.auth_required() {
+                let resp = tiny_http::Response::from_string(format!(
+                    "{path} requires Config::auth_token or auth_tokens to be set"
+                ))
+                .with_status_code(403);
+                respond(request, resp);
+                return;
+            }
+            if path == "/shutdown" {
+                context.shutdown.store(true, Ordering::SeqCst);
+                respond(request, tiny_http::Response::from_string("ok"));
+                return;
+            }
+            let code = match query_param(&url, "code").map(|v| v.parse::<i32>()) {
+                None => 0,
+                Some(Ok(code)) => code,
+                Some(Err(_)) => {
+                    let resp = tiny_http::Response::from_string("code must be an integer")
+                        .with_status_code(400);
+                    respond(request, resp);
+                    return;
+                }
+            };
+            logging::info!("[screenshot-hd] exiting with status {code} (POST /app/exit)");
+            // Answer first; the process is gone once exit() takes effect
+            respond(request, tiny_http::Response::from_string("ok"));
+            app_handle.exit(code);
+        }
+
+        "/read-only" => {
+            match request.method() {
+                tiny_http::Method::Get => {}
+                tiny_http::Method::Post if !context.auth_required() => {
+                    let resp = tiny_http::Response::from_string(
+                        "/read-only requires Config::auth_token or auth_tokens to be set",
+                    )
+                    .with_status_code(403);
+                    respond(request, resp);
+                    return;
+                }
+                tiny_http::Method::Post => {
+                    match query_param(&url, "enabled").map(|v| v.parse::<bool>()) {
+                        Some(Ok(enabled)) => context.read_only.store(enabled, Ordering::Relaxed),
+                        _ => {
                             let resp =
-                                tiny_http::Response::from_string(e).with_status_code(504);
-                            let _ = request.respond(resp);
+                                tiny_http::Response::from_string("enabled must be true or false")
+                                    .with_status_code(400);
+                            respond(request, resp);
+                            return;
                         }
                     }
-                } else {
-                    let resp = tiny_http::Response::from_string("ok");
-                    let _ = request.respond(resp);
+                }
+                _ => {
+                    let resp =
+                        tiny_http::Response::from_string("use GET or POST").with_status_code(405);
+                    respond(request, resp);
+                    return;
                 }
             }
+            let read_only = context.read_only.load(Ordering::Relaxed);
+            let resp = tiny_http::Response::from_string(
+                serde_json::json!({ "read_only": read_only }).to_string(),
+            )
+            .with_header(
+                "Content-Type: application/json"
+                    .parse::<tiny_http::Header>()
+                    .unwrap(),
+            );
+            respond(request, resp);
+        }
 
-            _ => {
-                let resp = tiny_http::Response::from_string(
-                    "tauri-plugin-screenshot-hd\n\n\
-                     GET  /screenshot        — capture PNG\n\
-                     POST /eval              — run JS in webview\n\
-                     POST /eval?wait=<ms>    — run JS, wait, then capture PNG",
+        "/devtools" => {
+            let action = match request.method() {
+                tiny_http::Method::Get => "status".to_string(),
+                tiny_http::Method::Post => {
+                    query_param(&url, "action").unwrap_or_else(|| "toggle".into())
+                }
+                _ => {
+                    let resp =
+                        tiny_http::Response::from_string("use GET or POST").with_status_code(405);
+                    respond(request, resp);
+                    return;
+                }
+            };
+            let resp = match devtools(window, &action) {
+                Ok(open) => tiny_http::Response::from_string(
+                    serde_json::json!({ "open": open }).to_string(),
                 )
-                .with_status_code(404);
-                let _ = request.respond(resp);
+                .with_header(
+                    "Content-Type: application/json"
+                        .parse::<tiny_http::Header>()
+                        .unwrap(),
+                ),
+                Err((status, e)) => tiny_http::Response::from_string(e).with_status_code(status),
+            };
+            respond(request, resp);
+        }
+
+        #[cfg(feature = "signing")]
+        "/public-key" => {
+            let resp = match &context.signer {
+                Some(signer) => tiny_http::Response::from_string(signer.public_key().to_string())
+                    .with_header(
+                        "Content-Type: application/json"
+                            .parse::<tiny_http::Header>()
+                            .unwrap(),
+                    ),
+                None => tiny_http::Response::from_string("Config::signing isn't set")
+                    .with_status_code(404),
+            };
+            respond(request, resp);
+        }
+
+        "/health" => {
+            let health = serde_json::json!({
+                "ready": context.readiness.is_ready(),
+                "window": window.label(),
+                "read_only": context.read_only.load(Ordering::Relaxed),
+                "warmup": context.warmup.to_json(),
+                "memory": {
+                    "used": context.memory.used(),
+                    "budget": context.memory.limit(),
+                },
+            });
+            let resp = tiny_http::Response::from_string(health.to_string()).with_header(
+                "Content-Type: application/json"
+                    .parse::<tiny_http::Header>()
+                    .unwrap(),
+            );
+            respond(request, resp);
+        }
+
+        "/state" => {
+            let resp = tiny_http::Response::from_string(window_state::report(window).to_string())
+                .with_header(
+                    "Content-Type: application/json"
+                        .parse::<tiny_http::Header>()
+                        .unwrap(),
+                );
+            respond(request, resp);
+        }
+
+        "/windows" => {
+            let mut labels: Vec<String> = app_handle.webview_windows().into_keys().collect();
+            labels.sort();
+            let windows: Vec<serde_json::Value> = labels
+                .into_iter()
+                .map(|label| {
+                    let default = label == window_label;
+                    serde_json::json!({ "label": label, "default": default })
+                })
+                .collect();
+            let resp = tiny_http::Response::from_string(
+                serde_json::Value::from(windows).to_string(),
+            )
+            .with_header(
+                "Content-Type: application/json"
+                    .parse::<tiny_http::Header>()
+                    .unwrap(),
+            );
+            respond(request, resp);
+        }
+
+        #[cfg(feature = "ui")]
+        "/" => {
+            let resp = tiny_http::Response::from_string(include_str!("ui.html")).with_header(
+                "Content-Type: text/html; charset=utf-8"
+                    .parse::<tiny_http::Header>()
+                    .unwrap(),
+            );
+            respond(request, resp);
+        }
+
+        #[cfg(feature = "bidi")]
+        "/session" => bidi::serve(request, window.clone(), context.clone()),
+
+        _ => {
+            let resp = tiny_http::Response::from_string(
+                "tauri-plugin-screenshot-hd\n\n\
+                 GET  /screenshot        — capture PNG (or ?format=, or per Accept)\n\
+                 GET  /screenshot?formats=png,jpeg — one capture in several formats (multipart)\n\
+                 GET  /screenshot/analyze — dominant colors, brightness, histogram\n\
+                 GET  /screenshot/blank  — is the frame (near-)uniform? (?tolerance=0.99)\n\
+                 POST /render            — capture {html|url, width, height} in a hidden window\n\
+                 POST /compare-url       — diff against a URL (body) loaded in a hidden window\n\
+                 GET  /watch?selector=|rect=x,y,w,h — SSE events when the region changes\n\
+                 GET  /events?types=a,b  — SSE feed of captures, evals, window events\n\
+                 POST /eval              — run JS in webview\n\
+                 POST /eval?wait=<ms>    — run JS, wait, then capture PNG\n\
+                 GET  /element?selector= — element geometry and visibility (JSON)\n\
+                 GET  /element/style?selector=&props=a,b — computed style (JSON)\n\
+                 GET  /text?selector=    — innerText of matching elements (JSON)\n\
+                 GET  /health            — readiness, warm-up and memory use (JSON)\n\
+                 GET  /state             — focus, visibility, occlusion, route, time since a DOM change (JSON)\n\
+                 GET  /windows           — list webview windows (JSON)\n\
+                 GET|POST /devtools?action=open|close|toggle — devtools state (JSON)\n\
+                 POST /reload?ignore_cache=true — reload and wait for the page to load\n\
+                 POST /clear-cache?all=true — clear HTTP caches (or all browsing data)\n\
+                 POST /replay?delays=false — re-run a recorded session (JSON report)\n\
+                 POST /iterate           — capture {routes|stories} by name (JSON report)\n\
+                 POST /storyboard        — run {steps} and return the captures as a GIF/APNG/WebP\n\
+                 POST /shutdown          — stop this server (auth_token)\n\
+                 POST /app/exit?code=<n> — exit the app (auth_token)\n\
+                 GET|POST /read-only?enabled=true|false — read-only mode (POST needs auth_token)\n\
+                 GET|POST|DELETE /mocks  — list, add or clear fetch/XHR mocks (emulation)\n\
+                 GET  /har               — fetch/XHR log and screenshots as HAR 1.2 (emulation)\n\
+                 GET|POST|DELETE /emulate/network — offline / slow-3g emulation (emulation)\n\
+                 GET|POST|DELETE /emulate/locale — language / time zone emulation (emulation)\n\
+                 GET|POST|DELETE /emulate/media — media feature emulation (emulation)\n\
+                 GET|POST|DELETE /emulate/geolocation — geolocation stub (emulation)\n\
+                 GET|POST|DELETE /emulate/permissions — permission query stub (emulation)\n\
+                 GET  /perf?capture=true — page performance metrics (JSON, optionally + PNG)\n\
+                 POST /coverage/start    — baseline Istanbul counters (window.__coverage__)\n\
+                 POST /coverage/stop     — coverage since start (Istanbul JSON)\n\
+                 GET  /history?last=<n>&name= — list buffered captures (JSON)\n\
+                 GET  /history?format=apng — the buffered captures as a GIF/APNG/WebP animation\n\
+                 GET  /history/<id>      — fetch a buffered capture (PNG)\n\
+                 GET  /archive?since=&until=&source=&name=&limit= — query the disk archive (JSON)\n\
+                 GET  /archive/<file>    — fetch an archived capture (PNG)\n\
+                 GET  /audit?last=<n>    — read the /eval audit log (JSON)\n\
+                 GET  /bench?iterations=<n> — capture latency per stage (JSON)\n\
+                 GET  /session           — WebDriver BiDi WebSocket (bidi feature)\n\
+                 POST /recording/start?interval=<ms>|fps=<n>&max_width=<px> — record a video through ffmpeg (ffmpeg feature)\n\
+                 POST /recording/stop    — finish the video and return its path (JSON)\n\
+                 POST /webrtc?interval=<ms>|fps=<n>&max_width=<px> — live view over WebRTC: SDP offer in, answer out (webrtc feature)\n\
+                 GET  /webrtc/<id>       — the quality a live view is streaming at (JSON)\n\
+                 DELETE /webrtc/<id>     — end a live view\n\
+                 GET  /public-key        — the key captures are signed with (signing feature)\n\
+                 GET  /openapi.json      — OpenAPI 3.1 description of these endpoints\n\n\
+                 Add ?window=<label> to target a window other than the default,\n\
+                 X-Test-Name: <name> (or ?name=) to file captures under a test name,\n\
+                 X-Deadline-Ms: <ms> (or ?deadline=) to bound a whole capture, and\n\
+                 ?wait_for_window=<ms> to wait for a window the app hasn't created yet.",
+            )
+            .with_status_code(404);
+            respond(request, resp);
+        }
+    }
+}
+
+/// An image response typed by the image's signature, with `X-Image-Width` /
+/// `X-Image-Height` headers so clients don't have to decode it to learn its
+/// size.
+fn image_response(image: impl Into<Bytes>) -> tiny_http::Response<std::io::Cursor<Bytes>> {
+    let image = image.into();
+    let dimensions = format::dimensions(&image);
+    let content_type = ImageFormat::detect(&image)
+        .unwrap_or_default()
+        .content_type();
+    // Written straight from the shared bytes; `from_data` would want a Vec
+    let length = image.len();
+    let mut resp = tiny_http::Response::new(
+        tiny_http::StatusCode(200),
+        Vec::new(),
+        std::io::Cursor::new(image),
+        Some(length),
+        None,
+    )
+    .with_header(
+        format!("Content-Type: {content_type}")
+            .parse::<tiny_http::Header>()
+            .unwrap(),
+    );
+    if let Some((width, height)) = dimensions {
+        resp.add_header(
+            format!("X-Image-Width: {width}")
+                .parse::<tiny_http::Header>()
+                .unwrap(),
+        );
+        resp.add_header(
+            format!("X-Image-Height: {height}")
+                .parse::<tiny_http::Header>()
+                .unwrap(),
+        );
+    }
+    resp
+}
+
+/// [`image_response`] for a capture just taken, adding `X-Scale-Factor`
+/// (image pixels per CSS pixel), `X-Capture-Duration-Ms`, `Server-Timing`
+/// for a native capture and, with [`Config::signing`], the signature.
+#[cfg_attr(not(feature = "signing"), allow(unused_variables))]
+fn capture_response<R: Runtime>(
+    context: &Context,
+    png: Bytes,
+    window: &tauri::WebviewWindow<R>,
+    settings: &CaptureOptions,
+    duration: Duration,
+) -> tiny_http::Response<std::io::Cursor<Bytes>> {
+    let scale = capture_scale(&png, window, settings);
+    #[cfg(feature = "signing")]
+    let (png, signature) = context.sign(png, window);
+    let mut resp = image_response(png);
+    #[cfg(feature = "signing")]
+    for header in signature.iter().flat_map(signing::Signature::headers) {
+        resp.add_header(header);
+    }
+    if let Some(scale) = scale {
+        resp.add_header(
+            format!("X-Scale-Factor: {scale}")
+                .parse::<tiny_http::Header>()
+                .unwrap(),
+        );
+    }
+    resp.add_header(
+        format!("X-Capture-Duration-Ms: {}", duration.as_millis())
+            .parse::<tiny_http::Header>()
+            .unwrap(),
+    );
+    if let Some(timings) = LAST_TIMINGS.take() {
+        resp.add_header(timings.server_timing());
+    }
+    resp
+}
+
+/// Image pixels per CSS pixel of a capture just taken, as captured: before
+/// any pipeline stage that crops or resizes it, which page coordinates
+/// have to be mapped through separately.
+fn capture_scale<R: Runtime>(
+    png: &[u8],
+    window: &tauri::WebviewWindow<R>,
+    settings: &CaptureOptions,
+) -> Option<f64> {
+    // A sized capture has already restored the window, so use the
+    // requested viewport rather than the current one.
+    let viewport = match settings.size {
+        Some((width, height)) => (f64::from(width), f64::from(height)),
+        None => {
+            let factor = window.scale_factor().ok()?;
+            let size = window.inner_size().ok()?.to_logical::<f64>(factor);
+            (size.width, size.height)
+        }
+    };
+    let css_width = framed_clip(settings, viewport).map_or(viewport.0, |clip| clip.width);
+    // Once the pipeline crops or resizes, the image no longer spans the
+    // captured CSS width, so go by what the frame was captured at
+    let resized = settings.pipeline.iter().any(|stage| {
+        matches!(
+            stage,
+            Stage::Crop { .. } | Stage::Scale(_) | Stage::Thumbnail(_) | Stage::Custom(_)
+        )
+    });
+    if resized {
+        let scale = match (settings.width, settings.scale) {
+            (Some(width), _) => f64::from(width) / css_width,
+            (None, Some(scale)) => scale,
+            (None, None) => window.scale_factor().ok()?,
+        };
+        let scale = (scale * 1000.0).round() / 1000.0;
+        return scale.is_finite().then_some(scale);
+    }
+    // An odd number of quarter turns leaves the CSS width along the image's
+    // height
+    let quarter_turns = settings
+        .pipeline
+        .iter()
+        .filter(|stage| {
+            matches!(
+                stage,
+                Stage::Rotate(Rotation::Clockwise90 | Rotation::Clockwise270)
+            )
+        })
+        .count();
+    let (width, height) = format::dimensions(png)?;
+    let width = if quarter_turns % 2 == 1 {
+        height
+    } else {
+        width
+    };
+    let scale = (f64::from(width) / css_width * 1000.0).round() / 1000.0;
+    scale.is_finite().then_some(scale)
+}
+
+/// Metadata sent alongside a multipart capture. Read right after the
+/// capture so it describes the same moment.
+fn capture_metadata<R: Runtime>(
+    png: &[u8],
+    window: &tauri::WebviewWindow<R>,
+    settings: &CaptureOptions,
+    duration: Duration,
+) -> serde_json::Value {
+    let dimensions = format::dimensions(png);
+    let size = window.inner_size().ok();
+    let position = window.outer_position().ok();
+    // `null` unless the console buffer is installed (or on non-macOS)
+    let console_errors = eval_json(window, crash::CONSOLE_ERROR_COUNT_JS).unwrap_or_default();
+
+    serde_json::json!({
+        "timestamp_ms": unix_millis(),
+        "capture_duration_ms": duration.as_millis() as u64,
+        "width": dimensions.map(|(w, _)| w),
+        "height": dimensions.map(|(_, h)| h),
+        "scale": capture_scale(png, window, settings),
+        "url": window.url().map(|u| u.to_string()).ok(),
+        "window": {
+            "label": window.label(),
+            "x": position.map(|p| p.x),
+            "y": position.map(|p| p.y),
+            "width": size.map(|s| s.width),
+            "height": size.map(|s| s.height),
+            "scale_factor": window.scale_factor().ok(),
+        },
+        "console_errors": console_errors,
+    })
+}
+
+/// [`Context::sign`] for a capture sent with its [`capture_metadata`],
+/// adding the signature to the metadata.
+#[cfg(feature = "signing")]
+fn signed<R: Runtime>(
+    context: &Context,
+    image: Bytes,
+    mut metadata: serde_json::Value,
+    window: &tauri::WebviewWindow<R>,
+) -> (Bytes, serde_json::Value) {
+    let (image, signature) = context.sign(image, window);
+    if let Some(signature) = signature {
+        metadata["signature"] = signature.to_json();
+    }
+    (image, metadata)
+}
+
+/// `capture` in each of `formats`: itself where it's already in that
+/// format, otherwise decoded (once) and encoded again.
+fn transcode(
+    capture: &Bytes,
+    formats: &[ImageFormat],
+) -> Result<Vec<(ImageFormat, Bytes)>, String> {
+    let source = ImageFormat::detect(capture);
+    let frame = formats
+        .iter()
+        .any(|&f| Some(f) != source)
+        .then(|| format::decode(capture))
+        .transpose()?;
+    let encoded = formats
+        .iter()
+        .map(|&f| match &frame {
+            Some(frame) if Some(f) != source => format::encode(frame, f).map(|b| (f, b.into())),
+            _ => Ok((f, capture.clone())),
+        })
+        .collect();
+    if let Some(frame) = frame {
+        budget::recycle(frame.rgba);
+    }
+    encoded
+}
+
+/// A `multipart/mixed` response with one part per `(content type, body)`.
+fn multipart_response(parts: &[(&str, &[u8])]) -> tiny_http::Response<std::io::Cursor<Vec<u8>>> {
+    let boundary = format!("screenshot-hd-{}", unix_millis());
+    let mut body = Vec::new();
+    for (content_type, data) in parts {
+        body.extend_from_slice(
+            format!("--{boundary}\r\nContent-Type: {content_type}\r\n\r\n").as_bytes(),
+        );
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+
+    tiny_http::Response::from_data(body).with_header(
+        format!("Content-Type: multipart/mixed; boundary={boundary}")
+            .parse::<tiny_http::Header>()
+            .unwrap(),
+    )
+}
+
+/// A request body, or the status and message to turn it away with.
+type Body = Result<String, (u16, String)>;
+
+/// Read a request body of at most [`Config::max_body_bytes`], handing the
+/// request back with it. Errors carry the status to respond with.
+///
+/// The read runs on a thread of its own, since a client that stalls
+/// mid-upload blocks it indefinitely. Once `deadline` passes this gives up
+/// with `None`, leaving that thread to answer 408 when the read ends.
+fn read_body(
+    request: tiny_http::Request,
+    context: &Arc<Context>,
+    deadline: Instant,
+) -> Option<(tiny_http::Request, Body)> {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let slot = RequestSlot::hold(context);
+    let max = context.max_body_bytes;
+    std::thread::spawn(move || {
+        let _slot = slot;
+        let mut request = request;
+        let body = read_limited(&mut request, max, deadline);
+        if let Err(std::sync::mpsc::SendError((request, _))) = tx.send((request, body)) {
+            let resp = tiny_http::Response::from_string("timed out reading the request body")
+                .with_status_code(408);
+            respond(request, resp);
+        }
+    });
+    rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        .ok()
+}
+
+/// Read a body of at most `max` bytes, stopping between chunks once
+/// `deadline` passes.
+fn read_limited(request: &mut tiny_http::Request, max: usize, deadline: Instant) -> Body {
+    let too_large = || (413, format!("body exceeds {max} bytes"));
+    if request.body_length().is_some_and(|len| len > max) {
+        return Err(too_large());
+    }
+
+    let mut reader = request.as_reader().take(max as u64 + 1);
+    let mut body = Vec::new();
+    let mut chunk = [0u8; 8192];
+    loop {
+        if Instant::now() >= deadline {
+            return Err((408, "timed out reading the request body".to_string()));
+        }
+        match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => body.extend_from_slice(&chunk[..n]),
+            Err(e) => return Err((400, format!("read error: {e}"))),
+        }
+    }
+    if body.len() > max {
+        return Err(too_large());
+    }
+    String::from_utf8(body).map_err(|_| (400, "body is not valid UTF-8".to_string()))
+}
+
+/// The window labelled `label`, looking again every [`WINDOW_POLL`] for up
+/// to `wait` until it's been created.
+fn find_window<R: Runtime>(
+    app_handle: &tauri::AppHandle<R>,
+    label: &str,
+    wait: Duration,
+) -> Option<tauri::WebviewWindow<R>> {
+    let until = Instant::now() + wait;
+    loop {
+        if let Some(window) = app_handle.get_webview_window(label) {
+            return Some(window);
+        }
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return None;
+        }
+        std::thread::sleep(WINDOW_POLL.min(left));
+    }
+}
+
+/// Test name a request is made under, namespacing its captures: the
+/// `X-Test-Name` header, or `?name=`.
+fn test_name(request: &tiny_http::Request, url: &str) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("X-Test-Name"))
+        .map(|h| h.value.as_str().to_string())
+        .or_else(|| query_param(url, "name"))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// Look up a query-string parameter, percent-decoding its value.
+///
+/// `+` decodes to a space, matching `application/x-www-form-urlencoded`.
+fn query_param(url: &str, name: &str) -> Option<String> {
+    let qs = url.split_once('?')?.1;
+    qs.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        (key == name).then(|| percent_decode(value))
+    })
+}
+
+/// A stream's time between frames: `?interval=` in ms, or `?fps=`, or
+/// `default` without either.
+pub(crate) fn stream_interval(url: &str, default: Duration) -> Result<Duration, String> {
+    match (query_param(url, "interval"), query_param(url, "fps")) {
+        (None, None) => Ok(default),
+        (Some(ms), None) => ms
+            .parse()
+            .map(Duration::from_millis)
+            .map_err(|_| "interval must be a number of milliseconds".to_string()),
+        (None, Some(fps)) => match fps.parse::<f64>() {
+            Ok(fps) if fps > 0.0 && fps.is_finite() => {
+                Ok(Duration::from_millis((1000.0 / fps).round() as u64))
             }
+            _ => Err(format!("fps must be a positive number, got '{fps}'")),
+        },
+        (Some(_), Some(_)) => Err("pass one of ?interval= and ?fps=".into()),
+    }
+}
+
+/// A stream's `?max_width=`: the widest frame it sends, in pixels. Wider
+/// captures are scaled down to it.
+pub(crate) fn stream_max_width(url: &str) -> Result<Option<u32>, String> {
+    match query_param(url, "max_width").map(|v| v.parse::<u32>()) {
+        None => Ok(None),
+        Some(Ok(width)) if (1..=MAX_WIDTH).contains(&width) => Ok(Some(width)),
+        Some(_) => Err(format!("max_width must be in 1..={MAX_WIDTH}")),
+    }
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => out.push(b' '),
+            (b, _) => out.push(b),
         }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Milliseconds since the Unix epoch.
+fn unix_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Answer `request` with `response`, adding the CORS headers an allowed
+/// page needs to read it (see [`Config::allowed_origins`]).
+fn respond<R: std::io::Read>(request: tiny_http::Request, mut response: tiny_http::Response<R>) {
+    for header in origin::cors_headers(&request).into_iter().flatten() {
+        response.add_header(header);
     }
+    let _ = request.respond(response);
+}
+
+/// Run a [`dom`] inspection script and respond with its JSON result.
+///
+/// The script evaluates to `null` when nothing matches (404) and to
+/// `{ error }` when the selector is invalid (400).
+fn respond_dom_query<R: Runtime>(
+    request: tiny_http::Request,
+    window: &tauri::WebviewWindow<R>,
+    selector: &str,
+    script: &str,
+) {
+    let resp = match eval_json(window, script) {
+        Ok(serde_json::Value::Null) => {
+            tiny_http::Response::from_string(format!("no element matches '{selector}'"))
+                .with_status_code(404)
+        }
+        Ok(serde_json::Value::Object(obj)) if obj.contains_key("error") => {
+            tiny_http::Response::from_string(format!("invalid selector: {}", obj["error"]))
+                .with_status_code(400)
+        }
+        Ok(value) => tiny_http::Response::from_string(value.to_string()).with_header(
+            "Content-Type: application/json"
+                .parse::<tiny_http::Header>()
+                .unwrap(),
+        ),
+        Err(e) => {
+            tiny_http::Response::from_string(format!("eval error: {e}")).with_status_code(500)
+        }
+    };
+    respond(request, resp);
+}
+
+/// Apply a `/devtools` action (`open`, `close`, `toggle` or `status`) and
+/// report whether devtools are open afterwards.
+#[cfg(any(debug_assertions, feature = "devtools"))]
+fn devtools<R: Runtime>(
+    window: &tauri::WebviewWindow<R>,
+    action: &str,
+) -> Result<bool, (u16, String)> {
+    match action {
+        "open" => window.open_devtools(),
+        "close" => window.close_devtools(),
+        "toggle" if window.is_devtools_open() => window.close_devtools(),
+        "toggle" => window.open_devtools(),
+        "status" => {}
+        other => {
+            return Err((
+                400,
+                format!("unknown action '{other}' (open, close, toggle)"),
+            ))
+        }
+    }
+    Ok(window.is_devtools_open())
+}
+
+#[cfg(not(any(debug_assertions, feature = "devtools")))]
+fn devtools<R: Runtime>(
+    _window: &tauri::WebviewWindow<R>,
+    _action: &str,
+) -> Result<bool, (u16, String)> {
+    Err((
+        501,
+        "devtools are only available in debug builds or with the `devtools` feature".into(),
+    ))
+}
+
+/// Where a capture's time went (see `GET /bench`).
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct CaptureTimings {
+    /// Waiting for the main thread to pick up the capture.
+    pub(crate) dispatch: Duration,
+    /// Preparing the snapshot configuration; next to nothing when the last
+    /// capture used the same settings.
+    pub(crate) setup: Duration,
+    /// WebKit rendering the snapshot.
+    pub(crate) snapshot: Duration,
+    /// Color conversion and encoding.
+    pub(crate) encode: Duration,
+    /// Handing the encoded image, or the raw pixels for the pipeline, back
+    /// from the main thread.
+    pub(crate) transfer: Duration,
+}
+
+impl CaptureTimings {
+    /// The stages as a `Server-Timing` header, in milliseconds.
+    fn server_timing(&self) -> tiny_http::Header {
+        let ms = |d: Duration| d.as_secs_f64() * 1e3;
+        format!(
+            "Server-Timing: dispatch;dur={:.2}, setup;dur={:.2}, snapshot;dur={:.2}, \
+             encode;dur={:.2}, transfer;dur={:.2}",
+            ms(self.dispatch),
+            ms(self.setup),
+            ms(self.snapshot),
+            ms(self.encode),
+            ms(self.transfer)
+        )
+        .parse::<tiny_http::Header>()
+        .unwrap()
+    }
+}
+
+thread_local! {
+    /// Stage timings of the last native capture on this thread, for the
+    /// response it ends up in (captures run on the request's thread).
+    static LAST_TIMINGS: Cell<Option<CaptureTimings>> = const { Cell::new(None) };
+}
+
+fn take_screenshot<R: Runtime>(
+    window: &tauri::WebviewWindow<R>,
+    settings: &CaptureOptions,
+) -> Result<Vec<u8>, String> {
+    take_screenshot_timed(window, settings).map(|(png, _)| png)
 }
 
 // ── macOS: native WKWebView.takeSnapshot ─────────────────────────────
 
 #[cfg(target_os = "macos")]
-fn take_screenshot<R: Runtime>(window: &tauri::WebviewWindow<R>) -> Result<Vec<u8>, String> {
-    let (tx, rx) = std::sync::mpsc::channel::<Result<Vec<u8>, String>>();
+fn take_screenshot_timed<R: Runtime>(
+    window: &tauri::WebviewWindow<R>,
+    settings: &CaptureOptions,
+) -> Result<(Vec<u8>, CaptureTimings), String> {
+    type Captured = (Snapshot, CaptureTimings, Instant);
+    let (tx, rx) = std::sync::mpsc::channel::<Result<Captured, String>>();
+    let snapshot_settings = settings.clone();
+    let deadline = settings.deadline;
+    let requested = Instant::now();
 
     window
         .with_webview(move |platform_webview| {
+            let settings = snapshot_settings;
             unsafe {
                 let wk_webview: cocoa::base::id = platform_webview.inner() as cocoa::base::id;
+                let dispatched = Instant::now();
+                let snapshot_config = snapshot_configuration(wk_webview, &settings);
+                let configured = Instant::now();
 
                 let block = block::ConcreteBlock::new(
                     move |ns_image: cocoa::base::id, ns_error: cocoa::base::id| {
+                        let snapshotted = Instant::now();
                         if ns_image == cocoa::base::nil {
-                            let desc: cocoa::base::id =
-                                objc::msg_send![ns_error, localizedDescription];
-                            let cstr: *const std::os::raw::c_char =
-                                objc::msg_send![desc, UTF8String];
-                            let msg = if cstr.is_null() {
-                                "takeSnapshot failed".to_string()
-                            } else {
-                                std::ffi::CStr::from_ptr(cstr)
-                                    .to_string_lossy()
-                                    .into_owned()
-                            };
-                            let _ = tx.send(Err(msg));
+                            let _ =
+                                tx.send(Err(ns_error_message(ns_error, "takeSnapshot failed")));
+                            return;
+                        }
+                        // Don't encode for a client that has given up
+                        if let Err(e) = deadline::check(settings.deadline, "snapshot") {
+                            let _ = tx.send(Err(e));
                             return;
                         }
 
-                        // NSImage → TIFF → NSBitmapImageRep → PNG
-                        let tiff_data: cocoa::base::id =
-                            objc::msg_send![ns_image, TIFFRepresentation];
-                        if tiff_data == cocoa::base::nil {
-                            let _ = tx.send(Err("TIFFRepresentation nil".into()));
+                        // NSImage → CGImage → NSBitmapImageRep → PNG. Wrapping
+                        // the snapshot's CGImage avoids encoding and decoding
+                        // a TIFF copy of the full frame.
+                        let cg_image: *const std::os::raw::c_void = objc::msg_send![
+                            ns_image,
+                            CGImageForProposedRect: std::ptr::null_mut::<cocoa::foundation::NSRect>()
+                            context: cocoa::base::nil
+                            hints: cocoa::base::nil
+                        ];
+                        if cg_image.is_null() {
+                            let _ = tx.send(Err("CGImageForProposedRect returned NULL".into()));
                             return;
                         }
 
                         let alloc: cocoa::base::id =
                             objc::msg_send![objc::class!(NSBitmapImageRep), alloc];
                         let bitmap_rep: cocoa::base::id =
-                            objc::msg_send![alloc, initWithData: tiff_data];
+                            objc::msg_send![alloc, initWithCGImage: cg_image];
                         if bitmap_rep == cocoa::base::nil {
                             let _ = tx.send(Err("NSBitmapImageRep nil".into()));
                             return;
                         }
 
-                        let png_type: u64 = 4; // NSBitmapImageFileTypePNG
+                        // Color conversion yields an autoreleased rep; keep
+                        // `bitmap_rep` around to release it either way.
+                        let target_space: cocoa::base::id = match settings.color_space {
+                            ColorSpace::Native => cocoa::base::nil,
+                            ColorSpace::Srgb => {
+                                objc::msg_send![objc::class!(NSColorSpace), sRGBColorSpace]
+                            }
+                            ColorSpace::DisplayP3 => {
+                                objc::msg_send![objc::class!(NSColorSpace), displayP3ColorSpace]
+                            }
+                        };
+                        let output_rep: cocoa::base::id = if target_space == cocoa::base::nil {
+                            bitmap_rep
+                        } else {
+                            let converted: cocoa::base::id = objc::msg_send![
+                                bitmap_rep,
+                                bitmapImageRepByConvertingToColorSpace: target_space
+                                renderingIntent: 0isize // NSColorRenderingIntentDefault
+                            ];
+                            if converted == cocoa::base::nil {
+                                let _: () = objc::msg_send![bitmap_rep, release];
+                                let _ = tx.send(Err("color space conversion failed".into()));
+                                return;
+                            }
+                            converted
+                        };
+
+                        if settings.embed_icc_profile {
+                            let color_space: cocoa::base::id =
+                                objc::msg_send![output_rep, colorSpace];
+                            let icc: cocoa::base::id = objc::msg_send![color_space, ICCProfileData];
+                            if icc != cocoa::base::nil {
+                                let key = ns_string("NSImageColorSyncProfileData");
+                                let _: () = objc::msg_send![
+                                    output_rep,
+                                    setProperty: key
+                                    withValue: icc
+                                ];
+                                let _: () = objc::msg_send![key, release];
+                            }
+                        }
+
+                        let native = settings
+                            .format
+                            .native_file_type()
+                            .filter(|_| !transform::any(&settings));
+                        let Some(file_type) = native else {
+                            // Copy the pixels out before the rep (which may
+                            // own them) is released, and leave decoding,
+                            // the pipeline and encoding to the request thread
+                            let bitmap = bitmap_data(output_rep);
+                            let _: () = objc::msg_send![bitmap_rep, release];
+                            let timings = CaptureTimings {
+                                dispatch: dispatched - requested,
+                                setup: configured - dispatched,
+                                snapshot: snapshotted - configured,
+                                encode: snapshotted.elapsed(),
+                                transfer: Duration::ZERO,
+                            };
+                            let _ = tx.send(
+                                bitmap.map(|b| (Snapshot::Bitmap(b), timings, Instant::now())),
+                            );
+                            return;
+                        };
+
                         let empty_dict: cocoa::base::id =
                             objc::msg_send![objc::class!(NSDictionary), dictionary];
-                        let png_data: cocoa::base::id = objc::msg_send![
-                            bitmap_rep,
-                            representationUsingType: png_type
+                        let data: cocoa::base::id = objc::msg_send![
+                            output_rep,
+                            representationUsingType: file_type
                             properties: empty_dict
                         ];
 
-                        if png_data == cocoa::base::nil {
+                        if data == cocoa::base::nil {
                             let _: () = objc::msg_send![bitmap_rep, release];
-                            let _ = tx.send(Err("PNG conversion nil".into()));
+                            let _ = tx.send(Err(format!(
+                                "{} conversion nil",
+                                settings.format.extension().to_uppercase()
+                            )));
                             return;
                         }
 
-                        let length: usize = objc::msg_send![png_data, length];
-                        let bytes_ptr: *const u8 = objc::msg_send![png_data, bytes];
-                        let png_bytes =
-                            std::slice::from_raw_parts(bytes_ptr, length).to_vec();
+                        // `data` is autoreleased and independent of the rep,
+                        // so free the decoded frame before copying it out.
                         let _: () = objc::msg_send![bitmap_rep, release];
-                        let _ = tx.send(Ok(png_bytes));
+                        let length: usize = objc::msg_send![data, length];
+                        let bytes_ptr: *const u8 = objc::msg_send![data, bytes];
+                        let bytes = std::slice::from_raw_parts(bytes_ptr, length).to_vec();
+                        let timings = CaptureTimings {
+                            dispatch: dispatched - requested,
+                            setup: configured - dispatched,
+                            snapshot: snapshotted - configured,
+                            encode: snapshotted.elapsed(),
+                            transfer: Duration::ZERO,
+                        };
+                        let _ = tx.send(Ok((Snapshot::Encoded(bytes), timings, Instant::now())));
                     },
                 );
                 let block = block.copy();
 
                 let _: () = objc::msg_send![
                     wk_webview,
-                    takeSnapshotWithConfiguration: cocoa::base::nil
+                    takeSnapshotWithConfiguration: snapshot_config
                     completionHandler: &*block
                 ];
             }
         })
         .map_err(|e| format!("with_webview: {e}"))?;
 
-    rx.recv_timeout(std::time::Duration::from_secs(10))
-        .map_err(|e| format!("snapshot timeout: {e}"))?
+    let timeout = deadline::remaining(deadline, Duration::from_secs(10), "snapshot")?;
+    let (snapshot, mut timings, sent) = rx.recv_timeout(timeout).map_err(|e| {
+        deadline::attribute(deadline, "snapshot", format!("snapshot timeout: {e}"))
+    })??;
+    timings.transfer = sent.elapsed();
+    let png = match snapshot {
+        Snapshot::Encoded(bytes) => bytes,
+        Snapshot::Bitmap(bitmap) => {
+            let started = Instant::now();
+            let frame = transform::apply(bitmap.frame(), settings)?;
+            let encoded = format::encode(&frame, settings.format);
+            budget::recycle(frame.rgba);
+            timings.encode += started.elapsed();
+            encoded?
+        }
+    };
+    Ok((png, timings))
+}
+
+/// What the snapshot completion handler sends back from the main thread.
+#[cfg(target_os = "macos")]
+enum Snapshot {
+    /// Encoded by AppKit, ready to return.
+    Encoded(Vec<u8>),
+    /// Raw pixels for the pipeline.
+    Bitmap(Bitmap),
+}
+
+/// An `NSBitmapImageRep`'s pixels as they are in memory, with what it
+/// takes to read them.
+#[cfg(target_os = "macos")]
+struct Bitmap {
+    width: usize,
+    height: usize,
+    bytes_per_row: usize,
+    /// Bytes per pixel.
+    stride: usize,
+    has_alpha: bool,
+    alpha_first: bool,
+    premultiplied: bool,
+    /// 32-bit little-endian pixels, i.e. the samples in reverse order.
+    swapped: bool,
+    data: Vec<u8>,
+}
+
+#[cfg(target_os = "macos")]
+impl Bitmap {
+    /// Convert to straight-alpha RGBA.
+    fn frame(self) -> format::Frame {
+        let (width, stride) = (self.width, self.stride);
+        let mut rgba = budget::buffer(width * self.height * 4);
+        for row in self.data.chunks(self.bytes_per_row) {
+            for px in row[..width * stride].chunks_exact(stride) {
+                let mut p = [px[0], px[1], px[2], px.get(3).copied().unwrap_or(255)];
+                if self.swapped {
+                    p.reverse();
+                }
+                let [r, g, b, a] = match (self.has_alpha, self.alpha_first) {
+                    (true, true) => [p[1], p[2], p[3], p[0]],
+                    (true, false) => p,
+                    (false, true) => [p[1], p[2], p[3], 255],
+                    (false, false) => [p[0], p[1], p[2], 255],
+                };
+                if self.premultiplied && a != 0 && a != 255 {
+                    let unpremultiply = |c: u8| (u32::from(c) * 255 / u32::from(a)).min(255) as u8;
+                    rgba.extend_from_slice(&[
+                        unpremultiply(r),
+                        unpremultiply(g),
+                        unpremultiply(b),
+                        a,
+                    ]);
+                } else {
+                    rgba.extend_from_slice(&[r, g, b, a]);
+                }
+            }
+        }
+        budget::recycle(self.data);
+        format::Frame {
+            width: width as u32,
+            height: self.height as u32,
+            rgba,
+        }
+    }
+}
+
+/// Copy an `NSBitmapImageRep`'s pixels out, to be decoded off the main
+/// thread.
+#[cfg(target_os = "macos")]
+unsafe fn bitmap_data(rep: cocoa::base::id) -> Result<Bitmap, String> {
+    // NSBitmapFormat flags
+    const ALPHA_FIRST: usize = 1 << 0;
+    const ALPHA_NONPREMULTIPLIED: usize = 1 << 1;
+    const FLOATING_POINT: usize = 1 << 2;
+    const LITTLE_ENDIAN_32: usize = 1 << 9;
+
+    let width: isize = objc::msg_send![rep, pixelsWide];
+    let height: isize = objc::msg_send![rep, pixelsHigh];
+    let bits_per_sample: isize = objc::msg_send![rep, bitsPerSample];
+    let samples: isize = objc::msg_send![rep, samplesPerPixel];
+    let bits_per_pixel: isize = objc::msg_send![rep, bitsPerPixel];
+    let bytes_per_row: isize = objc::msg_send![rep, bytesPerRow];
+    let planar: bool = objc::msg_send![rep, isPlanar];
+    let has_alpha: bool = objc::msg_send![rep, hasAlpha];
+    let bitmap_format: usize = objc::msg_send![rep, bitmapFormat];
+    let data: *const u8 = objc::msg_send![rep, bitmapData];
+
+    let stride = (bits_per_pixel / 8) as usize;
+    if data.is_null()
+        || planar
+        || bits_per_sample != 8
+        || !(3..=4).contains(&samples)
+        || !(3..=4).contains(&stride)
+        || bitmap_format & FLOATING_POINT != 0
+    {
+        return Err(format!(
+            "unsupported bitmap layout ({samples} samples, {bits_per_sample} bits, \
+             format {bitmap_format:#x}, planar {planar})"
+        ));
+    }
+    let height = height as usize;
+    let bytes = std::slice::from_raw_parts(data, bytes_per_row as usize * height);
+    let mut copy = budget::buffer(bytes.len());
+    copy.extend_from_slice(bytes);
+    Ok(Bitmap {
+        width: width as usize,
+        height,
+        bytes_per_row: bytes_per_row as usize,
+        stride,
+        has_alpha,
+        alpha_first: has_alpha && bitmap_format & ALPHA_FIRST != 0,
+        premultiplied: has_alpha && bitmap_format & ALPHA_NONPREMULTIPLIED == 0,
+        swapped: bitmap_format & LITTLE_ENDIAN_32 != 0,
+        data: copy,
+    })
+}
+
+/// What a cached `WKSnapshotConfiguration` was built for.
+#[cfg(target_os = "macos")]
+#[derive(PartialEq)]
+struct SnapshotKey {
+    clip: Option<Clip>,
+    /// `snapshotWidth`.
+    points: f64,
+}
+
+#[cfg(target_os = "macos")]
+thread_local! {
+    /// The last configuration built. Captures build them on the main thread,
+    /// and back-to-back ones (streams, `/bench`) nearly always want the
+    /// same, so it's kept rather than rebuilt.
+    static SNAPSHOT_CONFIG: std::cell::RefCell<Option<(SnapshotKey, cocoa::base::id)>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+/// A `WKSnapshotConfiguration` for a scale or width override, or nil to
+/// capture at the display's backing scale. The last one is cached and owned
+/// by the cache, so callers don't release it.
+///
+/// `snapshotWidth` is in points and the snapshot is still rendered at the
+/// backing scale, so the requested pixel width is divided by it.
+#[cfg(target_os = "macos")]
+unsafe fn snapshot_configuration(
+    wk_webview: cocoa::base::id,
+    settings: &CaptureOptions,
+) -> cocoa::base::id {
+    if settings.width.is_none() && settings.scale.is_none() && settings.clip.is_none() {
+        return cocoa::base::nil;
+    }
+
+    let bounds: cocoa::foundation::NSRect = objc::msg_send![wk_webview, bounds];
+    let ns_window: cocoa::base::id = objc::msg_send![wk_webview, window];
+    let backing: f64 = if ns_window == cocoa::base::nil {
+        1.0
+    } else {
+        objc::msg_send![ns_window, backingScaleFactor]
+    };
+    let css_width = settings.clip.map_or(bounds.size.width, |clip| clip.width);
+    let pixels = match (settings.width, settings.scale) {
+        (Some(width), _) => f64::from(width),
+        (None, scale) => css_width * scale.unwrap_or(backing),
+    };
+    let key = SnapshotKey {
+        clip: settings.clip,
+        points: pixels / backing,
+    };
+
+    SNAPSHOT_CONFIG.with_borrow_mut(|cached| {
+        if let Some((_, config)) = cached.as_ref().filter(|(k, _)| *k == key) {
+            return *config;
+        }
+        let config: cocoa::base::id = objc::msg_send![objc::class!(WKSnapshotConfiguration), new];
+        if let Some(clip) = settings.clip {
+            let rect = cocoa::foundation::NSRect::new(
+                cocoa::foundation::NSPoint::new(clip.x, clip.y),
+                cocoa::foundation::NSSize::new(clip.width, clip.height),
+            );
+            let _: () = objc::msg_send![config, setRect: rect];
+        }
+        let points: cocoa::base::id =
+            objc::msg_send![objc::class!(NSNumber), numberWithDouble: key.points];
+        let _: () = objc::msg_send![config, setSnapshotWidth: points];
+        // WebKit copies the configuration, so the old one can go
+        if let Some((_, old)) = cached.replace((key, config)) {
+            let _: () = objc::msg_send![old, release];
+        }
+        config
+    })
+}
+
+// ── macOS: native WKWebView.evaluateJavaScript ───────────────────────
+
+/// Evaluate a JS expression and return its value, round-tripped through
+/// `JSON.stringify`.
+///
+/// Unlike `WebviewWindow::eval`, this waits for the result — it's what the
+/// DOM inspection endpoints build on.
+#[cfg(target_os = "macos")]
+fn eval_json<R: Runtime>(
+    window: &tauri::WebviewWindow<R>,
+    expr: &str,
+) -> Result<serde_json::Value, String> {
+    let (tx, rx) = std::sync::mpsc::channel::<Result<String, String>>();
+    // Wrapping in an object keeps `undefined` results serializable.
+    let script = format!("JSON.stringify({{ value: {expr} }})");
+
+    window
+        .with_webview(move |platform_webview| {
+            unsafe {
+                let wk_webview: cocoa::base::id = platform_webview.inner() as cocoa::base::id;
+                let ns_script = ns_string(&script);
+
+                let block = block::ConcreteBlock::new(
+                    move |result: cocoa::base::id, ns_error: cocoa::base::id| {
+                        let msg = match ns_string_to_string(result) {
+                            Some(json) => Ok(json),
+                            None => Err(ns_error_message(ns_error, "evaluateJavaScript failed")),
+                        };
+                        let _ = tx.send(msg);
+                    },
+                );
+                let block = block.copy();
+
+                let _: () = objc::msg_send![
+                    wk_webview,
+                    evaluateJavaScript: ns_script
+                    completionHandler: &*block
+                ];
+                let _: () = objc::msg_send![ns_script, release];
+            }
+        })
+        .map_err(|e| format!("with_webview: {e}"))?;
+
+    let json = rx
+        .recv_timeout(std::time::Duration::from_secs(10))
+        .map_err(|e| format!("eval timeout: {e}"))??;
+    let mut envelope: serde_json::Value =
+        serde_json::from_str(&json).map_err(|e| format!("bad eval result: {e}"))?;
+    Ok(envelope["value"].take())
+}
+
+/// Create an owned `NSString` (caller releases).
+#[cfg(target_os = "macos")]
+unsafe fn ns_string(s: &str) -> cocoa::base::id {
+    let alloc: cocoa::base::id = objc::msg_send![objc::class!(NSString), alloc];
+    objc::msg_send![
+        alloc,
+        initWithBytes: s.as_ptr() as *const std::os::raw::c_void
+        length: s.len()
+        encoding: 4usize // NSUTF8StringEncoding
+    ]
+}
+
+/// Copy an `NSString` into a Rust string; `None` for nil.
+#[cfg(target_os = "macos")]
+unsafe fn ns_string_to_string(ns_string: cocoa::base::id) -> Option<String> {
+    if ns_string == cocoa::base::nil {
+        return None;
+    }
+    let cstr: *const std::os::raw::c_char = objc::msg_send![ns_string, UTF8String];
+    if cstr.is_null() {
+        return None;
+    }
+    Some(std::ffi::CStr::from_ptr(cstr).to_string_lossy().into_owned())
+}
+
+/// Describe an `NSError`, preferring the JS exception message WebKit attaches
+/// to script errors over its generic "A JavaScript exception occurred".
+#[cfg(target_os = "macos")]
+unsafe fn ns_error_message(ns_error: cocoa::base::id, fallback: &str) -> String {
+    if ns_error == cocoa::base::nil {
+        return fallback.to_string();
+    }
+    let user_info: cocoa::base::id = objc::msg_send![ns_error, userInfo];
+    let key = ns_string("WKJavaScriptExceptionMessage");
+    let exception: cocoa::base::id = objc::msg_send![user_info, objectForKey: key];
+    let _: () = objc::msg_send![key, release];
+
+    let desc: cocoa::base::id = if exception != cocoa::base::nil {
+        exception
+    } else {
+        objc::msg_send![ns_error, localizedDescription]
+    };
+    ns_string_to_string(desc).unwrap_or_else(|| fallback.to_string())
 }
 
 // ── Non-macOS: stub that returns an error ────────────────────────────
 
 #[cfg(not(target_os = "macos"))]
-fn take_screenshot<R: Runtime>(_window: &tauri::WebviewWindow<R>) -> Result<Vec<u8>, String> {
+fn take_screenshot_timed<R: Runtime>(
+    _window: &tauri::WebviewWindow<R>,
+    _settings: &CaptureOptions,
+) -> Result<(Vec<u8>, CaptureTimings), String> {
     Err("Native screenshots are only supported on macOS (WKWebView.takeSnapshot). \
          On other platforms, use the WebDriver screenshot endpoint instead."
         .into())
 }
+
+#[cfg(not(target_os = "macos"))]
+fn eval_json<R: Runtime>(
+    _window: &tauri::WebviewWindow<R>,
+    _expr: &str,
+) -> Result<serde_json::Value, String> {
+    Err("Reading values back from the webview is only supported on macOS \
+         (WKWebView.evaluateJavaScript)."
+        .into())
+}
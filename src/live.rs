@@ -0,0 +1,549 @@
+//! `POST /webrtc`: a live view of the window over WebRTC (`webrtc`
+//! feature), for remote reviewers who'd otherwise poll captures over a VPN.
+//!
+//! The exchange follows WHEP: the viewer posts its SDP offer and gets the
+//! answer back with a `Location` to `DELETE` when done. Captures are encoded
+//! as AV1 (rav1e, tuned for latency) and sent as RTP on a single video
+//! track; only host ICE candidates are gathered, so the viewer has to be
+//! able to reach the machine directly, e.g. on the same network or VPN.
+
+use crate::adaptive::{Adaptive, Quality};
+use crate::budget;
+use crate::idle::{self, IdleWatch};
+use crate::logging;
+use crate::{format, CaptureOptions, Context, ImageFormat};
+use rav1e::prelude::{
+    ChromaSampling, Config as EncoderSetup, Context as Encoder, EncoderConfig, EncoderStatus,
+    FrameParameters, FrameTypeOverride, PixelRange, Rational,
+};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{Runtime, WebviewWindow};
+use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_AV1};
+use webrtc::api::APIBuilder;
+use webrtc::interceptor::registry::Registry;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtcp::payload_feedbacks::full_intra_request::FullIntraRequest;
+use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+use webrtc::rtp_transceiver::rtp_codec::{
+    RTCRtpCodecCapability, RTCRtpCodecParameters, RTPCodecType,
+};
+use webrtc::rtp_transceiver::RTCPFeedback;
+use webrtc::track::track_local::track_local_static_rtp::TrackLocalStaticRTP;
+use webrtc::track::track_local::{TrackLocal, TrackLocalWriter};
+
+/// Time between frames when the request doesn't say: 10 fps.
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(100);
+const MIN_INTERVAL: Duration = Duration::from_millis(33);
+/// Each viewer has its own encoder, which is the expensive part.
+const MAX_SESSIONS: usize = 4;
+/// Force a keyframe this often, so a viewer recovers from losses the
+/// receiver didn't ask about.
+const KEYFRAME_INTERVAL: Duration = Duration::from_secs(5);
+/// Largest RTP payload, leaving room for headers and SRTP under a 1200-byte
+/// datagram.
+const MAX_PAYLOAD: usize = 1100;
+/// RTP clock of video, in Hz.
+const CLOCK_RATE: u32 = 90_000;
+/// Offered for AV1; the viewer's own number is used once negotiated.
+const PAYLOAD_TYPE: u8 = 45;
+
+/// The live views being streamed, by ID.
+#[derive(Default)]
+pub(crate) struct LiveSessions {
+    next_id: AtomicU64,
+    active: Mutex<HashMap<u64, Arc<Session>>>,
+}
+
+#[derive(Default)]
+struct Session {
+    /// Set to end the session.
+    stop: AtomicBool,
+    /// What the stream is sending at, for `GET /webrtc/<id>`.
+    quality: Mutex<Quality>,
+}
+
+impl LiveSessions {
+    /// End session `id`. `false` if there's no such session.
+    pub(crate) fn stop(&self, id: u64) -> bool {
+        match self.active.lock().unwrap().remove(&id) {
+            Some(session) => {
+                session.stop.store(true, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The quality session `id` is streaming at, if there is one.
+    pub(crate) fn quality(&self, id: u64) -> Option<Quality> {
+        let active = self.active.lock().unwrap();
+        active
+            .get(&id)
+            .map(|session| *session.quality.lock().unwrap())
+    }
+}
+
+/// Answer `offer` and start streaming `window` to it, a frame every
+/// `?interval=` ms (or `?fps=`) with the capture options in `url`. Returns the session ID
+/// and the SDP answer.
+pub(crate) fn connect<R: Runtime>(
+    window: WebviewWindow<R>,
+    context: Arc<Context>,
+    url: &str,
+    offer: String,
+    deadline: Instant,
+) -> Result<(u64, String), (u16, String)> {
+    let interval = crate::stream_interval(url, DEFAULT_INTERVAL).map_err(|e| (400, e))?;
+    if interval < MIN_INTERVAL {
+        return Err((
+            400,
+            format!(
+                "interval must be at least {} ms (fps at most {})",
+                MIN_INTERVAL.as_millis(),
+                1000 / MIN_INTERVAL.as_millis()
+            ),
+        ));
+    }
+    // QOI is the cheapest to encode and decode again
+    let settings = CaptureOptions {
+        format: ImageFormat::Qoi,
+        ..context
+            .capture_defaults
+            .clone()
+            .with_query(url)
+            .map_err(|e| (400, e))?
+    };
+    let adaptive = Adaptive::new(url, interval).map_err(|e| (400, e))?;
+    if context.live.active.lock().unwrap().len() >= MAX_SESSIONS {
+        return Err((503, format!("at most {MAX_SESSIONS} live views at once")));
+    }
+
+    let keyframe = Arc::new(AtomicBool::new(true));
+    let timeout = deadline.saturating_duration_since(Instant::now());
+    let (peer, track, answer) = tauri::async_runtime::block_on(async {
+        tokio::time::timeout(timeout, negotiate(offer, keyframe.clone())).await
+    })
+    .map_err(|_| (504, "ICE gathering didn't finish in time".to_string()))??;
+
+    let id = context.live.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+    let session = Arc::new(Session {
+        quality: Mutex::new(adaptive.quality()),
+        ..Default::default()
+    });
+    context
+        .live
+        .active
+        .lock()
+        .unwrap()
+        .insert(id, session.clone());
+
+    peer.on_peer_connection_state_change(Box::new({
+        let session = session.clone();
+        move |state| {
+            if matches!(
+                state,
+                RTCPeerConnectionState::Disconnected
+                    | RTCPeerConnectionState::Failed
+                    | RTCPeerConnectionState::Closed
+            ) {
+                session.stop.store(true, Ordering::SeqCst);
+            }
+            Box::pin(async {})
+        }
+    }));
+
+    std::thread::spawn(move || {
+        let stream = Stream {
+            track,
+            adaptive,
+            keyframe,
+            sequence: 0,
+        };
+        stream.run(&window, &context, &settings, &session);
+        context.live.active.lock().unwrap().remove(&id);
+        let _ = tauri::async_runtime::block_on(peer.close());
+    });
+    Ok((id, answer))
+}
+
+/// Set up a peer connection sending one AV1 track and answer `offer`,
+/// once ICE gathering has finished (there's no trickle ICE). `keyframe` is
+/// set whenever the viewer asks for one.
+async fn negotiate(
+    offer: String,
+    keyframe: Arc<AtomicBool>,
+) -> Result<(Arc<RTCPeerConnection>, Arc<TrackLocalStaticRTP>, String), (u16, String)> {
+    let failed = |e: webrtc::Error| (500, e.to_string());
+    let capability = RTCRtpCodecCapability {
+        mime_type: MIME_TYPE_AV1.to_string(),
+        clock_rate: CLOCK_RATE,
+        channels: 0,
+        sdp_fmtp_line: String::new(),
+        rtcp_feedback: ["nack", "nack pli", "ccm fir"]
+            .iter()
+            .map(|feedback| {
+                let (typ, parameter) = feedback.split_once(' ').unwrap_or((feedback, ""));
+                RTCPFeedback {
+                    typ: typ.to_string(),
+                    parameter: parameter.to_string(),
+                }
+            })
+            .collect(),
+    };
+    let mut media = MediaEngine::default();
+    media
+        .register_codec(
+            RTCRtpCodecParameters {
+                capability: capability.clone(),
+                payload_type: PAYLOAD_TYPE,
+                ..Default::default()
+            },
+            RTPCodecType::Video,
+        )
+        .map_err(failed)?;
+    let interceptors =
+        register_default_interceptors(Registry::new(), &mut media).map_err(failed)?;
+    let api = APIBuilder::new()
+        .with_media_engine(media)
+        .with_interceptor_registry(interceptors)
+        .build();
+    let peer = Arc::new(
+        api.new_peer_connection(RTCConfiguration::default())
+            .await
+            .map_err(failed)?,
+    );
+
+    let offer = RTCSessionDescription::offer(offer).map_err(|e| (400, e.to_string()))?;
+    peer.set_remote_description(offer)
+        .await
+        .map_err(|e| (400, format!("invalid offer: {e}")))?;
+    let track = Arc::new(TrackLocalStaticRTP::new(
+        capability,
+        "video".to_string(),
+        "screenshot-hd".to_string(),
+    ));
+    let sender = peer
+        .add_track(track.clone() as Arc<dyn TrackLocal + Send + Sync>)
+        .await
+        .map_err(failed)?;
+    let answer = peer.create_answer(None).await.map_err(failed)?;
+    let mut gathered = peer.gathering_complete_promise().await;
+    peer.set_local_description(answer).await.map_err(failed)?;
+    let _ = gathered.recv().await;
+    let answer = peer
+        .local_description()
+        .await
+        .ok_or((500, "no local description".to_string()))?;
+
+    // Reading RTCP also runs the interceptors, e.g. resending on NACK
+    tauri::async_runtime::spawn(async move {
+        while let Ok((packets, _)) = sender.read_rtcp().await {
+            let asked = packets.iter().any(|packet| {
+                let packet = packet.as_any();
+                packet.downcast_ref::<PictureLossIndication>().is_some()
+                    || packet.downcast_ref::<FullIntraRequest>().is_some()
+            });
+            if asked {
+                keyframe.store(true, Ordering::SeqCst);
+            }
+        }
+    });
+    Ok((peer, track, answer.sdp))
+}
+
+/// Encodes captures and sends them on `track`.
+struct Stream {
+    track: Arc<TrackLocalStaticRTP>,
+    adaptive: Adaptive,
+    /// Set when the next frame has to be a keyframe.
+    keyframe: Arc<AtomicBool>,
+    sequence: u16,
+}
+
+impl Stream {
+    /// Capture, encode and send until the session is stopped, a failure,
+    /// or the server shutting down. The encoder is rebuilt when the capture
+    /// size changes, e.g. when adapting to load.
+    fn run<R: Runtime>(
+        mut self,
+        window: &WebviewWindow<R>,
+        context: &Context,
+        settings: &CaptureOptions,
+        session: &Session,
+    ) {
+        let started = Instant::now();
+        let mut encoder: Option<(Encoder<u8>, (u32, u32))> = None;
+        let mut last_keyframe = started;
+        // While paused nothing is sent; RTP timestamps carry the gap
+        let mut idle = IdleWatch::new(context.idle_pause);
+
+        while !session.stop.load(Ordering::SeqCst) && !context.shutdown.load(Ordering::SeqCst) {
+            if let Some(idle) = &mut idle {
+                if idle.paused(window) {
+                    std::thread::sleep(idle::POLL);
+                    continue;
+                }
+                if idle.take_gap().is_some() {
+                    self.keyframe.store(true, Ordering::SeqCst);
+                }
+            }
+            let tick = Instant::now();
+            let frame = match context
+                .snapshot(window, &self.adaptive.settings(window, settings))
+                .and_then(|bytes| format::decode(&bytes))
+            {
+                Ok(frame) => frame,
+                Err(e) => {
+                    logging::debug!("[screenshot-hd] live view capture failed: {e}");
+                    std::thread::sleep(self.adaptive.interval());
+                    continue;
+                }
+            };
+            let _charge = match context
+                .memory
+                .reserve(frame.rgba.len(), "a live view frame")
+            {
+                Ok(charge) => charge,
+                Err(e) => {
+                    logging::debug!("[screenshot-hd] live view frame skipped: {e}");
+                    budget::recycle(frame.rgba);
+                    std::thread::sleep(self.adaptive.interval());
+                    continue;
+                }
+            };
+            // 4:2:0 needs even dimensions
+            let size = (frame.width & !1, frame.height & !1);
+            if size.0 == 0 || size.1 == 0 {
+                std::thread::sleep(self.adaptive.interval());
+                continue;
+            }
+            if encoder.as_ref().is_none_or(|(_, current)| *current != size) {
+                match new_encoder(size, self.adaptive.interval()) {
+                    Ok(new) => encoder = Some((new, size)),
+                    Err(e) => {
+                        logging::warn!("[screenshot-hd] live view encoder failed: {e}");
+                        break;
+                    }
+                }
+                self.keyframe.store(true, Ordering::SeqCst);
+            }
+            let Some((encoder, _)) = &mut encoder else {
+                break;
+            };
+
+            let mut input = encoder.new_frame();
+            let [y, u, v] = yuv420(&frame, size);
+            budget::recycle(frame.rgba);
+            let width = size.0 as usize;
+            input.planes[0].copy_from_raw_u8(&y, width, 1);
+            input.planes[1].copy_from_raw_u8(&u, width / 2, 1);
+            input.planes[2].copy_from_raw_u8(&v, width / 2, 1);
+
+            let force = self.keyframe.swap(false, Ordering::SeqCst)
+                || last_keyframe.elapsed() >= KEYFRAME_INTERVAL;
+            if force {
+                last_keyframe = Instant::now();
+            }
+            let params = FrameParameters {
+                frame_type_override: if force {
+                    FrameTypeOverride::Key
+                } else {
+                    FrameTypeOverride::No
+                },
+                ..Default::default()
+            };
+            if let Err(e) = encoder.send_frame((input, params)) {
+                logging::warn!("[screenshot-hd] live view encoding failed: {e:?}");
+                break;
+            }
+            let timestamp =
+                (started.elapsed().as_micros() * u128::from(CLOCK_RATE) / 1_000_000) as u32;
+            loop {
+                match encoder.receive_packet() {
+                    Ok(packet) => self.send(&packet.data, timestamp),
+                    Err(EncoderStatus::Encoded) => continue,
+                    Err(EncoderStatus::NeedMoreData) => break,
+                    Err(e) => {
+                        logging::warn!("[screenshot-hd] live view encoding failed: {e:?}");
+                        return;
+                    }
+                }
+            }
+
+            let work = tick.elapsed();
+            if self.adaptive.record(work) {
+                *session.quality.lock().unwrap() = self.adaptive.quality();
+            }
+            std::thread::sleep(self.adaptive.interval().saturating_sub(work));
+        }
+    }
+
+    /// Packetize one temporal unit as RTP (RFC-style AV1 payload: one OBU
+    /// per packet chain, fragmented to fit) and send it.
+    fn send(&mut self, temporal_unit: &[u8], timestamp: u32) {
+        let obus = obus(temporal_unit);
+        let new_sequence = obus
+            .first()
+            .is_some_and(|obu| obu_type(obu) == OBU_SEQUENCE_HEADER);
+        let mut packets = Vec::new();
+        for (i, obu) in obus.iter().enumerate() {
+            let chunks: Vec<&[u8]> = obu.chunks(MAX_PAYLOAD).collect();
+            for (j, chunk) in chunks.iter().enumerate() {
+                // Z: continues a fragment, Y: continued next, W=1: one
+                // element without a length, N: starts a coded video sequence
+                let mut aggregation = 0b0001_0000;
+                if j > 0 {
+                    aggregation |= 0b1000_0000;
+                }
+                if j + 1 < chunks.len() {
+                    aggregation |= 0b0100_0000;
+                }
+                if new_sequence && i == 0 && j == 0 {
+                    aggregation |= 0b0000_1000;
+                }
+                let mut payload = Vec::with_capacity(1 + chunk.len());
+                payload.push(aggregation);
+                payload.extend_from_slice(chunk);
+                packets.push(payload);
+            }
+        }
+
+        let count = packets.len();
+        for (i, payload) in packets.into_iter().enumerate() {
+            let packet = webrtc::rtp::packet::Packet {
+                header: webrtc::rtp::header::Header {
+                    version: 2,
+                    marker: i + 1 == count,
+                    sequence_number: self.sequence,
+                    timestamp,
+                    ..Default::default()
+                },
+                payload: payload.into(),
+            };
+            self.sequence = self.sequence.wrapping_add(1);
+            if let Err(e) = tauri::async_runtime::block_on(self.track.write_rtp(&packet)) {
+                logging::debug!("[screenshot-hd] live view send failed: {e}");
+            }
+        }
+    }
+}
+
+/// A low-latency AV1 encoder for `size` frames every `interval`.
+fn new_encoder(size: (u32, u32), interval: Duration) -> Result<Encoder<u8>, String> {
+    let mut config = EncoderConfig::with_speed_preset(10);
+    config.width = size.0 as usize;
+    config.height = size.1 as usize;
+    config.time_base = Rational::new(interval.as_millis() as u64, 1000);
+    config.chroma_sampling = ChromaSampling::Cs420;
+    config.pixel_range = PixelRange::Limited;
+    config.low_latency = true;
+    config.speed_settings.rdo_lookahead_frames = 1;
+    // Keyframes are forced (on request and every KEYFRAME_INTERVAL)
+    config.min_key_frame_interval = 0;
+    config.max_key_frame_interval = u64::from(u16::MAX);
+    EncoderSetup::new()
+        .with_encoder_config(config)
+        .new_context()
+        .map_err(|e| e.to_string())
+}
+
+/// BT.601 limited-range Y, U and V planes of the top-left `size` of
+/// `frame`, chroma averaged over 2x2 blocks.
+fn yuv420(frame: &format::Frame, size: (u32, u32)) -> [Vec<u8>; 3] {
+    let (width, height) = (size.0 as usize, size.1 as usize);
+    let stride = frame.width as usize * 4;
+    let pixel = |x: usize, y: usize| {
+        let i = y * stride + x * 4;
+        let p = &frame.rgba[i..i + 3];
+        [i32::from(p[0]), i32::from(p[1]), i32::from(p[2])]
+    };
+    let mut luma = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let [r, g, b] = pixel(x, y);
+            luma.push(((66 * r + 129 * g + 25 * b + 128) >> 8) as u8 + 16);
+        }
+    }
+    let mut u = Vec::with_capacity(width * height / 4);
+    let mut v = Vec::with_capacity(width * height / 4);
+    for y in (0..height).step_by(2) {
+        for x in (0..width).step_by(2) {
+            let [r, g, b] = [
+                pixel(x, y),
+                pixel(x + 1, y),
+                pixel(x, y + 1),
+                pixel(x + 1, y + 1),
+            ]
+            .iter()
+            .fold([0, 0, 0], |sum, p| {
+                [sum[0] + p[0], sum[1] + p[1], sum[2] + p[2]]
+            })
+            .map(|c| c / 4);
+            u.push((((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8);
+            v.push((((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8);
+        }
+    }
+    [luma, u, v]
+}
+
+const OBU_SEQUENCE_HEADER: u8 = 1;
+const OBU_TEMPORAL_DELIMITER: u8 = 2;
+const OBU_TILE_LIST: u8 = 8;
+
+fn obu_type(obu: &[u8]) -> u8 {
+    (obu[0] >> 3) & 0x0f
+}
+
+/// Split a temporal unit into OBUs as RTP carries them: without temporal
+/// delimiters and tile lists, and without their size fields.
+fn obus(data: &[u8]) -> Vec<Vec<u8>> {
+    let mut obus = Vec::new();
+    let mut rest = data;
+    while let Some(&header) = rest.first() {
+        let extension = header & 0b100 != 0;
+        let has_size = header & 0b10 != 0;
+        let header_len = 1 + usize::from(extension);
+        let Some(after_header) = rest.get(header_len..) else {
+            break;
+        };
+        let (size, body) = if has_size {
+            let Some((size, len)) = leb128(after_header) else {
+                break;
+            };
+            (size, &after_header[len..])
+        } else {
+            (after_header.len(), after_header)
+        };
+        let Some(payload) = body.get(..size) else {
+            break;
+        };
+        if !matches!((header >> 3) & 0x0f, OBU_TEMPORAL_DELIMITER | OBU_TILE_LIST) {
+            let mut obu = Vec::with_capacity(header_len + size);
+            obu.push(header & !0b10);
+            obu.extend_from_slice(&rest[1..header_len]);
+            obu.extend_from_slice(payload);
+            obus.push(obu);
+        }
+        rest = &body[size..];
+    }
+    obus
+}
+
+/// A LEB128 value and how many bytes it took.
+fn leb128(data: &[u8]) -> Option<(usize, usize)> {
+    let mut value = 0usize;
+    for (i, byte) in data.iter().take(8).enumerate() {
+        value |= usize::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
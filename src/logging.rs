@@ -0,0 +1,81 @@
+//! The plugin's own logging (see [`Config::log`](crate::Config::log)): every
+//! message goes to one target at most at the configured level, so apps can
+//! route or silence it apart from their own logs.
+
+use log::{Level, LevelFilter};
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::OnceLock;
+
+/// Target the plugin logs to unless configured otherwise. It's the crate
+/// name, so filters written for the plugin's module paths keep matching.
+pub const DEFAULT_LOG_TARGET: &str = "tauri_plugin_screenshot_hd";
+
+/// What the plugin logs (see [`Config::log`](crate::Config::log)).
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    /// Most verbose level logged; the app's logger may filter further.
+    /// Default: [`LevelFilter::Info`]
+    pub level: LevelFilter,
+    /// Target of every message. Default: [`DEFAULT_LOG_TARGET`]
+    pub target: String,
+    /// Log scripts run through `/eval` and the other eval paths in full at
+    /// debug level, instead of only their length. Default: `false`
+    pub include_bodies: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: LevelFilter::Info,
+            target: DEFAULT_LOG_TARGET.to_string(),
+            include_bodies: false,
+        }
+    }
+}
+
+/// Settings of the first plugin instance; logging is process-wide.
+static CONFIG: OnceLock<LogConfig> = OnceLock::new();
+
+pub(crate) fn init(config: &LogConfig) {
+    let _ = CONFIG.set(config.clone());
+}
+
+fn config() -> &'static LogConfig {
+    CONFIG.get_or_init(LogConfig::default)
+}
+
+/// Log `args` at `level`, if the configuration lets it through.
+pub(crate) fn log(level: Level, args: fmt::Arguments) {
+    let config = config();
+    if level <= config.level {
+        log::log!(target: &config.target, level, "{args}");
+    }
+}
+
+/// `body` as it may be logged: in full with `include_bodies`, otherwise
+/// just its size.
+pub(crate) fn body(body: &str) -> Cow<'_, str> {
+    if config().include_bodies {
+        Cow::Borrowed(body)
+    } else {
+        Cow::Owned(format!("<{} bytes>", body.len()))
+    }
+}
+
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::logging::log(::log::Level::Error, format_args!($($arg)+)) };
+}
+// Named apart from the built-in `warn` attribute, which `use` can't tell
+// it from
+macro_rules! warning {
+    ($($arg:tt)+) => { $crate::logging::log(::log::Level::Warn, format_args!($($arg)+)) };
+}
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::logging::log(::log::Level::Info, format_args!($($arg)+)) };
+}
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::logging::log(::log::Level::Debug, format_args!($($arg)+)) };
+}
+
+pub(crate) use {debug, error, info, warning as warn};
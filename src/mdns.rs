@@ -0,0 +1,65 @@
+//! Advertises the server as `_screenshot-hd._tcp` over mDNS/Bonjour, so
+//! device-lab dashboards can discover running instances (`Config::mdns`).
+
+use crate::logging;
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+const SERVICE_TYPE: &str = "_screenshot-hd._tcp.local.";
+
+/// Register the service, returning the daemon that answers queries for it
+/// (dropping it stops the advertisement). Loopback servers aren't
+/// advertised, since nothing else on the network could reach them.
+pub(crate) fn advertise(
+    host: &str,
+    port: u16,
+    window_label: &str,
+    app_name: &str,
+    app_version: &str,
+) -> Option<ServiceDaemon> {
+    let ip = match host.parse::<IpAddr>() {
+        Ok(ip) if ip.is_loopback() => {
+            logging::info!("[screenshot-hd] not advertising over mDNS: {host} is loopback");
+            return None;
+        }
+        Ok(ip) => ip,
+        Err(_) => {
+            logging::warn!("[screenshot-hd] not advertising over mDNS: {host} isn't an IP address");
+            return None;
+        }
+    };
+
+    let pid = std::process::id();
+    // Instance names are limited to 63 bytes
+    let app: String = app_name.chars().take(32).collect();
+    let instance = format!("{app} ({window_label}) [{pid}]");
+    let host_name = format!("screenshot-hd-{pid}.local.");
+    let properties = HashMap::from([
+        ("window".to_string(), window_label.to_string()),
+        ("version".to_string(), env!("CARGO_PKG_VERSION").to_string()),
+        ("app".to_string(), app_name.to_string()),
+        ("app_version".to_string(), app_version.to_string()),
+    ]);
+
+    let result = ServiceDaemon::new().and_then(|daemon| {
+        let info = if ip.is_unspecified() {
+            ServiceInfo::new(SERVICE_TYPE, &instance, &host_name, (), port, properties)?
+                .enable_addr_auto()
+        } else {
+            ServiceInfo::new(SERVICE_TYPE, &instance, &host_name, ip, port, properties)?
+        };
+        daemon.register(info)?;
+        Ok(daemon)
+    });
+    match result {
+        Ok(daemon) => {
+            logging::info!("[screenshot-hd] advertising '{instance}' as {SERVICE_TYPE}");
+            Some(daemon)
+        }
+        Err(e) => {
+            logging::warn!("[screenshot-hd] mDNS advertisement failed: {e}");
+            None
+        }
+    }
+}
@@ -0,0 +1,600 @@
+//! `GET /openapi.json`: an OpenAPI 3.1 description of every endpoint, so
+//! clients can be generated from (or checked against) what the server
+//! actually accepts.
+
+use crate::ImageFormat;
+use serde_json::{json, Map, Value};
+use std::net::SocketAddr;
+
+/// A query parameter: name, JSON Schema type, description.
+type Param = (&'static str, &'static str, &'static str);
+
+struct Endpoint {
+    methods: &'static [&'static str],
+    path: &'static str,
+    summary: &'static str,
+    params: &'static [Param],
+    /// Takes the capture options (`#/components/parameters/capture.*`).
+    capture: bool,
+    /// Request body content type, if any.
+    body: Option<&'static str>,
+    /// Content type of a successful response.
+    response: &'static str,
+}
+
+/// The capture options shared by every capture endpoint (see
+/// [`CaptureOptions`](crate::CaptureOptions)). `format` is added with its
+/// enum in [`spec`].
+const CAPTURE_PARAMS: &[Param] = &[
+    ("color_space", "string", "native, srgb or p3"),
+    ("icc", "boolean", "Embed the ICC profile (PNG and TIFF)"),
+    ("scale", "number", "Output pixels per CSS pixel"),
+    (
+        "width",
+        "integer",
+        "Output width in pixels; wins over scale",
+    ),
+    (
+        "clip",
+        "string",
+        "x,y,width,height of the viewport to capture, in CSS pixels",
+    ),
+    (
+        "padding",
+        "number",
+        "CSS pixels to add around the clip on every side",
+    ),
+    (
+        "aspect",
+        "string",
+        "w:h ratio to grow (or crop) the clip or viewport to, e.g. 16:9",
+    ),
+    (
+        "pipeline",
+        "string",
+        "Post-processing stages separated by ';', e.g. crop:0,0,800,600;thumbnail:256",
+    ),
+    (
+        "thumbnail",
+        "integer",
+        "Append a thumbnail stage with this longest side",
+    ),
+    ("grayscale", "boolean", "Append a grayscale stage"),
+    (
+        "channel",
+        "string",
+        "Append a channel stage: red, green, blue or alpha",
+    ),
+    (
+        "rotate",
+        "integer",
+        "Turn the capture clockwise by 90, 180 or 270 degrees first",
+    ),
+    (
+        "flip",
+        "string",
+        "Mirror the capture: h (left to right) or v (top to bottom)",
+    ),
+    (
+        "background",
+        "string",
+        "Flatten transparency onto #rrggbb or a checkerboard",
+    ),
+    (
+        "size",
+        "string",
+        "Resize the viewport to WxH CSS pixels for the capture",
+    ),
+    (
+        "restore",
+        "boolean",
+        "Restore the window after a size capture (default true)",
+    ),
+    (
+        "show_cursor",
+        "boolean",
+        "Draw the mouse pointer where it is at capture time",
+    ),
+    (
+        "priority",
+        "string",
+        "interactive (default) or background: which queued captures go first",
+    ),
+    (
+        "deadline",
+        "integer",
+        "Milliseconds the whole capture may take, like X-Deadline-Ms (504 past it)",
+    ),
+    ("wait", "integer", "Milliseconds to wait before capturing"),
+    (
+        "wait_until",
+        "string",
+        "stable: capture once two consecutive low-resolution frames match",
+    ),
+    (
+        "stable_timeout",
+        "integer",
+        "Milliseconds wait_until=stable waits for the page to settle (default 5000)",
+    ),
+];
+
+const SELECTOR: Param = ("selector", "string", "CSS selector");
+
+const ENDPOINTS: &[Endpoint] = &[
+    Endpoint {
+        methods: &["get"],
+        path: "/screenshot",
+        summary: "Capture the window (format from ?format= or the Accept header)",
+        params: &[(
+            "formats",
+            "string",
+            "Comma-separated formats to encode one snapshot in (multipart)",
+        )],
+        capture: true,
+        body: None,
+        response: "image/*",
+    },
+    Endpoint {
+        methods: &["get"],
+        path: "/screenshot/analyze",
+        summary: "Dominant colors, brightness and a luma histogram of a capture",
+        params: &[("tolerance", "number", "Share of pixels one color must cover for blank")],
+        capture: true,
+        body: None,
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["get"],
+        path: "/screenshot/blank",
+        summary: "Whether one color covers at least tolerance of a capture",
+        params: &[("tolerance", "number", "Share of pixels one color must cover (default 0.99)")],
+        capture: true,
+        body: None,
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["post"],
+        path: "/render",
+        summary: "Render {html|url, width, height} in a hidden window and capture it",
+        params: &[],
+        capture: true,
+        body: Some("application/json"),
+        response: "image/*",
+    },
+    Endpoint {
+        methods: &["post"],
+        path: "/compare-url",
+        summary: "Load the URL in the body into a hidden window and compare it with a capture",
+        params: &[
+            ("threshold", "number", "Share of changed pixels still reported as a match (default 0)"),
+            ("frames", "boolean", "Include both captures as base64"),
+        ],
+        capture: true,
+        body: Some("text/plain"),
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["get"],
+        path: "/watch",
+        summary: "Server-Sent Events whenever a region of the page changes",
+        params: &[
+            SELECTOR,
+            ("rect", "string", "x,y,width,height in CSS pixels, instead of selector"),
+            ("threshold", "number", "Share of changed pixels that sends an event (default 0.01)"),
+            ("interval", "integer", "Poll interval in ms (default 500, min 100)"),
+            ("fps", "number", "Polls per second, instead of interval"),
+            ("max_width", "integer", "Scale polls down to at most this many pixels wide"),
+            ("frames", "boolean", "Include the changed region as base64"),
+        ],
+        capture: true,
+        body: None,
+        response: "text/event-stream",
+    },
+    Endpoint {
+        methods: &["get"],
+        path: "/events",
+        summary: "Server-Sent Events for captures, evals, window changes and analyses",
+        params: &[("types", "string", "Comma-separated event types to receive")],
+        capture: false,
+        body: None,
+        response: "text/event-stream",
+    },
+    Endpoint {
+        methods: &["post"],
+        path: "/eval",
+        summary: "Run JavaScript in the webview; with ?wait= also capture afterwards",
+        params: &[],
+        capture: true,
+        body: Some("text/plain"),
+        response: "text/plain",
+    },
+    Endpoint {
+        methods: &["get"],
+        path: "/element",
+        summary: "Geometry and visibility of the first matching element",
+        params: &[SELECTOR],
+        capture: false,
+        body: None,
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["get"],
+        path: "/element/style",
+        summary: "Computed style values of the first matching element",
+        params: &[
+            SELECTOR,
+            ("props", "string", "Comma-separated properties (all if omitted)"),
+        ],
+        capture: false,
+        body: None,
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["get"],
+        path: "/text",
+        summary: "innerText of every matching element",
+        params: &[SELECTOR],
+        capture: false,
+        body: None,
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["get", "post"],
+        path: "/read-only",
+        summary: "Report, or switch (requires auth_token), read-only mode",
+        params: &[("enabled", "boolean", "Refuse state-changing endpoints (POST)")],
+        capture: false,
+        body: None,
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["get"],
+        path: "/health",
+        summary: "Readiness, the warm-up capture and memory use",
+        params: &[],
+        capture: false,
+        body: None,
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["get"],
+        path: "/state",
+        summary: "Focus, visibility, occlusion, URL and time since the DOM last changed",
+        params: &[],
+        capture: false,
+        body: None,
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["get"],
+        path: "/windows",
+        summary: "Webview window labels",
+        params: &[],
+        capture: false,
+        body: None,
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["get", "post"],
+        path: "/devtools",
+        summary: "Report, or open/close/toggle, the web inspector",
+        params: &[("action", "string", "open, close or toggle (POST)")],
+        capture: false,
+        body: None,
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["post"],
+        path: "/reload",
+        summary: "Reload the page and wait until it has loaded",
+        params: &[("ignore_cache", "boolean", "Bypass the HTTP cache")],
+        capture: false,
+        body: None,
+        response: "text/plain",
+    },
+    Endpoint {
+        methods: &["post"],
+        path: "/clear-cache",
+        summary: "Clear the webview's HTTP caches",
+        params: &[("all", "boolean", "Clear every kind of browsing data")],
+        capture: false,
+        body: None,
+        response: "text/plain",
+    },
+    Endpoint {
+        methods: &["post"],
+        path: "/replay",
+        summary: "Re-run a recorded session",
+        params: &[("delays", "boolean", "Keep the recorded delays (default true)")],
+        capture: false,
+        body: None,
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["post"],
+        path: "/iterate",
+        summary: "Visit {routes} or Storybook {stories} in turn and capture each by name",
+        params: &[],
+        capture: true,
+        body: Some("application/json"),
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["post"],
+        path: "/storyboard",
+        summary: "Run {steps} and return a capture after each as a GIF, APNG or WebP",
+        params: &[],
+        capture: true,
+        body: Some("application/json"),
+        response: "image/*",
+    },
+    Endpoint {
+        methods: &["post"],
+        path: "/shutdown",
+        summary: "Stop the screenshot server (requires auth_token)",
+        params: &[],
+        capture: false,
+        body: None,
+        response: "text/plain",
+    },
+    Endpoint {
+        methods: &["post"],
+        path: "/app/exit",
+        summary: "Exit the app (requires auth_token)",
+        params: &[("code", "integer", "Exit status (default 0)")],
+        capture: false,
+        body: None,
+        response: "text/plain",
+    },
+    Endpoint {
+        methods: &["get", "post", "delete"],
+        path: "/mocks",
+        summary: "List, add or clear fetch/XHR mocks (requires emulation)",
+        params: &[],
+        capture: false,
+        body: Some("application/json"),
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["get"],
+        path: "/har",
+        summary: "Logged fetch/XHR calls as HAR 1.2 (requires emulation)",
+        params: &[],
+        capture: false,
+        body: None,
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["get", "post", "delete"],
+        path: "/emulate/{section}",
+        summary: "Read, set or clear network, locale, media, geolocation or permissions emulation (requires emulation)",
+        params: &[],
+        capture: false,
+        body: Some("application/json"),
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["get"],
+        path: "/perf",
+        summary: "Navigation Timing, paint timings, long tasks and JS heap",
+        params: &[("capture", "boolean", "Also return a capture (multipart)")],
+        capture: false,
+        body: None,
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["post"],
+        path: "/coverage/start",
+        summary: "Baseline the page's Istanbul counters",
+        params: &[],
+        capture: false,
+        body: None,
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["post"],
+        path: "/coverage/stop",
+        summary: "Istanbul coverage since /coverage/start",
+        params: &[],
+        capture: false,
+        body: None,
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["get"],
+        path: "/history",
+        summary: "Buffered captures",
+        params: &[
+            ("last", "integer", "Only the last n entries"),
+            ("name", "string", "Only captures under this test name"),
+            (
+                "format",
+                "string",
+                "gif, apng or webp: return the captures as an animation instead",
+            ),
+        ],
+        capture: false,
+        body: None,
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["get"],
+        path: "/history/{id}",
+        summary: "A buffered capture",
+        params: &[],
+        capture: false,
+        body: None,
+        response: "image/*",
+    },
+    Endpoint {
+        methods: &["get"],
+        path: "/archive",
+        summary: "Query the disk archive index",
+        params: &[
+            ("since", "integer", "Unix ms"),
+            ("until", "integer", "Unix ms"),
+            ("source", "string", "What took the capture"),
+            ("name", "string", "Test name"),
+            ("limit", "integer", "Maximum entries"),
+        ],
+        capture: false,
+        body: None,
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["get"],
+        path: "/archive/{file}",
+        summary: "An archived capture",
+        params: &[],
+        capture: false,
+        body: None,
+        response: "image/*",
+    },
+    Endpoint {
+        methods: &["get"],
+        path: "/bench",
+        summary: "Capture latency percentiles per stage",
+        params: &[("iterations", "integer", "Captures to take (default 20, max 200)")],
+        capture: true,
+        body: None,
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["get"],
+        path: "/audit",
+        summary: "The /eval audit log (requires audit_log)",
+        params: &[("last", "integer", "Only the last n entries")],
+        capture: false,
+        body: None,
+        response: "application/json",
+    },
+    Endpoint {
+        methods: &["get"],
+        path: "/openapi.json",
+        summary: "This document",
+        params: &[],
+        capture: false,
+        body: None,
+        response: "application/json",
+    },
+];
+
+/// The spec, with `addr` as the server URL once the server is bound.
+pub(crate) fn spec(addr: Option<SocketAddr>) -> Value {
+    let mut parameters = Map::new();
+    let formats: Vec<&str> = ImageFormat::ALL.iter().map(|f| f.extension()).collect();
+    parameters.insert(
+        "capture.format".into(),
+        json!({
+            "name": "format",
+            "in": "query",
+            "description": "Output format",
+            "schema": { "type": "string", "enum": formats },
+        }),
+    );
+    for &param in CAPTURE_PARAMS {
+        parameters.insert(format!("capture.{}", param.0), query(param));
+    }
+    parameters.insert(
+        "window".into(),
+        query(("window", "string", "Label of the window to target")),
+    );
+    parameters.insert(
+        "wait_for_window".into(),
+        query((
+            "wait_for_window",
+            "integer",
+            "Milliseconds to wait for the window to be created (503 past it)",
+        )),
+    );
+    parameters.insert(
+        "name".into(),
+        query((
+            "name",
+            "string",
+            "Test name to file captures under (or X-Test-Name)",
+        )),
+    );
+
+    let mut paths = Map::new();
+    for endpoint in ENDPOINTS {
+        let mut params: Vec<Value> = vec![
+            json!({ "$ref": "#/components/parameters/window" }),
+            json!({ "$ref": "#/components/parameters/wait_for_window" }),
+        ];
+        for segment in endpoint.path.split('/') {
+            if let Some(name) = segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                params.push(json!({
+                    "name": name,
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" },
+                }));
+            }
+        }
+        if endpoint.capture {
+            params.push(json!({ "$ref": "#/components/parameters/name" }));
+            params.push(json!({ "$ref": "#/components/parameters/capture.format" }));
+            params.extend(
+                CAPTURE_PARAMS
+                    .iter()
+                    .map(|p| json!({ "$ref": format!("#/components/parameters/capture.{}", p.0) })),
+            );
+        }
+        params.extend(endpoint.params.iter().map(|&p| query(p)));
+
+        let mut item = Map::new();
+        for &method in endpoint.methods {
+            let mut operation = json!({
+                "summary": endpoint.summary,
+                "parameters": params,
+                "responses": {
+                    "200": { "description": "OK", "content": { endpoint.response: {} } },
+                    "default": {
+                        "description": "Error, as plain text",
+                        "content": { "text/plain": {} },
+                    },
+                },
+            });
+            if let (Some(body), "post") = (endpoint.body, method) {
+                operation["requestBody"] = json!({ "content": { body: {} } });
+            }
+            item.insert(method.into(), operation);
+        }
+        paths.insert(endpoint.path.into(), Value::Object(item));
+    }
+
+    let server = addr.map_or_else(
+        || format!("http://{}:{}", crate::DEFAULT_HOST, crate::DEFAULT_PORT),
+        |addr| format!("http://{addr}"),
+    );
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "tauri-plugin-screenshot-hd",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{ "url": server }],
+        "security": [{ "bearer": [] }, {}],
+        "paths": paths,
+        "components": {
+            "parameters": parameters,
+            "securitySchemes": {
+                "bearer": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "Required when Config::auth_token or auth_tokens is set",
+                },
+            },
+        },
+    })
+}
+
+fn query((name, kind, description): Param) -> Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "description": description,
+        "schema": { "type": kind },
+    })
+}
@@ -0,0 +1,413 @@
+//! [`CaptureOptions`]: how a single capture is taken, whichever surface asks
+//! for it — the HTTP query string, [`ScreenshotHd`](crate::ScreenshotHd) or
+//! a [`CaptureBackend`](crate::CaptureBackend).
+
+use crate::transform::{self, Background, Channel, Flip, Rotation, Stage};
+use crate::{query_param, ColorSpace, ImageFormat, MAX_SCALE, MAX_WIDTH};
+use std::time::{Duration, Instant};
+
+/// How long `wait_until=stable` waits for the page to settle by default.
+const DEFAULT_STABLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Options for one capture. [`Config`](crate::Config) provides the
+/// defaults, and capture endpoints override them from the query string.
+///
+/// Start from [`ScreenshotHd::capture_options`](crate::ScreenshotHd::capture_options)
+/// (or `CaptureOptions::default()`) and change what you need:
+///
+/// ```rust,no_run
+/// # fn run(screenshots: &tauri_plugin_screenshot_hd::ScreenshotHd) -> Result<(), String> {
+/// use tauri_plugin_screenshot_hd::{Clip, ImageFormat, Stage};
+///
+/// let mut options = screenshots.capture_options();
+/// options.format = ImageFormat::Jpeg;
+/// options.clip = Some(Clip { x: 0.0, y: 0.0, width: 800.0, height: 600.0 });
+/// options.pipeline.push(Stage::Thumbnail(256));
+/// let jpeg = screenshots.capture_with(&options)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct CaptureOptions {
+    /// Output format (`?format=`).
+    pub format: ImageFormat,
+    /// Color space to convert to (`?color_space=`).
+    pub color_space: ColorSpace,
+    /// Embed the capture's ICC profile, in PNGs and TIFFs (`?icc=`).
+    pub embed_icc_profile: bool,
+    /// Output pixels per CSS pixel, or `None` for the display's (`?scale=`).
+    pub scale: Option<f64>,
+    /// Output width in pixels; takes precedence over `scale` (`?width=`).
+    pub width: Option<u32>,
+    /// Capture only this part of the viewport (`?clip=`).
+    pub clip: Option<Clip>,
+    /// CSS pixels of the page to add around the clip on every side, as far
+    /// as the viewport goes (`?padding=`).
+    pub padding: f64,
+    /// Width to height ratio to bring the clip (or the whole viewport) to,
+    /// growing it around its center and cropping where it won't fit
+    /// (`?aspect=16:9`).
+    pub aspect: Option<(u32, u32)>,
+    /// Resize the window to this viewport (CSS pixels) for the capture
+    /// (`?size=`).
+    pub size: Option<(u32, u32)>,
+    /// Put the window back after a `size` capture (`?restore=`).
+    pub restore: bool,
+    /// What to wait for before capturing (`?wait=`, `?wait_until=`).
+    pub wait: Wait,
+    /// Post-processing stages, in order, e.g. [`Stage::Mask`] over content
+    /// that changes between runs (`?pipeline=`).
+    pub pipeline: Vec<Stage>,
+    /// Draw the mouse pointer where it is at capture time, as a
+    /// [`Stage::Cursor`] ahead of the pipeline (`?show_cursor=`).
+    pub show_cursor: bool,
+    /// Which captures go first when they queue for a slot (`?priority=`).
+    pub priority: Priority,
+    /// Give up once this passes. Every wait the capture makes is cut to
+    /// what's left, and running out fails naming the stage it happened in
+    /// (`X-Deadline-Ms` / `?deadline=`, in milliseconds from the request).
+    pub deadline: Option<Instant>,
+}
+
+/// A rect of the viewport, in CSS pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Clip {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// What a capture waits for before taking the snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[non_exhaustive]
+pub enum Wait {
+    /// Capture right away.
+    #[default]
+    None,
+    /// Sleep first, e.g. to let a transition finish.
+    Delay(Duration),
+    /// Take cheap low-resolution frames until two in a row are identical,
+    /// then capture; fail if that hasn't happened within the timeout.
+    Stable(Duration),
+}
+
+/// Queueing class of a capture (see
+/// [`Config::max_concurrent_captures`](crate::Config::max_concurrent_captures)).
+/// A background capture only takes a free slot when no interactive one is
+/// waiting for it, so a batch job that captures frame by frame lets an
+/// interactive request in between two frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum Priority {
+    /// A person (or a test) is waiting on this capture.
+    #[default]
+    Interactive,
+    /// Part of a batch or stream, e.g. archiving or recording; automatic
+    /// captures run at this priority.
+    Background,
+}
+
+impl Priority {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "interactive" => Some(Self::Interactive),
+            "background" => Some(Self::Background),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "client")]
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Interactive => "interactive",
+            Self::Background => "background",
+        }
+    }
+}
+
+impl Wait {
+    /// The longest this wait can take, `None` for [`Wait::None`].
+    pub(crate) fn max_duration(self) -> Option<Duration> {
+        match self {
+            Self::None => None,
+            Self::Delay(duration) | Self::Stable(duration) => Some(duration),
+        }
+    }
+}
+
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        Self {
+            format: ImageFormat::Png,
+            color_space: ColorSpace::Native,
+            embed_icc_profile: false,
+            scale: None,
+            width: None,
+            clip: None,
+            padding: 0.0,
+            aspect: None,
+            size: None,
+            restore: true,
+            wait: Wait::None,
+            pipeline: Vec::new(),
+            show_cursor: false,
+            priority: Priority::Interactive,
+            deadline: None,
+        }
+    }
+}
+
+impl CaptureOptions {
+    /// Apply the query-string overrides in `url` on top of `self`.
+    pub(crate) fn with_query(mut self, url: &str) -> Result<Self, String> {
+        if let Some(v) = query_param(url, "format") {
+            self.format = ImageFormat::parse(&v)
+                .ok_or_else(|| format!("unknown format '{v}' ({})", ImageFormat::names()))?;
+        }
+        if let Some(v) = query_param(url, "color_space") {
+            self.color_space = ColorSpace::parse(&v)
+                .ok_or_else(|| format!("unknown color_space '{v}' (native, srgb, p3)"))?;
+        }
+        if let Some(v) = query_param(url, "icc") {
+            self.embed_icc_profile = v
+                .parse()
+                .map_err(|_| format!("icc must be true or false, got '{v}'"))?;
+        }
+        if let Some(v) = query_param(url, "scale") {
+            let scale: f64 = v.parse().map_err(|_| format!("invalid scale '{v}'"))?;
+            if !(scale > 0.0 && scale <= MAX_SCALE) {
+                return Err(format!("scale must be in (0, {MAX_SCALE}]"));
+            }
+            self.scale = Some(scale);
+        }
+        if let Some(v) = query_param(url, "width") {
+            let width: u32 = v.parse().map_err(|_| format!("invalid width '{v}'"))?;
+            if width == 0 || width > MAX_WIDTH {
+                return Err(format!("width must be in 1..={MAX_WIDTH}"));
+            }
+            self.width = Some(width);
+        }
+        if let Some(v) = query_param(url, "clip") {
+            self.clip = Some(Clip::parse(&v)?);
+        }
+        if let Some(v) = query_param(url, "padding") {
+            let padding: f64 = v.parse().map_err(|_| format!("invalid padding '{v}'"))?;
+            if !(padding >= 0.0 && padding.is_finite()) {
+                return Err("padding must be a non-negative number of CSS pixels".into());
+            }
+            self.padding = padding;
+        }
+        if let Some(v) = query_param(url, "aspect") {
+            let aspect = v
+                .split_once(':')
+                .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+                .filter(|&(w, h)| w > 0 && h > 0)
+                .ok_or_else(|| format!("invalid aspect '{v}', expected e.g. 16:9"))?;
+            self.aspect = Some(aspect);
+        }
+        if let Some(v) = query_param(url, "pipeline") {
+            self.pipeline = Stage::parse_list(&v)?;
+        }
+        // Ahead of the rest, so its coordinates are of the upright frame
+        if let Some(v) = query_param(url, "flip") {
+            let flip = Flip::parse(&v).ok_or_else(|| format!("unknown flip '{v}' (h, v)"))?;
+            self.pipeline.insert(0, Stage::Flip(flip));
+        }
+        if let Some(v) = query_param(url, "rotate") {
+            let rotation = Rotation::parse(&v)
+                .ok_or_else(|| format!("unknown rotate '{v}' (90, 180, 270)"))?;
+            self.pipeline.insert(0, Stage::Rotate(rotation));
+        }
+        if let Some(v) = query_param(url, "thumbnail") {
+            let max = transform::parse_thumbnail(&v)?;
+            self.pipeline.push(Stage::Thumbnail(max));
+        }
+        if let Some(v) = query_param(url, "grayscale") {
+            let grayscale: bool = v
+                .parse()
+                .map_err(|_| format!("grayscale must be true or false, got '{v}'"))?;
+            if grayscale {
+                self.pipeline.push(Stage::Grayscale);
+            }
+        }
+        if let Some(v) = query_param(url, "channel") {
+            let channel = Channel::parse(&v)
+                .ok_or_else(|| format!("unknown channel '{v}' (red, green, blue, alpha)"))?;
+            self.pipeline.push(Stage::Channel(channel));
+        }
+        if let Some(v) = query_param(url, "background") {
+            let background = Background::parse(&v).ok_or_else(|| {
+                format!("invalid background '{v}', expected #rrggbb or checkerboard")
+            })?;
+            self.pipeline.push(Stage::Background(background));
+        }
+        if let Some(v) = query_param(url, "show_cursor") {
+            self.show_cursor = v
+                .parse()
+                .map_err(|_| format!("show_cursor must be true or false, got '{v}'"))?;
+        }
+        if let Some(v) = query_param(url, "priority") {
+            self.priority = Priority::parse(&v)
+                .ok_or_else(|| format!("unknown priority '{v}' (interactive, background)"))?;
+        }
+        if let Some(v) = query_param(url, "size") {
+            let size = v
+                .split_once('x')
+                .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+                .filter(|&(w, h)| (1..=MAX_WIDTH).contains(&w) && (1..=MAX_WIDTH).contains(&h))
+                .ok_or_else(|| format!("invalid size '{v}', expected e.g. 1280x800"))?;
+            self.size = Some(size);
+        }
+        if let Some(v) = query_param(url, "restore") {
+            self.restore = v
+                .parse()
+                .map_err(|_| format!("restore must be true or false, got '{v}'"))?;
+        }
+        if let Some(v) = query_param(url, "wait") {
+            let ms: u64 = v
+                .parse()
+                .map_err(|_| format!("wait must be a number of milliseconds, got '{v}'"))?;
+            self.wait = Wait::Delay(Duration::from_millis(ms));
+        }
+        if let Some(v) = query_param(url, "wait_until") {
+            if v != "stable" {
+                return Err(format!("unknown wait_until '{v}' (stable)"));
+            }
+            if query_param(url, "wait").is_some() {
+                return Err("pass only one of wait= and wait_until=".into());
+            }
+            let timeout = match query_param(url, "stable_timeout") {
+                None => DEFAULT_STABLE_TIMEOUT,
+                Some(v) => Duration::from_millis(v.parse().map_err(|_| {
+                    format!("stable_timeout must be a number of milliseconds, got '{v}'")
+                })?),
+            };
+            self.wait = Wait::Stable(timeout);
+        }
+        Ok(self)
+    }
+
+    /// The query parameters that reproduce `self` on the server, the
+    /// inverse of [`CaptureOptions::with_query`]. Fails for custom stages,
+    /// which can't be sent.
+    #[cfg(feature = "client")]
+    pub(crate) fn to_query(&self) -> Result<Vec<(&'static str, String)>, String> {
+        let mut query = vec![
+            ("format", self.format.extension().to_string()),
+            ("color_space", self.color_space.as_str().to_string()),
+            ("icc", self.embed_icc_profile.to_string()),
+            ("restore", self.restore.to_string()),
+            ("show_cursor", self.show_cursor.to_string()),
+            ("priority", self.priority.as_str().to_string()),
+        ];
+        if let Some(scale) = self.scale {
+            query.push(("scale", scale.to_string()));
+        }
+        if let Some(width) = self.width {
+            query.push(("width", width.to_string()));
+        }
+        if let Some(clip) = self.clip {
+            let Clip {
+                x,
+                y,
+                width,
+                height,
+            } = clip;
+            query.push(("clip", format!("{x},{y},{width},{height}")));
+        }
+        if self.padding > 0.0 {
+            query.push(("padding", self.padding.to_string()));
+        }
+        if let Some((width, height)) = self.aspect {
+            query.push(("aspect", format!("{width}:{height}")));
+        }
+        if let Some((width, height)) = self.size {
+            query.push(("size", format!("{width}x{height}")));
+        }
+        match self.wait {
+            Wait::None => {}
+            Wait::Delay(delay) => query.push(("wait", delay.as_millis().to_string())),
+            Wait::Stable(timeout) => {
+                query.push(("wait_until", "stable".into()));
+                query.push(("stable_timeout", timeout.as_millis().to_string()));
+            }
+        }
+        let stages: Vec<String> = self
+            .pipeline
+            .iter()
+            .map(Stage::to_query)
+            .collect::<Result<_, _>>()?;
+        query.push(("pipeline", stages.join(";")));
+        Ok(query)
+    }
+}
+
+impl Clip {
+    /// The part of a `viewport` (CSS pixels) to capture for this rect with
+    /// `padding` around it, brought to the `aspect` ratio: grown around its
+    /// center, then cropped to the viewport if it's too big and shifted
+    /// back inside it.
+    pub(crate) fn framed(
+        self,
+        padding: f64,
+        aspect: Option<(u32, u32)>,
+        viewport: (f64, f64),
+    ) -> Self {
+        let (viewport_width, viewport_height) = viewport;
+        let left = (self.x - padding).max(0.0);
+        let top = (self.y - padding).max(0.0);
+        let right = (self.x + self.width + padding).min(viewport_width);
+        let bottom = (self.y + self.height + padding).min(viewport_height);
+        let (mut width, mut height) = ((right - left).max(1.0), (bottom - top).max(1.0));
+        let Some((w, h)) = aspect else {
+            return Self {
+                x: left,
+                y: top,
+                width,
+                height,
+            };
+        };
+        let ratio = f64::from(w) / f64::from(h);
+        if width / height < ratio {
+            width = height * ratio;
+        } else {
+            height = width / ratio;
+        }
+        if width > viewport_width {
+            (width, height) = (viewport_width, viewport_width / ratio);
+        }
+        if height > viewport_height {
+            (width, height) = (viewport_height * ratio, viewport_height);
+        }
+        let (center_x, center_y) = ((left + right) / 2.0, (top + bottom) / 2.0);
+        Self {
+            x: (center_x - width / 2.0).clamp(0.0, (viewport_width - width).max(0.0)),
+            y: (center_y - height / 2.0).clamp(0.0, (viewport_height - height).max(0.0)),
+            width,
+            height,
+        }
+    }
+
+    /// Parse `x,y,width,height`.
+    fn parse(s: &str) -> Result<Self, String> {
+        let values: Vec<f64> = s
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("invalid clip '{s}', expected x,y,width,height"))?;
+        let [x, y, width, height] = values[..] else {
+            return Err(format!("invalid clip '{s}', expected x,y,width,height"));
+        };
+        if !(x >= 0.0 && y >= 0.0 && width > 0.0 && height > 0.0) {
+            return Err("clip must be inside the viewport, with a positive size".into());
+        }
+        Ok(Self {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+}
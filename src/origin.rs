@@ -0,0 +1,147 @@
+//! Checks on requests made by browser pages (see
+//! [`Config::allowed_origins`](crate::Config::allowed_origins)), so a web
+//! page open on the developer's machine can't drive `/eval` and friends
+//! through the user's browser (CSRF).
+//!
+//! A request is from a page when it has an `Origin` header or a
+//! `Sec-Fetch-Site` other than `none`; neither can be set by the page's
+//! scripts. Pages from other origins must be allowed, and requests from any
+//! page to endpoints beyond [`Scope::Capture`] must carry [`HEADER`], which
+//! a page can only add to a cross-origin request after a CORS preflight.
+//!
+//! Before any of that, every request's `Host` must name this server, so a
+//! page whose own domain has been rebound to `127.0.0.1` (DNS rebinding)
+//! can't pass for same-origin.
+
+use crate::Scope;
+use std::net::{IpAddr, SocketAddr};
+
+/// Header browser pages must send to state-changing endpoints.
+pub(crate) const HEADER: &str = "X-Screenshot-HD";
+
+/// Request headers an allowed page may send.
+const ALLOWED_HEADERS: &str =
+    "Authorization, Content-Type, X-Deadline-Ms, X-Screenshot-HD, traceparent, tracestate";
+
+fn header<'a>(request: &'a tiny_http::Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str())
+}
+
+/// Whether `origin` is one of `allowed` (`scheme://host[:port]`).
+fn allowed_origin(allowed: &[String], origin: &str) -> bool {
+    allowed
+        .iter()
+        .any(|a| a.trim_end_matches('/').eq_ignore_ascii_case(origin))
+}
+
+/// Whether `host` (a `Host` header) names this server rather than a domain
+/// someone could point at it: the `bound` address, a loopback name, an IP
+/// address (which can't be rebound) or the host of an allowed origin.
+fn trusted_host(host: &str, bound: Option<SocketAddr>, allowed: &[String]) -> bool {
+    if bound.is_some_and(|bound| bound.to_string().eq_ignore_ascii_case(host)) {
+        return true;
+    }
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split_once(']').map_or(rest, |(name, _)| name),
+        None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+    };
+    if name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok() {
+        return true;
+    }
+    allowed.iter().any(|a| {
+        a.split_once("://")
+            .is_some_and(|(_, h)| h.trim_end_matches('/').eq_ignore_ascii_case(host))
+    })
+}
+
+/// Refuse `request` for `path`, which needs `scope`, if its `Host` isn't
+/// this server, or it's from a page that isn't allowed or lacks [`HEADER`].
+pub(crate) fn check(
+    request: &tiny_http::Request,
+    path: &str,
+    scope: Scope,
+    allowed: &[String],
+    bound: Option<SocketAddr>,
+) -> Result<(), String> {
+    if let Some(host) = header(request, "Host") {
+        if !trusted_host(host, bound, allowed) {
+            return Err(format!(
+                "'{host}' isn't this server's address (see Config::allowed_origins)"
+            ));
+        }
+    }
+    let origin = header(request, "Origin");
+    let site = header(request, "Sec-Fetch-Site");
+    if origin.is_none() && site.is_none_or(|site| site == "none") {
+        return Ok(());
+    }
+    // Browsers without Sec-Fetch-Site still send Origin on anything but
+    // plain GETs; compare it with the address the page used
+    let same_origin = match site {
+        Some(site) => site == "same-origin",
+        None => origin
+            .and_then(|o| o.split_once("://"))
+            .is_some_and(|(_, host)| Some(host) == header(request, "Host")),
+    };
+    if !same_origin && !origin.is_some_and(|o| allowed_origin(allowed, o)) {
+        return Err(format!(
+            "requests from {} aren't allowed (see Config::allowed_origins)",
+            origin.unwrap_or("other sites")
+        ));
+    }
+    // Pages can't add headers to a WebSocket handshake; for one (BiDi),
+    // the Origin check above is the protection
+    let websocket = header(request, "Upgrade").is_some_and(|u| u.eq_ignore_ascii_case("websocket"));
+    if scope != Scope::Capture && !websocket && header(request, HEADER).is_none() {
+        return Err(format!(
+            "browser requests to {path} need an {HEADER} header"
+        ));
+    }
+    Ok(())
+}
+
+/// Answer a CORS preflight: allowed origins may send any method and the
+/// headers the server reads, everyone else gets 403.
+pub(crate) fn preflight(request: tiny_http::Request, allowed: &[String]) {
+    let origin = header(&request, "Origin").filter(|o| allowed_origin(allowed, o));
+    let Some(origin) = origin.map(str::to_string) else {
+        let resp = tiny_http::Response::from_string("origin not allowed").with_status_code(403);
+        let _ = request.respond(resp);
+        return;
+    };
+    let mut resp = tiny_http::Response::empty(204);
+    for line in [
+        format!("Access-Control-Allow-Origin: {origin}"),
+        "Access-Control-Allow-Methods: GET, POST, DELETE".to_string(),
+        format!("Access-Control-Allow-Headers: {ALLOWED_HEADERS}"),
+        "Access-Control-Max-Age: 600".to_string(),
+        "Vary: Origin".to_string(),
+    ] {
+        resp.add_header(line.parse::<tiny_http::Header>().unwrap());
+    }
+    // Chrome asks before a public page may reach a local server
+    if header(&request, "Access-Control-Request-Private-Network") == Some("true") {
+        resp.add_header(
+            "Access-Control-Allow-Private-Network: true"
+                .parse::<tiny_http::Header>()
+                .unwrap(),
+        );
+    }
+    let _ = request.respond(resp);
+}
+
+/// Headers letting the page that sent `request` read the response. Only
+/// requests [`check`] let through get this far, so its `Origin` is allowed.
+pub(crate) fn cors_headers(request: &tiny_http::Request) -> Option<[tiny_http::Header; 2]> {
+    let origin = header(request, "Origin")?;
+    Some([
+        format!("Access-Control-Allow-Origin: {origin}")
+            .parse::<tiny_http::Header>()
+            .ok()?,
+        "Vary: Origin".parse::<tiny_http::Header>().unwrap(),
+    ])
+}
@@ -0,0 +1,142 @@
+//! `POST /reload` and `POST /clear-cache`: reset page state between test
+//! scenarios without restarting the app. Also navigation, for `/iterate`.
+
+use std::time::{Duration, Instant};
+use tauri::{Runtime, Url, WebviewWindow};
+
+/// Set on the old page before reloading or navigating; the new page won't
+/// have it.
+const RELOAD_MARKER: &str = "__SCREENSHOT_HD_RELOADING__";
+
+/// Reload `window` (bypassing the cache if `ignore_cache`) and wait until
+/// the new page has finished loading, or `deadline` passes.
+pub(crate) fn reload<R: Runtime>(
+    window: &WebviewWindow<R>,
+    ignore_cache: bool,
+    deadline: Instant,
+) -> Result<(), String> {
+    crate::eval_json(window, &format!("window.{RELOAD_MARKER} = true"))?;
+    if ignore_cache {
+        reload_from_origin(window)?;
+    } else {
+        window.reload().map_err(|e| format!("reload failed: {e}"))?;
+    }
+    wait_for_load(window, deadline)
+}
+
+/// Navigate `window` to `url` and wait until the new page has finished
+/// loading, or `deadline` passes. Only the fragment changing loads no new
+/// page, so that returns right away.
+pub(crate) fn navigate<R: Runtime>(
+    window: &WebviewWindow<R>,
+    url: Url,
+    deadline: Instant,
+) -> Result<(), String> {
+    let without_fragment = |mut url: Url| {
+        url.set_fragment(None);
+        url
+    };
+    let current = window
+        .url()
+        .map_err(|e| format!("can't read the URL: {e}"))?;
+    let same_document = without_fragment(current) == without_fragment(url.clone());
+    if !same_document {
+        crate::eval_json(window, &format!("window.{RELOAD_MARKER} = true"))?;
+    }
+    window
+        .navigate(url)
+        .map_err(|e| format!("navigation failed: {e}"))?;
+    if same_document {
+        return Ok(());
+    }
+    wait_for_load(window, deadline)
+}
+
+/// Poll until the page marked before a reload or navigation is replaced by
+/// a fully loaded one.
+fn wait_for_load<R: Runtime>(window: &WebviewWindow<R>, deadline: Instant) -> Result<(), String> {
+    let loaded = format!("!window.{RELOAD_MARKER} && document.readyState === 'complete'");
+    loop {
+        // Errors are expected while the old page is torn down
+        if crate::eval_json(window, &loaded).is_ok_and(|v| v == true) {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err("timed out waiting for the page to load".into());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn reload_from_origin<R: Runtime>(window: &WebviewWindow<R>) -> Result<(), String> {
+    window
+        .with_webview(|platform_webview| unsafe {
+            let wk_webview: cocoa::base::id = platform_webview.inner() as cocoa::base::id;
+            let _: cocoa::base::id = objc::msg_send![wk_webview, reloadFromOrigin];
+        })
+        .map_err(|e| format!("with_webview: {e}"))
+}
+
+#[cfg(not(target_os = "macos"))]
+fn reload_from_origin<R: Runtime>(_window: &WebviewWindow<R>) -> Result<(), String> {
+    Err("Reloading without the cache is only supported on macOS (WKWebView).".into())
+}
+
+/// Clear the webview's HTTP caches, or with `all` every kind of browsing
+/// data (cookies, storage, caches).
+pub(crate) fn clear_cache<R: Runtime>(window: &WebviewWindow<R>, all: bool) -> Result<(), String> {
+    if all {
+        window
+            .clear_all_browsing_data()
+            .map_err(|e| format!("clearing browsing data failed: {e}"))
+    } else {
+        clear_http_caches(window)
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn clear_http_caches<R: Runtime>(window: &WebviewWindow<R>) -> Result<(), String> {
+    let (tx, rx) = std::sync::mpsc::channel::<()>();
+
+    window
+        .with_webview(move |platform_webview| unsafe {
+            let wk_webview: cocoa::base::id = platform_webview.inner() as cocoa::base::id;
+            let configuration: cocoa::base::id = objc::msg_send![wk_webview, configuration];
+            let store: cocoa::base::id = objc::msg_send![configuration, websiteDataStore];
+
+            let mut types: cocoa::base::id = objc::msg_send![objc::class!(NSSet), set];
+            for name in [
+                "WKWebsiteDataTypeDiskCache",
+                "WKWebsiteDataTypeMemoryCache",
+                "WKWebsiteDataTypeFetchCache",
+            ] {
+                let ns_name = crate::ns_string(name);
+                types = objc::msg_send![types, setByAddingObject: ns_name];
+                let _: () = objc::msg_send![ns_name, release];
+            }
+            let since: cocoa::base::id = objc::msg_send![objc::class!(NSDate), distantPast];
+
+            let block = block::ConcreteBlock::new(move || {
+                let _ = tx.send(());
+            });
+            let block = block.copy();
+            let _: () = objc::msg_send![
+                store,
+                removeDataOfTypes: types
+                modifiedSince: since
+                completionHandler: &*block
+            ];
+        })
+        .map_err(|e| format!("with_webview: {e}"))?;
+
+    rx.recv_timeout(Duration::from_secs(10))
+        .map_err(|e| format!("clearing caches timed out: {e}"))
+}
+
+#[cfg(not(target_os = "macos"))]
+fn clear_http_caches<R: Runtime>(_window: &WebviewWindow<R>) -> Result<(), String> {
+    Err("Clearing only the HTTP caches is only supported on macOS \
+         (WKWebsiteDataStore); use ?all=true."
+        .into())
+}
@@ -0,0 +1,69 @@
+//! `GET /perf`: Performance API metrics from the page, to line jank reports
+//! up with what was on screen.
+
+/// Counts main-thread stalls in `window.__SCREENSHOT_HD__.longTasks`.
+/// Injected on every page load when `Config::perf_observer` is set.
+///
+/// WebKit has no `longtask` entries, so where they're missing a timer that
+/// fires more than 50 ms late stands in for a long task.
+pub(crate) const OBSERVER_JS: &str = r#"(function () {
+  const ns = (window.__SCREENSHOT_HD__ = window.__SCREENSHOT_HD__ || {});
+  if (ns.longTasks) return;
+  const stats = (ns.longTasks = { count: 0, total_ms: 0, longest_ms: 0, source: 'timer' });
+  const record = (ms) => {
+    stats.count += 1;
+    stats.total_ms += ms;
+    stats.longest_ms = Math.max(stats.longest_ms, ms);
+  };
+  const types = (window.PerformanceObserver && PerformanceObserver.supportedEntryTypes) || [];
+  if (types.includes('longtask')) {
+    stats.source = 'longtask';
+    new PerformanceObserver((list) => list.getEntries().forEach((e) => record(e.duration)))
+      .observe({ type: 'longtask', buffered: true });
+    return;
+  }
+  const INTERVAL = 100;
+  let expected = performance.now() + INTERVAL;
+  const tick = () => {
+    const now = performance.now();
+    const late = now - expected;
+    // Hidden pages have their timers throttled, which isn't jank
+    if (late > 50 && !document.hidden) record(late);
+    expected = now + INTERVAL;
+    setTimeout(tick, INTERVAL);
+  };
+  setTimeout(tick, INTERVAL);
+})();"#;
+
+/// Evaluates to the metrics object returned by `GET /perf`.
+pub(crate) const METRICS_JS: &str = r#"(function () {
+  const round = (ms) => (typeof ms === 'number' ? Math.round(ms * 10) / 10 : ms);
+  const nav = performance.getEntriesByType('navigation')[0];
+  let navigation = null;
+  if (nav) {
+    navigation = { type: nav.type };
+    for (const key of [
+      'redirectStart', 'fetchStart', 'domainLookupStart', 'connectStart', 'requestStart',
+      'responseStart', 'responseEnd', 'domInteractive', 'domContentLoadedEventStart',
+      'domContentLoadedEventEnd', 'domComplete', 'loadEventStart', 'loadEventEnd', 'duration',
+    ]) navigation[key + '_ms'] = round(nav[key]);
+    navigation.transfer_size = nav.transferSize;
+  }
+  const paint = {};
+  for (const e of performance.getEntriesByType('paint')) paint[e.name + '_ms'] = round(e.startTime);
+  const tasks = window.__SCREENSHOT_HD__ && window.__SCREENSHOT_HD__.longTasks;
+  const memory = performance.memory;
+  return {
+    url: location.href,
+    time_origin_ms: round(performance.timeOrigin),
+    now_ms: round(performance.now()),
+    navigation,
+    paint,
+    long_tasks: tasks
+      ? { count: tasks.count, total_ms: round(tasks.total_ms), longest_ms: round(tasks.longest_ms), source: tasks.source }
+      : null,
+    js_heap: memory
+      ? { used_bytes: memory.usedJSHeapSize, total_bytes: memory.totalJSHeapSize, limit_bytes: memory.jsHeapSizeLimit }
+      : null,
+  };
+})()"#;
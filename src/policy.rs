@@ -0,0 +1,92 @@
+//! The eval policy (see [`Config::eval_policy`](crate::Config::eval_policy)):
+//! scripts run inside a wrapper that resolves every global through a proxy,
+//! which refuses denied (or unlisted) globals and, past the time limit, any
+//! global at all, so a runaway script stops at its next lookup.
+//!
+//! It's a guardrail against mistakes, not a security boundary: a script can
+//! still reach denied APIs through objects it's allowed, and a loop that
+//! touches no globals isn't stopped.
+
+use crate::dom::js_literal;
+use crate::EvalPolicy;
+
+impl EvalPolicy {
+    /// Refuse a script over `max_script_len` bytes.
+    pub(crate) fn check_length(&self, script: &str) -> Result<(), String> {
+        match self.max_script_len {
+            Some(max) if script.len() > max => Err(format!(
+                "the script is {} bytes; the eval policy allows {max}",
+                script.len()
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// `script`, a statement list as `/eval` takes, wrapped to run under
+    /// the policy.
+    pub(crate) fn wrap(&self, script: &str) -> Result<String, String> {
+        self.check_length(script)?;
+        Ok(self.sandbox(&format!("{script}\n")))
+    }
+
+    /// `expr`, an expression as BiDi's `script.evaluate` takes, wrapped to
+    /// evaluate under the policy.
+    #[cfg(feature = "bidi")]
+    pub(crate) fn wrap_expression(&self, expr: &str) -> Result<String, String> {
+        self.check_length(expr)?;
+        Ok(self.sandbox(&format!("return ({expr}\n);")))
+    }
+
+    fn sandbox(&self, body: &str) -> String {
+        let deny = js_literal(self.deny_globals.clone());
+        let allow = self
+            .allow_globals
+            .as_ref()
+            .map_or("null".to_string(), |allow| js_literal(allow.clone()));
+        let limit = self
+            .timeout
+            .map_or("null".to_string(), |t| t.as_millis().to_string());
+        // Sloppy-mode `with` over a proxy that claims every name, so bare
+        // identifiers (`fetch`) and `window.x`/`globalThis.x` all go
+        // through `check`. Function values are bound to the real window so
+        // `setTimeout` and friends don't throw on the proxy as `this`.
+        format!(
+            r#"(function (__deny, __allow, __limit) {{
+  const __start = performance.now();
+  const __check = (name) => {{
+    if (typeof name !== 'string') return;
+    if (__limit !== null && performance.now() - __start > __limit) {{
+      throw new Error('eval policy: the script ran past its ' + __limit + ' ms limit');
+    }}
+    if (__deny.includes(name) || (__allow !== null && !__allow.includes(name))) {{
+      throw new ReferenceError('eval policy: ' + name + ' is not allowed');
+    }}
+  }};
+  const __self = new Proxy(window, {{
+    has: () => true,
+    get(target, name) {{
+      if (name === Symbol.unscopables) return undefined;
+      if (name === 'window' || name === 'globalThis' || name === 'self') return __self;
+      if (name === 'undefined') return undefined;
+      __check(name);
+      const value = Reflect.get(target, name);
+      return typeof value === 'function' && !/^[A-Z]/.test(name) ? value.bind(target) : value;
+    }},
+    set(target, name, value) {{
+      __check(name);
+      return Reflect.set(target, name, value);
+    }},
+  }});
+  // Only the script is inside `with`, where every name is the proxy's
+  const __run = (() => {{
+    with (__self) {{
+      return function () {{
+{body}
+      }};
+    }}
+  }})();
+  return __run.call(__self);
+}})({deny}, {allow}, {limit})"#
+        )
+    }
+}
@@ -0,0 +1,71 @@
+//! Per-client token-bucket rate limiting.
+
+use crate::logging;
+use crate::RateLimit;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Buckets are dropped once there are this many clients and they've refilled.
+const MAX_TRACKED_CLIENTS: usize = 1024;
+
+/// Slowest refill accepted for [`RateLimit::per_second`]: one request every
+/// ~17 minutes. Slower (or non-finite) rates are raised to it.
+const MIN_PER_SECOND: f64 = 0.001;
+
+pub(crate) struct RateLimiter {
+    config: RateLimit,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(mut config: RateLimit) -> Self {
+        if !(config.per_second.is_finite() && config.per_second >= MIN_PER_SECOND) {
+            logging::warn!(
+                "[screenshot-hd] RateLimit::per_second must be finite and at least \
+                 {MIN_PER_SECOND}, got {}; using {MIN_PER_SECOND}",
+                config.per_second
+            );
+            config.per_second = MIN_PER_SECOND;
+        }
+        Self {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token for `ip`, or return how long until one is available.
+    pub(crate) fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        let burst = f64::from(self.config.burst.max(1));
+        let rate = self.config.per_second;
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            buckets.retain(|_, b| {
+                b.tokens + now.duration_since(b.updated).as_secs_f64() * rate < burst
+            });
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::try_from_secs_f64((1.0 - bucket.tokens) / rate).unwrap_or(Duration::MAX))
+        }
+    }
+}
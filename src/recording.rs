@@ -0,0 +1,314 @@
+//! `POST /recording/start` and `/recording/stop`: capture the window at a
+//! steady rate and pipe the frames into an external `ffmpeg`, for video
+//! formats the plugin doesn't encode itself (`ffmpeg` feature, see
+//! [`Config::ffmpeg`](crate::Config::ffmpeg)).
+
+use crate::budget;
+use crate::idle::{self, IdleWatch};
+use crate::logging;
+use crate::{format, CaptureOptions, Context, FfmpegConfig, ImageFormat};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tauri::{Runtime, WebviewWindow};
+
+/// Time between frames when the request doesn't say: 10 fps.
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(100);
+const MIN_INTERVAL: Duration = Duration::from_millis(20);
+/// How much of ffmpeg's error output a failed recording reports.
+const STDERR_TAIL: usize = 2000;
+
+/// The recording in progress, if any; one at a time.
+pub(crate) struct VideoRecorder {
+    config: FfmpegConfig,
+    active: Mutex<Option<Recording>>,
+}
+
+struct Recording {
+    path: PathBuf,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<Result<Value, String>>,
+}
+
+/// A running ffmpeg and the frame size it was started for.
+struct Encoder {
+    child: Child,
+    stdin: ChildStdin,
+    width: u32,
+    height: u32,
+}
+
+impl VideoRecorder {
+    pub(crate) fn new(config: FfmpegConfig) -> Self {
+        Self {
+            config,
+            active: Mutex::new(None),
+        }
+    }
+
+    /// Start recording `window` with the capture options in `url`, a frame
+    /// every `?interval=` ms (or `?fps=`), at most `?max_width=` pixels
+    /// wide. Returns the path the video will be written to.
+    pub(crate) fn start<R: Runtime>(
+        &self,
+        window: WebviewWindow<R>,
+        context: Arc<Context>,
+        url: &str,
+    ) -> Result<Value, (u16, String)> {
+        let interval = crate::stream_interval(url, DEFAULT_INTERVAL).map_err(|e| (400, e))?;
+        if interval < MIN_INTERVAL {
+            return Err((
+                400,
+                format!(
+                    "interval must be at least {} ms (fps at most {})",
+                    MIN_INTERVAL.as_millis(),
+                    1000 / MIN_INTERVAL.as_millis()
+                ),
+            ));
+        }
+        // QOI is the cheapest to encode and decode again
+        let settings = CaptureOptions {
+            format: ImageFormat::Qoi,
+            ..context
+                .capture_defaults
+                .clone()
+                .with_query(url)
+                .map_err(|e| (400, e))?
+        };
+        // Capped once, since ffmpeg takes frames of one size
+        let max_width = crate::stream_max_width(url).map_err(|e| (400, e))?;
+        let settings = crate::capped(&window, &settings, max_width);
+
+        let mut active = self.active.lock().unwrap();
+        if active.is_some() {
+            return Err((409, "a recording is already running".into()));
+        }
+        std::fs::create_dir_all(&self.config.dir).map_err(|e| {
+            (
+                500,
+                format!("can't create {}: {e}", self.config.dir.display()),
+            )
+        })?;
+        let path = self.config.dir.join(format!(
+            "recording-{}.{}",
+            crate::unix_millis(),
+            self.config.extension
+        ));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = std::thread::spawn({
+            let config = self.config.clone();
+            let path = path.clone();
+            let stop = stop.clone();
+            move || {
+                record(
+                    &window, &context, &settings, interval, &config, &path, &stop,
+                )
+            }
+        });
+        *active = Some(Recording {
+            path: path.clone(),
+            stop,
+            thread,
+        });
+        Ok(json!({ "path": path }))
+    }
+
+    /// Stop the recording, wait for ffmpeg to finish the file and report
+    /// its path.
+    pub(crate) fn stop(&self) -> Result<Value, (u16, String)> {
+        let Some(recording) = self.active.lock().unwrap().take() else {
+            return Err((409, "no recording is running".into()));
+        };
+        recording.stop.store(true, Ordering::SeqCst);
+        match recording.thread.join() {
+            Ok(Ok(summary)) => Ok(summary),
+            Ok(Err(e)) => Err((500, format!("{}: {e}", recording.path.display()))),
+            Err(_) => Err((500, "the recording thread panicked".into())),
+        }
+    }
+}
+
+/// Capture until `stop` (or the server shuts down), writing each frame as
+/// raw RGBA. A frame is repeated for the intervals a slow capture missed,
+/// so the video plays back in real time, except for idle pauses and runs of
+/// unchanged frames, which are cut and listed as `gaps` instead.
+fn record<R: Runtime>(
+    window: &WebviewWindow<R>,
+    context: &Context,
+    settings: &CaptureOptions,
+    interval: Duration,
+    config: &FfmpegConfig,
+    path: &Path,
+    stop: &AtomicBool,
+) -> Result<Value, String> {
+    let started = Instant::now();
+    let mut encoder: Option<Encoder> = None;
+    let mut written: u64 = 0;
+    let mut skipped: u64 = 0;
+    let mut failure = None;
+    let mut idle = IdleWatch::new(context.idle_pause);
+    // Time spent paused, which the video skips
+    let mut paused = Duration::ZERO;
+    let mut gaps = Vec::new();
+    // The last frame written, and the unchanged time cut since
+    let mut last: Option<[u8; 32]> = None;
+    let mut unchanged = Duration::ZERO;
+
+    while !stop.load(Ordering::SeqCst) && !context.shutdown.load(Ordering::SeqCst) {
+        if let Some(idle) = &mut idle {
+            if idle.paused(window) {
+                std::thread::sleep(idle::POLL);
+                continue;
+            }
+            if let Some(gap) = idle.take_gap() {
+                paused += gap;
+                gaps.push(json!({
+                    "at_ms": (interval * written as u32).as_millis() as u64,
+                    "duration_ms": gap.as_millis() as u64,
+                }));
+            }
+        }
+        let elapsed = started.elapsed().saturating_sub(paused);
+        let slot = elapsed.as_millis() / interval.as_millis();
+        let frame = match context
+            .snapshot(window, settings)
+            .and_then(|bytes| format::decode(&bytes))
+        {
+            Ok(frame) => frame,
+            Err(e) => {
+                logging::debug!("[screenshot-hd] recording capture failed: {e}");
+                std::thread::sleep(interval);
+                continue;
+            }
+        };
+        let _charge = match context
+            .memory
+            .reserve(frame.rgba.len(), "a recording frame")
+        {
+            Ok(charge) => charge,
+            Err(e) => {
+                logging::debug!("[screenshot-hd] recording frame skipped: {e}");
+                budget::recycle(frame.rgba);
+                std::thread::sleep(interval);
+                continue;
+            }
+        };
+
+        if encoder.is_none() {
+            encoder = Some(spawn(config, frame.width, frame.height, interval, path)?);
+        }
+        let encoder = encoder.as_mut().expect("started above");
+        let digest: [u8; 32] = Sha256::digest(&frame.rgba).into();
+        // ffmpeg's raw input has a fixed size, e.g. across a window resize
+        if (frame.width, frame.height) != (encoder.width, encoder.height) {
+            skipped += 1;
+        } else if last == Some(digest) {
+            // Cut back to the last frame's slot, so it shows once and the
+            // next change follows it
+            let at = interval * (written as u32 - 1);
+            let cut = started.elapsed().saturating_sub(paused).saturating_sub(at);
+            paused += cut;
+            unchanged += cut;
+            budget::recycle(frame.rgba);
+            std::thread::sleep(interval);
+            continue;
+        } else {
+            if !unchanged.is_zero() {
+                gaps.push(json!({
+                    "at_ms": (interval * written as u32).as_millis() as u64,
+                    "duration_ms": std::mem::take(&mut unchanged).as_millis() as u64,
+                }));
+            }
+            let repeat = (slot as u64 + 1).saturating_sub(written).max(1);
+            let result = (0..repeat).try_for_each(|_| encoder.stdin.write_all(&frame.rgba));
+            if let Err(e) = result {
+                // ffmpeg exited; its stderr says why
+                failure = Some(format!("writing to ffmpeg failed: {e}"));
+                break;
+            }
+            written += repeat;
+            last = Some(digest);
+        }
+        budget::recycle(frame.rgba);
+
+        let next = interval * (slot as u32 + 1);
+        std::thread::sleep(next.saturating_sub(started.elapsed().saturating_sub(paused)));
+    }
+
+    if !unchanged.is_zero() {
+        gaps.push(json!({
+            "at_ms": (interval * written as u32).as_millis() as u64,
+            "duration_ms": unchanged.as_millis() as u64,
+        }));
+    }
+
+    let Some(Encoder {
+        child,
+        stdin,
+        width,
+        height,
+    }) = encoder
+    else {
+        return Err("no frame was captured".into());
+    };
+    drop(stdin);
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("waiting for ffmpeg failed: {e}"))?;
+    if !output.status.success() || failure.is_some() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let skip = stderr.chars().count().saturating_sub(STDERR_TAIL);
+        let tail: String = stderr.chars().skip(skip).collect();
+        return Err(format!(
+            "{}; ffmpeg exited with {}: {}",
+            failure.unwrap_or_else(|| "encoding failed".into()),
+            output.status,
+            tail.trim()
+        ));
+    }
+    Ok(json!({
+        "path": path,
+        "frames": written,
+        "skipped": skipped,
+        "width": width,
+        "height": height,
+        "duration_ms": (interval * written as u32).as_millis() as u64,
+        "gaps": gaps,
+    }))
+}
+
+/// Start ffmpeg reading `width` x `height` RGBA frames from stdin, one per
+/// `interval`, and encoding them to `path` with the configured arguments.
+fn spawn(
+    config: &FfmpegConfig,
+    width: u32,
+    height: u32,
+    interval: Duration,
+    path: &Path,
+) -> Result<Encoder, String> {
+    let fps = format!("1000/{}", interval.as_millis());
+    let mut child = Command::new(&config.program)
+        .args(["-hide_banner", "-nostats", "-loglevel", "error", "-y"])
+        .args(["-f", "rawvideo", "-pix_fmt", "rgba"])
+        .args(["-s", &format!("{width}x{height}"), "-r", &fps, "-i", "-"])
+        .args(&config.args)
+        .arg(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("can't run {}: {e}", config.program.display()))?;
+    let stdin = child.stdin.take().ok_or("ffmpeg has no stdin")?;
+    Ok(Encoder {
+        child,
+        stdin,
+        width,
+        height,
+    })
+}
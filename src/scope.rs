@@ -0,0 +1,61 @@
+//! Token scopes (see [`Config::auth_tokens`](crate::Config::auth_tokens)):
+//! which kind of access each endpoint needs.
+
+use std::fmt;
+
+/// A kind of access a token can be given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Scope {
+    /// Read the app as it is: captures, SSE streams, element geometry and
+    /// text, page metrics, history and archive, and the settings endpoints
+    /// on `GET`. Nothing under it navigates or changes state.
+    Capture,
+    /// Run scripts in the page or load arbitrary URLs: `/eval`, `/replay`,
+    /// `/iterate`, `/storyboard`, `/render`, `/compare-url` and BiDi
+    /// sessions.
+    Eval,
+    /// Change the page or start work without a script: `/reload`,
+    /// `/clear-cache`, `/recording/*`, `/coverage/*`, starting and ending
+    /// `/webrtc` views, and `POST`/`DELETE` on `/mocks`, `/emulate/*` and
+    /// `/devtools`.
+    Input,
+    /// Run the server: `/shutdown`, `/app/exit`, `/audit` and switching
+    /// `/read-only`.
+    Admin,
+}
+
+impl Scope {
+    /// Every scope, as [`Config::auth_token`](crate::Config::auth_token)
+    /// has.
+    pub const ALL: [Scope; 4] = [Scope::Capture, Scope::Eval, Scope::Input, Scope::Admin];
+
+    /// The scope `method` on `path` needs.
+    pub(crate) fn of(method: &tiny_http::Method, path: &str) -> Scope {
+        let read = method == &tiny_http::Method::Get;
+        match path {
+            "/eval" | "/replay" | "/iterate" | "/storyboard" | "/session" | "/render"
+            | "/compare-url" => Scope::Eval,
+            "/reload" | "/clear-cache" => Scope::Input,
+            "/recording/start" | "/recording/stop" | "/coverage/start" | "/coverage/stop"
+            | "/webrtc" => Scope::Input,
+            p if p.starts_with("/webrtc/") && !read => Scope::Input,
+            "/mocks" | "/devtools" if !read => Scope::Input,
+            p if p.starts_with("/emulate/") && !read => Scope::Input,
+            "/shutdown" | "/app/exit" | "/audit" => Scope::Admin,
+            "/read-only" if !read => Scope::Admin,
+            _ => Scope::Capture,
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Scope::Capture => "capture",
+            Scope::Eval => "eval",
+            Scope::Input => "input",
+            Scope::Admin => "admin",
+        })
+    }
+}
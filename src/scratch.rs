@@ -0,0 +1,113 @@
+//! Temporary hidden windows, for rendering content the app isn't showing:
+//! `POST /render` (HTML or a URL to an image) and `POST /compare-url`.
+
+use crate::logging;
+use crate::CaptureOptions;
+use base64::Engine;
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::time::Instant;
+use tauri::webview::PageLoadEvent;
+use tauri::{AppHandle, Runtime, Url, WebviewUrl, WebviewWindow, WebviewWindowBuilder};
+
+/// Viewport of a `/render` window when the body doesn't give one, in CSS
+/// pixels.
+const DEFAULT_SIZE: (f64, f64) = (800.0, 600.0);
+const MAX_SIZE: f64 = 8192.0;
+
+/// Numbers the windows, so concurrent requests don't collide.
+static NEXT_WINDOW: AtomicU64 = AtomicU64::new(0);
+
+/// A hidden window, destroyed when dropped.
+pub(crate) struct ScratchWindow<R: Runtime>(WebviewWindow<R>);
+
+impl<R: Runtime> ScratchWindow<R> {
+    /// Open `url` in a hidden window with a `width` x `height` viewport (CSS
+    /// pixels) and wait until it has finished loading, or `deadline` passes.
+    pub(crate) fn open(
+        app: &AppHandle<R>,
+        url: Url,
+        width: f64,
+        height: f64,
+        deadline: Instant,
+    ) -> Result<Self, (u16, String)> {
+        let (loaded, load) = mpsc::channel();
+        let label = format!(
+            "screenshot-hd-scratch-{}",
+            NEXT_WINDOW.fetch_add(1, Ordering::Relaxed)
+        );
+        let window = WebviewWindowBuilder::new(app, &label, WebviewUrl::External(url))
+            .visible(false)
+            .focused(false)
+            .inner_size(width, height)
+            .on_page_load(move |_, payload| {
+                if payload.event() == PageLoadEvent::Finished {
+                    let _ = loaded.send(());
+                }
+            })
+            .build()
+            .map(Self)
+            .map_err(|e| (500, format!("can't create a hidden window: {e}")))?;
+
+        load.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            .map_err(|_| (504, "the page didn't finish loading in time".to_string()))?;
+        Ok(window)
+    }
+
+    pub(crate) fn window(&self) -> &WebviewWindow<R> {
+        &self.0
+    }
+}
+
+impl<R: Runtime> Drop for ScratchWindow<R> {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.destroy() {
+            logging::warn!("[screenshot-hd] failed to close a hidden window: {e}");
+        }
+    }
+}
+
+/// A `POST /render` request.
+pub(crate) struct Render {
+    pub(crate) url: Url,
+    pub(crate) width: f64,
+    pub(crate) height: f64,
+    pub(crate) settings: CaptureOptions,
+}
+
+impl Render {
+    /// Parse a `{"html": ..}` or `{"url": ..}` body with optional `width`
+    /// and `height`, and the capture options from the query.
+    pub(crate) fn parse(url: &str, body: &str, defaults: CaptureOptions) -> Result<Self, String> {
+        let body: Value = serde_json::from_str(body).map_err(|e| format!("invalid JSON: {e}"))?;
+        let page = match (body["html"].as_str(), body["url"].as_str()) {
+            (Some(html), None) => {
+                let html = base64::engine::general_purpose::STANDARD.encode(html);
+                Url::parse(&format!("data:text/html;charset=utf-8;base64,{html}"))
+                    .map_err(|e| e.to_string())?
+            }
+            (None, Some(page)) => {
+                let page = Url::parse(page).map_err(|e| format!("invalid url '{page}': {e}"))?;
+                if !matches!(page.scheme(), "http" | "https") {
+                    return Err("url must be http or https".into());
+                }
+                page
+            }
+            _ => return Err("pass exactly one of \"html\" and \"url\"".into()),
+        };
+        let dimension = |key: &str, default: f64| match &body[key] {
+            Value::Null => Ok(default),
+            v => v
+                .as_f64()
+                .filter(|v| *v >= 1.0 && *v <= MAX_SIZE)
+                .ok_or_else(|| format!("{key} must be a number in 1..={MAX_SIZE}")),
+        };
+        Ok(Self {
+            url: page,
+            width: dimension("width", DEFAULT_SIZE.0)?,
+            height: dimension("height", DEFAULT_SIZE.1)?,
+            settings: defaults.with_query(url)?,
+        })
+    }
+}
@@ -0,0 +1,172 @@
+//! Session recording (`Config::session_log`) and `POST /replay`: turn an
+//! exploratory session into a repeatable visual test.
+//!
+//! Each recorded command is one JSONL line with `t_ms` (time since the
+//! recording started) and a `command`:
+//!
+//! - `eval` — `script`, plus `url` carrying `?wait=` and capture options
+//! - `screenshot` — `url` with its capture options, and the test `name`
+//! - `reload` — `ignore_cache`
+//! - `emulate` — an emulation `section` and the `value` it was set to
+
+use crate::logging;
+use crate::{Context, Wait};
+use serde_json::{json, Value};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{Runtime, WebviewWindow};
+
+pub(crate) struct SessionRecorder {
+    path: PathBuf,
+    started: Instant,
+    /// Serializes appends so concurrent requests don't interleave lines.
+    write: Mutex<()>,
+}
+
+impl SessionRecorder {
+    pub(crate) fn open(path: PathBuf) -> Self {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            if let Err(e) = fs::create_dir_all(dir) {
+                logging::warn!(
+                    "[screenshot-hd] can't create session log dir {}: {e}",
+                    dir.display()
+                );
+            }
+        }
+        Self {
+            path,
+            started: Instant::now(),
+            write: Mutex::new(()),
+        }
+    }
+
+    /// Append `command` (an object with at least `"command"`), stamped with
+    /// the time since recording started. Failures are logged only.
+    pub(crate) fn record(&self, mut command: Value) {
+        command["t_ms"] = json!(self.started.elapsed().as_millis() as u64);
+
+        let _guard = self.write.lock().unwrap();
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut f| writeln!(f, "{command}"));
+        if let Err(e) = written {
+            logging::warn!("[screenshot-hd] session log write failed: {e}");
+        }
+    }
+}
+
+/// Re-run a recorded session (JSONL) in `window`. With `delays`, waits
+/// between commands as long as the recording did. Stops early (reporting
+/// `"truncated": true`) at `deadline` or on the first failing command.
+/// Evals are audited as if sent to `/eval` by `remote`.
+pub(crate) fn replay<R: Runtime>(
+    window: &WebviewWindow<R>,
+    context: &Context,
+    session: &str,
+    delays: bool,
+    remote: Option<SocketAddr>,
+    deadline: Instant,
+) -> Result<Value, String> {
+    let commands = session
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(i, line)| {
+            serde_json::from_str::<Value>(line).map_err(|e| format!("line {}: {e}", i + 1))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let started = Instant::now();
+    let mut results = Vec::with_capacity(commands.len());
+    let mut truncated = false;
+    for (index, command) in commands.iter().enumerate() {
+        if delays {
+            let at = started + Duration::from_millis(command["t_ms"].as_u64().unwrap_or(0));
+            if at >= deadline {
+                truncated = true;
+                break;
+            }
+            std::thread::sleep(at.saturating_duration_since(Instant::now()));
+        }
+        if Instant::now() >= deadline {
+            truncated = true;
+            break;
+        }
+
+        let result = run(window, context, index, command, remote, deadline);
+        let failed = result.is_err();
+        results.push(match result {
+            Ok(capture) => {
+                json!({ "index": index, "command": command["command"], "capture": capture })
+            }
+            Err(e) => json!({ "index": index, "command": command["command"], "error": e }),
+        });
+        if failed {
+            truncated = true;
+            break;
+        }
+    }
+
+    Ok(json!({
+        "commands": commands.len(),
+        "completed": results.iter().filter(|r| r.get("error").is_none()).count(),
+        "truncated": truncated,
+        "results": results,
+    }))
+}
+
+/// Run one command, returning the capture it took (if any) as
+/// `{ id, name, url }` of its history entry.
+fn run<R: Runtime>(
+    window: &WebviewWindow<R>,
+    context: &Context,
+    index: usize,
+    command: &Value,
+    remote: Option<SocketAddr>,
+    deadline: Instant,
+) -> Result<Value, String> {
+    let url = command["url"].as_str().unwrap_or_default();
+    let capture = |settings| {
+        let png = context.capture_with(window, "replay", None, &settings)?;
+        let name = command["name"]
+            .as_str()
+            .map_or_else(|| format!("replay/{index}"), str::to_string);
+        let id = context.history.push("replay", Some(&name), png);
+        Ok(json!({ "id": id, "name": name, "url": format!("/history/{id}") }))
+    };
+
+    match command["command"].as_str() {
+        Some("eval") => {
+            let settings = context.capture_defaults.clone().with_query(url)?;
+            let script = command["script"].as_str().ok_or("eval without a script")?;
+            if let Some(audit) = &context.audit {
+                audit.record(remote, "/replay", window.label(), script);
+            }
+            context.eval(window, script)?;
+            match settings.wait {
+                Wait::None => Ok(Value::Null),
+                _ => capture(settings),
+            }
+        }
+        Some("screenshot") => capture(context.capture_defaults.clone().with_query(url)?),
+        Some("reload") => {
+            let ignore_cache = command["ignore_cache"].as_bool().unwrap_or(false);
+            crate::page::reload(window, ignore_cache, deadline)?;
+            Ok(Value::Null)
+        }
+        Some("emulate") => {
+            let section = command["section"]
+                .as_str()
+                .ok_or("emulate without a section")?;
+            crate::emulation::set(window, section, &command["value"]).map_err(|(_, e)| e)?;
+            Ok(Value::Null)
+        }
+        other => Err(format!("unknown command {other:?}")),
+    }
+}
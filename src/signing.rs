@@ -0,0 +1,141 @@
+//! Signed captures (`signing` feature, see
+//! [`Config::signing`](crate::Config::signing)): each capture the server
+//! returns comes with an Ed25519 signature over a small JSON statement of
+//! its SHA-256, time, window and page URL, so an archived screenshot can be
+//! shown not to have changed since it was taken.
+
+use crate::logging;
+use crate::SigningConfig;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::Bytes;
+use ed25519_dalek::{Signer as _, SigningKey};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+/// Keyword of the PNG `tEXt` chunk carrying the signature.
+pub(crate) const PNG_KEYWORD: &str = "screenshot-hd-signature";
+
+/// DER prefix of an Ed25519 `SubjectPublicKeyInfo`, followed by the key.
+const SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// The last chunk of every PNG.
+const IEND: [u8; 12] = [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82];
+
+pub(crate) struct Signer {
+    key: SigningKey,
+    embed_in_png: bool,
+}
+
+/// A capture's signed statement and its signature, both base64.
+pub(crate) struct Signature {
+    metadata: String,
+    signature: String,
+}
+
+impl Signer {
+    /// A signer with the configured key, or a new one.
+    pub(crate) fn new(config: &SigningConfig) -> Result<Self, String> {
+        let seed = match config.key {
+            Some(seed) => seed,
+            None => {
+                let mut seed = [0; 32];
+                getrandom::getrandom(&mut seed)
+                    .map_err(|e| format!("can't generate a signing key: {e}"))?;
+                seed
+            }
+        };
+        let signer = Self {
+            key: SigningKey::from_bytes(&seed),
+            embed_in_png: config.embed_in_png,
+        };
+        if config.key.is_none() {
+            logging::info!(
+                "[screenshot-hd] signing captures with a new key; public key {}",
+                STANDARD.encode(signer.key.verifying_key().as_bytes())
+            );
+        }
+        Ok(signer)
+    }
+
+    /// The public key, for `GET /public-key`: raw as base64, and as a PEM
+    /// `SubjectPublicKeyInfo` for `openssl`.
+    pub(crate) fn public_key(&self) -> Value {
+        let key = self.key.verifying_key();
+        let mut spki = SPKI_PREFIX.to_vec();
+        spki.extend_from_slice(key.as_bytes());
+        json!({
+            "algorithm": "Ed25519",
+            "public_key": STANDARD.encode(key.as_bytes()),
+            "pem": format!(
+                "-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n",
+                STANDARD.encode(spki)
+            ),
+        })
+    }
+
+    /// Sign `image`, just captured from the window labelled `window` showing
+    /// `url`. With `embed_in_png`, a PNG comes back with the signature as a
+    /// `tEXt` chunk; the signed hash is of the image without it.
+    pub(crate) fn sign(
+        &self,
+        image: Bytes,
+        window: &str,
+        url: Option<String>,
+    ) -> (Bytes, Signature) {
+        let sha256: String = Sha256::digest(&image)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        let metadata = json!({
+            "sha256": sha256,
+            "timestamp_ms": crate::unix_millis(),
+            "window": window,
+            "url": url,
+        })
+        .to_string();
+        let signature = Signature {
+            signature: STANDARD.encode(self.key.sign(metadata.as_bytes()).to_bytes()),
+            metadata: STANDARD.encode(metadata),
+        };
+        if !self.embed_in_png || !image.ends_with(&IEND) {
+            return (image, signature);
+        }
+        let text = format!("{} {}", signature.metadata, signature.signature);
+        let mut chunk = b"tEXt".to_vec();
+        chunk.extend_from_slice(PNG_KEYWORD.as_bytes());
+        chunk.push(0);
+        chunk.extend_from_slice(text.as_bytes());
+        let crc = crc32fast::hash(&chunk);
+        let mut bytes = ((chunk.len() - 4) as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(&chunk);
+        bytes.extend_from_slice(&crc.to_be_bytes());
+        let end = image.len() - IEND.len();
+        let mut embedded = Vec::with_capacity(image.len() + bytes.len());
+        embedded.extend_from_slice(&image[..end]);
+        embedded.extend_from_slice(&bytes);
+        embedded.extend_from_slice(&IEND);
+        (embedded.into(), signature)
+    }
+}
+
+impl Signature {
+    /// `X-Signature` and `X-Signature-Metadata`, for an image response.
+    pub(crate) fn headers(&self) -> [tiny_http::Header; 2] {
+        [
+            format!("X-Signature: {}", self.signature)
+                .parse::<tiny_http::Header>()
+                .unwrap(),
+            format!("X-Signature-Metadata: {}", self.metadata)
+                .parse::<tiny_http::Header>()
+                .unwrap(),
+        ]
+    }
+
+    /// The same, for a JSON response.
+    pub(crate) fn to_json(&self) -> Value {
+        json!({ "metadata": self.metadata, "signature": self.signature })
+    }
+}
@@ -0,0 +1,62 @@
+//! Server-Sent Events responses for long-lived subscriptions.
+//!
+//! tiny_http buffers chunked bodies, so the stream takes over the
+//! connection and writes the response itself, flushing every event.
+
+use crate::origin;
+use serde_json::Value;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Send a comment at least this often, so a client that went away is
+/// noticed by the next failed write.
+pub(crate) const KEEPALIVE: Duration = Duration::from_secs(15);
+
+pub(crate) struct EventStream {
+    writer: Box<dyn Write + Send>,
+    last_write: Instant,
+}
+
+impl EventStream {
+    /// Answer `request` with `200 text/event-stream`.
+    pub(crate) fn open(request: tiny_http::Request) -> io::Result<Self> {
+        let cors = origin::cors_headers(&request);
+        let mut writer = request.into_writer();
+        writer.write_all(
+            b"HTTP/1.1 200 OK\r\n\
+              Content-Type: text/event-stream\r\n\
+              Cache-Control: no-cache\r\n\
+              Connection: close\r\n",
+        )?;
+        for header in cors.into_iter().flatten() {
+            write!(writer, "{}: {}\r\n", header.field, header.value)?;
+        }
+        writer.write_all(b"\r\n")?;
+        writer.flush()?;
+        Ok(Self {
+            writer,
+            last_write: Instant::now(),
+        })
+    }
+
+    /// Send `data` as JSON under the `event` name.
+    pub(crate) fn send(&mut self, event: &str, data: &Value) -> io::Result<()> {
+        write!(self.writer, "event: {event}\ndata: {data}\n\n")?;
+        self.flush()
+    }
+
+    /// Send a keepalive comment if nothing was sent for [`KEEPALIVE`].
+    pub(crate) fn keepalive(&mut self) -> io::Result<()> {
+        if self.last_write.elapsed() < KEEPALIVE {
+            return Ok(());
+        }
+        self.writer.write_all(b": keepalive\n\n")?;
+        self.flush()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.last_write = Instant::now();
+        Ok(())
+    }
+}
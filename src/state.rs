@@ -0,0 +1,388 @@
+//! The plugin's managed state ([`ScreenshotHd`]), for driving captures from
+//! Rust without going through HTTP, and its readiness and failure signals.
+
+use crate::logging;
+use crate::{events, CaptureOptions, Context, ErrorKind};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager, Runtime, WebviewWindow};
+
+/// Tauri event emitted once, when the server is bound, the target window
+/// exists and, with [`Config::warmup`](crate::Config::warmup), the warm-up
+/// capture is done. The payload carries `addr` and `window`.
+pub const READY_EVENT: &str = "screenshot-hd://ready";
+
+/// Tauri event emitted when the server fails to start or its thread dies.
+/// The payload carries `error`.
+pub const FAILED_EVENT: &str = "screenshot-hd://failed";
+
+/// How often [`ScreenshotHd::wait_ready`] re-checks, in case a window
+/// appeared without a notification.
+const READY_POLL: Duration = Duration::from_millis(50);
+
+/// The running plugin, managed as Tauri state:
+///
+/// ```rust,no_run
+/// # fn run(app: &tauri::AppHandle) -> Result<(), String> {
+/// use tauri::Manager;
+/// use tauri_plugin_screenshot_hd::ScreenshotHd;
+///
+/// let screenshots = app.state::<ScreenshotHd>();
+/// screenshots.eval("document.body.classList.add('dark')")?;
+/// let png = screenshots.capture()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Only managed when the plugin is active (see the `release` feature), so
+/// use `try_state` in code that also runs in release builds.
+///
+/// Captures and evals wait for the main thread, so call them from another
+/// thread (e.g. an async command or a test thread); on the main thread they
+/// time out.
+pub struct ScreenshotHd<R: Runtime = tauri::Wry> {
+    app: AppHandle<R>,
+    window_label: String,
+    context: Arc<Context>,
+}
+
+/// Counters since the plugin started, from [`ScreenshotHd::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Stats {
+    /// HTTP requests received, including refused ones.
+    pub requests: u64,
+    /// Captures taken (by HTTP, automatic triggers or [`ScreenshotHd::capture`]).
+    pub captures: u64,
+    /// Captures that failed.
+    pub capture_failures: u64,
+}
+
+/// Where the HTTP server is at, from [`ScreenshotHd::status`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ServerStatus {
+    /// Not bound yet.
+    Starting,
+    /// Accepting requests at this address.
+    Listening(SocketAddr),
+    /// Stopped by `POST /shutdown`.
+    Stopped,
+    /// Failed to bind, or the server thread panicked.
+    Failed(String),
+}
+
+#[derive(Default)]
+pub(crate) struct Counters {
+    pub(crate) requests: AtomicU64,
+    pub(crate) captures: AtomicU64,
+    pub(crate) capture_failures: AtomicU64,
+}
+
+/// Latched once the plugin is ready (see [`READY_EVENT`]).
+#[derive(Default)]
+pub(crate) struct Readiness {
+    ready: Mutex<bool>,
+    changed: Condvar,
+}
+
+/// The throwaway first capture (see [`Config::warmup`](crate::Config::warmup)).
+#[derive(Default)]
+pub(crate) struct Warmup {
+    enabled: bool,
+    started: AtomicBool,
+    /// How long it took, or why it failed.
+    outcome: OnceLock<Result<Duration, String>>,
+}
+
+impl Readiness {
+    pub(crate) fn is_ready(&self) -> bool {
+        *self.ready.lock().unwrap()
+    }
+}
+
+impl Warmup {
+    pub(crate) fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            ..Default::default()
+        }
+    }
+
+    /// Whether it's out of the way: off, done or failed.
+    fn finished(&self) -> bool {
+        !self.enabled || self.outcome.get().is_some()
+    }
+
+    /// Where it's at, for `GET /health`.
+    pub(crate) fn to_json(&self) -> Value {
+        match self.outcome.get() {
+            _ if !self.enabled => json!({ "status": "disabled" }),
+            None if self.started.load(Ordering::SeqCst) => json!({ "status": "running" }),
+            None => json!({ "status": "pending" }),
+            Some(Ok(took)) => json!({ "status": "done", "duration_ms": took.as_millis() as u64 }),
+            Some(Err(e)) => json!({ "status": "failed", "error": e }),
+        }
+    }
+}
+
+impl Counters {
+    /// Count a capture's outcome.
+    pub(crate) fn record<T, E>(&self, result: &Result<T, E>) {
+        let counter = match result {
+            Ok(_) => &self.captures,
+            Err(_) => &self.capture_failures,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl<R: Runtime> ScreenshotHd<R> {
+    pub(crate) fn new(app: AppHandle<R>, window_label: String, context: Arc<Context>) -> Self {
+        Self {
+            app,
+            window_label,
+            context,
+        }
+    }
+
+    /// Where the HTTP server listens, once it's up. `None` before then or if
+    /// it failed to bind.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.context.addr.get().copied()
+    }
+
+    /// The server's state, e.g. why it isn't running.
+    pub fn status(&self) -> ServerStatus {
+        if let Some(error) = self.context.failure.get() {
+            return ServerStatus::Failed(error.clone());
+        }
+        match self.context.addr.get() {
+            None => ServerStatus::Starting,
+            Some(_) if self.context.shutdown.load(Ordering::Relaxed) => ServerStatus::Stopped,
+            Some(addr) => ServerStatus::Listening(*addr),
+        }
+    }
+
+    /// Block until the server is bound and the target window exists (and,
+    /// with [`Config::warmup`](crate::Config::warmup), has been captured
+    /// once), then return the server's address. Fails right away if the server failed
+    /// to start. Don't call this on the main thread, which creates the
+    /// window.
+    pub fn wait_ready(&self, timeout: Duration) -> Result<SocketAddr, String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(error) = self.context.failure.get() {
+                return Err(format!("server failed: {error}"));
+            }
+            if check_ready(&self.app, &self.window_label, &self.context) {
+                return Ok(*self.context.addr.get().unwrap());
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(match self.context.addr.get() {
+                    None => format!("server not listening after {timeout:?}"),
+                    Some(_) => format!(
+                        "no window labelled '{}' after {timeout:?}",
+                        self.window_label
+                    ),
+                });
+            }
+            let readiness = &self.context.readiness;
+            let ready = readiness.ready.lock().unwrap();
+            let _ = readiness
+                .changed
+                .wait_timeout(ready, (deadline - now).min(READY_POLL))
+                .unwrap();
+        }
+    }
+
+    /// Request and capture counters.
+    pub fn stats(&self) -> Stats {
+        let counters = &self.context.counters;
+        Stats {
+            requests: counters.requests.load(Ordering::Relaxed),
+            captures: counters.captures.load(Ordering::Relaxed),
+            capture_failures: counters.capture_failures.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Capture the configured window with the configured settings, like
+    /// `GET /screenshot`.
+    pub fn capture(&self) -> Result<Vec<u8>, String> {
+        self.capture_window(&self.window_label)
+    }
+
+    /// Capture the window labelled `label`.
+    pub fn capture_window(&self, label: &str) -> Result<Vec<u8>, String> {
+        self.context
+            .capture(&self.window(label)?, "api")
+            .map(Vec::from)
+    }
+
+    /// The configured capture options, to adjust for
+    /// [`ScreenshotHd::capture_with`].
+    pub fn capture_options(&self) -> CaptureOptions {
+        self.context.capture_defaults.clone()
+    }
+
+    /// Capture the configured window with `options`, like `GET /screenshot`
+    /// with query parameters.
+    pub fn capture_with(&self, options: &CaptureOptions) -> Result<Vec<u8>, String> {
+        let window = self.window(&self.window_label)?;
+        self.context
+            .capture_with(&window, "api", None, options)
+            .map(Vec::from)
+    }
+
+    /// Whether read-only mode is on (see
+    /// [`Config::read_only`](crate::Config::read_only)).
+    pub fn read_only(&self) -> bool {
+        self.context.read_only.load(Ordering::Relaxed)
+    }
+
+    /// Switch read-only mode, e.g. from an app setting or a signed-in
+    /// admin. Applies to the server's next request.
+    pub fn set_read_only(&self, read_only: bool) {
+        self.context.read_only.store(read_only, Ordering::Relaxed);
+    }
+
+    /// Evaluate a JS expression in the configured window and return its
+    /// value (round-tripped through `JSON.stringify`; macOS only).
+    pub fn eval(&self, expr: &str) -> Result<Value, String> {
+        self.eval_window(&self.window_label, expr)
+    }
+
+    /// Evaluate a JS expression in the window labelled `label`.
+    pub fn eval_window(&self, label: &str, expr: &str) -> Result<Value, String> {
+        let window = self.window(label)?;
+        self.context.events.publish(
+            "eval",
+            serde_json::json!({
+                "window": label,
+                "length": expr.len(),
+                "script": expr.chars().take(events::SCRIPT_PREVIEW).collect::<String>(),
+            }),
+        );
+        logging::debug!("[screenshot-hd] eval in '{label}': {}", logging::body(expr));
+        crate::eval_json(&window, expr)
+            .inspect_err(|e| self.context.report(ErrorKind::Eval, &window, e))
+    }
+
+    fn window(&self, label: &str) -> Result<WebviewWindow<R>, String> {
+        self.app
+            .get_webview_window(label)
+            .ok_or_else(|| format!("no window labelled '{label}'"))
+    }
+}
+
+/// Whether the server is bound, `window_label` exists and the warm-up
+/// capture is out of the way. The first time all hold, wakes
+/// [`ScreenshotHd::wait_ready`] callers and emits [`READY_EVENT`].
+pub(crate) fn check_ready<R: Runtime>(
+    app: &AppHandle<R>,
+    window_label: &str,
+    context: &Arc<Context>,
+) -> bool {
+    let readiness = &context.readiness;
+    if readiness.is_ready() {
+        return true;
+    }
+    let Some(addr) = context.addr.get() else {
+        return false;
+    };
+    let Some(window) = app.get_webview_window(window_label) else {
+        return false;
+    };
+    if !warm_up(app, window, context) {
+        return false;
+    }
+
+    let mut ready = readiness.ready.lock().unwrap();
+    if std::mem::replace(&mut *ready, true) {
+        return true;
+    }
+    readiness.changed.notify_all();
+    drop(ready);
+    logging::info!("[screenshot-hd] ready: http://{addr}, window '{window_label}'");
+    let payload = serde_json::json!({ "addr": addr.to_string(), "window": window_label });
+    if let Err(e) = app.emit(READY_EVENT, payload) {
+        logging::warn!("[screenshot-hd] failed to emit {READY_EVENT}: {e}");
+    }
+    true
+}
+
+/// Start the warm-up capture of `window` the first time it's due, and
+/// whether it's out of the way. The first snapshot after launch is several
+/// times slower than the rest; this one takes the hit instead of a test's.
+fn warm_up<R: Runtime>(
+    app: &AppHandle<R>,
+    window: WebviewWindow<R>,
+    context: &Arc<Context>,
+) -> bool {
+    let warmup = &context.warmup;
+    if warmup.finished() {
+        return true;
+    }
+    if warmup.started.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+    let app = app.clone();
+    let context = context.clone();
+    // Off the main thread, which the capture waits for
+    std::thread::spawn(move || {
+        let started = Instant::now();
+        let outcome = context
+            .render(&window, &context.capture_defaults)
+            .map(|_| started.elapsed());
+        match &outcome {
+            Ok(took) => logging::info!("[screenshot-hd] warm-up capture took {took:?}"),
+            Err(e) => logging::warn!("[screenshot-hd] warm-up capture failed: {e}"),
+        }
+        let _ = context.warmup.outcome.set(outcome);
+        check_ready(&app, window.label(), &context);
+    });
+    false
+}
+
+/// Exit the app unless `screenshots` is ready, with a successful warm-up
+/// capture, within `timeout` (see
+/// [`Config::block_until_ready`](crate::Config::block_until_ready)).
+pub(crate) fn require_ready<R: Runtime>(screenshots: ScreenshotHd<R>, timeout: Duration) {
+    std::thread::spawn(move || {
+        let context = &screenshots.context;
+        let result =
+            screenshots
+                .wait_ready(timeout)
+                .and_then(|_| match context.warmup.outcome.get() {
+                    Some(Err(e)) => Err(format!("the trial capture failed: {e}")),
+                    _ => Ok(()),
+                });
+        let Err(e) = result else {
+            return;
+        };
+        logging::error!("[screenshot-hd] not ready (Config::block_until_ready): {e}; exiting");
+        if context.failure.get().is_none() {
+            fail(&screenshots.app, context, e);
+        }
+        screenshots.app.exit(1);
+    });
+}
+
+/// Record that the server is gone for good, wake [`ScreenshotHd::wait_ready`]
+/// callers and emit [`FAILED_EVENT`].
+pub(crate) fn fail<R: Runtime>(app: &AppHandle<R>, context: &Context, error: String) {
+    logging::warn!("[screenshot-hd] {error}");
+    let payload = serde_json::json!({ "error": error });
+    let _ = context.failure.set(error);
+    {
+        let _ready = context.readiness.ready.lock().unwrap();
+        context.readiness.changed.notify_all();
+    }
+    if let Err(e) = app.emit(FAILED_EVENT, payload) {
+        logging::warn!("[screenshot-hd] failed to emit {FAILED_EVENT}: {e}");
+    }
+}
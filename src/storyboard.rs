@@ -0,0 +1,143 @@
+//! `POST /storyboard`: run a list of interaction steps, capture after each
+//! and assemble the captures into an animated walkthrough.
+
+use crate::animation::{self, AnimationFormat};
+use crate::{format, CaptureOptions, Context, ImageFormat, Wait};
+use serde_json::Value;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tauri::{Runtime, Url, WebviewWindow};
+
+/// How long a frame shows when neither the step nor the storyboard says.
+const DEFAULT_DELAY: Duration = Duration::from_millis(1000);
+/// Every frame is held in memory until the animation is encoded.
+const MAX_STEPS: usize = 50;
+
+/// A `POST /storyboard` request.
+pub(crate) struct Storyboard {
+    steps: Vec<Step>,
+    pub(crate) format: AnimationFormat,
+    settings: CaptureOptions,
+}
+
+/// One frame: what to do before capturing it, and how long it shows.
+struct Step {
+    /// Navigate here first.
+    url: Option<Url>,
+    /// Then run this script.
+    script: Option<String>,
+    /// Then wait this long, instead of the query's `?wait=`.
+    wait: Option<Duration>,
+    delay: Duration,
+}
+
+impl Storyboard {
+    /// Parse a `{"steps": [..], "format": .., "delay": ..}` body. Step URLs
+    /// resolve against `current`, the window's URL. Capture options come
+    /// from the query.
+    pub(crate) fn parse(
+        url: &str,
+        body: &str,
+        current: &Url,
+        defaults: CaptureOptions,
+    ) -> Result<Self, String> {
+        let body: Value = serde_json::from_str(body).map_err(|e| format!("invalid JSON: {e}"))?;
+        let millis = |value: &Value, key: &str| match &value[key] {
+            Value::Null => Ok(None),
+            v => v
+                .as_u64()
+                .map(|ms| Some(Duration::from_millis(ms)))
+                .ok_or_else(|| format!("\"{key}\" must be a number of milliseconds")),
+        };
+        let format = match &body["format"] {
+            Value::Null => AnimationFormat::default(),
+            v => v
+                .as_str()
+                .and_then(AnimationFormat::parse)
+                .ok_or("unknown \"format\" (gif, apng, webp)")?,
+        };
+        let delay = millis(&body, "delay")?.unwrap_or(DEFAULT_DELAY);
+
+        let Some(steps) = body["steps"].as_array().filter(|s| !s.is_empty()) else {
+            return Err("\"steps\" must be a non-empty array".into());
+        };
+        if steps.len() > MAX_STEPS {
+            return Err(format!("at most {MAX_STEPS} steps"));
+        }
+        let steps = steps
+            .iter()
+            .enumerate()
+            .map(|(i, step)| {
+                let string = |key: &str| match &step[key] {
+                    Value::Null => Ok(None),
+                    Value::String(s) => Ok(Some(s.clone())),
+                    _ => Err(format!("step {i}: \"{key}\" must be a string")),
+                };
+                let url = string("url")?
+                    .map(|page| {
+                        current
+                            .join(&page)
+                            .map_err(|e| format!("step {i}: invalid URL '{page}': {e}"))
+                    })
+                    .transpose()?;
+                Ok(Step {
+                    url,
+                    script: string("script")?,
+                    wait: millis(step, "wait").map_err(|e| format!("step {i}: {e}"))?,
+                    delay: millis(step, "delay")
+                        .map_err(|e| format!("step {i}: {e}"))?
+                        .unwrap_or(delay),
+                })
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(Self {
+            steps,
+            format,
+            settings: defaults.with_query(url)?,
+        })
+    }
+}
+
+/// Run the steps in order, capturing after each, and encode the captures
+/// as the storyboard's animation. Scripts are audited as if sent to
+/// `/eval` by `remote`. Fails on the first step that does, or at `deadline`.
+pub(crate) fn run<R: Runtime>(
+    window: &WebviewWindow<R>,
+    context: &Context,
+    storyboard: &Storyboard,
+    remote: Option<SocketAddr>,
+    deadline: Instant,
+) -> Result<Vec<u8>, (u16, String)> {
+    let mut frames = Vec::with_capacity(storyboard.steps.len());
+    for (i, step) in storyboard.steps.iter().enumerate() {
+        if Instant::now() >= deadline {
+            return Err((504, format!("step {i}: out of time")));
+        }
+        if let Some(url) = &step.url {
+            crate::page::navigate(window, url.clone(), deadline)
+                .map_err(|e| (504, format!("step {i}: {e}")))?;
+        }
+        if let Some(script) = &step.script {
+            if let Some(audit) = &context.audit {
+                audit.record(remote, "/storyboard", window.label(), script);
+            }
+            context
+                .eval(window, script)
+                .map_err(|e| (500, format!("step {i}: {e}")))?;
+        }
+
+        // QOI is the cheapest to encode and decode again
+        let settings = CaptureOptions {
+            format: ImageFormat::Qoi,
+            wait: step.wait.map_or(storyboard.settings.wait, Wait::Delay),
+            ..storyboard.settings.clone()
+        };
+        let frame = context
+            .snapshot(window, &settings)
+            .and_then(|bytes| format::decode(&bytes))
+            .map_err(|e| (504, format!("step {i}: {e}")))?;
+        frames.push((frame, step.delay));
+    }
+    animation::encode(&frames, storyboard.format).map_err(|e| (500, e))
+}
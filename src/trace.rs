@@ -0,0 +1,66 @@
+//! Distributed tracing (`tracing` feature): each HTTP request gets a
+//! `tracing` span, with captures and evals as children, and a request
+//! carrying a W3C `traceparent` header is joined to that trace through
+//! `tracing-opentelemetry`. The app sets up the subscriber and exporter;
+//! without an OpenTelemetry layer the spans are plain `tracing` spans.
+
+use crate::logging;
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+use std::str::FromStr;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// The span for one HTTP request to `path`, parented to the caller's span
+/// when the request has a valid `traceparent`.
+pub(crate) fn request_span(request: &tiny_http::Request, path: &str) -> tracing::Span {
+    let method = request.method().as_str();
+    let span = tracing::info_span!(
+        "screenshot_hd.request",
+        otel.name = format!("{method} {path}"),
+        otel.kind = "server",
+        http.request.method = method,
+        url.path = path,
+    );
+    let header = |name: &'static str| {
+        request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv(name))
+            .map(|h| h.value.as_str())
+    };
+    if let Some(parent) = header("traceparent").and_then(|v| parse(v, header("tracestate"))) {
+        let context = opentelemetry::Context::new().with_remote_span_context(parent);
+        if let Err(e) = span.set_parent(context) {
+            logging::debug!("[screenshot-hd] can't join the request's trace: {e}");
+        }
+    }
+    span
+}
+
+/// A `traceparent` (`00-<trace id>-<parent id>-<flags>`) and optional
+/// `tracestate`, or `None` if it's malformed or all zeros.
+fn parse(traceparent: &str, tracestate: Option<&str>) -> Option<SpanContext> {
+    let mut parts = traceparent.trim().split('-');
+    let version = parts.next()?;
+    let (trace_id, span_id, flags) = (parts.next()?, parts.next()?, parts.next()?);
+    // Version 00 has exactly four fields; later versions may append more
+    if version.len() != 2 || version == "ff" || (version == "00" && parts.next().is_some()) {
+        return None;
+    }
+    if trace_id.len() != 32 || span_id.len() != 16 || flags.len() != 2 {
+        return None;
+    }
+    let trace_id = TraceId::from_hex(trace_id).ok()?;
+    let span_id = SpanId::from_hex(span_id).ok()?;
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+    let state = tracestate
+        .and_then(|s| TraceState::from_str(s).ok())
+        .unwrap_or_default();
+    let context = SpanContext::new(
+        trace_id,
+        span_id,
+        TraceFlags::new(flags) & TraceFlags::SAMPLED,
+        true,
+        state,
+    );
+    context.is_valid().then_some(context)
+}
@@ -0,0 +1,736 @@
+//! The capture pipeline: post-processing stages applied to a decoded
+//! capture before it's encoded ([`Config::pipeline`](crate::Config::pipeline),
+//! `?pipeline=`, and the `?thumbnail=`, `?grayscale=`, `?channel=`,
+//! `?background=`, `?rotate=` and `?flip=` shorthands).
+
+use crate::format::Frame;
+use crate::CaptureOptions;
+use image::imageops::{self, FilterType};
+use std::fmt;
+use std::sync::Arc;
+
+/// Outline width of [`Stage::Annotate`] rectangles, in pixels.
+const ANNOTATE_WIDTH: u32 = 3;
+
+/// Most pixels a stage may output (256 MiB of RGBA), on top of the
+/// per-side limit.
+const MAX_PIXELS: u64 = 1 << 26;
+
+/// Side of a [`Background::Checkerboard`] square, in pixels, and its two
+/// colors.
+const CHECKER_SIZE: u32 = 8;
+const CHECKER_COLORS: [[u8; 3]; 2] = [[255, 255, 255], [204, 204, 204]];
+
+/// The [`Stage::Cursor`] arrow at 1x, tip at the top left: `#` outline,
+/// `o` fill, transparent elsewhere.
+const CURSOR: [&str; 19] = [
+    "#           ",
+    "##          ",
+    "#o#         ",
+    "#oo#        ",
+    "#ooo#       ",
+    "#oooo#      ",
+    "#ooooo#     ",
+    "#oooooo#    ",
+    "#ooooooo#   ",
+    "#oooooooo#  ",
+    "#ooooooooo# ",
+    "#oooooo#####",
+    "#ooo#oo#    ",
+    "#oo##oo#    ",
+    "#o#  #oo#   ",
+    "##   #oo#   ",
+    "#     #oo#  ",
+    "      #oo#  ",
+    "       ##   ",
+];
+
+/// One post-processing step. Coordinates are in pixels of the frame as it
+/// reaches the stage.
+#[derive(Debug, Clone)]
+pub enum Stage {
+    /// Keep only this rect (clamped to the frame).
+    Crop {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    /// Resample (Lanczos) by this factor.
+    Scale(f64),
+    /// Turn clockwise, e.g. to bring a rotated kiosk window upright.
+    Rotate(Rotation),
+    /// Mirror along one axis.
+    Flip(Flip),
+    /// Downscale so neither side exceeds this many pixels; smaller frames
+    /// are left alone.
+    Thumbnail(u32),
+    /// Replace color with Rec. 709 luma, keeping alpha.
+    Grayscale,
+    /// Replace each pixel with an opaque gray of one channel's value.
+    Channel(Channel),
+    /// Flatten transparency onto a background, for tools that mishandle
+    /// alpha.
+    Background(Background),
+    /// Fill a rect with a solid RGBA color, e.g. over a clock or avatar
+    /// that changes between runs.
+    Mask {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        color: [u8; 4],
+    },
+    /// Outline a rect in an RGBA color, e.g. to point at a problem.
+    Annotate {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        color: [u8; 4],
+    },
+    /// Draw an arrow pointer with its tip at a point, `scale` pixels per
+    /// point of the arrow. `?show_cursor=` adds one where the mouse is.
+    Cursor { x: u32, y: u32, scale: f64 },
+    /// Arbitrary processing, built with [`Stage::custom`]. Only available
+    /// from [`Config::pipeline`](crate::Config::pipeline).
+    Custom(StageFn),
+}
+
+/// A single channel to extract as a grayscale image (`?channel=`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+    Alpha,
+}
+
+/// How far [`Stage::Rotate`] turns a capture, clockwise (`?rotate=`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Clockwise90,
+    Clockwise180,
+    Clockwise270,
+}
+
+/// Which way [`Stage::Flip`] mirrors a capture (`?flip=`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flip {
+    /// Left to right.
+    Horizontal,
+    /// Top to bottom.
+    Vertical,
+}
+
+/// What [`Stage::Background`] flattens a capture onto (`?background=`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    /// A solid RGB color.
+    Color([u8; 3]),
+    /// The usual light gray checkerboard, to show where the capture is
+    /// transparent.
+    Checkerboard,
+}
+
+/// The closure behind [`Stage::Custom`].
+#[derive(Clone)]
+pub struct StageFn(Arc<dyn Fn(Frame) -> Result<Frame, String> + Send + Sync>);
+
+impl fmt::Debug for StageFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StageFn(..)")
+    }
+}
+
+impl Channel {
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        match s {
+            "red" | "r" => Some(Self::Red),
+            "green" | "g" => Some(Self::Green),
+            "blue" | "b" => Some(Self::Blue),
+            "alpha" | "a" => Some(Self::Alpha),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "client")]
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Red => "red",
+            Self::Green => "green",
+            Self::Blue => "blue",
+            Self::Alpha => "alpha",
+        }
+    }
+}
+
+impl Rotation {
+    /// Degrees: `90`, `180` or `270`.
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        match s {
+            "90" => Some(Self::Clockwise90),
+            "180" => Some(Self::Clockwise180),
+            "270" => Some(Self::Clockwise270),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "client")]
+    fn degrees(self) -> u32 {
+        match self {
+            Self::Clockwise90 => 90,
+            Self::Clockwise180 => 180,
+            Self::Clockwise270 => 270,
+        }
+    }
+}
+
+impl Flip {
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        match s {
+            "h" | "horizontal" => Some(Self::Horizontal),
+            "v" | "vertical" => Some(Self::Vertical),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "client")]
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Horizontal => "h",
+            Self::Vertical => "v",
+        }
+    }
+}
+
+impl Background {
+    /// `rrggbb` (with or without `#`) or `checkerboard`.
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        if s == "checkerboard" {
+            return Some(Self::Checkerboard);
+        }
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        match parse_color(s)? {
+            [r, g, b, _] if hex.len() == 6 => Some(Self::Color([r, g, b])),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "client")]
+    fn to_query(self) -> String {
+        match self {
+            Self::Color(rgb) => rgb.iter().map(|b| format!("{b:02x}")).collect(),
+            Self::Checkerboard => "checkerboard".into(),
+        }
+    }
+}
+
+impl Stage {
+    /// A stage running `stage` on every frame. It runs on the capturing
+    /// thread and must return a frame whose buffer matches its size.
+    pub fn custom(stage: impl Fn(Frame) -> Result<Frame, String> + Send + Sync + 'static) -> Self {
+        Self::Custom(StageFn(Arc::new(stage)))
+    }
+
+    /// Parse a `?pipeline=` list: stages separated by `;`, each a name and
+    /// optionally `:` and comma-separated arguments, e.g.
+    /// `crop:0,0,800,600;mask:10,10,120,24,000000;thumbnail:256`.
+    pub(crate) fn parse_list(s: &str) -> Result<Vec<Self>, String> {
+        s.split(';')
+            .filter(|stage| !stage.trim().is_empty())
+            .map(|stage| Self::parse(stage.trim()))
+            .collect()
+    }
+
+    fn parse(s: &str) -> Result<Self, String> {
+        let (name, args) = s.split_once(':').unwrap_or((s, ""));
+        let args: Vec<&str> = args.split(',').filter(|a| !a.is_empty()).collect();
+        let invalid = |expected: &str| format!("invalid stage '{s}', expected {expected}");
+        let rect = || -> Option<(u32, u32, u32, u32)> {
+            match args[..] {
+                [x, y, w, h, ..] => Some((
+                    x.parse().ok()?,
+                    y.parse().ok()?,
+                    w.parse().ok()?,
+                    h.parse().ok()?,
+                )),
+                _ => None,
+            }
+        };
+        let color = args.get(4).map_or(Some([0, 0, 0, 255]), |c| parse_color(c));
+
+        match name {
+            "crop" => match (rect(), args.len()) {
+                (Some((x, y, width, height)), 4) => Ok(Self::Crop {
+                    x,
+                    y,
+                    width,
+                    height,
+                }),
+                _ => Err(invalid("crop:x,y,width,height")),
+            },
+            "scale" => args
+                .first()
+                .filter(|_| args.len() == 1)
+                .and_then(|f| f.parse::<f64>().ok())
+                .filter(|f| *f > 0.0 && *f <= crate::MAX_SCALE)
+                .map(Self::Scale)
+                .ok_or_else(|| invalid(&format!("scale:<factor in (0, {}]>", crate::MAX_SCALE))),
+            "rotate" => args
+                .first()
+                .filter(|_| args.len() == 1)
+                .and_then(|r| Rotation::parse(r))
+                .map(Self::Rotate)
+                .ok_or_else(|| invalid("rotate:90|180|270")),
+            "flip" => args
+                .first()
+                .filter(|_| args.len() == 1)
+                .and_then(|f| Flip::parse(f))
+                .map(Self::Flip)
+                .ok_or_else(|| invalid("flip:h|v")),
+            "thumbnail" => match args[..] {
+                [max] => parse_thumbnail(max).map(Self::Thumbnail),
+                _ => Err(invalid("thumbnail:<px>")),
+            },
+            "grayscale" if args.is_empty() => Ok(Self::Grayscale),
+            "channel" => args
+                .first()
+                .filter(|_| args.len() == 1)
+                .and_then(|c| Channel::parse(c))
+                .map(Self::Channel)
+                .ok_or_else(|| invalid("channel:red|green|blue|alpha")),
+            "background" => args
+                .first()
+                .filter(|_| args.len() == 1)
+                .and_then(|b| Background::parse(b))
+                .map(Self::Background)
+                .ok_or_else(|| invalid("background:rrggbb|checkerboard")),
+            "mask" | "annotate" => match (rect(), color, args.len()) {
+                (Some((x, y, width, height)), Some(color), 4 | 5) if name == "mask" => {
+                    Ok(Self::Mask {
+                        x,
+                        y,
+                        width,
+                        height,
+                        color,
+                    })
+                }
+                (Some((x, y, width, height)), Some(color), 4 | 5) => Ok(Self::Annotate {
+                    x,
+                    y,
+                    width,
+                    height,
+                    color,
+                }),
+                _ => Err(invalid(&format!("{name}:x,y,width,height[,rrggbb[aa]]"))),
+            },
+            "cursor" => match args[..] {
+                [x, y] | [x, y, _] => {
+                    let scale = args.get(2).map_or(Some(1.0), |s| {
+                        s.parse::<f64>()
+                            .ok()
+                            .filter(|s| *s > 0.0 && *s <= crate::MAX_SCALE)
+                    });
+                    match (x.parse(), y.parse(), scale) {
+                        (Ok(x), Ok(y), Some(scale)) => Ok(Self::Cursor { x, y, scale }),
+                        _ => Err(invalid("cursor:x,y[,scale]")),
+                    }
+                }
+                _ => Err(invalid("cursor:x,y[,scale]")),
+            },
+            _ => Err(format!(
+                "unknown stage '{name}' (crop, scale, rotate, flip, thumbnail, grayscale, \
+                 channel, background, mask, annotate, cursor)"
+            )),
+        }
+    }
+
+    /// This stage in `?pipeline=` syntax.
+    #[cfg(feature = "client")]
+    pub(crate) fn to_query(&self) -> Result<String, String> {
+        let hex = |c: &[u8; 4]| c.iter().map(|b| format!("{b:02x}")).collect::<String>();
+        Ok(match self {
+            Self::Crop {
+                x,
+                y,
+                width,
+                height,
+            } => format!("crop:{x},{y},{width},{height}"),
+            Self::Scale(factor) => format!("scale:{factor}"),
+            Self::Rotate(rotation) => format!("rotate:{}", rotation.degrees()),
+            Self::Flip(flip) => format!("flip:{}", flip.as_str()),
+            Self::Thumbnail(max) => format!("thumbnail:{max}"),
+            Self::Grayscale => "grayscale".into(),
+            Self::Channel(channel) => format!("channel:{}", channel.as_str()),
+            Self::Background(background) => format!("background:{}", background.to_query()),
+            Self::Mask {
+                x,
+                y,
+                width,
+                height,
+                color,
+            } => format!("mask:{x},{y},{width},{height},{}", hex(color)),
+            Self::Annotate {
+                x,
+                y,
+                width,
+                height,
+                color,
+            } => format!("annotate:{x},{y},{width},{height},{}", hex(color)),
+            Self::Cursor { x, y, scale } => format!("cursor:{x},{y},{scale}"),
+            Self::Custom(_) => return Err("custom stages can't be sent to a server".into()),
+        })
+    }
+}
+
+/// Validate a `?thumbnail=` / `thumbnail:` size.
+pub(crate) fn parse_thumbnail(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(max) if (1..=crate::MAX_WIDTH).contains(&max) => Ok(max),
+        _ => Err(format!("thumbnail must be in 1..={}", crate::MAX_WIDTH)),
+    }
+}
+
+/// `rrggbb` or `rrggbbaa`, with or without a leading `#`.
+fn parse_color(s: &str) -> Option<[u8; 4]> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+        return None;
+    }
+    let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    let alpha = if hex.len() == 8 { byte(6)? } else { 255 };
+    Some([byte(0)?, byte(2)?, byte(4)?, alpha])
+}
+
+/// Whether `settings` have any stages, i.e. whether the capture has to be
+/// decoded rather than encoded straight from AppKit.
+#[cfg(target_os = "macos")]
+pub(crate) fn any(settings: &CaptureOptions) -> bool {
+    !settings.pipeline.is_empty()
+}
+
+/// Run `frame` through the pipeline in `settings`, in order.
+pub(crate) fn apply(mut frame: Frame, settings: &CaptureOptions) -> Result<Frame, String> {
+    for stage in &settings.pipeline {
+        if let Some((width, height)) = output_size(stage, frame.width, frame.height) {
+            check_size(width, height)?;
+        }
+        frame = match *stage {
+            Stage::Crop {
+                x,
+                y,
+                width,
+                height,
+            } => crop(&frame, x, y, width, height)?,
+            Stage::Scale(_) => {
+                let (width, height) = output_size(stage, frame.width, frame.height)
+                    .unwrap_or((frame.width, frame.height));
+                resize(frame, width, height)
+            }
+            Stage::Rotate(rotation) => rotate(frame, rotation),
+            Stage::Flip(flip) => mirror(frame, flip),
+            Stage::Thumbnail(max) => thumbnail(frame, max),
+            Stage::Grayscale => {
+                grayscale(&mut frame);
+                frame
+            }
+            Stage::Channel(channel) => {
+                extract(&mut frame, channel);
+                frame
+            }
+            Stage::Background(background) => {
+                flatten(&mut frame, background);
+                frame
+            }
+            Stage::Mask {
+                x,
+                y,
+                width,
+                height,
+                color,
+            } => {
+                fill(&mut frame, x, y, width, height, color);
+                frame
+            }
+            Stage::Annotate {
+                x,
+                y,
+                width,
+                height,
+                color,
+            } => {
+                let w = ANNOTATE_WIDTH.min(width).min(height);
+                fill(&mut frame, x, y, width, w, color);
+                fill(
+                    &mut frame,
+                    x,
+                    y.saturating_add(height).saturating_sub(w),
+                    width,
+                    w,
+                    color,
+                );
+                fill(&mut frame, x, y, w, height, color);
+                fill(
+                    &mut frame,
+                    x.saturating_add(width).saturating_sub(w),
+                    y,
+                    w,
+                    height,
+                    color,
+                );
+                frame
+            }
+            Stage::Cursor { x, y, scale } => {
+                cursor(&mut frame, x, y, scale);
+                frame
+            }
+            Stage::Custom(StageFn(ref stage)) => {
+                let frame = stage(frame)?;
+                let expected = u64::from(frame.width) * u64::from(frame.height) * 4;
+                if frame.rgba.len() as u64 != expected {
+                    return Err("custom stage returned a frame of the wrong size".into());
+                }
+                check_size(frame.width, frame.height)?;
+                frame
+            }
+        };
+    }
+    Ok(frame)
+}
+
+/// The most memory the pipeline in `settings` allocates for a frame made
+/// from a `width`x`height` capture, e.g. the output of an upscale. Stages
+/// that work in place don't count; a custom stage ends the estimate.
+pub(crate) fn peak_bytes(width: u32, height: u32, settings: &CaptureOptions) -> usize {
+    let (mut width, mut height) = (width, height);
+    let mut peak = 0;
+    for stage in &settings.pipeline {
+        let Some(size) = output_size(stage, width, height) else {
+            break;
+        };
+        if matches!(
+            stage,
+            Stage::Crop { .. } | Stage::Scale(_) | Stage::Rotate(_) | Stage::Flip(_)
+        ) || matches!(stage, Stage::Thumbnail(_) if size != (width, height))
+        {
+            peak = peak.max(size.0 as usize * size.1 as usize * 4);
+        }
+        (width, height) = size;
+    }
+    peak
+}
+
+/// Reject a stage output too big to allocate safely, e.g. from a large
+/// `scale:`.
+fn check_size(width: u32, height: u32) -> Result<(), String> {
+    if width > crate::MAX_WIDTH
+        || height > crate::MAX_WIDTH
+        || u64::from(width) * u64::from(height) > MAX_PIXELS
+    {
+        return Err(format!(
+            "a stage would output a {width}x{height} frame, over the {}px side or \
+             {MAX_PIXELS} pixel limit",
+            crate::MAX_WIDTH
+        ));
+    }
+    Ok(())
+}
+
+/// The size `stage` turns a `width`x`height` frame into, or `None` for a
+/// custom stage, which can't be known before it runs.
+fn output_size(stage: &Stage, width: u32, height: u32) -> Option<(u32, u32)> {
+    let scaled = |factor: f64| {
+        (
+            ((f64::from(width) * factor).round() as u32).max(1),
+            ((f64::from(height) * factor).round() as u32).max(1),
+        )
+    };
+    Some(match *stage {
+        Stage::Crop {
+            x,
+            y,
+            width: w,
+            height: h,
+        } => (
+            x.saturating_add(w).min(width).saturating_sub(x),
+            y.saturating_add(h).min(height).saturating_sub(y),
+        ),
+        Stage::Scale(factor) => scaled(factor),
+        Stage::Thumbnail(max) if width.max(height) > max => {
+            scaled(f64::from(max) / f64::from(width.max(height)))
+        }
+        Stage::Rotate(Rotation::Clockwise90 | Rotation::Clockwise270) => (height, width),
+        Stage::Custom(_) => return None,
+        _ => (width, height),
+    })
+}
+
+fn crop(frame: &Frame, x: u32, y: u32, width: u32, height: u32) -> Result<Frame, String> {
+    let right = x.saturating_add(width).min(frame.width);
+    let bottom = y.saturating_add(height).min(frame.height);
+    if right <= x || bottom <= y {
+        return Err(format!(
+            "crop {x},{y},{width},{height} is outside the {}x{} frame",
+            frame.width, frame.height
+        ));
+    }
+    let row = |row: u32| {
+        let start = (row as usize * frame.width as usize + x as usize) * 4;
+        &frame.rgba[start..start + (right - x) as usize * 4]
+    };
+    Ok(Frame {
+        width: right - x,
+        height: bottom - y,
+        rgba: (y..bottom).flat_map(row).copied().collect(),
+    })
+}
+
+/// Downscale `frame` so neither side exceeds `max` pixels. Smaller frames
+/// are left alone.
+fn thumbnail(frame: Frame, max: u32) -> Frame {
+    let longest = frame.width.max(frame.height);
+    if longest <= max {
+        return frame;
+    }
+    let ratio = f64::from(max) / f64::from(longest);
+    let width = ((f64::from(frame.width) * ratio).round() as u32).max(1);
+    let height = ((f64::from(frame.height) * ratio).round() as u32).max(1);
+    resize(frame, width, height)
+}
+
+fn resize(frame: Frame, width: u32, height: u32) -> Frame {
+    let Some(source) = image::ImageBuffer::<image::Rgba<u8>, _>::from_raw(
+        frame.width,
+        frame.height,
+        &frame.rgba[..],
+    ) else {
+        return frame;
+    };
+    let resized = imageops::resize(&source, width, height, FilterType::Lanczos3);
+    Frame {
+        width,
+        height,
+        rgba: resized.into_raw(),
+    }
+}
+
+fn rotate(frame: Frame, rotation: Rotation) -> Frame {
+    let Some(source) = image::ImageBuffer::<image::Rgba<u8>, _>::from_raw(
+        frame.width,
+        frame.height,
+        &frame.rgba[..],
+    ) else {
+        return frame;
+    };
+    let rotated = match rotation {
+        Rotation::Clockwise90 => imageops::rotate90(&source),
+        Rotation::Clockwise180 => imageops::rotate180(&source),
+        Rotation::Clockwise270 => imageops::rotate270(&source),
+    };
+    Frame {
+        width: rotated.width(),
+        height: rotated.height(),
+        rgba: rotated.into_raw(),
+    }
+}
+
+fn mirror(frame: Frame, flip: Flip) -> Frame {
+    let Some(source) = image::ImageBuffer::<image::Rgba<u8>, _>::from_raw(
+        frame.width,
+        frame.height,
+        &frame.rgba[..],
+    ) else {
+        return frame;
+    };
+    let flipped = match flip {
+        Flip::Horizontal => imageops::flip_horizontal(&source),
+        Flip::Vertical => imageops::flip_vertical(&source),
+    };
+    Frame {
+        width: frame.width,
+        height: frame.height,
+        rgba: flipped.into_raw(),
+    }
+}
+
+/// Replace color with Rec. 709 luma, keeping alpha.
+fn grayscale(frame: &mut Frame) {
+    for pixel in frame.rgba.chunks_exact_mut(4) {
+        let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(f32::from);
+        let luma = (0.2126 * r + 0.7152 * g + 0.0722 * b).round() as u8;
+        pixel[..3].fill(luma);
+    }
+}
+
+/// Replace each pixel with an opaque gray of `channel`'s value, so e.g. the
+/// alpha channel shows as white where the capture is opaque.
+fn extract(frame: &mut Frame, channel: Channel) {
+    let index = match channel {
+        Channel::Red => 0,
+        Channel::Green => 1,
+        Channel::Blue => 2,
+        Channel::Alpha => 3,
+    };
+    for pixel in frame.rgba.chunks_exact_mut(4) {
+        let value = pixel[index];
+        pixel.copy_from_slice(&[value, value, value, 255]);
+    }
+}
+
+/// Composite every pixel over `background`, leaving the frame opaque.
+fn flatten(frame: &mut Frame, background: Background) {
+    let width = frame.width as usize;
+    for (i, pixel) in frame.rgba.chunks_exact_mut(4).enumerate() {
+        let under = match background {
+            Background::Color(rgb) => rgb,
+            Background::Checkerboard => {
+                let (x, y) = ((i % width) as u32, (i / width) as u32);
+                CHECKER_COLORS[((x / CHECKER_SIZE + y / CHECKER_SIZE) % 2) as usize]
+            }
+        };
+        let alpha = u32::from(pixel[3]);
+        for (channel, under) in pixel[..3].iter_mut().zip(under) {
+            let blended = u32::from(*channel) * alpha + u32::from(under) * (255 - alpha);
+            *channel = ((blended + 127) / 255) as u8;
+        }
+        pixel[3] = 255;
+    }
+}
+
+/// Set every pixel of a rect (clamped to the frame) to `color`.
+fn fill(frame: &mut Frame, x: u32, y: u32, width: u32, height: u32, color: [u8; 4]) {
+    let right = x.saturating_add(width).min(frame.width);
+    let bottom = y.saturating_add(height).min(frame.height);
+    for row in y..bottom {
+        for column in x..right {
+            let i = (row as usize * frame.width as usize + column as usize) * 4;
+            frame.rgba[i..i + 4].copy_from_slice(&color);
+        }
+    }
+}
+
+/// Draw [`CURSOR`] with its tip at (`x`, `y`), `scale` pixels per point,
+/// clipped to the frame.
+fn cursor(frame: &mut Frame, x: u32, y: u32, scale: f64) {
+    let right = x
+        .saturating_add((CURSOR[0].len() as f64 * scale).ceil() as u32)
+        .min(frame.width);
+    let bottom = y
+        .saturating_add((CURSOR.len() as f64 * scale).ceil() as u32)
+        .min(frame.height);
+    for row in y..bottom {
+        let line = CURSOR
+            .get((f64::from(row - y) / scale) as usize)
+            .map_or(&[][..], |line| line.as_bytes());
+        for column in x..right {
+            let color = match line.get((f64::from(column - x) / scale) as usize) {
+                Some(b'#') => [0, 0, 0, 255],
+                Some(b'o') => [255, 255, 255, 255],
+                _ => continue,
+            };
+            let i = (row as usize * frame.width as usize + column as usize) * 4;
+            frame.rgba[i..i + 4].copy_from_slice(&color);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! Automatic captures: window events, route changes, DOM changes and the
+//! flight recorder.
+
+use crate::idle::{self, IdleWatch};
+use crate::logging;
+use crate::{window_state, CaptureEvent, CaptureOptions, Context, MutationCapture, Priority};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tauri::{Manager, Runtime, WindowEvent};
+
+/// How long a resize must be quiet before it's captured, so a drag produces
+/// one capture of the final layout rather than dozens of intermediate ones.
+const RESIZE_SETTLE: Duration = Duration::from_millis(300);
+
+/// Time for the page to repaint after focus or theme changes.
+const REPAINT_DELAY: Duration = Duration::from_millis(100);
+
+/// How often the page's URL is checked for a route change.
+const ROUTE_POLL: Duration = Duration::from_millis(200);
+
+/// How long a new route must hold before it's captured, so redirects and
+/// guards yield one capture of where the app ended up, rendered.
+const ROUTE_SETTLE: Duration = Duration::from_millis(400);
+
+/// Capture `window` into the history buffer whenever one of `events` fires.
+pub(crate) fn watch_window_events<R: Runtime>(
+    window: tauri::Window<R>,
+    events: Vec<CaptureEvent>,
+    context: Arc<Context>,
+) {
+    let app = window.app_handle().clone();
+    let label = window.label().to_string();
+    let resize_generation = Arc::new(AtomicU64::new(0));
+    if events.contains(&CaptureEvent::RouteChanged) {
+        watch_route(app.clone(), label.clone(), context.clone());
+    }
+
+    window.on_window_event(move |event| {
+        let trigger = match event {
+            WindowEvent::Resized(_) => CaptureEvent::Resized,
+            WindowEvent::Focused(true) => CaptureEvent::Focused,
+            WindowEvent::Focused(false) => CaptureEvent::Blurred,
+            WindowEvent::ThemeChanged(_) => CaptureEvent::ThemeChanged,
+            _ => return,
+        };
+        if !events.contains(&trigger) {
+            return;
+        }
+
+        let generation = if trigger == CaptureEvent::Resized {
+            resize_generation.fetch_add(1, Ordering::SeqCst) + 1
+        } else {
+            0
+        };
+        let resize_generation = resize_generation.clone();
+        let (app, label, context) = (app.clone(), label.clone(), context.clone());
+
+        // Event callbacks run on the main thread, which the capture itself
+        // needs — never block here.
+        std::thread::spawn(move || {
+            if trigger == CaptureEvent::Resized {
+                std::thread::sleep(RESIZE_SETTLE);
+                if resize_generation.load(Ordering::SeqCst) != generation {
+                    return; // superseded by a later event
+                }
+            } else {
+                std::thread::sleep(REPAINT_DELAY);
+            }
+
+            let Some(window) = app.get_webview_window(&label) else {
+                return;
+            };
+            match context.capture_with(&window, trigger.as_str(), None, &background(&context)) {
+                Ok(png) => {
+                    context.history.push(trigger.as_str(), None, png);
+                }
+                Err(e) => {
+                    logging::warn!("[screenshot-hd] {} capture failed: {e}", trigger.as_str())
+                }
+            }
+        });
+    });
+}
+
+/// Capture the window labelled `label` whenever its route changes, under
+/// the route as the capture's name.
+///
+/// The URL the webview reports follows `pushState`, `replaceState` and
+/// fragment changes as well as navigations, so watching it needs no
+/// script in the page and works on every platform.
+fn watch_route<R: Runtime>(app: tauri::AppHandle<R>, label: String, context: Arc<Context>) {
+    let trigger = CaptureEvent::RouteChanged.as_str();
+    std::thread::spawn(move || {
+        let route = |window: &tauri::WebviewWindow<R>| {
+            window.url().ok().map(|url| window_state::route(&url))
+        };
+        let mut captured = None;
+        loop {
+            std::thread::sleep(ROUTE_POLL);
+            let Some(window) = app.get_webview_window(&label) else {
+                continue;
+            };
+            let Some(current) = route(&window) else {
+                continue;
+            };
+            if captured.as_ref() == Some(&current) {
+                continue;
+            }
+            std::thread::sleep(ROUTE_SETTLE);
+            if route(&window).as_ref() != Some(&current) {
+                continue; // moved on again; settle on the next one
+            }
+            let settings = background(&context);
+            match context.capture_with(&window, trigger, Some(&current), &settings) {
+                Ok(_) => logging::debug!("[screenshot-hd] captured route {current}"),
+                Err(e) => logging::warn!("[screenshot-hd] {trigger} capture failed: {e}"),
+            }
+            captured = Some(current);
+        }
+    });
+}
+
+/// How often the page is checked for DOM changes.
+const MUTATION_POLL: Duration = Duration::from_millis(100);
+
+/// Capture the window into the history buffer once its DOM has changed and
+/// settled for `config.debounce`, at most every `config.min_interval`.
+pub(crate) fn run_mutation_captures<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    label: String,
+    config: MutationCapture,
+    context: Arc<Context>,
+) {
+    std::thread::spawn(move || {
+        // Nothing captured yet, so the first change (the page load) counts
+        let mut last_capture: Option<Instant> = None;
+        loop {
+            std::thread::sleep(MUTATION_POLL);
+            let Some(window) = app.get_webview_window(&label) else {
+                continue;
+            };
+            let Some(quiet) = idle::since_mutation(&window) else {
+                continue;
+            };
+            let changed = last_capture.is_none_or(|at| quiet < at.elapsed());
+            let due = last_capture.is_none_or(|at| at.elapsed() >= config.min_interval);
+            if !changed || !due || quiet < config.debounce {
+                continue;
+            }
+            last_capture = Some(Instant::now());
+            match context.capture_with(&window, "mutation", None, &background(&context)) {
+                Ok(png) => {
+                    context.history.push("mutation", None, png);
+                }
+                Err(e) => logging::debug!("[screenshot-hd] mutation capture failed: {e}"),
+            }
+        }
+    });
+}
+
+/// Capture the window every `interval` into the history buffer, so recent frames can
+/// be rewound via `GET /history`.
+pub(crate) fn run_flight_recorder<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    label: String,
+    interval: Duration,
+    context: Arc<Context>,
+) {
+    let mut idle = IdleWatch::new(context.idle_pause);
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        // The window may not exist yet (or anymore); just try again later.
+        let Some(window) = app.get_webview_window(&label) else {
+            continue;
+        };
+        // Idle gaps show as gaps between the captures' timestamps
+        if let Some(idle) = &mut idle {
+            if idle.paused(&window) {
+                std::thread::sleep(idle::POLL.saturating_sub(interval));
+                continue;
+            }
+            if let Some(gap) = idle.take_gap() {
+                logging::debug!("[screenshot-hd] flight recorder resumed after {gap:?} idle");
+            }
+        }
+        match context.capture_with(&window, "flight-recorder", None, &background(&context)) {
+            Ok(png) => {
+                context.history.push("flight-recorder", None, png);
+            }
+            Err(e) => logging::debug!("[screenshot-hd] flight recorder capture failed: {e}"),
+        }
+    });
+}
+
+/// The configured capture settings at [`Priority::Background`], so automatic
+/// captures don't hold up ones a client is waiting for.
+fn background(context: &Context) -> CaptureOptions {
+    CaptureOptions {
+        priority: Priority::Background,
+        ..context.capture_defaults.clone()
+    }
+}
@@ -0,0 +1,76 @@
+//! Captures at a fixed viewport size (`?size=WxH`), independent of how the
+//! window was last left.
+
+use crate::{deadline, logging};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{LogicalSize, Runtime, WebviewWindow};
+
+/// How long the page may take to report the new viewport size.
+const RELAYOUT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Extra time after relayout for WebKit to paint the new layout.
+const REPAINT_DELAY: Duration = Duration::from_millis(100);
+
+/// Held while a window is resized for a capture, so concurrent sized
+/// captures can't interleave their resize and restore.
+static RESIZING: Mutex<()> = Mutex::new(());
+
+/// Resize `window` to `size` (CSS pixels), wait for the page to relayout
+/// (by `deadline` at the latest), run `f`, then put the window back where
+/// it was if `restore` is set.
+pub(crate) fn with_size<R: Runtime, T>(
+    window: &WebviewWindow<R>,
+    (width, height): (u32, u32),
+    restore: bool,
+    deadline: Option<Instant>,
+    f: impl FnOnce() -> Result<T, String>,
+) -> Result<T, String> {
+    let _guard = RESIZING.lock().unwrap_or_else(|e| e.into_inner());
+
+    let previous_size = window.inner_size().map_err(|e| e.to_string())?;
+    let previous_position = window.outer_position().map_err(|e| e.to_string())?;
+
+    let result = window
+        .set_size(LogicalSize::new(f64::from(width), f64::from(height)))
+        .map_err(|e| format!("resize failed: {e}"))
+        .and_then(|_| wait_for_viewport(window, width, height, deadline))
+        .and_then(|_| f());
+
+    if restore {
+        if let Err(e) = window
+            .set_size(previous_size)
+            .and_then(|_| window.set_position(previous_position))
+        {
+            logging::warn!("[screenshot-hd] failed to restore window geometry: {e}");
+        }
+    }
+    result
+}
+
+/// Poll the page until its viewport reports `width`x`height`.
+fn wait_for_viewport<R: Runtime>(
+    window: &WebviewWindow<R>,
+    width: u32,
+    height: u32,
+    client_deadline: Option<Instant>,
+) -> Result<(), String> {
+    let expected = serde_json::json!([width, height]);
+    let deadline =
+        Instant::now() + deadline::remaining(client_deadline, RELAYOUT_TIMEOUT, "resize")?;
+    loop {
+        let actual = crate::eval_json(window, "[window.innerWidth, window.innerHeight]")?;
+        if actual == expected {
+            std::thread::sleep(REPAINT_DELAY);
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            deadline::check(client_deadline, "resize")?;
+            return Err(format!(
+                "viewport didn't reach {width}x{height} (it's {actual}); \
+                 the window may be constrained by its min/max size or the screen"
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,222 @@
+//! `GET /watch`: a Server-Sent Events subscription to one region of the
+//! page, so monitors get an event when e.g. a status badge changes instead
+//! of polling full screenshots.
+//!
+//! The region is polled every `interval` and compared with the previous
+//! poll. Events:
+//!
+//! - `ready` — the subscription started; `rect` is the region in CSS pixels
+//! - `change` — `changed` (share of the region's pixels that differ) went
+//!   over `threshold`; with `?frames=true` also the cropped region as
+//!   base64 `image` in the capture format
+//! - `error` — the region couldn't be captured (e.g. the element is gone);
+//!   sent once per distinct error, and polling continues
+
+use crate::analyze;
+use crate::format::{self, Frame};
+use crate::sse::EventStream;
+use crate::{CaptureOptions, Context, ImageFormat, Wait};
+use base64::Engine;
+use serde_json::{json, Value};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tauri::{Runtime, WebviewWindow};
+
+const MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What to watch.
+pub(crate) enum Region {
+    /// The first element matching a CSS selector, re-resolved every poll.
+    Selector(String),
+    /// A fixed rect in CSS pixels.
+    Rect {
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+    },
+}
+
+pub(crate) struct Watch {
+    region: Region,
+    /// Share of the region's pixels that must change to send an event.
+    threshold: f64,
+    interval: Duration,
+    /// See [`crate::stream_max_width`].
+    max_width: Option<u32>,
+    frames: bool,
+    settings: CaptureOptions,
+}
+
+impl Watch {
+    /// Parse `?selector=` or `?rect=x,y,w,h`, `?threshold=` (default
+    /// `0.01`), `?interval=` in ms (default 500) or `?fps=`, `?max_width=`
+    /// and `?frames=`. Capture options apply to the polls.
+    pub(crate) fn from_query(url: &str, defaults: CaptureOptions) -> Result<Self, String> {
+        let region = match (
+            crate::query_param(url, "selector"),
+            crate::query_param(url, "rect"),
+        ) {
+            (Some(selector), None) => Region::Selector(selector),
+            (None, Some(rect)) => {
+                let values: Vec<f64> = rect
+                    .split(',')
+                    .map(|v| v.trim().parse::<f64>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| format!("invalid rect '{rect}', expected x,y,width,height"))?;
+                let [x, y, width, height] = values[..] else {
+                    return Err(format!("invalid rect '{rect}', expected x,y,width,height"));
+                };
+                if !(width > 0.0 && height > 0.0) {
+                    return Err("rect must have a positive width and height".into());
+                }
+                Region::Rect {
+                    x,
+                    y,
+                    width,
+                    height,
+                }
+            }
+            _ => return Err("pass exactly one of ?selector= and ?rect=".into()),
+        };
+        let threshold = match crate::query_param(url, "threshold").map(|v| v.parse::<f64>()) {
+            None => 0.01,
+            Some(Ok(t)) if (0.0..=1.0).contains(&t) => t,
+            Some(_) => return Err("threshold must be in 0..=1".into()),
+        };
+        let interval = crate::stream_interval(url, Duration::from_millis(500))?.max(MIN_INTERVAL);
+        let frames = match crate::query_param(url, "frames").map(|v| v.parse::<bool>()) {
+            None => false,
+            Some(Ok(frames)) => frames,
+            Some(Err(_)) => return Err("frames must be true or false".into()),
+        };
+        Ok(Self {
+            region,
+            threshold,
+            interval,
+            max_width: crate::stream_max_width(url)?,
+            frames,
+            settings: defaults.with_query(url)?,
+        })
+    }
+}
+
+/// Stream events for `watch` until the client disconnects or the server
+/// shuts down.
+pub(crate) fn serve<R: Runtime>(
+    request: tiny_http::Request,
+    window: &WebviewWindow<R>,
+    context: &Context,
+    watch: Watch,
+) {
+    let Ok(mut stream) = EventStream::open(request) else {
+        return;
+    };
+    let mut previous: Option<Frame> = None;
+    let mut last_error: Option<String> = None;
+
+    while !context.shutdown.load(Ordering::Relaxed) {
+        let sent = match poll(window, context, &watch) {
+            Ok((rect, crop)) => {
+                last_error = None;
+                let changed = previous.as_ref().map(|p| analyze::changed_share(p, &crop));
+                let sent = match changed {
+                    None => stream.send("ready", &json!({ "rect": rect })),
+                    Some(changed) if changed > watch.threshold => {
+                        let mut event = json!({
+                            "timestamp_ms": crate::unix_millis(),
+                            "changed": (changed * 10_000.0).round() / 10_000.0,
+                            "rect": rect,
+                        });
+                        context.events.publish("region-changed", event.clone());
+                        if watch.frames {
+                            event["image"] = format::encode(&crop, watch.settings.format)
+                                .map(|bytes| {
+                                    base64::engine::general_purpose::STANDARD.encode(bytes)
+                                })
+                                .map_or(Value::Null, Value::from);
+                        }
+                        stream.send("change", &event)
+                    }
+                    Some(_) => stream.keepalive(),
+                };
+                previous = Some(crop);
+                sent
+            }
+            Err(e) if last_error.as_ref() != Some(&e) => {
+                let sent = stream.send("error", &json!({ "message": e }));
+                last_error = Some(e);
+                sent
+            }
+            Err(_) => stream.keepalive(),
+        };
+        if sent.is_err() {
+            break;
+        }
+        std::thread::sleep(watch.interval);
+    }
+}
+
+/// Capture the page and crop it to the watched region, returning the
+/// region's CSS rect alongside.
+fn poll<R: Runtime>(
+    window: &WebviewWindow<R>,
+    context: &Context,
+    watch: &Watch,
+) -> Result<(Value, Frame), String> {
+    let rect = match &watch.region {
+        Region::Rect {
+            x,
+            y,
+            width,
+            height,
+        } => json!({ "x": x, "y": y, "width": width, "height": height }),
+        Region::Selector(selector) => {
+            let element = crate::eval_json(window, &crate::dom::element_script(selector))?;
+            if let Some(error) = element["error"].as_str() {
+                return Err(format!("invalid selector: {error}"));
+            }
+            if element.is_null() {
+                return Err(format!("no element matches '{selector}'"));
+            }
+            element["css"].clone()
+        }
+    };
+
+    // QOI is the cheapest to encode and decode again. The pipeline runs on
+    // the cropped region instead, since its stages would move the region,
+    // as would a clip.
+    let settings = CaptureOptions {
+        format: ImageFormat::Qoi,
+        clip: None,
+        wait: Wait::None,
+        pipeline: Vec::new(),
+        ..watch.settings.clone()
+    };
+    let settings = crate::capped(window, &settings, watch.max_width);
+    let bytes = context.snapshot(window, &settings)?;
+    let scale = crate::capture_scale(&bytes, window, &settings)
+        .ok_or("can't determine the capture scale")?;
+    let frame = format::decode(&bytes)?;
+
+    let px = |key: &str| (rect[key].as_f64().unwrap_or(0.0) * scale).round();
+    let left = px("x").clamp(0.0, f64::from(frame.width)) as u32;
+    let top = px("y").clamp(0.0, f64::from(frame.height)) as u32;
+    let right = (px("x") + px("width")).clamp(0.0, f64::from(frame.width)) as u32;
+    let bottom = (px("y") + px("height")).clamp(0.0, f64::from(frame.height)) as u32;
+    if right <= left || bottom <= top {
+        return Err("the region is outside the viewport".into());
+    }
+
+    let row = |y: u32| {
+        let start = ((y * frame.width + left) * 4) as usize;
+        &frame.rgba[start..start + ((right - left) * 4) as usize]
+    };
+    let crop = Frame {
+        width: right - left,
+        height: bottom - top,
+        rgba: (top..bottom).flat_map(row).copied().collect(),
+    };
+    crate::budget::recycle(frame.rgba);
+    Ok((rect, crate::transform::apply(crop, &watch.settings)?))
+}
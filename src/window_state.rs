@@ -0,0 +1,80 @@
+//! `GET /state`: whether a capture of the window would show anything
+//! worthwhile right now — focus, visibility, occlusion, where the page is
+//! and how long since it last changed.
+
+use serde_json::{json, Value};
+use tauri::{Runtime, Url, WebviewWindow};
+
+/// What the page reports about itself. Times are `null` unless the
+/// activity tracker is installed (see `Config::activity_tracker`).
+const PAGE_STATE_JS: &str = r#"(function () {
+  const ns = window.__SCREENSHOT_HD__ || {};
+  const since = (t) => (t === undefined ? null : Math.round(performance.now() - t));
+  return {
+    title: document.title,
+    ready_state: document.readyState,
+    visibility: document.visibilityState,
+    ms_since_mutation: since(ns.lastMutation),
+    ms_since_activity: since(ns.lastActivity),
+  };
+})()"#;
+
+/// The state of `window`, as JSON. Anything that can't be read (occlusion
+/// and the page's own report are macOS only) is `null`.
+pub(crate) fn report<R: Runtime>(window: &WebviewWindow<R>) -> Value {
+    let url = window.url().ok();
+    let route = url.as_ref().map(route);
+    json!({
+        "window": window.label(),
+        "focused": window.is_focused().ok(),
+        "visible": window.is_visible().ok(),
+        "minimized": window.is_minimized().ok(),
+        "occluded": occluded(window).ok(),
+        "url": url.map(|u| u.to_string()),
+        "route": route,
+        "page": crate::eval_json(window, PAGE_STATE_JS).ok(),
+    })
+}
+
+/// The part of `url` an app's router looks at: path, query and fragment.
+pub(crate) fn route(url: &Url) -> String {
+    let mut route = url.path().to_string();
+    if let Some(query) = url.query() {
+        route = format!("{route}?{query}");
+    }
+    if let Some(fragment) = url.fragment() {
+        route = format!("{route}#{fragment}");
+    }
+    route
+}
+
+/// Whether no part of the window is visible on screen (covered by other
+/// windows, on another space, or the screen is locked).
+#[cfg(target_os = "macos")]
+fn occluded<R: Runtime>(window: &WebviewWindow<R>) -> Result<bool, String> {
+    // NSWindowOcclusionStateVisible
+    const VISIBLE: u64 = 1 << 1;
+    let (tx, rx) = std::sync::mpsc::channel();
+    window
+        .with_webview(move |platform_webview| unsafe {
+            let wk_webview: cocoa::base::id = platform_webview.inner() as cocoa::base::id;
+            let ns_window: cocoa::base::id = objc::msg_send![wk_webview, window];
+            let state = if ns_window == cocoa::base::nil {
+                None
+            } else {
+                let state: u64 = objc::msg_send![ns_window, occlusionState];
+                Some(state)
+            };
+            let _ = tx.send(state);
+        })
+        .map_err(|e| format!("with_webview: {e}"))?;
+    rx.recv_timeout(std::time::Duration::from_secs(5))
+        .map_err(|e| format!("reading the occlusion state timed out: {e}"))?
+        .map(|state| state & VISIBLE == 0)
+        .ok_or_else(|| "the webview isn't in a window".to_string())
+}
+
+#[cfg(not(target_os = "macos"))]
+fn occluded<R: Runtime>(_window: &WebviewWindow<R>) -> Result<bool, String> {
+    Err("Occlusion is only reported on macOS (NSWindow).".into())
+}